@@ -0,0 +1,304 @@
+//! Python bindings for the fnr rename engine.
+//!
+//! ```python
+//! import fnr
+//!
+//! plan = fnr.plan("old", "new", globs=["**/*.csv"], base_dir="data")
+//! done = fnr.apply(plan)
+//! fnr.undo(done)
+//! ```
+//!
+//! `apply()` and `undo()` rename like the `fnr` command: chains and
+//! circles (`a -> b`, `b -> a`) go through staging, nothing that exists is
+//! overwritten, and every rename is logged to the undo journal, so `fnr
+//! undo` can take it back too.
+
+use anyhow::{anyhow, Result};
+use fnr_tool::config::UserConfig;
+use fnr_tool::journal::{BatchWriter, Journal};
+use fnr_tool::staging::{Staged, Staging};
+use fnr_tool::transfer::{move_match, Retry};
+use fnr_tool::{FileType, Finder, Match, Options};
+use pyo3::exceptions::{PyFileExistsError, PyOSError, PyValueError};
+use pyo3::prelude::*;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A single planned (or applied) rename.
+#[pyclass(frozen, get_all, module = "fnr")]
+#[derive(Clone)]
+struct Rename {
+    source: PathBuf,
+    target: PathBuf,
+    is_dir: bool,
+}
+
+#[pymethods]
+impl Rename {
+    #[new]
+    #[pyo3(signature = (source, target, is_dir = false))]
+    fn new(source: PathBuf, target: PathBuf, is_dir: bool) -> Self {
+        Rename { source, target, is_dir }
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Rename({:?} -> {:?})", self.source, self.target)
+    }
+}
+
+fn parse_file_type(file_type: &str) -> PyResult<FileType> {
+    match file_type {
+        "file" => Ok(FileType::File),
+        "dir" => Ok(FileType::Dir),
+        "both" => Ok(FileType::Both),
+        "special" => Ok(FileType::Special),
+        other => Err(PyValueError::new_err(format!(
+            "Invalid file_type '{}', expected 'file', 'dir', 'both' or 'special'",
+            other
+        ))),
+    }
+}
+
+/// Compute the renames for `pattern` -> `replacement` without touching the disk.
+#[pyfunction]
+#[pyo3(signature = (
+    pattern,
+    replacement,
+    globs = Vec::new(),
+    base_dir = PathBuf::from("."),
+    regex = false,
+    file_type = "both",
+    case_sensitive = false,
+    hidden = false,
+    recursive = true,
+    gitignore = true,
+))]
+#[allow(clippy::too_many_arguments)]
+fn plan(
+    py: Python<'_>,
+    pattern: String,
+    replacement: String,
+    globs: Vec<String>,
+    base_dir: PathBuf,
+    regex: bool,
+    file_type: &str,
+    case_sensitive: bool,
+    hidden: bool,
+    recursive: bool,
+    gitignore: bool,
+) -> PyResult<Vec<Rename>> {
+    let options = Options {
+        pattern,
+        replacement: Some(replacement),
+        glob_patterns: globs,
+        base_dir,
+        regex,
+        file_type: parse_file_type(file_type)?,
+        recursive,
+        case_sensitive,
+        hidden,
+        git_ignore: gitignore,
+        ..Options::default()
+    };
+
+    let matches = py
+        .allow_threads(|| Finder::new(options).find())
+        .map_err(|e| PyValueError::new_err(format!("{:#}", e)))?;
+
+    Ok(matches
+        .into_iter()
+        .filter(|m| m.path.file_name().is_some_and(|name| name != m.new_name.as_str()))
+        .map(|m| Rename {
+            target: m.new_path(),
+            source: m.path,
+            is_dir: m.is_dir,
+        })
+        .collect())
+}
+
+/// The renames as matches for the engine. Like everything fnr plans, they
+/// stay in their directory.
+fn to_matches(renames: &[Rename]) -> PyResult<Vec<Match>> {
+    renames
+        .iter()
+        .map(|r| match r.target.file_name().and_then(|name| name.to_str()) {
+            Some(name) if r.target.parent() == r.source.parent() => {
+                let mut m = Match::unchanged(&r.source, r.is_dir, "");
+                m.new_name = name.to_string();
+                Ok(m)
+            }
+            _ => Err(PyValueError::new_err(format!(
+                "Cannot rename {} to {}, renames stay in their directory",
+                r.source.display(),
+                r.target.display()
+            ))),
+        })
+        .collect()
+}
+
+/// Refuse plans that would overwrite existing entries or each other.
+/// Targets held by another source are fine, staging frees them.
+fn check_collisions(matches: &[Match]) -> PyResult<()> {
+    let sources: HashSet<&Path> = matches.iter().map(|m| m.path.as_path()).collect();
+    let mut targets = HashSet::new();
+
+    for m in matches {
+        let target = m.new_path();
+        if target != m.path && fs::symlink_metadata(&target).is_ok() && !sources.contains(target.as_path()) {
+            return Err(PyFileExistsError::new_err(format!(
+                "Refusing to overwrite existing {} (renaming {})",
+                target.display(),
+                m.path.display()
+            )));
+        }
+        if !targets.insert(target) {
+            return Err(PyFileExistsError::new_err(format!(
+                "Multiple entries would be renamed to {}",
+                m.new_path().display()
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Renames carried out so far, in the order they happened.
+struct Renamer<'a> {
+    batch: &'a BatchWriter,
+    staging: Staging,
+    retry: Retry,
+    applied: Vec<Rename>,
+}
+
+impl Renamer<'_> {
+    /// Rename `m`, or move it into staging if its new name is still held.
+    fn rename(&mut self, m: &Match) -> Result<()> {
+        if self.staging.holds(m) {
+            let tmp = self.staging.tmp_path(&m.path, m.path.file_name().unwrap_or_default());
+            let intent = self.batch.intend(&m.path, &tmp, m.is_dir)?;
+            let staged = self.staging.stage(m, &tmp, Some(intent), &self.retry);
+            if staged.is_err() {
+                self.batch.cancel(intent)?;
+            }
+            return staged;
+        }
+        let intent = self.batch.intend(&m.path, &m.new_path(), m.is_dir)?;
+        let new_path = match move_match(m, false, &self.retry, false) {
+            Ok(new_path) => new_path,
+            Err(e) => {
+                self.batch.cancel(intent)?;
+                return Err(e);
+            }
+        };
+        if m.is_dir {
+            self.staging.moved(&m.path, &new_path);
+        }
+        self.applied.push(Rename {
+            source: m.path.clone(),
+            target: new_path,
+            is_dir: m.is_dir,
+        });
+        Ok(())
+    }
+
+    /// Move an entry waiting in staging on to its new name, or back where
+    /// it was if that fails.
+    fn unstage(&mut self, staged: Staged) -> Result<()> {
+        let is_dir = staged.m.is_dir;
+        let intent = self.batch.intend(&staged.tmp, &staged.target, is_dir)?;
+        let moved = if fs::symlink_metadata(&staged.target).is_ok() {
+            Err(anyhow!("{} already exists", staged.target.display()))
+        } else {
+            self.retry.run(|| fs::rename(&staged.tmp, &staged.target)).map_err(anyhow::Error::from)
+        };
+        if let Err(e) = moved {
+            self.batch.cancel(intent)?;
+            let e = e.context(format!("Failed to rename {} to {}", staged.origin.display(), staged.target.display()));
+            if let Err(back) = self.retry.run(|| fs::rename(&staged.tmp, &staged.origin)) {
+                return Err(e.context(format!("it is still in {} ({})", staged.tmp.display(), back)));
+            }
+            if let Some(intent) = staged.intent {
+                self.batch.cancel(intent)?;
+            }
+            return Err(e);
+        }
+        if is_dir {
+            self.staging.moved(&staged.tmp, &staged.target);
+        }
+        self.applied.push(Rename {
+            source: staged.origin,
+            target: staged.target,
+            is_dir,
+        });
+        Ok(())
+    }
+}
+
+/// Rename every match in order, the ones whose new name is still held last
+/// through staging, and return the renames applied.
+fn rename_all(matches: Vec<Match>) -> PyResult<Vec<Rename>> {
+    check_collisions(&matches)?;
+    let journal = (|| {
+        let base_dir = matches.first().and_then(|m| m.path.parent()).filter(|dir| !dir.as_os_str().is_empty());
+        Journal::open(&UserConfig::load()?.journal, base_dir.unwrap_or(Path::new(".")))?.begin()
+    })()
+    .map_err(|e| PyOSError::new_err(format!("{:#}", e)))?;
+
+    let mut renamer = Renamer {
+        batch: &journal,
+        staging: Staging::default(),
+        retry: Retry::default(),
+        applied: Vec::with_capacity(matches.len()),
+    };
+    renamer.staging.prepare(&matches);
+    let mut result = matches.iter().try_for_each(|m| renamer.rename(m));
+    // Nothing stays in staging, not even after a failure
+    while let Some(staged) = renamer.staging.next() {
+        let unstaged = renamer.unstage(staged);
+        result = result.and(unstaged);
+    }
+    renamer.staging.clean_up();
+    let Renamer { applied, .. } = renamer;
+    let finished = journal.finish();
+    result.and(finished.map(|_| ())).map_err(|e| {
+        PyOSError::new_err(format!(
+            "{:#} after {} successful renames, `fnr undo` takes those back",
+            e,
+            applied.len()
+        ))
+    })?;
+    Ok(applied)
+}
+
+/// Apply a plan returned by `plan()`. Collisions are checked before anything
+/// is renamed; the returned list can be passed to `undo()`.
+#[pyfunction]
+fn apply(py: Python<'_>, plan: Vec<Rename>) -> PyResult<Vec<Rename>> {
+    let matches = to_matches(&plan)?;
+    py.allow_threads(|| rename_all(matches))
+}
+
+/// Revert renames returned by `apply()`, last one first.
+#[pyfunction]
+fn undo(py: Python<'_>, applied: Vec<Rename>) -> PyResult<Vec<Rename>> {
+    let renames: Vec<Rename> = applied
+        .into_iter()
+        .rev()
+        .map(|r| Rename {
+            source: r.target,
+            target: r.source,
+            is_dir: r.is_dir,
+        })
+        .collect();
+    let matches = to_matches(&renames)?;
+    py.allow_threads(|| rename_all(matches))
+}
+
+#[pymodule]
+fn fnr(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Rename>()?;
+    m.add_function(wrap_pyfunction!(plan, m)?)?;
+    m.add_function(wrap_pyfunction!(apply, m)?)?;
+    m.add_function(wrap_pyfunction!(undo, m)?)?;
+    Ok(())
+}
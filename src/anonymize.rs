@@ -0,0 +1,40 @@
+//! Replacing names with opaque identifiers.
+
+use crate::{file_name, Match};
+use sha2::{Digest, Sha256};
+use std::process;
+use std::time::SystemTime;
+
+/// Hex digits kept from the hash, 64 bits make collisions a non-issue.
+const ID_LEN: usize = 16;
+
+/// Give every match a random-looking name, keeping file extensions. The
+/// names are derived from a per-run salt, so they can't be reversed by
+/// hashing guessed names; keep the inverse plan to restore them.
+pub fn anonymize(matches: Vec<Match>) -> Vec<Match> {
+    let mut salt = Sha256::new();
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    salt.update(nanos.to_le_bytes());
+    salt.update(process::id().to_le_bytes());
+
+    matches
+        .into_iter()
+        .map(|mut m| {
+            let name = file_name(&m.path).to_string();
+            let mut hasher = salt.clone();
+            hasher.update(m.path.as_os_str().as_encoded_bytes());
+            let id: String = hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect();
+            let extension = match name.rfind('.') {
+                Some(i) if i > 0 && !m.is_dir => &name[i..],
+                _ => "",
+            };
+            m.new_name = format!("{}{}", &id[..ID_LEN], extension);
+            m.replacement = m.new_name.as_str().into();
+            m.pattern = name.into();
+            m
+        })
+        .collect()
+}
@@ -0,0 +1,250 @@
+//! Treat zip and tar archives like directories: member names are matched as
+//! if the archive was a folder (`data.zip/images/old.png`) and renamed by
+//! repacking the archive without extracting it.
+
+use crate::metadata::MetadataCache;
+use crate::owner;
+use crate::{build_glob_set, compile_rules, compute_new_names_cached, file_name, sort_matches};
+use crate::{FileType, Finder, Match, Options};
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use zip::{ZipArchive, ZipWriter};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl Kind {
+    /// Detect the archive kind from the file name.
+    pub fn detect(path: &Path) -> Option<Kind> {
+        let name = file_name(path).to_lowercase();
+        if name.ends_with(".zip") {
+            Some(Kind::Zip)
+        } else if name.ends_with(".tar") {
+            Some(Kind::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Kind::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+/// Member names without `./` prefix and trailing slash.
+fn normalize(raw: &str) -> &str {
+    raw.trim_start_matches("./").trim_end_matches('/')
+}
+
+fn list_members(path: &Path, kind: Kind) -> Result<Vec<(String, bool)>> {
+    let file = File::open(path)?;
+    let mut members = Vec::new();
+    match kind {
+        Kind::Zip => {
+            let mut archive = ZipArchive::new(BufReader::new(file))?;
+            for i in 0..archive.len() {
+                let member = archive.by_index_raw(i)?;
+                members.push((normalize(member.name()).to_string(), member.is_dir()));
+            }
+        }
+        Kind::Tar => list_tar(BufReader::new(file), &mut members)?,
+        Kind::TarGz => list_tar(GzDecoder::new(BufReader::new(file)), &mut members)?,
+    }
+    members.retain(|(name, _)| !name.is_empty());
+    Ok(members)
+}
+
+fn list_tar<R: Read>(reader: R, members: &mut Vec<(String, bool)>) -> Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let path = entry.path()?.to_string_lossy().into_owned();
+        members.push((normalize(&path).to_string(), entry.header().entry_type().is_dir()));
+    }
+    Ok(())
+}
+
+/// Find matching members in all archives below the base directory. The
+/// match paths are the archive path joined with the member path.
+pub fn find_in_archives(options: &Options) -> Result<Vec<Match>> {
+    let archives = Finder::new(Options {
+        glob_patterns: Vec::new(),
+        file_type: FileType::File,
+        ..options.clone()
+    })
+    .entries()?;
+
+    let glob_set = build_glob_set(&options.glob_patterns)?;
+    let rules = compile_rules(options)?;
+
+    let mut matches = Vec::new();
+    for archive in archives {
+        let Some(kind) = Kind::detect(&archive.path) else {
+            continue;
+        };
+        let members = match list_members(&archive.path, kind) {
+            Ok(members) => members,
+            Err(e) => {
+                eprintln!("Warning: {}: {:#}", archive.path.display(), e);
+                continue;
+            }
+        };
+        for (member, is_dir) in members {
+            let path = archive.path.join(&member);
+            match options.file_type {
+                FileType::File if is_dir => continue,
+                FileType::Dir if !is_dir => continue,
+                _ => {}
+            }
+            if !glob_set.is_match(&path) {
+                continue;
+            }
+            let name = file_name(&path);
+            if let Some((matcher, _)) = rules.iter().find(|(matcher, _)| matcher.is_match(name)) {
+                matches.push(Match::unchanged(&path, is_dir, matcher.pattern()));
+            }
+        }
+    }
+
+    sort_matches(&mut matches);
+    compute_new_names_cached(&mut matches, &rules, &options.date_style, &options.base_dir, &MetadataCache::new())?;
+    Ok(matches)
+}
+
+/// Split a match path into the archive containing it and the member path.
+pub fn split_member(path: &Path) -> Option<(&Path, String)> {
+    let archive = path
+        .ancestors()
+        .skip(1)
+        .find(|p| Kind::detect(p).is_some() && p.is_file())?;
+    let member = path.strip_prefix(archive).ok()?.to_string_lossy().into_owned();
+    Some((archive, member))
+}
+
+/// Apply member matches as returned by [`find_in_archives`], rewriting each
+/// affected archive once.
+pub fn rename_members(matches: &[Match]) -> Result<()> {
+    let mut by_archive: BTreeMap<&Path, HashMap<String, String>> = BTreeMap::new();
+    for m in matches {
+        let (archive, member) = split_member(&m.path)
+            .with_context(|| format!("{} is not inside an archive", m.path.display()))?;
+        by_archive.entry(archive).or_default().insert(member, m.new_name.clone());
+    }
+    for (archive, renames) in by_archive {
+        rewrite(archive, &renames)?;
+    }
+    Ok(())
+}
+
+/// Rename members of a single archive. `renames` maps member paths (as in
+/// [`ArchiveMatch::member`]) to their new file names; members below renamed
+/// directories move along. The archive is repacked next to the original and
+/// swapped in once complete.
+pub fn rewrite(archive: &Path, renames: &HashMap<String, String>) -> Result<()> {
+    let kind = Kind::detect(archive).context("Not a supported archive")?;
+    let temp = archive.with_file_name(format!(".{}.fnr-tmp", file_name(archive)));
+
+    let result = (|| -> Result<()> {
+        let input = BufReader::new(File::open(archive)?);
+        let output = BufWriter::new(File::create(&temp)?);
+        match kind {
+            Kind::Zip => rewrite_zip(input, output, renames)?,
+            Kind::Tar => {
+                rewrite_tar(input, output, renames)?.flush()?;
+            }
+            Kind::TarGz => {
+                let encoder = GzEncoder::new(output, Compression::default());
+                rewrite_tar(GzDecoder::new(input), encoder, renames)?.finish()?.flush()?;
+            }
+        }
+        // The repacked archive is a new file, the original's mode and owner
+        // go with the name
+        let metadata = fs::metadata(archive)?;
+        fs::set_permissions(&temp, metadata.permissions())?;
+        owner::keep_owner(&metadata, &temp)?;
+        fs::rename(&temp, archive)?;
+        Ok(())
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result.with_context(|| format!("Failed to rewrite archive {}", archive.display()))
+}
+
+/// New raw member name, keeping a trailing slash for directories. Members
+/// that are not affected keep their name byte for byte.
+fn renamed(raw: &str, renames: &HashMap<String, String>) -> String {
+    let normalized = normalize(raw);
+    let parts: Vec<&str> = normalized.split('/').collect();
+    let mut out = Vec::with_capacity(parts.len());
+    let mut changed = false;
+    for i in 0..parts.len() {
+        let prefix = parts[..=i].join("/");
+        match renames.get(&prefix) {
+            Some(new_name) => {
+                changed = true;
+                out.push(new_name.as_str());
+            }
+            None => out.push(parts[i]),
+        }
+    }
+    if !changed {
+        return raw.to_string();
+    }
+    let mut name = out.join("/");
+    if raw.ends_with('/') {
+        name.push('/');
+    }
+    name
+}
+
+fn rewrite_zip<R, W>(input: R, output: W, renames: &HashMap<String, String>) -> Result<()>
+where
+    R: Read + std::io::Seek,
+    W: Write + std::io::Seek,
+{
+    let mut source = ZipArchive::new(input)?;
+    let mut writer = ZipWriter::new(output);
+    for i in 0..source.len() {
+        let member = source.by_index_raw(i)?;
+        let name = renamed(member.name(), renames);
+        if name == member.name() {
+            writer.raw_copy_file(member)?;
+        } else {
+            writer.raw_copy_file_rename(member, name)?;
+        }
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+fn rewrite_tar<R: Read, W: Write>(input: R, output: W, renames: &HashMap<String, String>) -> Result<W> {
+    let mut source = tar::Archive::new(input);
+    let mut builder = tar::Builder::new(output);
+    for entry in source.entries()? {
+        let mut entry = entry?;
+        let raw = entry.path()?.to_string_lossy().into_owned();
+        let name = renamed(&raw, renames);
+        let mut header = entry.header().clone();
+        let entry_type = header.entry_type();
+        if name == raw && header.path()? == entry.path()? {
+            // Untouched and without extended name records: copy verbatim
+            builder.append(&header, &mut entry)?;
+        } else if entry_type.is_symlink() || entry_type.is_hard_link() {
+            let target = entry.link_name()?.context("Link without target")?.into_owned();
+            builder.append_link(&mut header, &name, target)?;
+        } else {
+            builder.append_data(&mut header, &name, &mut entry)?;
+        }
+    }
+    Ok(builder.into_inner()?)
+}
@@ -0,0 +1,21 @@
+//! Async wrappers around the blocking engine for use inside a tokio runtime.
+//!
+//! Directory traversal is inherently blocking, so the work is moved onto
+//! tokio's blocking thread pool instead of stalling the async executor.
+
+use crate::{Finder, Match, Options};
+use anyhow::{Context, Result};
+
+impl Finder {
+    /// Run [`Finder::find`] on the blocking thread pool.
+    pub async fn find_async(self) -> Result<Vec<Match>> {
+        tokio::task::spawn_blocking(move || self.find())
+            .await
+            .context("Search task panicked or was cancelled")?
+    }
+}
+
+/// Async counterpart of [`crate::find_matches`].
+pub async fn find_matches(options: Options) -> Result<Vec<Match>> {
+    Finder::new(options).find_async().await
+}
@@ -0,0 +1,581 @@
+//! Plan-time checks run on computed names before anything is renamed.
+
+use crate::config::DirConfigs;
+use crate::remote::Probe;
+use crate::{transfer, Match};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Filesystem whose naming rules the planned names have to satisfy.
+#[derive(clap::ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum TargetFs {
+    Ext4,
+    Ntfs,
+    Fat32,
+    #[value(name = "apfs-ci")]
+    ApfsCi,
+}
+
+const WINDOWS_FORBIDDEN: &[char] = &['<', '>', ':', '"', '\\', '|', '?', '*'];
+
+const WINDOWS_RESERVED: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+impl fmt::Display for TargetFs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TargetFs::Ext4 => "ext4",
+            TargetFs::Ntfs => "ntfs",
+            TargetFs::Fat32 => "fat32",
+            TargetFs::ApfsCi => "apfs-ci",
+        };
+        f.write_str(name)
+    }
+}
+
+impl TargetFs {
+    pub fn case_sensitive(self) -> bool {
+        matches!(self, TargetFs::Ext4)
+    }
+
+    /// Check a single file name, returning the reason it is invalid.
+    pub fn check_name(self, name: &str) -> Result<(), String> {
+        if name.is_empty() || name == "." || name == ".." {
+            return Err(format!("'{}' is not a valid file name", name));
+        }
+        if name.contains('/') || name.contains('\0') {
+            return Err("contains '/' or NUL".to_string());
+        }
+
+        match self {
+            TargetFs::Ext4 | TargetFs::ApfsCi => {
+                if name.len() > 255 {
+                    return Err(format!("is {} bytes long (limit 255)", name.len()));
+                }
+            }
+            TargetFs::Ntfs | TargetFs::Fat32 => {
+                if let Some(c) = name.chars().find(|c| WINDOWS_FORBIDDEN.contains(c) || c.is_control()) {
+                    return Err(format!("contains forbidden character {:?}", c));
+                }
+                let base = name.split('.').next().unwrap_or("").trim_end();
+                if WINDOWS_RESERVED.iter().any(|r| r.eq_ignore_ascii_case(base)) {
+                    return Err(format!("'{}' is a reserved device name", base));
+                }
+                if name.ends_with(' ') || name.ends_with('.') {
+                    return Err("ends with a space or dot".to_string());
+                }
+                let units = name.encode_utf16().count();
+                if units > 255 {
+                    return Err(format!("is {} UTF-16 units long (limit 255)", units));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What to do with names ending in spaces or dots, which Windows silently
+/// strips or refuses.
+#[derive(clap::ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TrailingPolicy {
+    #[default]
+    Warn,
+    Strip,
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// A problem found in a planned rename.
+#[derive(Debug, Clone)]
+pub struct Issue {
+    pub path: PathBuf,
+    pub message: String,
+    pub severity: Severity,
+}
+
+impl Issue {
+    pub fn error(path: &Path, message: String) -> Self {
+        Issue {
+            path: path.to_path_buf(),
+            message,
+            severity: Severity::Error,
+        }
+    }
+
+    pub fn warning(path: &Path, message: String) -> Self {
+        Issue {
+            path: path.to_path_buf(),
+            message,
+            severity: Severity::Warning,
+        }
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.message)
+    }
+}
+
+fn fold(name: &str, case_sensitive: bool) -> String {
+    if case_sensitive {
+        name.to_string()
+    } else {
+        name.to_lowercase()
+    }
+}
+
+/// Check planned names against the rules of `target`, including names that
+/// would only collide on a case-insensitive filesystem.
+pub fn check_target_fs(matches: &[Match], target: TargetFs) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    for m in matches {
+        if let Err(reason) = target.check_name(&m.new_name) {
+            issues.push(Issue::error(
+                &m.path,
+                format!("'{}' is invalid on {}: {}", m.new_name, target, reason),
+            ));
+        }
+    }
+
+    let case_sensitive = target.case_sensitive();
+    let mut by_parent: BTreeMap<&Path, Vec<&Match>> = BTreeMap::new();
+    for m in matches {
+        by_parent.entry(m.path.parent().unwrap_or(Path::new("."))).or_default().push(m);
+    }
+
+    for (parent, group) in by_parent {
+        let renamed: HashSet<OsString> = group
+            .iter()
+            .filter_map(|m| m.path.file_name().map(|n| n.to_os_string()))
+            .collect();
+
+        // Names that stay in place claim their slot first.
+        let mut taken: HashMap<String, String> = HashMap::new();
+        if let Ok(entries) = fs::read_dir(parent) {
+            for entry in entries.flatten() {
+                let name = entry.file_name();
+                if !renamed.contains(&name) {
+                    let name = name.to_string_lossy().into_owned();
+                    taken.insert(fold(&name, case_sensitive), name);
+                }
+            }
+        }
+
+        for m in group {
+            match taken.get(&fold(&m.new_name, case_sensitive)) {
+                Some(other) => issues.push(Issue::error(
+                    &m.path,
+                    format!("'{}' collides with '{}' on {}", m.new_name, other, target),
+                )),
+                None => {
+                    taken.insert(fold(&m.new_name, case_sensitive), m.new_name.clone());
+                }
+            }
+        }
+    }
+
+    issues
+}
+
+/// Apply `policy` to new names ending in spaces or dots. With
+/// [`TrailingPolicy::Strip`] the names are fixed in place.
+pub fn check_trailing(matches: &mut [Match], policy: TrailingPolicy) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    for m in matches.iter_mut() {
+        if !m.new_name.ends_with([' ', '.']) || m.new_name == "." || m.new_name == ".." {
+            continue;
+        }
+        let message = format!("'{}' ends with a space or dot", m.new_name);
+        match policy {
+            TrailingPolicy::Warn => issues.push(Issue::warning(&m.path, message)),
+            TrailingPolicy::Error => issues.push(Issue::error(&m.path, message)),
+            TrailingPolicy::Strip => {
+                let stripped = m.new_name.trim_end_matches([' ', '.']);
+                if stripped.is_empty() {
+                    issues.push(Issue::error(&m.path, format!("{}, nothing left after stripping", message)));
+                } else {
+                    m.new_name = stripped.to_string();
+                }
+            }
+        }
+    }
+    issues
+}
+
+/// Directory names fnr refuses to rename into or out of unless configured
+/// otherwise.
+pub const DEFAULT_PROTECTED: &[&str] = &[".git", ".hg", ".svn"];
+
+/// Reject renames of anything inside or named like a protected directory,
+/// and renames that would produce such a name.
+pub fn check_protected(matches: &[Match], protected: &[String]) -> Vec<Issue> {
+    let is_protected = |name: &str| protected.iter().any(|p| p.eq_ignore_ascii_case(name));
+    let mut issues = Vec::new();
+    for m in matches {
+        let inside = m
+            .path
+            .components()
+            .find(|c| is_protected(&c.as_os_str().to_string_lossy()));
+        if let Some(component) = inside {
+            issues.push(Issue::error(
+                &m.path,
+                format!("'{}' is protected", component.as_os_str().to_string_lossy()),
+            ));
+        } else if is_protected(&m.new_name) {
+            issues.push(Issue::error(&m.path, format!("'{}' is a protected name", m.new_name)));
+        }
+    }
+    issues
+}
+
+/// Finds the git submodules (and other nested repositories) below a base
+/// directory, remembering which directories have a `.git` of their own.
+/// The repository the base directory itself is in doesn't count.
+#[derive(Debug)]
+pub struct Submodules {
+    base_dir: PathBuf,
+    known: Mutex<HashMap<PathBuf, bool>>,
+}
+
+impl Submodules {
+    pub fn new(base_dir: &Path) -> Self {
+        Submodules {
+            base_dir: base_dir.to_path_buf(),
+            known: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The outermost submodule `path` is in, or is itself.
+    pub fn containing(&self, path: &Path) -> Option<PathBuf> {
+        let relative = path.strip_prefix(&self.base_dir).ok()?;
+        let mut dir = self.base_dir.clone();
+        for component in relative.components() {
+            dir.push(component);
+            let mut known = self.known.lock().unwrap();
+            let is_repo = *known
+                .entry(dir.clone())
+                .or_insert_with(|| fs::symlink_metadata(dir.join(".git")).is_ok());
+            if is_repo {
+                return Some(dir);
+            }
+        }
+        None
+    }
+}
+
+/// Warn about renames inside submodules, which the superproject never
+/// sees, and renames of submodules, which leave `.gitmodules` behind.
+pub fn check_submodules(matches: &[Match], submodules: &Submodules) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    for m in matches {
+        match submodules.containing(&m.path) {
+            Some(dir) if dir == m.path => issues.push(Issue::warning(
+                &m.path,
+                "renaming a git submodule leaves .gitmodules and the superproject behind".to_string(),
+            )),
+            Some(dir) => issues.push(Issue::warning(
+                &m.path,
+                format!("inside the git submodule {}, the superproject won't see this rename", dir.display()),
+            )),
+            None => {}
+        }
+    }
+    issues
+}
+
+/// A new path wanted by several renames, or taken by an entry that stays.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Collision {
+    pub target: PathBuf,
+    /// The matches renamed to `target`.
+    pub sources: Vec<PathBuf>,
+    /// Whether an entry that isn't renamed away already has the path.
+    pub taken: bool,
+}
+
+/// The new paths that are taken: by another match's new path, or by an
+/// existing entry that isn't renamed away itself. Renames that only change
+/// the case of a name don't count. Whether new paths exist asks `probe`.
+pub fn collisions(matches: &[Match], probe: &Probe) -> Vec<Collision> {
+    let sources: HashSet<&Path> = matches.iter().map(|m| m.path.as_path()).collect();
+    let mut targets: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    for m in matches.iter().filter(|m| m.path.file_name().is_some_and(|old| old != m.new_name.as_str())) {
+        targets.entry(m.new_path()).or_default().push(m.path.clone());
+    }
+
+    let mut collisions = Vec::new();
+    for (target, from) in targets {
+        let case_only = from.len() == 1
+            && from[0]
+                .file_name()
+                .is_some_and(|old| old.to_string_lossy().eq_ignore_ascii_case(&file_name_lossy(&target)));
+        let taken = !sources.contains(target.as_path()) && !case_only && probe.exists(&target);
+        if from.len() > 1 || taken {
+            collisions.push(Collision {
+                target,
+                sources: from,
+                taken,
+            });
+        }
+    }
+    collisions
+}
+
+/// Paths of the matches whose new path is taken, see [`collisions`].
+pub fn colliding(matches: &[Match], probe: &Probe) -> HashSet<PathBuf> {
+    collisions(matches, probe).into_iter().flat_map(|collision| collision.sources).collect()
+}
+
+/// Errors for renames that would overwrite each other or an entry that
+/// stays, which a plain rename happily does without asking.
+pub fn check_collisions(matches: &[Match], probe: &Probe) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    for collision in collisions(matches, probe) {
+        for source in &collision.sources {
+            let message = if collision.taken {
+                format!("{} already exists", collision.target.display())
+            } else {
+                format!("{} renames want {}", collision.sources.len(), collision.target.display())
+            };
+            issues.push(Issue::error(source, message));
+        }
+    }
+    issues
+}
+
+/// Errors for renames going round in a circle, see [`cycles`].
+pub fn check_cycles(matches: &[Match]) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    for cycle in cycles(matches) {
+        let circle: Vec<String> = cycle.iter().chain(&cycle[..1]).map(|path| path.display().to_string()).collect();
+        let message = format!("{} goes round in a circle", circle.join(" -> "));
+        issues.extend(cycle.iter().map(|path| Issue::error(path, message.clone())));
+    }
+    issues
+}
+
+/// Names longer than this many bytes break encrypted home directories
+/// (eCryptfs) and some sync clients, although most filesystems allow 255.
+const LONG_NAME: usize = 143;
+
+/// Paths longer than this break Windows tools without long path support.
+const LONG_PATH: usize = 260;
+
+/// Warnings for new names and paths that are valid, but long enough to hurt
+/// elsewhere.
+pub fn check_lengths(matches: &[Match]) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    for m in matches {
+        let new_path = m.new_path();
+        let length = std::path::absolute(&new_path).unwrap_or(new_path).to_string_lossy().chars().count();
+        if m.new_name.len() > LONG_NAME {
+            issues.push(Issue::warning(
+                &m.path,
+                format!("'{}' is {} bytes long, more than {} break encrypted home directories", m.new_name, m.new_name.len(), LONG_NAME),
+            ));
+        } else if length > LONG_PATH {
+            issues.push(Issue::warning(
+                &m.path,
+                format!("the new path is {} characters long, more than {} break Windows tools", length, LONG_PATH),
+            ));
+        }
+    }
+    issues
+}
+
+/// Names longer than this many characters are pointed out in prompts.
+const NOTABLE_NAME: usize = 200;
+
+/// Things worth knowing before saying yes to `m`, looked up now: its new
+/// path being taken, a case-only change, a move to another filesystem and
+/// a very long new name.
+pub fn annotate(m: &Match) -> Vec<String> {
+    let new_path = m.new_path();
+    let old = m.path.file_name().unwrap_or_default().to_string_lossy();
+    let mut notes = Vec::new();
+    if transfer::taken(&m.path, &new_path) {
+        notes.push("target exists".to_string());
+    }
+    if old != m.new_name && old.to_lowercase() == m.new_name.to_lowercase() {
+        notes.push("case-only change".to_string());
+    }
+    if transfer::crosses_filesystem(&m.path, &new_path) {
+        notes.push("crosses filesystem".to_string());
+    }
+    if m.new_name.chars().count() > NOTABLE_NAME {
+        notes.push(format!("name exceeds {} chars", NOTABLE_NAME));
+    }
+    notes
+}
+
+/// Renames that go round in a circle, like `a -> b` and `b -> a`: each one
+/// needs a name the next one still holds, so none of them can go first.
+/// Every cycle starts at its smallest path.
+pub fn cycles(matches: &[Match]) -> Vec<Vec<PathBuf>> {
+    let next: BTreeMap<&Path, PathBuf> = matches
+        .iter()
+        .filter(|m| m.path.file_name().is_some_and(|old| old != m.new_name.as_str()))
+        .map(|m| (m.path.as_path(), m.new_path()))
+        .collect();
+    let mut seen: HashSet<&Path> = HashSet::new();
+    let mut cycles = Vec::new();
+    for &start in next.keys() {
+        // Follow the chain until it leaves the renames or meets itself
+        let mut chain: Vec<&Path> = Vec::new();
+        let mut current = Some(start);
+        while let Some(path) = current.filter(|&path| seen.insert(path)) {
+            chain.push(path);
+            current = next.get_key_value(next[path].as_path()).map(|(&following, _)| following);
+        }
+        if let Some(i) = current.and_then(|met| chain.iter().position(|&path| path == met)) {
+            cycles.push(chain[i..].iter().map(|path| path.to_path_buf()).collect());
+        }
+    }
+    cycles
+}
+
+fn file_name_lossy(path: &Path) -> String {
+    path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+/// Run the trailing and target filesystem checks with the policy in effect
+/// for each match: `.fnr.toml` settings from `configs` override the given
+/// defaults.
+pub fn check_policies(
+    matches: &mut [Match],
+    target: Option<TargetFs>,
+    trailing: TrailingPolicy,
+    configs: Option<&DirConfigs>,
+) -> Result<Vec<Issue>> {
+    let Some(configs) = configs else {
+        let mut issues = check_trailing(matches, trailing);
+        if let Some(target) = target {
+            issues.extend(check_target_fs(matches, target));
+        }
+        return Ok(issues);
+    };
+
+    let mut issues = Vec::new();
+    let mut by_target: BTreeMap<TargetFs, Vec<Match>> = BTreeMap::new();
+    for m in matches.iter_mut() {
+        let policy = configs.policy_for(&m.path)?;
+        issues.extend(check_trailing(std::slice::from_mut(m), policy.trailing.unwrap_or(trailing)));
+        if let Some(target) = policy.target_fs.or(target) {
+            by_target.entry(target).or_default().push(m.clone());
+        }
+    }
+    for (target, group) in by_target {
+        issues.extend(check_target_fs(&group, target));
+    }
+    Ok(issues)
+}
+
+/// Strip the new names where the trailing policy in effect says so, before
+/// conflicts are looked at: the stripped names are the ones that collide.
+/// The issues are left to [`check_policies`], which finds the same ones.
+pub fn strip_trailing(matches: &mut [Match], trailing: TrailingPolicy, configs: Option<&DirConfigs>) -> Result<()> {
+    for m in matches.iter_mut() {
+        let policy = match configs {
+            Some(configs) => configs.policy_for(&m.path)?.trailing.unwrap_or(trailing),
+            None => trailing,
+        };
+        if policy == TrailingPolicy::Strip {
+            check_trailing(std::slice::from_mut(m), policy);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rename(path: &str, new_name: &str) -> Match {
+        Match {
+            new_name: new_name.to_string(),
+            ..Match::unchanged(Path::new(path), false, "")
+        }
+    }
+
+    #[test]
+    fn stripped_names_are_the_ones_that_collide() {
+        let mut matches = vec![rename("./x.txt_", "x.txt "), rename("./y.", "y. ")];
+        strip_trailing(&mut matches, TrailingPolicy::Strip, None).unwrap();
+        assert_eq!(matches[0].new_name, "x.txt");
+        assert_eq!(matches[1].new_name, "y");
+        // Nothing left for the later check to strip or report
+        assert!(check_trailing(&mut matches, TrailingPolicy::Strip).is_empty());
+
+        let mut warned = vec![rename("./x.txt_", "x.txt ")];
+        strip_trailing(&mut warned, TrailingPolicy::Warn, None).unwrap();
+        assert_eq!(warned[0].new_name, "x.txt ");
+    }
+
+    #[test]
+    fn cycles_start_at_their_smallest_path() {
+        let matches = [
+            rename("./e", "c"),
+            rename("./a", "b"),
+            rename("./c", "d"),
+            rename("./b", "a"),
+            rename("./d", "e"),
+            rename("./f", "g"),
+            rename("./g", "h"),
+            rename("./i", "i"),
+        ];
+        let paths = |names: &[&str]| -> Vec<PathBuf> { names.iter().map(|name| Path::new(".").join(name)).collect() };
+        assert_eq!(cycles(&matches), [paths(&["a", "b"]), paths(&["c", "d", "e"])]);
+    }
+
+    #[test]
+    fn collisions_are_shared_targets_and_entries_that_stay() {
+        let dir = std::env::temp_dir().join(format!("fnr-collisions-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["a", "b", "c", "keep", "Readme"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        let at = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        let matches = [
+            rename(&at("a"), "keep"),
+            rename(&at("b"), "x"),
+            rename(&at("c"), "x"),
+            rename(&at("d"), "b"),
+            rename(&at("Readme"), "README"),
+        ];
+        for probe in [Probe::new(false), Probe::new(true)] {
+            assert_eq!(
+                collisions(&matches, &probe),
+                [
+                    Collision {
+                        target: dir.join("keep"),
+                        sources: vec![dir.join("a")],
+                        taken: true,
+                    },
+                    Collision {
+                        target: dir.join("x"),
+                        sources: vec![dir.join("b"), dir.join("c")],
+                        taken: false,
+                    },
+                ]
+            );
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,246 @@
+//! User and per-directory configuration.
+//!
+//! The user configuration lives in `$FNR_CONFIG`, or
+//! `$XDG_CONFIG_HOME/fnr/config.toml` (`~/.config/fnr/config.toml`), and
+//! holds [hooks](crate::hooks), the [journal](crate::journal) settings, the
+//! list of protected directory names, the [color theme](crate::theme), the
+//! [prompt keys](crate::keys) and saved searches.
+//!
+//! A `.fnr.toml` applies to its directory and everything below it. Files
+//! closer to an entry win, like editorconfig; lookup continues up to the
+//! filesystem root unless a file sets `root = true`.
+//!
+//! ```toml
+//! root = true
+//! exclude = ["raw/**", "*.bak"]   # relative to this directory, accumulates
+//! target-fs = "fat32"             # overrides parent files and --target-fs
+//! trailing = "error"              # overrides parent files and --trailing
+//! ```
+
+use crate::check::{TargetFs, TrailingPolicy, DEFAULT_PROTECTED};
+use crate::hooks::Hooks;
+use crate::journal::JournalConfig;
+use crate::keys::Keys;
+use crate::theme::ThemeConfig;
+use anyhow::{Context, Result};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+pub const DIR_CONFIG_FILE: &str = ".fnr.toml";
+
+/// Settings from the user configuration file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UserConfig {
+    pub hooks: Hooks,
+    pub journal: JournalConfig,
+    /// Directory names never renamed into or out of, replacing
+    /// [`DEFAULT_PROTECTED`].
+    pub protect: Option<Vec<String>>,
+    pub theme: ThemeConfig,
+    pub keys: Keys,
+    /// Saved searches by name, from the `[search.NAME]` tables.
+    pub search: BTreeMap<String, SavedSearch>,
+}
+
+/// A named search, run as `fnr @NAME`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SavedSearch {
+    pub pattern: String,
+    /// Makes the search a rename.
+    pub replacement: Option<String>,
+    pub glob: Vec<String>,
+    pub regex: bool,
+    pub case_sensitive: bool,
+    pub hidden: bool,
+    pub base_dir: Option<PathBuf>,
+}
+
+impl UserConfig {
+    /// Location of the user configuration, if a home directory is known.
+    pub fn path() -> Option<PathBuf> {
+        if let Some(path) = std::env::var_os("FNR_CONFIG") {
+            return Some(PathBuf::from(path));
+        }
+        let config_home = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))?;
+        Some(config_home.join("fnr").join("config.toml"))
+    }
+
+    /// Load the user configuration. A missing file is an empty configuration.
+    pub fn load() -> Result<UserConfig> {
+        match Self::path() {
+            Some(path) if path.is_file() => {
+                let text = fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                toml::from_str(&text)
+                    .with_context(|| format!("Invalid configuration {}", path.display()))
+            }
+            _ => Ok(UserConfig::default()),
+        }
+    }
+
+    /// The effective protected names.
+    pub fn protected(&self) -> Vec<String> {
+        match &self.protect {
+            Some(names) => names.clone(),
+            None => DEFAULT_PROTECTED.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+}
+
+/// Contents of a single `.fnr.toml`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct DirConfig {
+    /// Stop looking for configuration in parent directories.
+    pub root: bool,
+    pub exclude: Vec<String>,
+    pub target_fs: Option<TargetFs>,
+    pub trailing: Option<TrailingPolicy>,
+}
+
+impl DirConfig {
+    pub fn load(path: &Path) -> Result<DirConfig> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("Invalid configuration {}", path.display()))
+    }
+}
+
+/// The merged configuration in effect for one directory.
+#[derive(Debug, Clone, Default)]
+pub struct DirPolicy {
+    /// Exclude globs together with the directory they are relative to.
+    excludes: Vec<(PathBuf, GlobSet)>,
+    pub target_fs: Option<TargetFs>,
+    pub trailing: Option<TrailingPolicy>,
+}
+
+impl DirPolicy {
+    fn merge(parent: &DirPolicy, dir: &Path, config: DirConfig) -> Result<DirPolicy> {
+        let mut policy = parent.clone();
+        if !config.exclude.is_empty() {
+            let mut builder = GlobSetBuilder::new();
+            for pattern in &config.exclude {
+                builder.add(Glob::new(pattern).with_context(|| {
+                    format!("Invalid exclude '{}' in {}", pattern, dir.join(DIR_CONFIG_FILE).display())
+                })?);
+            }
+            policy.excludes.push((dir.to_path_buf(), builder.build()?));
+        }
+        policy.target_fs = config.target_fs.or(policy.target_fs);
+        policy.trailing = config.trailing.or(policy.trailing);
+        Ok(policy)
+    }
+
+    /// Whether `path` (canonical) or one of its parents is excluded.
+    fn is_excluded(&self, path: &Path) -> bool {
+        self.excludes.iter().any(|(dir, globs)| {
+            path.strip_prefix(dir).is_ok_and(|relative| {
+                relative
+                    .ancestors()
+                    .filter(|p| !p.as_os_str().is_empty())
+                    .any(|p| globs.is_match(p))
+            })
+        })
+    }
+}
+
+/// Resolves and caches [`DirPolicy`]s during a walk. Safe to share between
+/// threads.
+#[derive(Debug, Default)]
+pub struct DirConfigs {
+    canonical: Mutex<HashMap<PathBuf, PathBuf>>,
+    policies: Mutex<HashMap<PathBuf, Arc<DirPolicy>>>,
+}
+
+impl DirConfigs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The policy in effect for entries of `dir`.
+    pub fn policy(&self, dir: &Path) -> Result<Arc<DirPolicy>> {
+        let canonical = self.canonicalize(dir)?;
+        self.resolve(&canonical)
+    }
+
+    /// The policy in effect for `path` itself, i.e. for its parent directory.
+    pub fn policy_for(&self, path: &Path) -> Result<Arc<DirPolicy>> {
+        self.policy(parent_dir(path))
+    }
+
+    /// Whether `path` is excluded by a `.fnr.toml` above it.
+    pub fn is_excluded(&self, path: &Path) -> Result<bool> {
+        let dir = parent_dir(path);
+        let policy = self.policy(dir)?;
+        let Some(name) = path.file_name() else {
+            return Ok(false);
+        };
+        Ok(policy.is_excluded(&self.canonicalize(dir)?.join(name)))
+    }
+
+    fn canonicalize(&self, dir: &Path) -> Result<PathBuf> {
+        if let Some(canonical) = self.canonical.lock().unwrap().get(dir) {
+            return Ok(canonical.clone());
+        }
+        // Directories a plan only creates by renaming resolve through their
+        // nearest existing ancestor
+        let mut existing = dir;
+        let mut missing = Vec::new();
+        let canonical = loop {
+            match existing.canonicalize() {
+                Ok(canonical) => break missing.iter().rev().fold(canonical, |path, name| path.join(name)),
+                Err(e) => match (existing.parent(), existing.file_name()) {
+                    (Some(parent), Some(name)) => {
+                        missing.push(name);
+                        existing = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+                    }
+                    _ => return Err(e).with_context(|| format!("Failed to resolve {}", dir.display())),
+                },
+            }
+        };
+        self.canonical.lock().unwrap().insert(dir.to_path_buf(), canonical.clone());
+        Ok(canonical)
+    }
+
+    fn resolve(&self, dir: &Path) -> Result<Arc<DirPolicy>> {
+        if let Some(policy) = self.policies.lock().unwrap().get(dir) {
+            return Ok(policy.clone());
+        }
+
+        let file = dir.join(DIR_CONFIG_FILE);
+        let config = if file.is_file() {
+            Some(DirConfig::load(&file)?)
+        } else {
+            None
+        };
+        let parent = match (dir.parent(), &config) {
+            (_, Some(config)) if config.root => Arc::new(DirPolicy::default()),
+            (Some(parent), _) => self.resolve(parent)?,
+            (None, _) => Arc::new(DirPolicy::default()),
+        };
+        let policy = match config {
+            Some(config) => Arc::new(DirPolicy::merge(&parent, dir, config)?),
+            None => parent,
+        };
+
+        self.policies.lock().unwrap().insert(dir.to_path_buf(), policy.clone());
+        Ok(policy)
+    }
+}
+
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
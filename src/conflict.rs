@@ -0,0 +1,213 @@
+//! Picking free names when several entries want the same one, and settling
+//! renames onto taken paths, see [`resolve`].
+
+use crate::check::collisions;
+use crate::remote::Probe;
+use crate::{file_name, sort_matches, Match};
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Where two files count as duplicates.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DuplicateScope {
+    /// Same directory (only differing in case, unless case-sensitive).
+    Dir,
+    /// Anywhere in the searched tree, as if it was flattened.
+    Tree,
+}
+
+/// `name` with ` (n)` inserted before the extension.
+pub fn numbered(name: &str, n: usize) -> String {
+    let (stem, extension) = match name.rfind('.') {
+        Some(0) | None => (name, ""),
+        Some(i) => name.split_at(i),
+    };
+    format!("{} ({}){}", stem, n, extension)
+}
+
+fn fold(name: &str, case_sensitive: bool) -> String {
+    if case_sensitive {
+        name.to_string()
+    } else {
+        name.to_lowercase()
+    }
+}
+
+/// Names in use per directory, read from disk on first access and updated
+/// as names are claimed.
+#[derive(Debug, Default)]
+pub struct NameRegistry {
+    case_sensitive: bool,
+    dirs: HashMap<PathBuf, HashSet<String>>,
+}
+
+impl NameRegistry {
+    pub fn new(case_sensitive: bool) -> Self {
+        NameRegistry {
+            case_sensitive,
+            dirs: HashMap::new(),
+        }
+    }
+
+    fn names(&mut self, dir: &Path) -> &mut HashSet<String> {
+        let case_sensitive = self.case_sensitive;
+        self.dirs.entry(dir.to_path_buf()).or_insert_with(|| {
+            fs::read_dir(dir)
+                .into_iter()
+                .flatten()
+                .flatten()
+                .map(|entry| fold(&entry.file_name().to_string_lossy(), case_sensitive))
+                .collect()
+        })
+    }
+
+    pub fn is_taken(&mut self, dir: &Path, name: &str) -> bool {
+        let key = fold(name, self.case_sensitive);
+        self.names(dir).contains(&key)
+    }
+
+    pub fn claim(&mut self, dir: &Path, name: &str) {
+        let key = fold(name, self.case_sensitive);
+        self.names(dir).insert(key);
+    }
+
+    /// Claim the first `name (n)` that is free in all of `dirs`, counting
+    /// from `start`.
+    pub fn claim_numbered(&mut self, dirs: &[&Path], name: &str, start: usize) -> String {
+        let mut n = start;
+        loop {
+            let candidate = numbered(name, n);
+            if !dirs.iter().any(|dir| self.is_taken(dir, &candidate)) {
+                for dir in dirs {
+                    self.claim(dir, &candidate);
+                }
+                return candidate;
+            }
+            n += 1;
+        }
+    }
+}
+
+/// Number files sharing a name within `scope`: the first one (by path) keeps
+/// its name, the others get ` (1)`, ` (2)`, ... appended. Returns only the
+/// matches that need renaming, in rename order.
+pub fn number_duplicates(matches: Vec<Match>, scope: DuplicateScope, case_sensitive: bool) -> Vec<Match> {
+    let mut registry = NameRegistry::new(case_sensitive);
+    // Flattened, all names share one namespace, which has no directory on
+    // disk to read them from
+    let tree = Path::new("");
+    let mut groups: BTreeMap<(PathBuf, String), Vec<Match>> = BTreeMap::new();
+    for m in matches.into_iter().filter(|m| !m.is_dir) {
+        if scope == DuplicateScope::Tree {
+            registry.claim(tree, file_name(&m.path));
+        }
+        let dir = match scope {
+            DuplicateScope::Dir => m.path.parent().unwrap_or(Path::new("")).to_path_buf(),
+            DuplicateScope::Tree => PathBuf::new(),
+        };
+        let key = fold(file_name(&m.path), case_sensitive);
+        groups.entry((dir, key)).or_default().push(m);
+    }
+
+    let mut renames = Vec::new();
+    for mut group in groups.into_values().filter(|g| g.len() > 1) {
+        group.sort_by(|a, b| a.path.cmp(&b.path));
+        for mut m in group.into_iter().skip(1) {
+            let dir = m.path.parent().unwrap_or(Path::new(".")).to_path_buf();
+            let old_name = file_name(&m.path).to_string();
+            m.new_name = match scope {
+                DuplicateScope::Dir => registry.claim_numbered(&[&dir], &old_name, 1),
+                DuplicateScope::Tree => registry.claim_numbered(&[&dir, tree], &old_name, 1),
+            };
+            m.replacement = m.new_name.as_str().into();
+            m.pattern = old_name.into();
+            renames.push(m);
+        }
+    }
+    sort_matches(&mut renames);
+    renames
+}
+
+/// What happens to renames whose new path is taken, `--on-conflict`.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OnConflict {
+    /// Refuse the plan, or skip them with `--keep-going`.
+    #[default]
+    Error,
+    /// Leave them out.
+    Skip,
+    /// Replace the entries that have the names.
+    Overwrite,
+    /// Append ` (1)`, ` (2)`, ... until the name is free.
+    Number,
+    /// Ask about each.
+    Prompt,
+}
+
+/// How one rename onto a taken path goes ahead.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resolution {
+    Skip,
+    Overwrite,
+    Number,
+}
+
+#[derive(Debug, Default)]
+pub struct Resolved {
+    pub matches: Vec<Match>,
+    /// Renames left out.
+    pub skipped: Vec<Match>,
+    /// Sources of renames that may replace what has their new path.
+    pub overwrite: HashSet<PathBuf>,
+}
+
+/// Settle the renames whose new path is taken (see [`collisions`]) the way
+/// `decide` says. It learns whether an entry that stays has the path; if
+/// only an earlier rename wants it, there is nothing to overwrite yet and
+/// overwriting it would lose that rename, so those are skipped instead. Of
+/// several renames wanting a free path, the first keeps it. Numbered names
+/// are free on disk and among the new names.
+pub fn resolve(
+    matches: Vec<Match>,
+    probe: &Probe,
+    case_sensitive: bool,
+    mut decide: impl FnMut(&Match, bool) -> Result<Resolution>,
+) -> Result<Resolved> {
+    let mut contested: HashMap<PathBuf, bool> = HashMap::new();
+    for collision in collisions(&matches, probe) {
+        for (i, source) in collision.sources.into_iter().enumerate() {
+            if collision.taken || i > 0 {
+                contested.insert(source, collision.taken && i == 0);
+            }
+        }
+    }
+
+    let mut registry = NameRegistry::new(case_sensitive);
+    for m in matches.iter().filter(|m| !contested.contains_key(&m.path)) {
+        registry.claim(m.path.parent().unwrap_or(Path::new(".")), &m.new_name);
+    }
+    let mut resolved = Resolved::default();
+    for mut m in matches {
+        let Some(&existing) = contested.get(&m.path) else {
+            resolved.matches.push(m);
+            continue;
+        };
+        match decide(&m, existing)? {
+            Resolution::Overwrite if existing => {
+                resolved.overwrite.insert(m.path.clone());
+                resolved.matches.push(m);
+            }
+            Resolution::Skip | Resolution::Overwrite => resolved.skipped.push(m),
+            Resolution::Number => {
+                let dir = m.path.parent().unwrap_or(Path::new(".")).to_path_buf();
+                m.new_name = registry.claim_numbered(&[&dir], &m.new_name, 1);
+                m.replacement = m.new_name.as_str().into();
+                m.pattern = file_name(&m.path).into();
+                resolved.matches.push(m);
+            }
+        }
+    }
+    Ok(resolved)
+}
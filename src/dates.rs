@@ -0,0 +1,108 @@
+//! File timestamps for date placeholders and filters.
+
+use anyhow::{bail, Context, Result};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local, Locale, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+/// Format used by date placeholders without an explicit format.
+pub const DEFAULT_FORMAT: &str = "%Y-%m-%d";
+
+/// Timezone and locale dates are rendered and parsed in. Defaults to the
+/// local timezone and English names.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DateStyle {
+    pub timezone: Option<Tz>,
+    pub locale: Option<Locale>,
+}
+
+impl DateStyle {
+    pub fn format(&self, time: SystemTime, format: &str) -> String {
+        let utc = DateTime::<Utc>::from(time);
+        match (self.timezone, self.locale) {
+            (Some(tz), Some(locale)) => utc.with_timezone(&tz).format_localized(format, locale).to_string(),
+            (Some(tz), None) => utc.with_timezone(&tz).format(format).to_string(),
+            (None, Some(locale)) => utc.with_timezone(&Local).format_localized(format, locale).to_string(),
+            (None, None) => utc.with_timezone(&Local).format(format).to_string(),
+        }
+    }
+
+    /// A wall clock time without a timezone, like the capture time of a
+    /// photo, formatted as it is.
+    pub fn format_naive(&self, naive: NaiveDateTime, format: &str) -> String {
+        let time = Utc.from_utc_datetime(&naive);
+        match self.locale {
+            Some(locale) => time.format_localized(format, locale).to_string(),
+            None => time.format(format).to_string(),
+        }
+    }
+
+    /// The instant `naive` denotes in this style's timezone. Times skipped
+    /// by a DST change are taken as UTC.
+    pub fn resolve(&self, naive: NaiveDateTime) -> SystemTime {
+        let resolved = match self.timezone {
+            Some(tz) => tz.from_local_datetime(&naive).earliest().map(SystemTime::from),
+            None => Local.from_local_datetime(&naive).earliest().map(SystemTime::from),
+        };
+        resolved.unwrap_or_else(|| Utc.from_utc_datetime(&naive).into())
+    }
+}
+
+/// Check a strftime-style format string.
+pub fn validate_format(format: &str) -> Result<()> {
+    if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+        bail!("Invalid date format '{}'", format);
+    }
+    Ok(())
+}
+
+pub const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Parse an age in hours, days, weeks, months (30 days) or years (365
+/// days), like `12h`, `5d`, `2w`, `6m` or `1y`.
+pub fn parse_age(text: &str) -> Result<Duration> {
+    let text = text.trim();
+    let unit = match text.chars().last() {
+        Some('h') => DAY / 24,
+        Some('d') => DAY,
+        Some('w') => 7 * DAY,
+        Some('m') => 30 * DAY,
+        Some('y') => 365 * DAY,
+        _ => bail!("Invalid age '{}', expected a number of hours, days, weeks, months or years like 12h, 5d, 2w, 6m or 1y", text),
+    };
+    match text[..text.len() - 1].parse::<u32>() {
+        Ok(count) => Ok(unit * count),
+        Err(_) => bail!("Invalid age '{}', expected a number of hours, days, weeks, months or years like 12h, 5d, 2w, 6m or 1y", text),
+    }
+}
+
+/// Parse `YYYY-MM-DD`, `YYYY-MM-DD HH:MM` or `YYYY-MM-DD HH:MM:SS`.
+pub fn parse_date(text: &str) -> Result<NaiveDateTime> {
+    let text = text.trim();
+    NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M"))
+        .or_else(|_| NaiveDate::parse_from_str(text, "%Y-%m-%d").map(|d| d.and_hms_opt(0, 0, 0).unwrap()))
+        .with_context(|| format!("Invalid date '{}', expected YYYY-MM-DD[ HH:MM[:SS]]", text))
+}
+
+/// Parse an IANA timezone name like `Europe/Berlin` or `UTC`.
+pub fn parse_timezone(text: &str) -> Result<Tz> {
+    text.parse::<Tz>()
+        .map_err(|_| anyhow::anyhow!("Unknown timezone '{}', expected a name like Europe/Berlin or UTC", text))
+}
+
+/// Parse a POSIX locale name like `de_DE` (`de-DE` works too).
+pub fn parse_locale(text: &str) -> Result<Locale> {
+    Locale::try_from(text.replace('-', "_").as_str())
+        .map_err(|_| anyhow::anyhow!("Unknown locale '{}', expected a name like de_DE", text))
+}
+
+/// Creation time of `path`, if the platform and filesystem record one.
+pub fn birth_time(path: &Path) -> Result<SystemTime> {
+    fs::symlink_metadata(path)
+        .and_then(|m| m.created())
+        .with_context(|| format!("Creation time of {} is not available", path.display()))
+}
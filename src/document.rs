@@ -0,0 +1,181 @@
+//! Document titles from PDF, Office Open XML (`.docx`, `.xlsx`, `.pptx`)
+//! and OpenDocument (`.odt`, `.ods`, `.odp`) metadata.
+//!
+//! PDFs are scanned for an uncompressed `/Title` entry or XMP `dc:title`;
+//! titles only stored in compressed object streams are not found.
+
+use crate::open_regular;
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+/// Largest PDF scanned for a title.
+const MAX_PDF_SIZE: u64 = 256 << 20;
+
+/// Title of the document at `path`, `None` if it has no title.
+pub fn title(path: &Path) -> Result<Option<String>> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    let title = match extension.as_str() {
+        "pdf" => pdf_title(path)?,
+        "docx" | "xlsx" | "pptx" | "docm" | "xlsm" | "pptm" => zip_xml_title(path, "docProps/core.xml")?,
+        "odt" | "ods" | "odp" | "odg" => zip_xml_title(path, "meta.xml")?,
+        _ => bail!("{} is not a supported document (pdf, docx, xlsx, pptx, odt, ods, odp)", path.display()),
+    };
+    Ok(title.map(|t| clean(&t)).filter(|t| !t.is_empty()))
+}
+
+/// Title of a PDF, `None` if it has no title.
+pub fn pdf_title(path: &Path) -> Result<Option<String>> {
+    if fs::metadata(path)?.len() > MAX_PDF_SIZE {
+        bail!("{} is too large to scan for a title", path.display());
+    }
+    let mut data = Vec::new();
+    open_regular(path)
+        .and_then(|mut file| file.read_to_end(&mut data))
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    if !data.starts_with(b"%PDF") {
+        bail!("{} is not a PDF", path.display());
+    }
+    // The last definition wins, incremental updates append to the file
+    let info = find_all(&data, b"/Title").filter_map(|at| pdf_string(&data[at + 6..])).last();
+    Ok(info.or_else(|| xml_element(&String::from_utf8_lossy(&data), "dc:title")))
+}
+
+fn find_all<'a>(data: &'a [u8], needle: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+    data.windows(needle.len())
+        .enumerate()
+        .filter(move |(_, w)| *w == needle)
+        .map(|(i, _)| i)
+}
+
+/// Parse a PDF literal `(...)` or hex `<...>` string after optional
+/// whitespace.
+fn pdf_string(data: &[u8]) -> Option<String> {
+    let start = data.iter().position(|b| !b.is_ascii_whitespace())?;
+    let data = &data[start..];
+    let bytes = match data.first()? {
+        b'(' => {
+            let mut out = Vec::new();
+            let mut depth = 0;
+            let mut iter = data[1..].iter().copied();
+            while let Some(b) = iter.next() {
+                match b {
+                    b'\\' => match iter.next()? {
+                        b'n' => out.push(b'\n'),
+                        b'r' => out.push(b'\r'),
+                        b't' => out.push(b'\t'),
+                        b'b' => out.push(8),
+                        b'f' => out.push(12),
+                        d @ b'0'..=b'7' => {
+                            let mut value = (d - b'0') as u32;
+                            for _ in 0..2 {
+                                match iter.clone().next() {
+                                    Some(d @ b'0'..=b'7') => {
+                                        iter.next();
+                                        value = value * 8 + (d - b'0') as u32;
+                                    }
+                                    _ => break,
+                                }
+                            }
+                            out.push(value as u8);
+                        }
+                        b'\n' | b'\r' => {}
+                        other => out.push(other),
+                    },
+                    b'(' => {
+                        depth += 1;
+                        out.push(b);
+                    }
+                    b')' if depth == 0 => break,
+                    b')' => {
+                        depth -= 1;
+                        out.push(b);
+                    }
+                    _ => out.push(b),
+                }
+            }
+            out
+        }
+        b'<' if data.get(1) != Some(&b'<') => {
+            let end = data.iter().position(|&b| b == b'>')?;
+            let hex: Vec<u8> = data[1..end].iter().copied().filter(|b| b.is_ascii_hexdigit()).collect();
+            hex.chunks(2)
+                .map(|pair| {
+                    let text = std::str::from_utf8(pair).ok()?;
+                    u8::from_str_radix(&format!("{:0<2}", text), 16).ok()
+                })
+                .collect::<Option<Vec<u8>>>()?
+        }
+        _ => return None,
+    };
+    Some(decode_pdf_text(&bytes))
+}
+
+/// PDF text strings are UTF-16BE with a BOM or (roughly) Latin-1.
+fn decode_pdf_text(bytes: &[u8]) -> String {
+    if let Some(utf16) = bytes.strip_prefix(&[0xFE, 0xFF]) {
+        let units: Vec<u16> = utf16.chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect();
+        String::from_utf16_lossy(&units)
+    } else if let Ok(text) = std::str::from_utf8(bytes) {
+        text.to_string()
+    } else {
+        bytes.iter().map(|&b| b as char).collect()
+    }
+}
+
+fn zip_xml_title(path: &Path, member: &str) -> Result<Option<String>> {
+    let file = BufReader::new(open_regular(path)?);
+    let mut archive = zip::ZipArchive::new(file).with_context(|| format!("{} is not a valid document", path.display()))?;
+    let mut xml = String::new();
+    match archive.by_name(member) {
+        Ok(mut entry) => {
+            entry.read_to_string(&mut xml)?;
+        }
+        Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    Ok(xml_element(&xml, "dc:title"))
+}
+
+/// Text of the first `<tag>` element, with nested elements (like XMP's
+/// `rdf:li`) stripped and entities decoded.
+fn xml_element(xml: &str, tag: &str) -> Option<String> {
+    let open = format!("<{}", tag);
+    let start = xml.find(&open)?;
+    let body_start = start + xml[start..].find('>')? + 1;
+    if xml[..body_start].ends_with("/>") {
+        return None;
+    }
+    let body_end = body_start + xml[body_start..].find(&format!("</{}>", tag))?;
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in xml[body_start..body_end].chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    Some(
+        text.replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&amp;", "&"),
+    )
+}
+
+/// Make a title usable as (part of) a file name.
+fn clean(title: &str) -> String {
+    let title: String = title
+        .chars()
+        .map(|c| if c == '/' || c == '\\' || c.is_control() { ' ' } else { c })
+        .collect();
+    title.split_whitespace().collect::<Vec<_>>().join(" ")
+}
@@ -0,0 +1,97 @@
+//! Repairing mojibake: UTF-8 names that went through a program reading
+//! them in a legacy codepage and writing them back as UTF-8, like `café`
+//! turning into `cafÃ©`, for `--fix-encoding`.
+
+use crate::{file_name, Match};
+
+/// Codepages names get mis-decoded through.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codepage {
+    /// ISO 8859-1, what many Unix tools assume.
+    Latin1,
+    /// Windows-1252, Latin-1 with curly quotes and the euro sign.
+    Cp1252,
+    /// The IBM PC codepage, which old zip tools use for names.
+    Cp437,
+}
+
+/// Windows-1252 from 0x80 to 0x9F, where it differs from Latin-1. The
+/// unassigned bytes keep their Latin-1 meaning.
+const CP1252: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8D}', 'Ž', '\u{8F}', '\u{90}', '‘', '’',
+    '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9D}', 'ž', 'Ÿ',
+];
+
+/// Codepage 437 from 0x80 to 0xFF.
+const CP437: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', 'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û',
+    'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', 'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡',
+    '«', '»', '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', '└', '┴', '┬', '├',
+    '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘',
+    '┌', '█', '▄', '▌', '▐', '▀', 'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{A0}',
+];
+
+/// Mis-decodings undone before giving up, for names that went through
+/// the same mistake more than once.
+const MAX_ROUNDS: usize = 3;
+
+impl Codepage {
+    /// The byte `c` is in this codepage, if it is in it.
+    fn byte(self, c: char) -> Option<u8> {
+        if c.is_ascii() {
+            return Some(c as u8);
+        }
+        let high: &[char] = match self {
+            Codepage::Latin1 => &[],
+            Codepage::Cp1252 => &CP1252,
+            Codepage::Cp437 => &CP437,
+        };
+        if let Some(i) = high.iter().position(|&known| known == c) {
+            return Some(0x80 + i as u8);
+        }
+        match self {
+            Codepage::Cp437 => None,
+            _ => u8::try_from(c as u32).ok(),
+        }
+    }
+
+    /// `name` read back into the bytes it was decoded from, and those
+    /// decoded as UTF-8. `None` unless that makes sense: every character
+    /// has to be in the codepage, the bytes have to be UTF-8, and some of
+    /// them have to join into characters beyond ASCII.
+    pub fn redecode(self, name: &str) -> Option<String> {
+        let bytes: Vec<u8> = name.chars().map(|c| self.byte(c)).collect::<Option<_>>()?;
+        let decoded = String::from_utf8(bytes).ok()?;
+        (decoded.chars().count() < name.chars().count()).then_some(decoded)
+    }
+}
+
+/// The name `name` was before it went through `codepage`, as many times as
+/// it takes, or `None` if it doesn't look mis-decoded.
+pub fn repair(name: &str, codepage: Codepage) -> Option<String> {
+    let mut repaired = codepage.redecode(name)?;
+    for _ in 1..MAX_ROUNDS {
+        match codepage.redecode(&repaired) {
+            Some(again) => repaired = again,
+            None => break,
+        }
+    }
+    Some(repaired)
+}
+
+/// Repair the new names of `matches` that look mis-decoded through
+/// `codepage`. Keeps the matches whose name changes after all.
+pub fn fix_encoding(matches: Vec<Match>, codepage: Codepage) -> Vec<Match> {
+    matches
+        .into_iter()
+        .filter_map(|mut m| {
+            if let Some(repaired) = repair(&m.new_name, codepage) {
+                m.new_name = repaired;
+                m.replacement = m.new_name.as_str().into();
+                m.pattern = file_name(&m.path).into();
+            }
+            (m.new_name != file_name(&m.path)).then_some(m)
+        })
+        .collect()
+}
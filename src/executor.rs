@@ -0,0 +1,182 @@
+//! Parallel execution of rename plans.
+//!
+//! Renames are grouped by parent directory. Each group is applied serially by
+//! a single worker, so checks against sibling names stay valid, while idle
+//! workers pick up the next pending group from a shared queue. Directories are
+//! processed one depth level at a time (deepest first, after all files), since
+//! renaming a directory invalidates the paths of everything below it.
+//!
+//! A [`Throttle`] shared by the workers caps the rate of renames across all
+//! of them, to keep busy network storage usable for everyone else, and a
+//! [`HandleLimit`] the number of files they hold open, so many workers
+//! copying across devices don't run into a low `ulimit -n`.
+
+use crate::Match;
+use anyhow::{anyhow, bail, Result};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Caps the number of file handles held by all workers together.
+#[derive(Debug)]
+pub struct HandleLimit {
+    max: usize,
+    in_use: Mutex<usize>,
+    released: Condvar,
+}
+
+/// Handles held until dropped.
+pub struct Handles<'a> {
+    limit: &'a HandleLimit,
+    count: usize,
+}
+
+impl HandleLimit {
+    pub fn new(max: usize) -> Self {
+        HandleLimit {
+            max: max.max(1),
+            in_use: Mutex::new(0),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Wait until `count` handles (at most the whole limit) are free.
+    pub fn acquire(&self, count: usize) -> Handles<'_> {
+        let count = count.min(self.max);
+        let mut in_use = self.in_use.lock().unwrap();
+        while *in_use + count > self.max {
+            in_use = self.released.wait(in_use).unwrap();
+        }
+        *in_use += count;
+        Handles { limit: self, count }
+    }
+}
+
+impl Drop for Handles<'_> {
+    fn drop(&mut self) {
+        *self.limit.in_use.lock().unwrap() -= self.count;
+        self.limit.released.notify_all();
+    }
+}
+
+/// Limits operations to a fixed rate, shared between threads.
+#[derive(Debug)]
+pub struct Throttle {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl Throttle {
+    pub fn new(ops_per_sec: f64) -> Self {
+        Throttle {
+            interval: Duration::from_secs_f64(1.0 / ops_per_sec),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Block until the next operation is due.
+    pub fn wait(&self) {
+        let due = {
+            let mut next = self.next.lock().unwrap();
+            let due = (*next).max(Instant::now());
+            *next = due + self.interval;
+            due
+        };
+        thread::sleep(due.saturating_duration_since(Instant::now()));
+    }
+}
+
+/// Parse a rate in operations per second, or one of the presets `gentle`
+/// (100/s), `nice` (20/s) and `idle` (5/s).
+pub fn parse_throttle(text: &str) -> Result<f64> {
+    let rate = match text {
+        "gentle" => 100.0,
+        "nice" => 20.0,
+        "idle" => 5.0,
+        _ => match text.parse::<f64>() {
+            Ok(rate) if rate > 0.0 && rate.is_finite() => rate,
+            _ => bail!("Invalid throttle '{}', expected operations per second or gentle, nice, idle", text),
+        },
+    };
+    Ok(rate)
+}
+
+/// Rename all `matches` using up to `jobs` worker threads.
+///
+/// `matches` must be ordered as returned by [`crate::Finder::find`].
+/// `apply` performs a single rename (usually [`crate::rename`] plus
+/// reporting) and is called from the worker threads. The first error stops
+/// further work and is returned.
+pub fn rename_parallel<F>(matches: &[Match], jobs: usize, apply: F) -> Result<()>
+where
+    F: Fn(&Match) -> Result<()> + Sync,
+{
+    for phase in phases(matches) {
+        run_phase(phase, jobs.max(1), &apply)?;
+    }
+    Ok(())
+}
+
+/// Split matches into batches that may run concurrently: all files first,
+/// then directories one depth level at a time, deepest first.
+fn phases(matches: &[Match]) -> Vec<Vec<&Match>> {
+    let mut files = Vec::new();
+    let mut dirs: BTreeMap<usize, Vec<&Match>> = BTreeMap::new();
+    for m in matches {
+        if m.is_dir {
+            dirs.entry(m.path.components().count()).or_default().push(m);
+        } else {
+            files.push(m);
+        }
+    }
+
+    let mut phases = vec![files];
+    phases.extend(dirs.into_values().rev());
+    phases.retain(|phase| !phase.is_empty());
+    phases
+}
+
+fn run_phase<F>(phase: Vec<&Match>, jobs: usize, apply: &F) -> Result<()>
+where
+    F: Fn(&Match) -> Result<()> + Sync,
+{
+    let mut groups: BTreeMap<PathBuf, Vec<&Match>> = BTreeMap::new();
+    for m in phase {
+        let parent = m.path.parent().unwrap_or(Path::new(".")).to_path_buf();
+        groups.entry(parent).or_default().push(m);
+    }
+    let groups: Vec<Vec<&Match>> = groups.into_values().collect();
+
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let first_error = Mutex::new(None);
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(groups.len()) {
+            scope.spawn(|| {
+                while !failed.load(Ordering::Relaxed) {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(group) = groups.get(index) else {
+                        break;
+                    };
+                    for m in group {
+                        if let Err(e) = apply(m) {
+                            failed.store(true, Ordering::Relaxed);
+                            first_error.lock().unwrap().get_or_insert(e);
+                            break;
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    match first_error.into_inner() {
+        Ok(Some(e)) => Err(e),
+        Ok(None) => Ok(()),
+        Err(_) => Err(anyhow!("Rename worker panicked")),
+    }
+}
@@ -0,0 +1,324 @@
+//! EXIF metadata from JPEG and TIFF-based raw files (`.cr2`, `.nef`,
+//! `.arw`, `.dng`, `.orf`, `.rw2`, `.tif`) for the `{exif:TAG}`
+//! placeholders. Only the metadata directories are read, never the image.
+
+use crate::open_regular;
+use anyhow::{bail, Context, Result};
+use chrono::NaiveDateTime;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Tags the placeholders know, by the names exiftool and friends use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tag {
+    DateTimeOriginal,
+    DateTimeDigitized,
+    /// When the file was last changed, by the camera or an editor.
+    DateTime,
+    Make,
+    Model,
+    LensModel,
+    Iso,
+    FNumber,
+    ExposureTime,
+    FocalLength,
+}
+
+pub const TAGS: &[(&str, Tag)] = &[
+    ("DateTimeOriginal", Tag::DateTimeOriginal),
+    ("DateTimeDigitized", Tag::DateTimeDigitized),
+    ("DateTime", Tag::DateTime),
+    ("Make", Tag::Make),
+    ("Model", Tag::Model),
+    ("LensModel", Tag::LensModel),
+    ("ISO", Tag::Iso),
+    ("FNumber", Tag::FNumber),
+    ("ExposureTime", Tag::ExposureTime),
+    ("FocalLength", Tag::FocalLength),
+];
+
+/// Where the Exif sub-directory is, in IFD0.
+const EXIF_IFD: u16 = 0x8769;
+
+/// Directories with more entries than this are taken for garbage.
+const MAX_ENTRIES: u16 = 1024;
+
+/// Upper bound for a single value, text included.
+const MAX_VALUE_LEN: u32 = 64 << 10;
+
+impl Tag {
+    pub fn parse(name: &str) -> Option<Tag> {
+        TAGS.iter().find(|(known, _)| known.eq_ignore_ascii_case(name)).map(|&(_, tag)| tag)
+    }
+
+    pub fn name(self) -> &'static str {
+        TAGS.iter().find(|&&(_, tag)| tag == self).map_or("", |(name, _)| name)
+    }
+
+    pub fn is_date(self) -> bool {
+        matches!(self, Tag::DateTimeOriginal | Tag::DateTimeDigitized | Tag::DateTime)
+    }
+
+    /// The tag number, and whether it lives in the Exif sub-directory
+    /// rather than IFD0.
+    fn id(self) -> (u16, bool) {
+        match self {
+            Tag::DateTimeOriginal => (0x9003, true),
+            Tag::DateTimeDigitized => (0x9004, true),
+            Tag::DateTime => (0x0132, false),
+            Tag::Make => (0x010F, false),
+            Tag::Model => (0x0110, false),
+            Tag::LensModel => (0xA434, true),
+            Tag::Iso => (0x8827, true),
+            Tag::FNumber => (0x829D, true),
+            Tag::ExposureTime => (0x829A, true),
+            Tag::FocalLength => (0x920A, true),
+        }
+    }
+}
+
+/// A tag value, decoded as far as the tag calls for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// Camera wall clock time, EXIF has no timezone.
+    Date(NaiveDateTime),
+    Text(String),
+    Number(u32),
+    Rational(u32, u32),
+}
+
+impl Value {
+    /// The value as it can go into a file name: `/` in text becomes a
+    /// space, exposure times are written `1-250`, and apertures and focal
+    /// lengths drop a `.0`. Dates are formatted by the caller.
+    pub fn to_name(&self, tag: Tag) -> String {
+        match *self {
+            Value::Date(date) => date.to_string(),
+            Value::Text(ref text) => text.replace('/', " "),
+            Value::Number(n) => n.to_string(),
+            Value::Rational(n, d) if tag == Tag::ExposureTime && n > 0 && n < d => {
+                format!("1-{}", (d as f64 / n as f64).round())
+            }
+            Value::Rational(n, d) => {
+                let value = n as f64 / d.max(1) as f64;
+                if value.fract() == 0.0 {
+                    format!("{}", value)
+                } else {
+                    format!("{:.1}", value)
+                }
+            }
+        }
+    }
+}
+
+/// The value of `tag` in the EXIF data of `path`, `None` if it isn't
+/// recorded.
+pub fn read(path: &Path, tag: Tag) -> Result<Option<Value>> {
+    read_tag(path, tag).with_context(|| format!("Failed to read EXIF data of {}", path.display()))
+}
+
+fn read_tag(path: &Path, tag: Tag) -> Result<Option<Value>> {
+    let mut file = BufReader::new(open_regular(path)?);
+    let base = tiff_start(&mut file)?;
+    let mut tiff = Tiff::open(file, base)?;
+    let (id, in_exif) = tag.id();
+    let mut ifd = tiff.first_ifd()?;
+    if in_exif {
+        match tiff.find(ifd, EXIF_IFD)? {
+            Some(entry) => ifd = tiff.number(&entry)?,
+            None => return Ok(None),
+        }
+    }
+    let Some(entry) = tiff.find(ifd, id)? else {
+        return Ok(None);
+    };
+    let value = match entry.kind {
+        // ASCII
+        2 => {
+            let text = String::from_utf8_lossy(&tiff.data(&entry)?).trim_matches(['\0', ' ']).to_string();
+            if text.is_empty() {
+                return Ok(None);
+            }
+            if tag.is_date() {
+                // Unknown parts are written as spaces or zeros
+                match NaiveDateTime::parse_from_str(&text, "%Y:%m:%d %H:%M:%S") {
+                    Ok(date) => Value::Date(date),
+                    Err(_) => return Ok(None),
+                }
+            } else {
+                Value::Text(text)
+            }
+        }
+        // RATIONAL and SRATIONAL
+        5 | 10 => {
+            let data = tiff.data(&entry)?;
+            if data.len() < 8 {
+                bail!("Corrupt EXIF data");
+            }
+            Value::Rational(tiff.u32(&data[..4]), tiff.u32(&data[4..8]))
+        }
+        _ => Value::Number(tiff.number(&entry)?),
+    };
+    Ok(Some(value))
+}
+
+/// Offset of the TIFF header: right at the start of raw files, inside the
+/// APP1 segment of JPEGs.
+fn tiff_start<R: Read + Seek>(file: &mut R) -> Result<u64> {
+    let mut magic = [0u8; 4];
+    file.read_exact(&mut magic).context("Not a JPEG or TIFF-based file")?;
+    match magic {
+        // Plain TIFF, Panasonic and Olympus raw
+        [b'I', b'I', 42 | 0x55 | b'R', 0] | [b'I', b'I', b'R', b'O' | b'S'] | [b'M', b'M', 0, 42] => return Ok(0),
+        [0xFF, 0xD8, 0xFF, _] => {}
+        _ => bail!("Not a JPEG or TIFF-based file"),
+    }
+    file.seek(SeekFrom::Start(2))?;
+    loop {
+        let mut marker = [0u8; 2];
+        file.read_exact(&mut marker).context("No EXIF data")?;
+        if marker[0] != 0xFF {
+            bail!("Corrupt JPEG");
+        }
+        match marker[1] {
+            // Fill bytes
+            0xFF => {
+                file.seek(SeekFrom::Current(-1))?;
+                continue;
+            }
+            // Standalone markers
+            0x01 | 0xD0..=0xD7 => continue,
+            // Image data starts, metadata comes before it
+            0xD9 | 0xDA => bail!("No EXIF data"),
+            _ => {}
+        }
+        let mut len = [0u8; 2];
+        file.read_exact(&mut len)?;
+        let len = u16::from_be_bytes(len) as i64 - 2;
+        if marker[1] == 0xE1 && len >= 6 {
+            let mut header = [0u8; 6];
+            file.read_exact(&mut header)?;
+            if &header == b"Exif\0\0" {
+                return Ok(file.stream_position()?);
+            }
+            file.seek(SeekFrom::Current(len - 6))?;
+        } else {
+            file.seek(SeekFrom::Current(len))?;
+        }
+    }
+}
+
+/// A directory entry.
+struct Entry {
+    kind: u16,
+    count: u32,
+    /// The value itself if it fits in four bytes, its offset otherwise.
+    field: [u8; 4],
+}
+
+impl Entry {
+    fn len(&self) -> Option<u32> {
+        let size = match self.kind {
+            1 | 2 | 6 | 7 => 1,
+            3 | 8 => 2,
+            4 | 9 | 11 => 4,
+            5 | 10 | 12 => 8,
+            _ => return None,
+        };
+        self.count.checked_mul(size)
+    }
+}
+
+/// TIFF structure starting at `base` in a file.
+struct Tiff<R> {
+    file: R,
+    base: u64,
+    little_endian: bool,
+}
+
+impl<R: Read + Seek> Tiff<R> {
+    fn open(mut file: R, base: u64) -> Result<Self> {
+        file.seek(SeekFrom::Start(base))?;
+        let mut order = [0u8; 2];
+        file.read_exact(&mut order)?;
+        let little_endian = match &order {
+            b"II" => true,
+            b"MM" => false,
+            _ => bail!("Corrupt EXIF data"),
+        };
+        Ok(Tiff {
+            file,
+            base,
+            little_endian,
+        })
+    }
+
+    fn u16(&self, bytes: &[u8]) -> u16 {
+        let bytes = [bytes[0], bytes[1]];
+        if self.little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        }
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        }
+    }
+
+    fn read_at(&mut self, offset: u32, len: usize) -> Result<Vec<u8>> {
+        self.file.seek(SeekFrom::Start(self.base + offset as u64))?;
+        let mut data = vec![0; len];
+        self.file.read_exact(&mut data).context("Corrupt EXIF data")?;
+        Ok(data)
+    }
+
+    fn first_ifd(&mut self) -> Result<u32> {
+        let header = self.read_at(4, 4)?;
+        Ok(self.u32(&header))
+    }
+
+    /// The entry for `tag` in the directory at `ifd`.
+    fn find(&mut self, ifd: u32, tag: u16) -> Result<Option<Entry>> {
+        let count = self.read_at(ifd, 2)?;
+        let count = self.u16(&count);
+        if count > MAX_ENTRIES {
+            bail!("Corrupt EXIF data");
+        }
+        let start = ifd.checked_add(2).context("Corrupt EXIF data")?;
+        let entries = self.read_at(start, count as usize * 12)?;
+        Ok(entries.chunks_exact(12).find(|entry| self.u16(entry) == tag).map(|entry| Entry {
+            kind: self.u16(&entry[2..]),
+            count: self.u32(&entry[4..]),
+            field: [entry[8], entry[9], entry[10], entry[11]],
+        }))
+    }
+
+    /// The bytes of an entry's value.
+    fn data(&mut self, entry: &Entry) -> Result<Vec<u8>> {
+        match entry.len() {
+            Some(len) if len <= 4 => Ok(entry.field[..len as usize].to_vec()),
+            Some(len) if len <= MAX_VALUE_LEN => {
+                let offset = self.u32(&entry.field);
+                self.read_at(offset, len as usize)
+            }
+            _ => bail!("Corrupt EXIF data"),
+        }
+    }
+
+    /// The first value of a BYTE, SHORT or LONG entry.
+    fn number(&mut self, entry: &Entry) -> Result<u32> {
+        let data = self.data(entry)?;
+        match entry.kind {
+            1 | 7 if !data.is_empty() => Ok(data[0] as u32),
+            3 if data.len() >= 2 => Ok(self.u16(&data) as u32),
+            4 if data.len() >= 4 => Ok(self.u32(&data)),
+            _ => bail!("Corrupt EXIF data"),
+        }
+    }
+}
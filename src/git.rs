@@ -0,0 +1,112 @@
+//! When paths last changed according to git, for `--untouched-for`.
+
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
+
+/// Last commit times of the files in the repository around `base_dir`,
+/// read from `git log` on first use. Directories changed when anything
+/// below them did. Files with uncommitted changes changed now.
+#[derive(Debug)]
+pub struct History {
+    base_dir: PathBuf,
+    changes: OnceLock<Result<Changes, String>>,
+}
+
+#[derive(Debug)]
+struct Changes {
+    /// Canonical base directory.
+    base_dir: PathBuf,
+    /// Top level of the repository.
+    root: PathBuf,
+    /// Last change of every tracked path below `root`, relative to it.
+    last: HashMap<PathBuf, SystemTime>,
+}
+
+impl History {
+    pub fn new(base_dir: &Path) -> Self {
+        History {
+            base_dir: base_dir.to_path_buf(),
+            changes: OnceLock::new(),
+        }
+    }
+
+    /// When `path` (below the base directory) last changed, `None` if git
+    /// never saw it.
+    pub fn last_change(&self, path: &Path) -> Result<Option<SystemTime>> {
+        let changes = match self.changes.get_or_init(|| Changes::read(&self.base_dir).map_err(|e| format!("{:#}", e))) {
+            Ok(changes) => changes,
+            Err(e) => bail!("{}", e),
+        };
+        let relative = path.strip_prefix(&self.base_dir).unwrap_or(path);
+        let absolute = changes.base_dir.join(relative);
+        let Ok(in_repo) = absolute.strip_prefix(&changes.root) else {
+            return Ok(None);
+        };
+        Ok(changes.last.get(&normalize(in_repo)).copied())
+    }
+}
+
+/// `path` without `.` components, as git spells it.
+fn normalize(path: &Path) -> PathBuf {
+    path.components().filter(|c| !matches!(c, std::path::Component::CurDir)).collect()
+}
+
+fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        bail!("git {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+impl Changes {
+    fn read(base_dir: &Path) -> Result<Changes> {
+        let base_dir = fs::canonicalize(base_dir).with_context(|| format!("Failed to resolve {}", base_dir.display()))?;
+        let root = git(&base_dir, &["rev-parse", "--show-toplevel"])
+            .with_context(|| format!("--untouched-for needs a git repository, {} is not in one", base_dir.display()))?;
+        let root = fs::canonicalize(root.trim_end_matches('\n'))?;
+
+        let mut last: HashMap<PathBuf, SystemTime> = HashMap::new();
+        let mut touch = |file: &str, time: SystemTime| {
+            for path in Path::new(file).ancestors().filter(|p| !p.as_os_str().is_empty()) {
+                let known = last.entry(path.to_path_buf()).or_insert(time);
+                *known = (*known).max(time);
+            }
+        };
+
+        // Newest commits first, each a NUL and the commit time, then the
+        // files it touched
+        let log = git(&root, &["-c", "core.quotePath=false", "log", "--format=%x00%ct", "--name-only", "--no-renames"])?;
+        let mut time = SystemTime::UNIX_EPOCH;
+        for line in log.lines().filter(|line| !line.is_empty()) {
+            match line.strip_prefix('\0') {
+                Some(seconds) => time = SystemTime::UNIX_EPOCH + Duration::from_secs(seconds.parse().unwrap_or(0)),
+                None => touch(line, time),
+            }
+        }
+
+        // Uncommitted changes to tracked files are changes too
+        let status = git(&root, &["status", "--porcelain", "-z", "--untracked-files=no"])?;
+        let now = SystemTime::now();
+        let mut entries = status.split('\0').filter(|entry| !entry.is_empty());
+        while let Some(entry) = entries.next() {
+            let (code, file) = entry.split_at(entry.len().min(3));
+            touch(file, now);
+            if code.starts_with(['R', 'C']) {
+                // The original name follows
+                entries.next();
+            }
+        }
+        Ok(Changes { base_dir, root, last })
+    }
+}
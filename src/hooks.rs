@@ -0,0 +1,100 @@
+//! User hook commands run around renames.
+//!
+//! Hooks are shell commands from the user configuration (never from files in
+//! the searched tree, which could be anyone's):
+//!
+//! ```toml
+//! [hooks]
+//! pre_rename = "git mv -n \"$1\" \"$2\""   # non-zero exit aborts the run
+//! post_rename = "echo \"$FNR_OLD_PATH -> $FNR_NEW_PATH\" >> renames.log"
+//! post_batch = "touch .rebuild"
+//! ```
+//!
+//! `pre_rename` and `post_rename` get the old and new path as `$1`/`$2` and
+//! as `FNR_OLD_PATH`/`FNR_NEW_PATH`, plus `FNR_IS_DIR` (`0` or `1`).
+//! `post_batch` runs once after the run with `FNR_COUNT` set and one
+//! `old<TAB>new` line per applied rename on stdin.
+
+use crate::plan::PlanEntry;
+use crate::Match;
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Hooks {
+    pub pre_rename: Option<String>,
+    pub post_rename: Option<String>,
+    pub post_batch: Option<String>,
+}
+
+fn shell(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C").arg(command);
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(command).arg("fnr");
+        cmd
+    }
+}
+
+fn run(name: &str, mut cmd: Command, stdin: Option<String>) -> Result<()> {
+    cmd.stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() });
+    let mut child = cmd.spawn().with_context(|| format!("Failed to run {} hook", name))?;
+    if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+        // A hook that does not read its input is fine
+        let _ = pipe.write_all(input.as_bytes());
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        bail!("{} hook failed ({})", name, status);
+    }
+    Ok(())
+}
+
+impl Hooks {
+    pub fn is_empty(&self) -> bool {
+        self.pre_rename.is_none() && self.post_rename.is_none() && self.post_batch.is_none()
+    }
+
+    fn rename_hook(name: &str, command: &Option<String>, m: &Match, new_path: &Path) -> Result<()> {
+        let Some(command) = command else {
+            return Ok(());
+        };
+        let mut cmd = shell(command);
+        cmd.arg(&m.path)
+            .arg(new_path)
+            .env("FNR_OLD_PATH", &m.path)
+            .env("FNR_NEW_PATH", new_path)
+            .env("FNR_IS_DIR", if m.is_dir { "1" } else { "0" });
+        run(name, cmd, None)
+    }
+
+    /// Run before `m` is renamed. An error means the rename must not happen.
+    pub fn pre_rename(&self, m: &Match, new_path: &Path) -> Result<()> {
+        Self::rename_hook("pre_rename", &self.pre_rename, m, new_path)
+    }
+
+    pub fn post_rename(&self, m: &Match, new_path: &Path) -> Result<()> {
+        Self::rename_hook("post_rename", &self.post_rename, m, new_path)
+    }
+
+    /// Run once after all renames with the list of what was applied.
+    pub fn post_batch(&self, applied: &[PlanEntry]) -> Result<()> {
+        let Some(command) = &self.post_batch else {
+            return Ok(());
+        };
+        let input: String = applied
+            .iter()
+            .map(|e| format!("{}\t{}\n", e.from.display(), e.to.display()))
+            .collect();
+        let mut cmd = shell(command);
+        cmd.env("FNR_COUNT", applied.len().to_string());
+        run("post_batch", cmd, Some(input))
+    }
+}
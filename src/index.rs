@@ -0,0 +1,287 @@
+//! Optional on-disk index of the names below a root directory, built by
+//! `fnr index build` and consulted by later searches under that root.
+//!
+//! Indexes live in `$XDG_CACHE_HOME/fnr/index` (`~/.cache/fnr/index`), one
+//! file per root. Every directory is stored with its modification time, and
+//! a search only lists the directories whose time changed since, so repeated
+//! queries over a big, slowly changing tree get away with a `stat` per
+//! directory. Edits to `.gitignore` files don't touch the time of their
+//! directory and go unnoticed until the index is rebuilt.
+
+use anyhow::{bail, Context, Result};
+use ignore::WalkBuilder;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+const VERSION: u32 = 2;
+
+/// The walker settings an index was built with. A search only uses an
+/// index built the way it would walk itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexSettings {
+    pub hidden: bool,
+    pub git_ignore: bool,
+    pub follow_links: bool,
+}
+
+/// An entry below the searched directory.
+#[derive(Debug, Clone)]
+pub struct IndexedEntry {
+    /// Relative to the searched directory.
+    pub path: PathBuf,
+    pub is_dir: bool,
+    pub is_symlink: bool,
+    /// A FIFO, socket or device node.
+    pub is_special: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Dir {
+    /// Modification time as nanoseconds since the epoch.
+    mtime: u128,
+    entries: Vec<Name>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Name {
+    name: String,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    dir: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    link: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    special: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Index {
+    version: u32,
+    root: PathBuf,
+    settings: IndexSettings,
+    /// Keyed by the path relative to `root`, so parents sort before their
+    /// children.
+    dirs: BTreeMap<PathBuf, Dir>,
+}
+
+impl Index {
+    /// Walk `root` and index everything below it.
+    pub fn build(root: &Path, settings: IndexSettings) -> Result<Self> {
+        let root = root
+            .canonicalize()
+            .with_context(|| format!("Failed to resolve {}", root.display()))?;
+        let mut index = Index {
+            version: VERSION,
+            root,
+            settings,
+            dirs: BTreeMap::new(),
+        };
+        index.scan(Path::new(""), true)?;
+        Ok(index)
+    }
+
+    /// The index covering `dir` (the one of its nearest indexed ancestor)
+    /// if it was built with `settings`.
+    pub fn find(dir: &Path, settings: IndexSettings) -> Result<Option<Self>> {
+        let Ok(dir) = dir.canonicalize() else {
+            return Ok(None);
+        };
+        for root in dir.ancestors() {
+            let file = index_file(root)?;
+            if !file.exists() {
+                continue;
+            }
+            let text = fs::read_to_string(&file).with_context(|| format!("Failed to read {}", file.display()))?;
+            // A stale format is as good as no index
+            let Ok(index) = serde_json::from_str::<Index>(&text) else {
+                return Ok(None);
+            };
+            if index.version == VERSION && index.root == root && index.settings == settings {
+                return Ok(Some(index));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Remove the index of `root`, returning whether there was one.
+    pub fn remove(root: &Path) -> Result<bool> {
+        let root = root
+            .canonicalize()
+            .with_context(|| format!("Failed to resolve {}", root.display()))?;
+        let file = index_file(&root)?;
+        if !file.exists() {
+            return Ok(false);
+        }
+        fs::remove_file(&file).with_context(|| format!("Failed to remove {}", file.display()))?;
+        Ok(true)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Number of indexed directories and entries.
+    pub fn len(&self) -> (usize, usize) {
+        (self.dirs.len(), self.dirs.values().map(|dir| dir.entries.len()).sum())
+    }
+
+    /// List again the directories that changed since they were indexed.
+    /// Returns whether anything changed.
+    pub fn refresh(&mut self) -> Result<bool> {
+        let mut changed = false;
+        let dirs: Vec<PathBuf> = self.dirs.keys().cloned().collect();
+        for dir in dirs {
+            // Gone with a parent that changed earlier
+            let Some(known) = self.dirs.get(&dir).map(|d| d.mtime) else {
+                continue;
+            };
+            match fs::metadata(self.root.join(&dir)).map(|meta| modified(&meta)) {
+                Ok(mtime) if mtime == known => {}
+                Ok(_) => {
+                    self.scan(&dir, false)?;
+                    changed = true;
+                }
+                Err(_) => {
+                    self.dirs.retain(|path, _| !path.starts_with(&dir));
+                    changed = true;
+                }
+            }
+        }
+        Ok(changed)
+    }
+
+    /// The indexed entries below `dir`, relative to it, down to `max_depth`.
+    pub fn entries_below(&self, dir: &Path, max_depth: Option<usize>) -> Result<Vec<IndexedEntry>> {
+        let dir = dir
+            .canonicalize()
+            .with_context(|| format!("Failed to resolve {}", dir.display()))?;
+        let Ok(start) = dir.strip_prefix(&self.root) else {
+            bail!("{} is not below the indexed {}", dir.display(), self.root.display());
+        };
+        let mut found = Vec::new();
+        for (path, indexed) in self.dirs.range(start.to_path_buf()..) {
+            let Ok(relative) = path.strip_prefix(start) else {
+                break;
+            };
+            if max_depth.is_some_and(|max| relative.components().count() >= max) {
+                continue;
+            }
+            found.extend(indexed.entries.iter().map(|name| IndexedEntry {
+                path: relative.join(&name.name),
+                is_dir: name.dir,
+                is_symlink: name.link,
+                is_special: name.special,
+            }));
+        }
+        Ok(found)
+    }
+
+    pub fn save(&self) -> Result<PathBuf> {
+        let file = index_file(&self.root)?;
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let partial = file.with_extension("partial");
+        fs::write(&partial, serde_json::to_vec(self)?)
+            .with_context(|| format!("Failed to write {}", partial.display()))?;
+        fs::rename(&partial, &file).with_context(|| format!("Failed to write {}", file.display()))?;
+        Ok(file)
+    }
+
+    /// Index the directory `dir` (relative to the root), and everything
+    /// below it with `recursive`. Otherwise only subdirectories that are
+    /// new are walked, and the records of those that vanished dropped.
+    fn scan(&mut self, dir: &Path, recursive: bool) -> Result<()> {
+        let mut walker = WalkBuilder::new(self.root.join(dir));
+        walker
+            .follow_links(self.settings.follow_links)
+            .git_ignore(self.settings.git_ignore)
+            .hidden(!self.settings.hidden);
+        if !recursive {
+            walker.max_depth(Some(1));
+        }
+
+        let mut listed: BTreeMap<PathBuf, Dir> = BTreeMap::new();
+        for result in walker.build() {
+            let entry = match result {
+                Ok(e) => e,
+                Err(e) => {
+                    eprintln!("Warning: {}", e);
+                    continue;
+                }
+            };
+            let relative = match entry.path().strip_prefix(self.root.join(dir)) {
+                Ok(below) if entry.depth() > 0 => dir.join(below),
+                _ => dir.to_path_buf(),
+            };
+            let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+            if is_dir {
+                let meta = fs::metadata(entry.path())
+                    .with_context(|| format!("Failed to read metadata of {}", entry.path().display()))?;
+                listed.entry(relative.clone()).or_default().mtime = modified(&meta);
+            }
+            if entry.depth() == 0 {
+                continue;
+            }
+            let Some(name) = entry.file_name().to_str() else {
+                bail!("Cannot index {}: the name is not valid UTF-8", entry.path().display());
+            };
+            let parent = relative.parent().unwrap_or(Path::new("")).to_path_buf();
+            listed.entry(parent).or_default().entries.push(Name {
+                name: name.to_string(),
+                dir: is_dir,
+                link: entry.path_is_symlink(),
+                special: entry.file_type().is_some_and(crate::is_special),
+            });
+        }
+
+        if recursive {
+            self.dirs.retain(|path, _| !path.starts_with(dir));
+            self.dirs.extend(listed);
+            return Ok(());
+        }
+
+        let Some(own) = listed.remove(dir) else {
+            // Vanished between the stat and the walk
+            self.dirs.retain(|path, _| !path.starts_with(dir));
+            return Ok(());
+        };
+        let subdirs: Vec<PathBuf> = own.entries.iter().filter(|e| e.dir).map(|e| dir.join(&e.name)).collect();
+        self.dirs.retain(|path, _| {
+            path == dir || !path.starts_with(dir) || subdirs.iter().any(|sub| path.starts_with(sub))
+        });
+        self.dirs.insert(dir.to_path_buf(), own);
+        for sub in subdirs {
+            if !self.dirs.contains_key(&sub) {
+                self.scan(&sub, true)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn modified(meta: &fs::Metadata) -> u128 {
+    meta.modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since| since.as_nanos())
+}
+
+/// Where the index of the canonical `root` is kept.
+fn index_file(root: &Path) -> Result<PathBuf> {
+    let cache_home = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .or_else(|| std::env::var_os("LOCALAPPDATA").map(PathBuf::from));
+    let Some(cache_home) = cache_home else {
+        bail!("No home directory to keep the index in");
+    };
+    let key: String = Sha256::digest(root.as_os_str().as_encoded_bytes())[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    Ok(cache_home.join("fnr").join("index").join(format!("{}.json", key)))
+}
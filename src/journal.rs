@@ -0,0 +1,536 @@
+//! The undo journal: every applied batch of renames, oldest first.
+//!
+//! Each batch is one file of checksummed JSON lines in the global data
+//! directory (`$XDG_DATA_HOME/fnr/journal`, `~/.local/share/fnr/journal`) or,
+//! with
+//! `location = "repo"`, in `.fnr-journal/` at the root of the repository the
+//! renames happened in. Configured in the user configuration:
+//!
+//! ```toml
+//! [journal]
+//! location = "repo"     # or "global" (default)
+//! keep_batches = 50     # drop all but the newest 50 batches
+//! keep_days = 30        # drop batches older than 30 days
+//! ```
+//!
+//! While a batch runs, every rename is appended (and synced) to a
+//! `.partial` file before it is carried out, followed by a cancellation
+//! record if it fails. Completing the batch renames the file into place. A
+//! `.partial` file left behind means fnr died mid-batch;
+//! [`Journal::recover`] turns it into a regular batch.
+//!
+//! Renames through staging (see [`crate::staging`]) are logged as two
+//! moves, into the staging directory and out of it, and read back as one.
+
+use crate::plan::PlanEntry;
+use crate::staging::{in_staging, unstaged};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{self, Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+pub const JOURNAL_VERSION: u32 = 1;
+
+/// Extension of completed batches.
+const EXTENSION: &str = "jsonl";
+
+/// Extension of batches still being written, or cut short by a crash.
+const PARTIAL_EXTENSION: &str = "partial";
+
+/// Directory name of per-repository journals.
+pub const REPO_JOURNAL_DIR: &str = ".fnr-journal";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JournalLocation {
+    /// One journal for everything, in the user's data directory.
+    #[default]
+    Global,
+    /// A journal per repository (or base directory outside of one).
+    Repo,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JournalConfig {
+    pub location: JournalLocation,
+    /// Explicit journal directory, overriding `location`.
+    pub dir: Option<PathBuf>,
+    /// Number of most recent batches kept.
+    pub keep_batches: Option<usize>,
+    /// Age in days after which batches are dropped.
+    pub keep_days: Option<u64>,
+}
+
+/// One applied batch. Paths are absolute, so it can be undone from anywhere.
+#[derive(Debug, Clone)]
+pub struct Batch {
+    /// Seconds since the Unix epoch.
+    pub time: u64,
+    /// Recovered by `fnr doctor` after fnr died during the batch.
+    pub interrupted: bool,
+    pub renames: Vec<PlanEntry>,
+}
+
+impl Batch {
+    pub fn time(&self) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(self.time)
+    }
+}
+
+/// First record of every journal file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Header {
+    version: u32,
+    time: u64,
+    #[serde(default)]
+    interrupted: bool,
+}
+
+/// Outcome of recovering a partial batch.
+#[derive(Debug, Clone)]
+pub struct Recovery {
+    /// Renames that had been carried out, now a regular batch.
+    pub applied: Vec<PlanEntry>,
+    /// Renames that were about to happen but did not.
+    pub pending: Vec<PlanEntry>,
+    /// Renames stuck in a staging directory, now moved back.
+    pub unstaged: Vec<PlanEntry>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Journal {
+    dir: PathBuf,
+}
+
+impl Journal {
+    /// The journal for renames below `base_dir`.
+    pub fn open(config: &JournalConfig, base_dir: &Path) -> Result<Journal> {
+        let dir = match (&config.dir, config.location) {
+            (Some(dir), _) => dir.clone(),
+            (None, JournalLocation::Global) => global_dir()?,
+            (None, JournalLocation::Repo) => repo_root(base_dir)?.join(REPO_JOURNAL_DIR),
+        };
+        Ok(Journal { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Start a batch. Every rename is logged to a partial file before it
+    /// happens, so a batch cut short by a crash can be recovered.
+    pub fn begin(&self) -> Result<BatchWriter> {
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create journal {}", self.dir.display()))?;
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?;
+        // Zero-padded milliseconds keep the file names in chronological order
+        let stem = format!("{:016}-{}", now.as_millis(), process::id());
+        let partial = self.dir.join(format!("{}.{}", stem, PARTIAL_EXTENSION));
+        let mut file = OpenOptions::new()
+            .append(true)
+            .create_new(true)
+            .open(&partial)
+            .with_context(|| format!("Failed to create journal entry {}", partial.display()))?;
+        let header = Header {
+            version: JOURNAL_VERSION,
+            time: now.as_secs(),
+            interrupted: false,
+        };
+        file.write_all(record(&header)?.as_bytes())?;
+        file.sync_all()?;
+        sync_dir(&self.dir)?;
+        Ok(BatchWriter {
+            complete: self.dir.join(format!("{}.{}", stem, EXTENSION)),
+            partial,
+            file: Mutex::new(BatchFile {
+                file,
+                intended: 0,
+                cancelled: 0,
+            }),
+        })
+    }
+
+    /// All completed batches with the files they are stored in, oldest first.
+    pub fn batches(&self) -> Result<Vec<(PathBuf, Batch)>> {
+        self.files(EXTENSION)?
+            .into_iter()
+            .map(|path| {
+                let (header, renames, intact) = read(&path)?;
+                if !intact {
+                    bail!("Journal entry {} is corrupt", path.display());
+                }
+                let batch = Batch {
+                    time: header.time,
+                    interrupted: header.interrupted,
+                    renames,
+                };
+                Ok((path, batch))
+            })
+            .collect()
+    }
+
+    /// Batches that never completed, because fnr died (or is still running).
+    pub fn partial_batches(&self) -> Result<Vec<PathBuf>> {
+        self.files(PARTIAL_EXTENSION)
+    }
+
+    /// Turn a partial batch into a completed one holding the renames that
+    /// were carried out, judged by which of the two paths exists now. Records
+    /// torn by the crash are dropped. Entries the crash left in a staging
+    /// directory are moved back where they came from.
+    pub fn recover(&self, partial: &Path) -> Result<Recovery> {
+        let (header, intended, _) = read(partial)?;
+        let (mut applied, mut pending, mut unstaged) = (Vec::new(), Vec::new(), Vec::new());
+        for entry in intended {
+            if fs::symlink_metadata(&entry.to).is_err() || fs::symlink_metadata(&entry.from).is_ok() {
+                pending.push(entry);
+            } else if in_staging(&entry.to) && fs::rename(&entry.to, &entry.from).is_ok() {
+                // Fails for staging directories still holding something else
+                let _ = fs::remove_dir(entry.to.parent().unwrap_or(Path::new(".")));
+                unstaged.push(entry);
+            } else {
+                applied.push(entry);
+            }
+        }
+        if !applied.is_empty() {
+            let header = Header {
+                interrupted: true,
+                ..header
+            };
+            write_atomic(&partial.with_extension(EXTENSION), &header, &applied)?;
+        }
+        fs::remove_file(partial)
+            .with_context(|| format!("Failed to remove journal entry {}", partial.display()))?;
+        sync_dir(&self.dir)?;
+        Ok(Recovery {
+            applied,
+            pending,
+            unstaged,
+        })
+    }
+
+    /// Keep only `renames` of the batch stored at `path`, dropping the batch
+    /// if none are left, after the others were undone.
+    pub fn rewrite(&self, path: &Path, renames: &[PlanEntry]) -> Result<()> {
+        if renames.is_empty() {
+            fs::remove_file(path).with_context(|| format!("Failed to remove journal entry {}", path.display()))?;
+        } else {
+            let (header, _, _) = read(path)?;
+            write_atomic(path, &header, renames)?;
+        }
+        sync_dir(&self.dir)
+    }
+
+    /// Delete batches beyond the newest `keep_batches` and those older than
+    /// `keep_days`. Returns the number of batches deleted.
+    pub fn prune(&self, keep_batches: Option<usize>, keep_days: Option<u64>) -> Result<usize> {
+        if keep_batches.is_none() && keep_days.is_none() {
+            return Ok(0);
+        }
+        let batches = self.batches()?;
+        let excess = batches.len().saturating_sub(keep_batches.unwrap_or(usize::MAX));
+        let cutoff = keep_days.map(|days| SystemTime::now() - Duration::from_secs(days * 24 * 60 * 60));
+
+        let mut removed = 0;
+        for (i, (path, batch)) in batches.iter().enumerate() {
+            if i < excess || cutoff.is_some_and(|cutoff| batch.time() < cutoff) {
+                fs::remove_file(path)
+                    .with_context(|| format!("Failed to remove journal entry {}", path.display()))?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    fn files(&self, extension: &str) -> Result<Vec<PathBuf>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read journal {}", self.dir.display())),
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|e| e == extension))
+            .collect();
+        paths.sort();
+        Ok(paths)
+    }
+}
+
+/// An open batch. Safe to share between rename workers.
+#[derive(Debug)]
+pub struct BatchWriter {
+    partial: PathBuf,
+    complete: PathBuf,
+    file: Mutex<BatchFile>,
+}
+
+#[derive(Debug)]
+struct BatchFile {
+    file: File,
+    /// Renames logged so far.
+    intended: usize,
+    /// Of those, the ones that did not happen.
+    cancelled: usize,
+}
+
+/// A logged rename, to cancel it if it fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Intent(usize);
+
+/// A record after the header: a rename, or the cancellation of one.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Line {
+    Cancel { cancel: usize },
+    Entry(PlanEntry),
+}
+
+impl BatchWriter {
+    /// Log a rename that is about to happen and wait for it to hit the disk.
+    pub fn intend(&self, from: &Path, to: &Path, is_dir: bool) -> Result<Intent> {
+        let entry = PlanEntry {
+            from: path::absolute(from)?,
+            to: path::absolute(to)?,
+            is_dir,
+        };
+        let mut batch = self.file.lock().unwrap();
+        self.append(&mut batch.file, &Line::Entry(entry))?;
+        batch.intended += 1;
+        Ok(Intent(batch.intended - 1))
+    }
+
+    /// Log that an intended rename did not happen.
+    pub fn cancel(&self, intent: Intent) -> Result<()> {
+        let mut batch = self.file.lock().unwrap();
+        self.append(&mut batch.file, &Line::Cancel { cancel: intent.0 })?;
+        batch.cancelled += 1;
+        Ok(())
+    }
+
+    fn append(&self, file: &mut File, line: &Line) -> Result<()> {
+        file.write_all(record(line)?.as_bytes())
+            .and_then(|_| file.sync_data())
+            .with_context(|| format!("Failed to write journal entry {}", self.partial.display()))
+    }
+
+    /// Complete the batch by renaming the synced partial file into place.
+    /// Returns the journal file, `None` if nothing was renamed.
+    pub fn finish(self) -> Result<Option<PathBuf>> {
+        let batch = self.file.into_inner().unwrap();
+        drop(batch.file);
+        if batch.intended == batch.cancelled {
+            fs::remove_file(&self.partial)
+                .with_context(|| format!("Failed to remove journal entry {}", self.partial.display()))?;
+            return Ok(None);
+        }
+        fs::rename(&self.partial, &self.complete)
+            .with_context(|| format!("Failed to complete journal entry {}", self.complete.display()))?;
+        sync_dir(self.complete.parent().unwrap_or(Path::new(".")))?;
+        Ok(Some(self.complete))
+    }
+}
+
+/// A journal line: the first 16 hex digits of the SHA-256 of the JSON, a
+/// space and the JSON.
+fn record(value: &impl Serialize) -> Result<String> {
+    let json = serde_json::to_string(value)?;
+    Ok(format!("{} {}\n", checksum(&json), json))
+}
+
+fn checksum(json: &str) -> String {
+    Sha256::digest(json.as_bytes())[..8].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Parse a journal file into the renames that were not cancelled. Reading
+/// stops at the first damaged record; the flag tells whether all records
+/// were intact.
+fn read(path: &Path) -> Result<(Header, Vec<PlanEntry>, bool)> {
+    let text = fs::read(path).with_context(|| format!("Failed to read journal entry {}", path.display()))?;
+    let text = String::from_utf8_lossy(&text);
+    let mut intact = true;
+    let mut payloads = text.split_inclusive('\n').map_while(|line| {
+        let payload = line
+            .strip_suffix('\n')
+            .and_then(|line| line.split_once(' '))
+            .filter(|(sum, json)| checksum(json) == *sum)
+            .map(|(_, json)| json.to_string());
+        intact &= payload.is_some();
+        payload
+    });
+
+    let header: Header = match payloads.next() {
+        Some(json) => serde_json::from_str(&json)?,
+        None => bail!("Journal entry {} has no valid header", path.display()),
+    };
+    if header.version != JOURNAL_VERSION {
+        bail!("Unsupported journal version {} in {}", header.version, path.display());
+    }
+    let mut entries = Vec::new();
+    for json in payloads.by_ref() {
+        match serde_json::from_str(&json)? {
+            Line::Entry(entry) => entries.push(Some(entry)),
+            Line::Cancel { cancel } => {
+                if let Some(entry) = entries.get_mut(cancel) {
+                    *entry = None;
+                }
+            }
+        }
+    }
+    let mut renames: Vec<PlanEntry> = Vec::new();
+    for entry in entries.into_iter().flatten() {
+        // The move out of staging finishes an earlier move into it, which
+        // may have moved along with a renamed directory since. The two
+        // become one rename where the second happened, from where the entry
+        // would have been by then.
+        let earlier = if in_staging(&entry.from) {
+            renames.iter().rposition(|e| e.to == entry.from).or_else(|| {
+                renames
+                    .iter()
+                    .rposition(|e| in_staging(&e.to) && staged_tail(&e.to) == staged_tail(&entry.from))
+            })
+        } else {
+            None
+        };
+        if let Some(i) = earlier {
+            renames.remove(i);
+            renames.push(PlanEntry {
+                from: unstaged(&entry.from),
+                ..entry
+            });
+        } else {
+            renames.push(entry);
+        }
+    }
+    Ok((header, renames, intact))
+}
+
+/// The staging directory and file name of a staged path, which stay the
+/// same when a directory above is renamed.
+fn staged_tail(path: &Path) -> Vec<&std::ffi::OsStr> {
+    path.iter().rev().take(2).collect()
+}
+
+/// Write a complete journal file: to a temporary file first, synced, then
+/// renamed into place, so readers see all of it or nothing.
+fn write_atomic(path: &Path, header: &Header, renames: &[PlanEntry]) -> Result<()> {
+    let mut text = record(header)?;
+    for entry in renames {
+        text.push_str(&record(&Line::Entry(entry.clone()))?);
+    }
+    let tmp = path.with_extension("tmp");
+    let write = || -> io::Result<()> {
+        let mut file = File::create(&tmp)?;
+        file.write_all(text.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)
+    };
+    write().with_context(|| format!("Failed to write journal entry {}", path.display()))?;
+    sync_dir(path.parent().unwrap_or(Path::new(".")))
+}
+
+/// Make renames and new files in `dir` durable.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> Result<()> {
+    File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .with_context(|| format!("Failed to sync {}", dir.display()))
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> Result<()> {
+    Ok(())
+}
+
+fn global_dir() -> Result<PathBuf> {
+    let data_home = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("share")))
+        .or_else(|| std::env::var_os("LOCALAPPDATA").map(PathBuf::from));
+    match data_home {
+        Some(dir) => Ok(dir.join("fnr").join("journal")),
+        None => bail!("No home directory to keep the journal in, set journal.dir in the configuration"),
+    }
+}
+
+/// The enclosing git repository of `dir`, or `dir` itself outside of one.
+fn repo_root(dir: &Path) -> Result<PathBuf> {
+    let dir = dir
+        .canonicalize()
+        .with_context(|| format!("Failed to resolve {}", dir.display()))?;
+    Ok(dir
+        .ancestors()
+        .find(|ancestor| ancestor.join(".git").exists())
+        .unwrap_or(&dir)
+        .to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn journal(name: &str) -> Journal {
+        let dir = std::env::temp_dir().join(format!("fnr-journal-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        Journal { dir }
+    }
+
+    #[test]
+    fn reading_drops_cancelled_renames_and_stops_at_torn_records() {
+        let journal = journal("read");
+        let batch = journal.begin().unwrap();
+        batch.intend(Path::new("/a"), Path::new("/b"), false).unwrap();
+        let failed = batch.intend(Path::new("/c"), Path::new("/d"), false).unwrap();
+        batch.cancel(failed).unwrap();
+        batch.intend(Path::new("/e"), Path::new("/f"), true).unwrap();
+        let partial = batch.partial.clone();
+        // fnr dies in the middle of the next record
+        drop(batch);
+        let mut file = OpenOptions::new().append(true).open(&partial).unwrap();
+        file.write_all(b"0123456789abcdef {\"from\":\"/g\",").unwrap();
+
+        let (header, renames, intact) = read(&partial).unwrap();
+        assert_eq!(header.version, JOURNAL_VERSION);
+        let pairs: Vec<_> = renames
+            .iter()
+            .map(|e| (e.from.to_str().unwrap(), e.to.to_str().unwrap(), e.is_dir))
+            .collect();
+        assert_eq!(pairs, [("/a", "/b", false), ("/e", "/f", true)]);
+        assert!(!intact);
+        fs::remove_dir_all(journal.dir()).unwrap();
+    }
+
+    #[test]
+    fn recovery_keeps_what_happened_and_reports_what_did_not() {
+        let journal = journal("recover");
+        let files = journal.dir().join("files");
+        fs::create_dir_all(&files).unwrap();
+        fs::write(files.join("renamed"), "").unwrap();
+        fs::write(files.join("left"), "").unwrap();
+        let batch = journal.begin().unwrap();
+        batch.intend(&files.join("old"), &files.join("renamed"), false).unwrap();
+        batch.intend(&files.join("left"), &files.join("never"), false).unwrap();
+        let partial = batch.partial.clone();
+        drop(batch);
+
+        assert_eq!(journal.partial_batches().unwrap(), std::slice::from_ref(&partial));
+        let recovery = journal.recover(&partial).unwrap();
+        assert_eq!(recovery.applied.len(), 1);
+        assert_eq!(recovery.applied[0].to, files.join("renamed"));
+        assert_eq!(recovery.pending.len(), 1);
+        assert_eq!(recovery.pending[0].from, files.join("left"));
+        assert!(journal.partial_batches().unwrap().is_empty());
+        let batches = journal.batches().unwrap();
+        assert_eq!(batches.len(), 1);
+        assert!(batches[0].1.interrupted);
+        assert_eq!(batches[0].1.renames.len(), 1);
+        fs::remove_dir_all(journal.dir()).unwrap();
+    }
+}
@@ -0,0 +1,82 @@
+//! Keys answering the interactive prompts, from the `[keys]` table of the
+//! user configuration.
+//!
+//! ```toml
+//! [keys]
+//! yes = "j"
+//! no = "n"
+//! quit = "x"
+//! ```
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Keys {
+    pub yes: char,
+    pub no: char,
+    /// No to the rest of the directory.
+    pub skip_dir: char,
+    pub all: char,
+    pub quit: char,
+    /// Ask about the entries of a chunk one by one.
+    pub edit: char,
+    /// Keep scanning after a preview.
+    pub scan: char,
+    /// Leave out a rename whose new name is taken.
+    pub skip: char,
+    /// Rename over the entry that has the name.
+    pub overwrite: char,
+    /// Number the new name until it is free.
+    pub number: char,
+}
+
+impl Default for Keys {
+    fn default() -> Self {
+        Keys {
+            yes: 'y',
+            no: 'n',
+            skip_dir: 's',
+            all: 'a',
+            quit: 'q',
+            edit: 'e',
+            scan: 'c',
+            skip: 's',
+            overwrite: 'o',
+            number: 'n',
+        }
+    }
+}
+
+impl Keys {
+    /// Lowercase the keys and make sure no two of them answer the same
+    /// prompt.
+    pub fn normalized(&self) -> Result<Keys> {
+        let keys = Keys {
+            yes: self.yes.to_ascii_lowercase(),
+            no: self.no.to_ascii_lowercase(),
+            skip_dir: self.skip_dir.to_ascii_lowercase(),
+            all: self.all.to_ascii_lowercase(),
+            quit: self.quit.to_ascii_lowercase(),
+            edit: self.edit.to_ascii_lowercase(),
+            scan: self.scan.to_ascii_lowercase(),
+            skip: self.skip.to_ascii_lowercase(),
+            overwrite: self.overwrite.to_ascii_lowercase(),
+            number: self.number.to_ascii_lowercase(),
+        };
+        let renames = [keys.yes, keys.no, keys.skip_dir, keys.all, keys.quit, keys.edit, keys.scan];
+        let conflicts = [keys.skip, keys.overwrite, keys.number, keys.quit];
+        for prompt in [&renames[..], &conflicts] {
+            for (i, key) in prompt.iter().enumerate() {
+                if key.is_whitespace() || key.is_control() {
+                    bail!("'{}' can't be used as a key", key.escape_default());
+                }
+                if prompt[..i].contains(key) {
+                    bail!("Key '{}' is bound twice", key);
+                }
+            }
+        }
+        Ok(keys)
+    }
+}
@@ -0,0 +1,1076 @@
+//! Search and batch-rename engine behind the `fnr` command line tool.
+//!
+//! The [`Finder`] walks a directory tree (respecting `.gitignore` by default),
+//! applies glob, type and user supplied filters, and returns the entries whose
+//! names match the search pattern together with their computed new names.
+
+use anyhow::{bail, Context, Result};
+use check::Submodules;
+use git::History;
+use config::DirConfigs;
+use dates::DateStyle;
+use metadata::MetadataCache;
+use report::{Derivation, Reason, Record};
+use sniff::Content;
+use index::{Index, IndexSettings};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+pub mod anonymize;
+#[cfg(feature = "archives")]
+pub mod archive;
+pub mod check;
+pub mod config;
+pub mod conflict;
+pub mod dates;
+#[cfg(feature = "documents")]
+pub mod document;
+pub mod encoding;
+#[cfg(feature = "exif")]
+pub mod exif;
+pub mod executor;
+pub mod git;
+pub mod hooks;
+pub mod index;
+pub mod journal;
+pub mod keys;
+pub mod matcher;
+pub mod metadata;
+#[cfg(feature = "media")]
+pub mod media;
+pub mod owner;
+pub mod plan;
+pub mod references;
+pub mod remote;
+pub mod report;
+pub mod script;
+pub mod session;
+pub mod shorten;
+pub mod sidecar;
+pub mod sniff;
+pub mod staging;
+pub mod template;
+pub mod theme;
+pub mod transfer;
+pub mod unicode;
+#[cfg(feature = "xattrs")]
+pub mod xattrs;
+#[cfg(feature = "async")]
+pub mod asynchronous;
+
+pub use ignore::DirEntry;
+pub use matcher::{NameMatcher, Rule, Transforms};
+
+#[derive(clap::ValueEnum, Clone, Debug, Default, PartialEq, Eq)]
+pub enum FileType {
+    /// Regular files (and links to them).
+    File,
+    /// Directories.
+    Dir,
+    /// Files and directories.
+    #[default]
+    Both,
+    /// FIFOs, sockets and device nodes, which are passed over otherwise.
+    Special,
+}
+
+/// Which symbolic links to follow.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Follow {
+    /// Don't descend into linked directories; links are matched as links.
+    Never,
+    /// Descend into linked directories, leave links to files alone.
+    Dirs,
+    /// Descend into linked directories and match links to files.
+    #[default]
+    Always,
+}
+
+/// Options controlling traversal and matching.
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub pattern: String,
+    pub replacement: Option<String>,
+    pub glob_patterns: Vec<String>,
+    pub base_dir: PathBuf,
+    pub regex: bool,
+    pub file_type: FileType,
+    pub recursive: bool,
+    pub case_sensitive: bool,
+    pub hidden: bool,
+    pub follow: Follow,
+    pub git_ignore: bool,
+    pub max_depth: Option<usize>,
+    pub min_depth: Option<usize>,
+    /// Bounds on the number of components of the path relative to
+    /// `base_dir`, independent of how the walker got there.
+    pub min_components: Option<usize>,
+    pub max_components: Option<usize>,
+    /// Only directories with this many direct entries, counting hidden
+    /// and ignored ones. Files are left out when set.
+    pub min_dir_entries: Option<usize>,
+    pub max_dir_entries: Option<usize>,
+    /// Also match the base directory itself. It sorts after everything
+    /// below it, so it is renamed last.
+    pub include_root: bool,
+    /// Keep at most this many matches per directory (the first ones in
+    /// rename order).
+    pub limit_per_dir: Option<usize>,
+    /// Only entries created in this range (requires creation times).
+    pub created_after: Option<SystemTime>,
+    pub created_before: Option<SystemTime>,
+    /// Only entries git hasn't seen change for this long, see
+    /// [`git::History`].
+    pub untouched_for: Option<Duration>,
+    /// Timezone and locale for date placeholders.
+    pub date_style: DateStyle,
+    /// Rules tried in order; the first matching one decides the new name.
+    /// When non-empty, used instead of `pattern` and `replacement`.
+    pub rules: Vec<Rule>,
+    /// Honor `exclude` lists from `.fnr.toml` files, see [`config`].
+    pub dir_configs: bool,
+    /// Read the names from an index covering `base_dir` instead of walking,
+    /// if one was built with the same settings, see [`index`].
+    pub index: bool,
+    /// Look inside git submodules and other nested repositories below
+    /// `base_dir`, see [`check::Submodules`].
+    pub cross_submodules: bool,
+    /// Only files holding text or only binaries, see [`sniff::sniff_content`].
+    pub content: Option<Content>,
+    /// Lookup table for `{N|map}` in replacements, see
+    /// [`matcher::load_map`].
+    pub map: Option<PathBuf>,
+    /// Replace in the case of each occurrence, see
+    /// [`matcher::match_case`].
+    pub preserve_case: bool,
+    /// Match literal patterns with up to this many typos, see
+    /// [`matcher::fuzzy_find`]. `Some(None)` allows
+    /// [`matcher::default_fuzziness`].
+    pub fuzzy: Option<Option<usize>>,
+    /// Match literal patterns by how they sound, see
+    /// [`matcher::phonetic_find`].
+    pub phonetic: bool,
+    /// Walk with this many threads, filtering as they go but reporting
+    /// the matches only once the whole tree is read. Pays off where listing a directory is a round trip,
+    /// see [`remote`]. Each thread holds one directory open while it reads
+    /// it, so this also bounds the directory handles of the walk.
+    pub walk_threads: Option<usize>,
+}
+
+impl Options {
+    /// The effective rule list.
+    pub fn rules(&self) -> Vec<Rule> {
+        if self.rules.is_empty() {
+            vec![Rule {
+                pattern: self.pattern.clone(),
+                replacement: self.replacement.clone(),
+            }]
+        } else {
+            self.rules.clone()
+        }
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            pattern: String::new(),
+            replacement: None,
+            glob_patterns: Vec::new(),
+            base_dir: PathBuf::from("."),
+            regex: false,
+            file_type: FileType::Both,
+            recursive: true,
+            case_sensitive: false,
+            hidden: false,
+            follow: Follow::Always,
+            git_ignore: true,
+            max_depth: None,
+            min_depth: None,
+            min_components: None,
+            min_dir_entries: None,
+            max_dir_entries: None,
+            max_components: None,
+            include_root: false,
+            limit_per_dir: None,
+            created_after: None,
+            created_before: None,
+            untouched_for: None,
+            date_style: DateStyle::default(),
+            rules: Vec::new(),
+            dir_configs: false,
+            index: false,
+            cross_submodules: true,
+            content: None,
+            map: None,
+            preserve_case: false,
+            fuzzy: None,
+            phonetic: false,
+            walk_threads: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Match {
+    pub path: PathBuf,
+    pub new_name: String,
+    pub is_dir: bool,
+    /// The pattern that matched, shared by all matches of a rule.
+    pub pattern: Arc<str>,
+    /// The replacement with placeholders filled in. Shared by all matches of
+    /// a rule unless it has placeholders.
+    pub replacement: Arc<str>,
+}
+
+impl Match {
+    /// A match that keeps its current name.
+    pub fn unchanged(path: &Path, is_dir: bool, pattern: impl Into<Arc<str>>) -> Self {
+        Match {
+            path: path.to_path_buf(),
+            new_name: file_name(path).to_string(),
+            is_dir,
+            pattern: pattern.into(),
+            replacement: "".into(),
+        }
+    }
+
+    /// Full path the entry will have after renaming.
+    pub fn new_path(&self) -> PathBuf {
+        let parent = self.path.parent().unwrap_or(Path::new("."));
+        parent.join(&self.new_name)
+    }
+}
+
+type Filter = Box<dyn Fn(&DirEntry) -> bool + Send + Sync>;
+
+/// An entry up for filtering, from the walker or from an index.
+struct Candidate<'a> {
+    path: &'a Path,
+    depth: usize,
+    is_dir: bool,
+    is_symlink: bool,
+    /// A FIFO, socket or device node.
+    is_special: bool,
+    entry: Option<&'a DirEntry>,
+}
+
+impl<'a> Candidate<'a> {
+    fn walked(entry: &'a DirEntry) -> Self {
+        Candidate {
+            path: entry.path(),
+            depth: entry.depth(),
+            // Without following, a link to a directory is not a directory
+            is_dir: entry.file_type().is_some_and(|t| t.is_dir()),
+            is_symlink: entry.path_is_symlink(),
+            is_special: entry.file_type().is_some_and(is_special),
+            entry: Some(entry),
+        }
+    }
+}
+
+/// An entry of a parallel walk, waiting for the walk to finish.
+enum Walked {
+    Admitted(bool, DirEntry),
+    Skipped(PathBuf, bool, Reason),
+}
+
+impl Walked {
+    fn path(&self) -> &Path {
+        match self {
+            Walked::Admitted(_, entry) => entry.path(),
+            Walked::Skipped(path, _, _) => path,
+        }
+    }
+}
+
+/// Collects matches for a set of [`Options`].
+///
+/// Custom predicates added with [`Finder::filter`] run after the glob and
+/// type filters; an entry is only reported if every predicate returns `true`.
+pub struct Finder {
+    options: Options,
+    filters: Vec<Filter>,
+    metadata: MetadataCache,
+    submodules: Option<Submodules>,
+    history: Option<History>,
+    special: AtomicUsize,
+}
+
+impl Finder {
+    pub fn new(options: Options) -> Self {
+        Finder {
+            submodules: (!options.cross_submodules).then(|| Submodules::new(&options.base_dir)),
+            history: options.untouched_for.map(|_| History::new(&options.base_dir)),
+            options,
+            filters: Vec::new(),
+            metadata: MetadataCache::new(),
+            special: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    /// Special files the walks so far passed over because the type filter
+    /// doesn't ask for them.
+    pub fn skipped_special(&self) -> usize {
+        self.special.load(Ordering::Relaxed)
+    }
+
+    /// Add a predicate every reported entry has to satisfy.
+    pub fn filter<F>(mut self, filter: F) -> Self
+    where
+        F: Fn(&DirEntry) -> bool + Send + Sync + 'static,
+    {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    /// Walk the tree and call `visit` with every entry that passes the glob,
+    /// type and custom filters, along with whether it is a directory.
+    pub fn walk<F>(&self, mut visit: F) -> Result<()>
+    where
+        F: FnMut(&DirEntry, bool),
+    {
+        self.walk_while(|entry, is_dir| {
+            visit(entry, is_dir);
+            true
+        })
+    }
+
+    /// Like [`Finder::walk`], but stops as soon as `visit` returns `false`.
+    pub fn walk_while<F>(&self, visit: F) -> Result<()>
+    where
+        F: FnMut(&DirEntry, bool) -> bool,
+    {
+        self.walk_filtered(visit, None::<fn(&Path, bool, Reason)>)
+    }
+
+    /// Like [`Finder::walk_while`], also calling `skip` with every entry
+    /// that was passed over and why. Entries the walker never sees (hidden
+    /// or ignored ones) are not reported.
+    pub fn walk_explain<F, S>(&self, visit: F, skip: S) -> Result<()>
+    where
+        F: FnMut(&DirEntry, bool) -> bool,
+        S: FnMut(&Path, bool, Reason),
+    {
+        self.walk_filtered(visit, Some(skip))
+    }
+
+    /// [`Finder::walk_explain`], reporting skipped entries only if there is
+    /// a `skip` to tell. A parallel walk holds on to them until it's done.
+    fn walk_filtered<F, S>(&self, mut visit: F, mut skip: Option<S>) -> Result<()>
+    where
+        F: FnMut(&DirEntry, bool) -> bool,
+        S: FnMut(&Path, bool, Reason),
+    {
+        let opts = &self.options;
+        let glob_set = build_glob_set(&opts.glob_patterns)?;
+        let dir_configs = opts.dir_configs.then(DirConfigs::new);
+
+        // Build walker with gitignore support
+        let mut walker_builder = WalkBuilder::new(&opts.base_dir);
+        walker_builder
+            .follow_links(opts.follow != Follow::Never)
+            .git_ignore(opts.git_ignore)
+            .hidden(!opts.hidden);
+
+        if !opts.recursive {
+            walker_builder.max_depth(Some(1));
+        } else if let Some(max_depth) = opts.max_depth {
+            walker_builder.max_depth(Some(max_depth));
+        }
+
+        let Some(threads) = opts.walk_threads else {
+            for result in walker_builder.build() {
+                let entry = match result {
+                    Ok(e) => e,
+                    Err(e) => {
+                        eprintln!("Warning: {}", e);
+                        continue;
+                    }
+                };
+                let candidate = Candidate::walked(&entry);
+                let is_dir = candidate.is_dir;
+                let admitted = self.admit(&candidate, &glob_set, dir_configs.as_ref(), &mut |path, is_dir, reason| {
+                    if let Some(skip) = &mut skip {
+                        skip(path, is_dir, reason);
+                    }
+                })?;
+                if admitted && !visit(&entry, is_dir) {
+                    break;
+                }
+            }
+            return Ok(());
+        };
+
+        // The threads filter as they go, so only what passes (and what was
+        // skipped, if anyone asks) waits for the walk to finish
+        let explain = skip.is_some();
+        let found = Mutex::new(Vec::new());
+        let failed = Mutex::new(None);
+        walker_builder.threads(threads).build_parallel().run(|| {
+            Box::new(|result| {
+                let entry = match result {
+                    Ok(e) => e,
+                    Err(e) => {
+                        eprintln!("Warning: {}", e);
+                        return ignore::WalkState::Continue;
+                    }
+                };
+                let mut walked = Vec::new();
+                let candidate = Candidate::walked(&entry);
+                let is_dir = candidate.is_dir;
+                let admitted = self.admit(&candidate, &glob_set, dir_configs.as_ref(), &mut |path, is_dir, reason| {
+                    if explain {
+                        walked.push(Walked::Skipped(path.to_path_buf(), is_dir, reason));
+                    }
+                });
+                match admitted {
+                    Ok(true) => walked.push(Walked::Admitted(is_dir, entry)),
+                    Ok(false) => {}
+                    Err(e) => {
+                        failed.lock().unwrap().get_or_insert(e);
+                        return ignore::WalkState::Quit;
+                    }
+                }
+                if !walked.is_empty() {
+                    found.lock().unwrap().append(&mut walked);
+                }
+                ignore::WalkState::Continue
+            })
+        });
+        if let Some(e) = failed.into_inner().unwrap() {
+            return Err(e);
+        }
+
+        // Parallel walks finish in any order; sorted paths keep parents
+        // ahead of their entries, like the sequential walk
+        let mut found = found.into_inner().unwrap();
+        found.sort_by(|a, b| a.path().cmp(b.path()));
+        for walked in found {
+            match walked {
+                Walked::Admitted(is_dir, entry) => {
+                    if !visit(&entry, is_dir) {
+                        break;
+                    }
+                }
+                Walked::Skipped(path, is_dir, reason) => {
+                    if let Some(skip) = &mut skip {
+                        skip(&path, is_dir, reason);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `candidate` passes the filters, reporting why not to `skip`.
+    fn admit<S>(
+        &self,
+        candidate: &Candidate,
+        glob_set: &GlobSet,
+        dir_configs: Option<&DirConfigs>,
+        skip: &mut S,
+    ) -> Result<bool>
+    where
+        S: FnMut(&Path, bool, Reason),
+    {
+        let opts = &self.options;
+        let (path, is_dir) = (candidate.path, candidate.is_dir);
+
+        if opts.min_depth.is_some_and(|min| candidate.depth < min) {
+            skip(path, is_dir, Reason::Depth);
+            return Ok(false);
+        }
+
+        // The base directory is only a match on request, and only if it
+        // has a name to match against (not `.` or `/`)
+        if candidate.depth == 0 && is_dir && !(opts.include_root && path.file_name().is_some()) {
+            return Ok(false);
+        }
+
+        if opts.min_components.is_some() || opts.max_components.is_some() {
+            let relative = path.strip_prefix(&opts.base_dir).unwrap_or(path);
+            let count = relative.components().count();
+            if opts.min_components.is_some_and(|min| count < min)
+                || opts.max_components.is_some_and(|max| count > max)
+            {
+                skip(path, is_dir, Reason::Components);
+                return Ok(false);
+            }
+        }
+
+        // Check if path matches any glob pattern
+        if !glob_set.is_match(path) {
+            skip(path, is_dir, Reason::Glob);
+            return Ok(false);
+        }
+
+        if opts.follow == Follow::Dirs && candidate.is_symlink && !is_dir {
+            skip(path, is_dir, Reason::Symlink);
+            return Ok(false);
+        }
+
+        // Filter by type. Special files are counted, they are easily
+        // forgotten and only renamed on request
+        if candidate.is_special != (opts.file_type == FileType::Special) {
+            let reason = if candidate.is_special {
+                self.special.fetch_add(1, Ordering::Relaxed);
+                Reason::Special
+            } else {
+                Reason::FileType
+            };
+            skip(path, is_dir, reason);
+            return Ok(false);
+        }
+        if matches!((&opts.file_type, is_dir), (FileType::File, true) | (FileType::Dir, false)) {
+            skip(path, is_dir, Reason::FileType);
+            return Ok(false);
+        }
+
+        if opts.created_after.is_some() || opts.created_before.is_some() {
+            let created = self.metadata.created(path)?;
+            if opts.created_after.is_some_and(|after| created < after)
+                || opts.created_before.is_some_and(|before| created >= before)
+            {
+                skip(path, is_dir, Reason::Created);
+                return Ok(false);
+            }
+        }
+
+        if let (Some(history), Some(period)) = (&self.history, opts.untouched_for) {
+            let untouched = history
+                .last_change(path)?
+                .is_some_and(|changed| changed.elapsed().unwrap_or_default() >= period);
+            if !untouched {
+                skip(path, is_dir, Reason::Touched);
+                return Ok(false);
+            }
+        }
+
+        // Custom filters need a walker entry, see `indexed`
+        if let Some(entry) = candidate.entry {
+            if !self.filters.iter().all(|filter| filter(entry)) {
+                skip(path, is_dir, Reason::Filter);
+                return Ok(false);
+            }
+        }
+
+        if let Some(submodules) = &self.submodules {
+            if submodules.containing(path).is_some() {
+                skip(path, is_dir, Reason::Submodule);
+                return Ok(false);
+            }
+        }
+
+        if let Some(configs) = dir_configs {
+            if configs.is_excluded(path)? {
+                skip(path, is_dir, Reason::Excluded);
+                return Ok(false);
+            }
+        }
+
+        if opts.min_dir_entries.is_some() || opts.max_dir_entries.is_some() {
+            let fits = is_dir && {
+                let count = fs::read_dir(path)
+                    .with_context(|| format!("Failed to count the entries of {}", path.display()))?
+                    .count();
+                opts.min_dir_entries.is_none_or(|min| count >= min)
+                    && opts.max_dir_entries.is_none_or(|max| count <= max)
+            };
+            if !fits {
+                skip(path, is_dir, Reason::Entries);
+                return Ok(false);
+            }
+        }
+
+        // Reads the start of every file, so it goes last. Unreadable files
+        // can't be told either way.
+        if let Some(wanted) = opts.content {
+            if is_dir || candidate.is_special || sniff::sniff_content(path).ok() != Some(wanted) {
+                skip(path, is_dir, Reason::Content);
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// The index to search instead of walking, if enabled and one covers
+    /// the base directory. Custom filters and the base directory itself
+    /// need the walker.
+    fn indexed(&self) -> Result<Option<Index>> {
+        let opts = &self.options;
+        if !opts.index || !self.filters.is_empty() || opts.include_root {
+            return Ok(None);
+        }
+        let settings = IndexSettings {
+            hidden: opts.hidden,
+            git_ignore: opts.git_ignore,
+            follow_links: opts.follow != Follow::Never,
+        };
+        let Some(mut index) = Index::find(&opts.base_dir, settings)? else {
+            return Ok(None);
+        };
+        if index.refresh()? {
+            index.save()?;
+        }
+        Ok(Some(index))
+    }
+
+    /// Like [`Finder::walk_filtered`], but reads the names from the index
+    /// when there is one to use.
+    fn walk_paths<F, S>(&self, mut visit: F, mut skip: Option<S>) -> Result<()>
+    where
+        F: FnMut(&Path, bool) -> bool,
+        S: FnMut(&Path, bool, Reason),
+    {
+        let Some(index) = self.indexed()? else {
+            return self.walk_filtered(|entry, is_dir| visit(entry.path(), is_dir), skip);
+        };
+        let opts = &self.options;
+        let glob_set = build_glob_set(&opts.glob_patterns)?;
+        let dir_configs = opts.dir_configs.then(DirConfigs::new);
+        let max_depth = if opts.recursive { opts.max_depth } else { Some(1) };
+
+        for indexed in index.entries_below(&opts.base_dir, max_depth)? {
+            let path = opts.base_dir.join(&indexed.path);
+            let candidate = Candidate {
+                path: &path,
+                depth: indexed.path.components().count(),
+                is_dir: indexed.is_dir,
+                is_symlink: indexed.is_symlink,
+                is_special: indexed.is_special,
+                entry: None,
+            };
+            let admitted = self.admit(&candidate, &glob_set, dir_configs.as_ref(), &mut |path, is_dir, reason| {
+                if let Some(skip) = &mut skip {
+                    skip(path, is_dir, reason);
+                }
+            })?;
+            if admitted && !visit(&path, indexed.is_dir) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// All entries passing the filters, ignoring the search pattern, in
+    /// rename order. Useful to match many patterns against one traversal.
+    pub fn entries(&self) -> Result<Vec<Match>> {
+        let mut entries = Vec::new();
+        self.walk(|entry, is_dir| {
+            entries.push(Match::unchanged(entry.path(), is_dir, ""));
+        })?;
+        sort_matches(&mut entries);
+        Ok(entries)
+    }
+
+    /// Walk the tree and return all matches, files first and directories
+    /// deepest first, so they can be renamed in order.
+    pub fn find(&self) -> Result<Vec<Match>> {
+        Ok(self.find_inspect(|_| true)?.unwrap_or_default())
+    }
+
+    /// Like [`Finder::find`], but calls `inspect` with the matches found so
+    /// far (in discovery order, new names not computed yet) after every new
+    /// match. Returns `None` if `inspect` stopped the search by returning
+    /// `false`.
+    pub fn find_inspect<F>(&self, inspect: F) -> Result<Option<Vec<Match>>>
+    where
+        F: FnMut(&[Match]) -> bool,
+    {
+        self.find_with(inspect, None::<fn(Record)>)
+    }
+
+    /// Like [`Finder::find`], also returning a [`Record`] for every entry
+    /// that was passed over, with the reason.
+    pub fn find_explained(&self) -> Result<(Vec<Match>, Vec<Record>)> {
+        let mut skipped = Vec::new();
+        let matches = self.find_with(|_| true, Some(|record| skipped.push(record)))?;
+        Ok((matches.unwrap_or_default(), skipped))
+    }
+
+    /// Walk the tree and hand the matches to `apply` one directory at a
+    /// time, with their new names, as soon as the walk is done with that
+    /// directory. Renaming them then can't disturb the walk, and only the
+    /// matches of the directories the walk is in are kept in memory.
+    /// Counters and `limit_per_dir` apply per directory.
+    pub fn stream<F>(&self, mut apply: F) -> Result<()>
+    where
+        F: FnMut(Vec<Match>) -> Result<()>,
+    {
+        let rules = compile_rules(&self.options)?;
+        let mut flush = |mut batch: Vec<Match>| -> Result<()> {
+            sort_matches(&mut batch);
+            if let Some(limit) = self.options.limit_per_dir {
+                batch.truncate(limit);
+            }
+            compute_new_names_cached(&mut batch, &rules, &self.options.date_style, &self.options.base_dir, &self.metadata)?;
+            apply(batch)
+        };
+
+        // Directories with pending matches, each one inside the one below
+        let mut open: Vec<(PathBuf, Vec<Match>)> = Vec::new();
+        let mut error = None;
+        self.walk_while(|entry, is_dir| {
+            let path = entry.path();
+            let name = file_name(path);
+            let Some((matcher, _)) = rules.iter().find(|(matcher, _)| matcher.is_match(name)) else {
+                return true;
+            };
+            // The walk is depth first: directories this entry is not inside
+            // of are done
+            while open.last().is_some_and(|(dir, _)| !path.starts_with(dir)) {
+                let (_, batch) = open.pop().unwrap();
+                if let Err(e) = flush(batch) {
+                    error = Some(e);
+                    return false;
+                }
+            }
+            let parent = path.parent().unwrap_or(Path::new(""));
+            let m = Match::unchanged(path, is_dir, matcher.shared_pattern());
+            match open.last_mut() {
+                Some((dir, batch)) if dir == parent => batch.push(m),
+                _ => open.push((parent.to_path_buf(), vec![m])),
+            }
+            true
+        })?;
+        if let Some(e) = error {
+            return Err(e);
+        }
+        while let Some((_, batch)) = open.pop() {
+            flush(batch)?;
+        }
+        Ok(())
+    }
+
+    fn find_with<F, S>(&self, mut inspect: F, skip: Option<S>) -> Result<Option<Vec<Match>>>
+    where
+        F: FnMut(&[Match]) -> bool,
+        S: FnMut(Record),
+    {
+        let rules = compile_rules(&self.options)?;
+        let explain = skip.is_some();
+        // Called from both walk callbacks
+        let skip = RefCell::new(skip);
+        let report = |record| {
+            if let Some(skip) = skip.borrow_mut().as_mut() {
+                skip(record);
+            }
+        };
+
+        let mut matches = Vec::new();
+        let mut stopped = false;
+        self.walk_paths(
+            |path, is_dir| {
+                let name = file_name(path);
+                if let Some((matcher, _)) = rules.iter().find(|(matcher, _)| matcher.is_match(name)) {
+                    matches.push(Match::unchanged(path, is_dir, matcher.shared_pattern()));
+                    stopped = !inspect(&matches);
+                } else {
+                    report(Record::skipped(path, is_dir, Reason::NoMatch));
+                }
+                !stopped
+            },
+            explain.then_some(|path: &Path, is_dir, reason| report(Record::skipped(path, is_dir, reason))),
+        )?;
+        if stopped {
+            return Ok(None);
+        }
+
+        sort_matches(&mut matches);
+        if let Some(limit) = self.options.limit_per_dir {
+            let mut per_dir: HashMap<PathBuf, usize> = HashMap::new();
+            matches.retain(|m| {
+                let count = per_dir.entry(m.path.parent().unwrap_or(Path::new("")).to_path_buf()).or_default();
+                *count += 1;
+                if *count > limit {
+                    report(Record::skipped(&m.path, m.is_dir, Reason::LimitPerDir));
+                }
+                *count <= limit
+            });
+        }
+        compute_new_names_cached(
+            &mut matches,
+            &rules,
+            &self.options.date_style,
+            &self.options.base_dir,
+            &self.metadata,
+        )?;
+        Ok(Some(matches))
+    }
+}
+
+/// Compile the effective rules of `opts`.
+pub fn compile_rules(opts: &Options) -> Result<Vec<(NameMatcher, Option<String>)>> {
+    let transforms = match &opts.map {
+        Some(path) => Transforms::with_map(matcher::load_map(path)?),
+        None => Transforms::default(),
+    };
+    if opts.map.is_none() && opts.rules().iter().any(|rule| rule.replacement.as_ref().is_some_and(|r| r.contains("|map"))) {
+        bail!("{{N|map}} or {{placeholder|map}} in a replacement needs a lookup table from --map FILE");
+    }
+    opts.rules()
+        .into_iter()
+        .map(|rule| {
+            if opts.phonetic && (opts.regex || rule.pattern.contains('*')) {
+                bail!("--phonetic only works with literal patterns, not '{}'", rule.pattern);
+            }
+            let fuzzy = match opts.fuzzy {
+                Some(_) if opts.regex || rule.pattern.contains('*') => {
+                    bail!("--fuzzy only works with literal patterns, not '{}'", rule.pattern)
+                }
+                Some(_) if rule.pattern.is_empty() => None,
+                Some(max_edits) => Some(max_edits.unwrap_or_else(|| matcher::default_fuzziness(&rule.pattern))),
+                None => None,
+            };
+            let matcher = NameMatcher::new(&rule.pattern, opts.regex, opts.case_sensitive)?
+                .with_transforms(transforms.clone())
+                .with_preserve_case(opts.preserve_case)
+                .with_fuzzy(fuzzy)
+                .with_phonetic(opts.phonetic && !rule.pattern.is_empty());
+            Ok((matcher, rule.replacement))
+        })
+        .collect()
+}
+
+/// Build glob set from patterns, matching everything if there are none.
+pub(crate) fn build_glob_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut glob_builder = GlobSetBuilder::new();
+    if patterns.is_empty() {
+        glob_builder.add(Glob::new("**/*")?);
+    }
+    for pattern in patterns {
+        glob_builder.add(Glob::new(pattern)?);
+    }
+    Ok(glob_builder.build()?)
+}
+
+pub(crate) fn file_name(path: &Path) -> &str {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("")
+}
+
+/// Whether an entry of type `file_type` is a FIFO, socket or device node.
+pub(crate) fn is_special(file_type: fs::FileType) -> bool {
+    !file_type.is_file() && !file_type.is_dir() && !file_type.is_symlink()
+}
+
+/// Open `path` for reading if it is a regular file. Opening a FIFO waits
+/// for a writer and a device can be read forever, so anything else is
+/// refused before it is opened.
+pub(crate) fn open_regular(path: &Path) -> io::Result<fs::File> {
+    if is_special(fs::metadata(path)?.file_type()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a regular file", path.display()),
+        ));
+    }
+    fs::File::open(path)
+}
+
+/// Sort matches: files first, then directories (by depth, deepest first)
+pub fn sort_matches(matches: &mut [Match]) {
+    matches.sort_by(|a, b| {
+        match (a.is_dir, b.is_dir) {
+            (false, true) => std::cmp::Ordering::Less,  // Files before dirs
+            (true, false) => std::cmp::Ordering::Greater, // Dirs after files
+            _ => {
+                // Same type: sort by depth (deepest first for dirs, shallowest first for files)
+                let a_depth = a.path.components().count();
+                let b_depth = b.path.components().count();
+                let by_depth = if a.is_dir {
+                    b_depth.cmp(&a_depth) // Deepest dirs first
+                } else {
+                    a_depth.cmp(&b_depth) // Shallowest files first
+                };
+                by_depth.then_with(|| a.path.cmp(&b.path))
+            }
+        }
+    });
+}
+
+/// Compute new names for sorted matches using the first rule matching each
+/// name. Placeholders in replacements are rendered per match, so this has to
+/// run on the final match list. Rules without a replacement keep the name.
+pub fn compute_new_names(matches: &mut [Match], rules: &[(NameMatcher, Option<String>)]) -> Result<()> {
+    compute_new_names_with(matches, rules, &DateStyle::default())
+}
+
+/// [`compute_new_names`] with date placeholders rendered in `date_style`.
+pub fn compute_new_names_with(
+    matches: &mut [Match],
+    rules: &[(NameMatcher, Option<String>)],
+    date_style: &DateStyle,
+) -> Result<()> {
+    compute_new_names_cached(matches, rules, date_style, Path::new("."), &MetadataCache::new())
+}
+
+/// [`compute_new_names_with`] with `{relpath}` relative to `base_dir`,
+/// looking up file metadata in `metadata`, for callers computing names for
+/// the same entries repeatedly.
+pub fn compute_new_names_cached(
+    matches: &mut [Match],
+    rules: &[(NameMatcher, Option<String>)],
+    date_style: &DateStyle,
+    base_dir: &Path,
+    metadata: &MetadataCache,
+) -> Result<()> {
+    let templates = rules
+        .iter()
+        .map(|(_, replacement)| replacement.as_deref().map(template::Template::parse).transpose())
+        .collect::<Result<Vec<_>>>()?;
+    let replacements: Vec<Option<Arc<str>>> =
+        rules.iter().map(|(_, replacement)| replacement.as_deref().map(Arc::from)).collect();
+
+    let total = matches.len();
+    for (index, m) in matches.iter_mut().enumerate() {
+        let name = file_name(&m.path);
+        let Some(rule) = rules.iter().position(|(matcher, _)| matcher.is_match(name)) else {
+            continue;
+        };
+        let matcher = &rules[rule].0;
+        let (Some(replacement), Some(template)) = (&replacements[rule], &templates[rule]) else {
+            continue;
+        };
+        let (rendered, values): (Arc<str>, _) = if template.has_placeholders() {
+            if matcher::has_value_markers(name) {
+                bail!("Cannot fill in placeholders for {}, its name contains Unicode noncharacters", m.path.display());
+            }
+            let (text, values) = template.render_values(&template::Context {
+                index,
+                total,
+                path: &m.path,
+                is_dir: m.is_dir,
+                base_dir,
+                date_style,
+                metadata,
+                transforms: matcher.transforms(),
+            })?;
+            (text.into(), values)
+        } else {
+            (replacement.clone(), Vec::new())
+        };
+        if let Some(new_name) = matcher.replace_with_values(name, &rendered, &values) {
+            m.new_name = new_name;
+        }
+        m.pattern = matcher.shared_pattern();
+        m.replacement = if values.is_empty() {
+            rendered
+        } else {
+            matcher::fill_values(&rendered, &values).into()
+        };
+    }
+    Ok(())
+}
+
+/// Which of `rules` gave `m` its new name, and what the pattern captured.
+/// `None` for matches the rules didn't rename.
+pub fn derivation(m: &Match, rules: &[(NameMatcher, Option<String>)]) -> Option<Derivation> {
+    let name = file_name(&m.path);
+    let (index, (matcher, replacement)) =
+        rules.iter().enumerate().find(|(_, (matcher, _))| matcher.is_match(name))?;
+    // Sidecars and the like got their names elsewhere
+    if replacement.is_none() || *m.pattern != *matcher.pattern() {
+        return None;
+    }
+    let (matched, captures) = matcher.explain(name)?;
+    Some(Derivation {
+        rule: index + 1,
+        pattern: matcher.pattern().to_string(),
+        replacement: m.replacement.to_string(),
+        matched,
+        captures,
+    })
+}
+
+/// Convenience wrapper for [`Finder::find`] without custom filters.
+pub fn find_matches(options: &Options) -> Result<Vec<Match>> {
+    Finder::new(options.clone()).find()
+}
+
+/// Rename a single match on disk and return its new path. Files that
+/// cannot be renamed across devices are copied and the original removed.
+pub fn rename(m: &Match) -> Result<PathBuf> {
+    transfer::move_match(m, false, &transfer::Retry::default(), false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Files below a fresh directory in the temp directory, removed again
+    /// when dropped.
+    struct Tree(PathBuf);
+
+    impl Tree {
+        fn new(name: &str, files: &[&str]) -> Tree {
+            let root = std::env::temp_dir().join(format!("fnr-{}-{}", name, std::process::id()));
+            for file in files {
+                let path = root.join(file);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, "").unwrap();
+            }
+            Tree(root)
+        }
+
+        /// The files `options` find in the tree, relative to it.
+        fn found(&self, options: Options) -> Vec<String> {
+            let options = Options {
+                base_dir: self.0.clone(),
+                file_type: FileType::File,
+                ..options
+            };
+            let mut found: Vec<String> = Finder::new(options)
+                .entries()
+                .unwrap()
+                .into_iter()
+                .map(|m| m.path.strip_prefix(&self.0).unwrap().to_string_lossy().replace('\\', "/"))
+                .collect();
+            found.sort();
+            found
+        }
+    }
+
+    impl Drop for Tree {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn hidden_entries_only_with_hidden() {
+        let tree = Tree::new("hidden", &["a.txt", ".b.txt", ".dir/c.txt"]);
+        assert_eq!(tree.found(Options::default()), ["a.txt"]);
+        let hidden = Options {
+            hidden: true,
+            ..Options::default()
+        };
+        assert_eq!(tree.found(hidden), [".b.txt", ".dir/c.txt", "a.txt"]);
+    }
+
+    #[test]
+    fn min_depth_leaves_out_shallower_entries() {
+        let tree = Tree::new("min-depth", &["a.txt", "sub/b.txt", "sub/deep/c.txt"]);
+        let deeper = |min_depth| Options {
+            min_depth: Some(min_depth),
+            ..Options::default()
+        };
+        assert_eq!(tree.found(deeper(1)), ["a.txt", "sub/b.txt", "sub/deep/c.txt"]);
+        assert_eq!(tree.found(deeper(2)), ["sub/b.txt", "sub/deep/c.txt"]);
+        assert_eq!(tree.found(deeper(3)), ["sub/deep/c.txt"]);
+    }
+}
@@ -1,29 +1,75 @@
-use anyhow::{Context, Result};
-use clap::Parser;
+mod playground;
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{Parser, Subcommand};
 use colored::*;
 use crossterm::{
     event::{self, Event, KeyCode, KeyModifiers},
     terminal::{disable_raw_mode, enable_raw_mode},
 };
-use globset::{Glob, GlobSetBuilder};
-use ignore::WalkBuilder;
-use regex::Regex;
-use std::fs;
-use std::io::{self, Write};
+use fnr_tool::anonymize::anonymize;
+use fnr_tool::check::{
+    annotate, check_collisions, check_cycles, check_lengths, check_policies, check_protected, check_submodules, colliding, collisions,
+    cycles, strip_trailing, Issue, Severity, Submodules, TargetFs, TrailingPolicy,
+};
+use fnr_tool::config::{DirConfigs, UserConfig};
+use fnr_tool::conflict::{number_duplicates, resolve, DuplicateScope, OnConflict, Resolution};
+use fnr_tool::hooks::Hooks;
+use fnr_tool::index::{Index, IndexSettings};
+use fnr_tool::journal::{BatchWriter, Intent, Journal};
+use fnr_tool::keys::Keys;
+use chrono::{Locale, NaiveDateTime};
+use chrono_tz::Tz;
+use fnr_tool::dates::{parse_age, parse_date, parse_locale, parse_timezone, DateStyle};
+use fnr_tool::executor::{parse_throttle, rename_parallel, HandleLimit, Throttle};
+use fnr_tool::plan::{diff, Plan, PlanChange, PlanEntry, Recorder};
+use fnr_tool::references::{self, Edit, LinkFormat, Relocation};
+use fnr_tool::remote::{network_filesystem, Probe, REMOTE_JOBS, REMOTE_THREADS};
+use fnr_tool::report::{ConflictReport, Derivation, Reason, Record, ReportedIssue, Status};
+use fnr_tool::script::{write_script, ScriptKind};
+use fnr_tool::session::{Answer, Session};
+use fnr_tool::sidecar::{add_sidecars, group_by_stem, parse_sidecars, SidecarSpec};
+use fnr_tool::shorten::{shorten_names, Shorten, MIN_NAME_LEN};
+use fnr_tool::encoding::{fix_encoding, Codepage};
+use fnr_tool::sniff::{fix_extensions, Content};
+use fnr_tool::unicode::fix_unicode;
+use fnr_tool::staging::{self, Staged, Staging};
+use fnr_tool::theme::{Style, Theme};
+use fnr_tool::transfer::{copy_match, move_match, Retry, HANDLES};
+use fnr_tool::matcher::{looks_like_regex, parse_rules};
+use fnr_tool::metadata::MetadataCache;
+use fnr_tool::owner;
+use fnr_tool::{
+    compile_rules, compute_new_names_cached, derivation, sort_matches, FileType, Finder, Follow, Match,
+    NameMatcher, Options, Rule,
+};
+use playground::Mode;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
 use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 #[command(
     author,
     version,
     name = "fnr",
     about = "Fast file and directory name search and rename tool",
     long_about = "A high-performance tool for searching and batch renaming files and directories \
-                   with regex support, interactive confirmation, and safety features."
+                   with regex support, interactive confirmation, and safety features.",
+    args_conflicts_with_subcommands = true
 )]
 struct Cli {
-    #[arg(help = "Pattern to search for (or old pattern for rename)")]
-    pattern: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(
+        help = "Pattern to search for (or old pattern for rename); empty matches every name"
+    )]
+    pattern: Option<String>,
 
     #[arg(help = "New pattern for rename (if provided, enables rename mode)")]
     replacement: Option<String>,
@@ -48,6 +94,29 @@ struct Cli {
     )]
     regex: bool,
 
+    #[arg(
+        long = "fuzzy",
+        value_name = "EDITS",
+        num_args = 0..=1,
+        require_equals = true,
+        conflicts_with = "regex",
+        help = "Also match misspellings, up to EDITS typos away (default: one per four characters of the pattern)"
+    )]
+    fuzzy: Option<Option<usize>>,
+
+    #[arg(
+        long = "phonetic",
+        conflicts_with_all = ["regex", "fuzzy"],
+        help = "Match words that sound like the pattern (Soundex), like Smyth for Smith"
+    )]
+    phonetic: bool,
+
+    #[arg(
+        long = "no-hints",
+        help = "Don't point out patterns that look like a regex without --regex, or network mounts without --remote"
+    )]
+    no_hints: bool,
+
     #[arg(
         short = 't',
         long = "type",
@@ -69,6 +138,50 @@ struct Cli {
     )]
     no_interactive: bool,
 
+    #[arg(
+        short = 'j',
+        long = "jobs",
+        help = "Number of parallel rename workers (with --no-interactive) [default: 1, 8 with --remote]"
+    )]
+    jobs: Option<usize>,
+
+    #[arg(
+        long = "remote",
+        help = "Tune for network filesystems (NFS, SMB, sshfs): parallel walk, listings instead of stats, more --jobs"
+    )]
+    remote: bool,
+
+    #[arg(
+        long = "throttle",
+        value_name = "RATE",
+        value_parser = parse_throttle,
+        help = "At most RATE renames per second across all workers, or a preset: gentle (100), nice (20), idle (5)"
+    )]
+    throttle: Option<f64>,
+
+    #[arg(
+        long = "max-open",
+        value_name = "N",
+        default_value_t = 64,
+        help = "Keep at most N files open at once across all workers, and at most N directories read at once by the --remote walk"
+    )]
+    max_open: usize,
+
+    #[arg(
+        long = "confirm-every",
+        value_name = "N",
+        conflicts_with = "no_interactive",
+        help = "Show N matches at a time and confirm each chunk at once"
+    )]
+    confirm_every: Option<usize>,
+
+    #[arg(
+        long = "session",
+        value_name = "FILE",
+        help = "Remember answers and the playground selection in FILE, and pick up from there next time"
+    )]
+    session: Option<PathBuf>,
+
     #[arg(
         long = "no-recursive",
         help = "Don't search subdirectories"
@@ -81,6 +194,13 @@ struct Cli {
     )]
     case_sensitive: bool,
 
+    #[arg(
+        long = "preserve-case",
+        conflicts_with = "case_sensitive",
+        help = "Replace every occurrence in its own case: color -> colour also makes Color -> Colour and COLOR -> COLOUR"
+    )]
+    preserve_case: bool,
+
     #[arg(
         long = "hidden",
         help = "Include hidden files and directories"
@@ -94,9 +214,14 @@ struct Cli {
     no_color: bool,
 
     #[arg(
-        long = "no-symlink",
-        help = "Disable symbolic link follow"
+        long = "follow",
+        value_enum,
+        default_value = "always",
+        help = "Which symbolic links to follow: never, dirs (traverse linked directories only) or always"
     )]
+    follow: Follow,
+
+    #[arg(long = "no-symlink", hide = true, conflicts_with = "follow", help = "Same as --follow never")]
     no_symlink: bool,
 
     #[arg(
@@ -113,402 +238,2702 @@ struct Cli {
 
     #[arg(
         long = "min-depth",
-        help = "Minimum depth to search"
+        help = "Minimum depth to search (entries of the base directory are at depth 1)"
     )]
     min_depth: Option<usize>,
-}
 
-#[derive(clap::ValueEnum, Clone, Debug)]
-enum FileType {
-    File,
-    Dir,
-    Both,
-}
+    #[arg(
+        long = "components",
+        value_name = "N",
+        conflicts_with_all = ["min_components", "max_components"],
+        help = "Only entries whose path below the base directory has exactly N components"
+    )]
+    components: Option<usize>,
 
-#[derive(Debug)]
-enum ConfirmResult {
-    Yes,
-    No,
-    All,
-    Quit,
-}
+    #[arg(
+        long = "dir-min-entries",
+        value_name = "N",
+        help = "Only directories with at least N direct entries (files are left out)"
+    )]
+    dir_min_entries: Option<usize>,
 
-#[derive(Debug)]
-struct Match {
-    path: PathBuf,
-    new_name: String,
-    is_dir: bool,
-    pattern: String,
-    replacement: String,
-}
+    #[arg(
+        long = "dir-max-entries",
+        value_name = "N",
+        help = "Only directories with at most N direct entries (files are left out)"
+    )]
+    dir_max_entries: Option<usize>,
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
+    #[arg(
+        long = "min-components",
+        value_name = "N",
+        help = "Only entries whose path below the base directory has at least N components"
+    )]
+    min_components: Option<usize>,
 
-    if let Some(replacement) = &cli.replacement {
-        // Rename mode
-        rename_mode(&cli, replacement)
-    } else {
-        // Search mode
-        search_mode(&cli)
-    }
-}
+    #[arg(
+        long = "max-components",
+        value_name = "N",
+        help = "Only entries whose path below the base directory has at most N components"
+    )]
+    max_components: Option<usize>,
 
-fn search_mode(cli: &Cli) -> Result<()> {
-    let matches = find_matches(cli, None)?;
-    
-    for m in matches {
-        let type_indicator = if m.is_dir { "d" } else { "f" };
-        let path_str = m.path.display().to_string();
-        
-        if cli.no_color {
-            println!("[{}] {}", type_indicator, path_str);
-        } else {
-            let colored_type = if m.is_dir {
-                type_indicator.blue().bold()
-            } else {
-                type_indicator.green().bold()
-            };
-            println!("[{}] {}", colored_type, path_str.white());
-        }
-    }
-    
-    Ok(())
-}
+    #[arg(
+        long = "target-fs",
+        value_enum,
+        help = "Check new names against the rules of another filesystem"
+    )]
+    target_fs: Option<TargetFs>,
 
-fn rename_mode(cli: &Cli, replacement: &str) -> Result<()> {
-    let matches = find_matches(cli, Some(replacement))?;
-    
-    if matches.is_empty() {
-        println!("No matches found.");
-        return Ok(());
-    }
+    #[arg(
+        long = "trailing",
+        value_enum,
+        default_value = "warn",
+        help = "How to handle new names ending in spaces or dots"
+    )]
+    trailing: TrailingPolicy,
 
-    if cli.dry_run {
-        let header = if cli.no_color {
-            "Dry run - showing what would be renamed:"
-        } else {
-            &"Dry run - showing what would be renamed:".yellow().to_string()
-        };
-        println!("{}", header);
-        
-        for m in matches {
-            if cli.no_color {
-                println!("    {}", m.path.display());
-                println!(" -> {}", m.new_name);
-            } else {
-                let old_filename = m.path.file_name().unwrap().to_str().unwrap();
-                let parent_path = if let Some(parent) = m.path.parent() {
-                    format!("{}/", parent.display())
-                } else {
-                    String::new()
-                };
-                
-                println!("    {}{}", 
-                    parent_path.white(),
-                    highlight_pattern(old_filename, &cli.pattern, cli.no_color)
-                );
-                println!(" -> {}{}", 
-                    parent_path.white(),
-                    highlight_replacement(&m.new_name, old_filename, &cli.pattern, replacement, cli.no_color)
-                );
-            }
-        }
-        return Ok(());
-    }
+    #[arg(
+        long = "apply-plan",
+        value_name = "FILE",
+        conflicts_with_all = ["replacement", "jobs"],
+        help = "Apply the renames listed in a plan file"
+    )]
+    apply_plan: Option<PathBuf>,
 
-    if !cli.no_interactive {
-        let mut apply_all = false;
-        for m in matches {
-            if !apply_all {
-                match confirm_rename(&m, cli.no_color)? {
-                    ConfirmResult::Yes => {},
-                    ConfirmResult::No => continue,
-                    ConfirmResult::All => apply_all = true,
-                    ConfirmResult::Quit => return Ok(()),
-                }
-            }
-            perform_rename(&m, cli.no_color)?;
-        }
-    } else {
-        for m in matches {
-            perform_rename(&m, cli.no_color)?;
-        }
-    }
+    #[arg(
+        long = "only",
+        value_name = "GLOB",
+        requires = "apply_plan",
+        help = "Only apply plan entries whose path matches GLOB (repeatable)"
+    )]
+    only: Vec<String>,
 
-    Ok(())
-}
+    #[arg(
+        long = "skip",
+        value_name = "N",
+        default_value_t = 0,
+        requires = "apply_plan",
+        help = "Skip the first N plan entries"
+    )]
+    skip: usize,
 
-fn find_matches(cli: &Cli, replacement: Option<&str>) -> Result<Vec<Match>> {
-    let mut matches = Vec::new();
-    
-    let regex = if cli.regex {
-        Some(build_regex(&cli.pattern, cli.case_sensitive)?)
-    } else {
-        None
-    };
+    #[arg(
+        long = "take",
+        value_name = "N",
+        requires = "apply_plan",
+        help = "Apply at most N plan entries"
+    )]
+    take: Option<usize>,
 
-    // Build glob set from patterns
-    let mut glob_builder = GlobSetBuilder::new();
-    let patterns = if cli.glob_patterns.is_empty() {
-        vec!["**/*".to_string()]
-    } else {
-        cli.glob_patterns.clone()
-    };
-    
-    for pattern in &patterns {
-        glob_builder.add(Glob::new(pattern)?);
-    }
-    let glob_set = glob_builder.build()?;
+    #[arg(
+        long = "emit-inverse",
+        value_name = "FILE",
+        help = "Write a plan file undoing the applied renames"
+    )]
+    emit_inverse: Option<PathBuf>,
 
-    // Build walker with gitignore support
-    let mut walker_builder = WalkBuilder::new(&cli.base_dir);
-    walker_builder
-        .follow_links(!cli.no_symlink)
-        .git_ignore(!cli.no_skip_gitignore)
-        .hidden(cli.hidden);
-    
-    if cli.no_recursive {
-        walker_builder.max_depth(Some(1));
-    } else if let Some(max_depth) = cli.max_depth {
-        walker_builder.max_depth(Some(max_depth));
-    }
-    
-    let walker = walker_builder.build();
+    #[arg(
+        long = "emit-script",
+        value_enum,
+        value_name = "SHELL",
+        conflicts_with_all = ["emit_inverse", "copy", "anonymize", "archives", "stream", "format"],
+        help = "Write a sh, bat or powershell script performing the renames instead of renaming (to stdout or --output)"
+    )]
+    emit_script: Option<ScriptKind>,
 
-    for result in walker {
-        let entry = match result {
-            Ok(e) => e,
-            Err(e) => {
-                eprintln!("Warning: {}", e);
-                continue;
-            }
-        };
-        
-        let path = entry.path();
-        
-        // Check if path matches any glob pattern
-        if !glob_set.is_match(path) {
-            continue;
-        }
-        
-        let is_dir = path.is_dir();
-        
-        // Filter by type
-        match cli.file_type {
-            FileType::File if is_dir => continue,
-            FileType::Dir if !is_dir => continue,
-            _ => {}
-        }
-
-        let filename = path.file_name()
-            .and_then(|n| n.to_str())
-            .unwrap_or("");
-
-        if let Some(new_name) = check_match(filename, &cli.pattern, replacement, &regex, cli.case_sensitive) {
-            matches.push(Match {
-                path: path.to_path_buf(),
-                new_name,
-                is_dir,
-                pattern: cli.pattern.clone(),
-                replacement: replacement.unwrap_or("").to_string(),
-            });
-        }
-    }
+    #[arg(
+        long = "copy",
+        conflicts_with_all = ["emit_inverse", "archives"],
+        help = "Copy files to their new names instead of renaming them (directories are skipped)"
+    )]
+    copy: bool,
 
-    // Sort matches: files first, then directories (by depth, deepest first)
-    matches.sort_by(|a, b| {
-        match (a.is_dir, b.is_dir) {
-            (false, true) => std::cmp::Ordering::Less,  // Files before dirs
-            (true, false) => std::cmp::Ordering::Greater, // Dirs after files
-            _ => {
-                // Same type: sort by depth (deepest first for dirs, any order for files)
-                let a_depth = a.path.components().count();
-                let b_depth = b.path.components().count();
-                if a.is_dir {
-                    b_depth.cmp(&a_depth) // Deepest dirs first
-                } else {
-                    a_depth.cmp(&b_depth) // Shallowest files first
-                }
-            }
-        }
-    });
+    #[arg(
+        long = "verify",
+        help = "Compare SHA-256 checksums of source and copy before reporting success or deleting the source"
+    )]
+    verify: bool,
 
-    Ok(matches)
-}
+    #[arg(
+        long = "chown-to-invoker",
+        conflicts_with = "emit_script",
+        help = "Give renamed entries and copies to the user who ran sudo (needs sudo)"
+    )]
+    chown_to_invoker: bool,
 
-fn build_regex(pattern: &str, case_sensitive: bool) -> Result<Regex> {
-    let mut builder = regex::RegexBuilder::new(pattern);
-    builder.case_insensitive(!case_sensitive);
-    builder.build().context("Invalid regex pattern")
-}
+    #[arg(
+        long = "retry",
+        value_name = "N",
+        default_value_t = 0,
+        help = "Retry renames failing with busy, sharing violations or (on Windows) access denied up to N times"
+    )]
+    retry: u32,
 
-fn check_match(
-    filename: &str,
-    pattern: &str,
-    replacement: Option<&str>,
-    regex: &Option<Regex>,
-    case_sensitive: bool,
-) -> Option<String> {
-    if let Some(regex) = regex {
-        if let Some(replacement) = replacement {
-            if regex.is_match(filename) {
-                Some(regex.replace_all(filename, replacement).to_string())
-            } else {
-                None
-            }
-        } else {
-            if regex.is_match(filename) {
-                Some(filename.to_string())
-            } else {
-                None
-            }
-        }
-    } else {
-        // Simple glob-like matching
-        let matches = if case_sensitive {
-            simple_match(filename, pattern)
-        } else {
-            simple_match(&filename.to_lowercase(), &pattern.to_lowercase())
-        };
+    #[arg(
+        long = "retry-delay",
+        value_name = "MS",
+        default_value_t = 100,
+        help = "Milliseconds to wait before the first retry, doubled for every further one"
+    )]
+    retry_delay: u64,
 
-        if matches {
-            if let Some(replacement) = replacement {
-                Some(simple_replace(filename, pattern, replacement, case_sensitive))
-            } else {
-                Some(filename.to_string())
-            }
-        } else {
-            None
-        }
-    }
-}
+    #[arg(
+        long = "playground",
+        conflicts_with = "apply_plan",
+        help = "Edit pattern and replacement with a live preview before running"
+    )]
+    playground: bool,
 
-fn simple_match(text: &str, pattern: &str) -> bool {
-    if pattern.contains('*') {
-        // Basic glob matching
-        let parts: Vec<&str> = pattern.split('*').collect();
-        if parts.len() == 2 {
-            text.starts_with(parts[0]) && text.ends_with(parts[1])
-        } else {
-            text.contains(&pattern.replace('*', ""))
-        }
-    } else {
-        text.contains(pattern)
-    }
-}
+    /// Matches turned off in the playground.
+    #[arg(skip)]
+    deselected: HashSet<PathBuf>,
 
-fn simple_replace(text: &str, pattern: &str, replacement: &str, case_sensitive: bool) -> String {
-    if case_sensitive {
-        text.replace(pattern, replacement)
-    } else {
-        // Case-insensitive replace (simple version)
-        let lower_text = text.to_lowercase();
-        let lower_pattern = pattern.to_lowercase();
-        if let Some(pos) = lower_text.find(&lower_pattern) {
-            let mut result = text.to_string();
-            result.replace_range(pos..pos + pattern.len(), replacement);
-            result
-        } else {
-            text.to_string()
-        }
-    }
-}
+    /// New names edited in the playground.
+    #[arg(skip)]
+    edited: HashMap<PathBuf, String>,
 
-fn is_hidden(path: &Path) -> bool {
-    path.file_name()
-        .and_then(|name| name.to_str())
-        .map(|name| name.starts_with('.'))
-        .unwrap_or(false)
-}
+    #[arg(
+        short = 'o',
+        long = "output",
+        value_name = "FILE",
+        help = "Write search results, the dry-run plan or the script to FILE instead of stdout"
+    )]
+    output: Option<PathBuf>,
 
-fn highlight_replacement(new_name: &str, old_name: &str, pattern: &str, replacement: &str, no_color: bool) -> String {
-    if no_color {
-        new_name.to_string()
-    } else {
-        // Find where the replacement happened
-        if let Some(pos) = old_name.to_lowercase().find(&pattern.to_lowercase()) {
-            let before = &old_name[..pos];
-            let after = &old_name[pos + pattern.len()..];
-            format!("{}{}{}", 
-                before.white(), 
-                replacement.yellow(), 
-                after.white()
-            )
-        } else {
-            new_name.white().to_string()
-        }
-    }
-}
+    #[arg(
+        long = "conflicts-report",
+        value_name = "FILE",
+        conflicts_with = "stream",
+        help = "Write the collisions, rename cycles, invalid names and policy violations of the plan to FILE as JSON"
+    )]
+    conflicts_report: Option<PathBuf>,
 
-fn highlight_pattern(text: &str, pattern: &str, no_color: bool) -> String {
-    if no_color {
-        text.to_string()
-    } else {
-        if let Some(pos) = text.to_lowercase().find(&pattern.to_lowercase()) {
-            let before = &text[..pos];
-            let matched = &text[pos..pos + pattern.len()];
-            let after = &text[pos + pattern.len()..];
-            format!("{}{}{}", before.white(), matched.yellow(), after.white())
-        } else {
-            text.white().to_string()
-        }
-    }
-}
+    #[arg(
+        long = "patterns-from",
+        value_name = "FILE",
+        conflicts_with_all = ["apply_plan", "playground", "finder"],
+        help = "Read patterns from FILE, one per line (pattern<TAB>replacement to rename); \
+                all positional arguments become glob patterns"
+    )]
+    patterns_from: Option<PathBuf>,
 
-fn confirm_rename(m: &Match, no_color: bool) -> Result<ConfirmResult> {
-    if no_color {
-        println!("    {}", m.path.display());
-        println!(" -> {}", m.new_name);
-        print!("Replace filename/dirname? [Y]es/[n]o/[a]ll/[q]uit: ");
-    } else {
-        let old_filename = m.path.file_name().unwrap().to_str().unwrap();
-        let parent_path = if let Some(parent) = m.path.parent() {
-            format!("{}/", parent.display())
-        } else {
-            String::new()
-        };
-        
-        println!("    {}{}", 
-            parent_path.white(),
-            highlight_pattern(old_filename, &m.pattern, no_color)
-        );
-        println!(" -> {}{}", 
-            parent_path.white(),
-            highlight_replacement(&m.new_name, old_filename, &m.pattern, &m.replacement, no_color)
-        );
-        print!("{} ", "Replace filename/dirname? [Y]es/[n]o/[a]ll/[q]uit:".cyan());
-    }
-    io::stdout().flush()?;
-    
-    enable_raw_mode()?;
-    let result = loop {
-        if let Event::Key(key_event) = event::read()? {
-            match key_event.code {
-                KeyCode::Char('y') | KeyCode::Char('Y') | KeyCode::Enter => {
-                    print!("\ry");
-                    io::stdout().flush()?;
-                    break Ok(ConfirmResult::Yes);
-                }
-                KeyCode::Char('n') | KeyCode::Char('N') => {
-                    print!("\rn");
-                    io::stdout().flush()?;
-                    break Ok(ConfirmResult::No);
-                }
-                KeyCode::Char('a') | KeyCode::Char('A') => {
-                    print!("\ra");
-                    io::stdout().flush()?;
-                    break Ok(ConfirmResult::All);
-                }
-                KeyCode::Char('q') | KeyCode::Char('Q') | KeyCode::Esc => {
-                    print!("\rq");
-                    io::stdout().flush()?;
-                    break Ok(ConfirmResult::Quit);
+    #[arg(skip)]
+    rules: Vec<Rule>,
+
+    #[arg(
+        long = "format",
+        value_enum,
+        default_value = "text",
+        help = "Output format for search results, dry runs and applied renames"
+    )]
+    format: OutputFormat,
+
+    #[arg(
+        long = "stream",
+        conflicts_with_all = [
+            "apply_plan", "preview", "emit_inverse", "anonymize", "archives", "explain", "confirm_every", "playground",
+            "finder", "session",
+        ],
+        help = "Plan and apply one directory at a time to keep memory flat on huge trees (counters restart per directory)"
+    )]
+    stream: bool,
+
+    #[arg(
+        short = '1',
+        long = "first",
+        conflicts_with_all = ["replacement", "apply_plan", "explain", "archives"],
+        help = "Stop at the first match; exit status 1 if nothing matched"
+    )]
+    first: bool,
+
+    #[arg(
+        short = '0',
+        long = "print0",
+        conflicts_with_all = ["replacement", "apply_plan", "format", "explain"],
+        help = "Print matching paths separated by NUL bytes, without type markers, for xargs -0"
+    )]
+    print0: bool,
+
+    #[arg(
+        long = "show-order",
+        conflicts_with_all = ["stream", "emit_script"],
+        help = "Print the order the renames happen in and why: directories after what is inside, detours through staging"
+    )]
+    show_order: bool,
+
+    #[arg(
+        long = "explain",
+        help = "Also report the entries that were skipped, and why; dry runs also show the rule behind each new name"
+    )]
+    explain: bool,
+
+    #[arg(
+        long = "keep-going",
+        help = "Skip invalid names and failed renames instead of stopping (exits non-zero if anything failed)"
+    )]
+    keep_going: bool,
+
+    #[arg(
+        long = "atomic-per-dir",
+        conflicts_with = "copy",
+        help = "When a rename fails, roll back the others in its directory and carry on with the next directory"
+    )]
+    atomic_per_dir: bool,
+
+    #[arg(
+        long = "all-or-nothing",
+        conflicts_with_all = ["copy", "keep_going", "atomic_per_dir"],
+        help = "When a rename fails, roll back every rename of the run before exiting"
+    )]
+    all_or_nothing: bool,
+
+    #[arg(
+        long = "strict",
+        help = "Treat warnings about the plan (trailing dots, long names, submodules) as errors"
+    )]
+    strict: bool,
+
+    #[arg(
+        long = "archives",
+        conflicts_with = "apply_plan",
+        help = "Also match and rename entries inside zip and tar archives"
+    )]
+    archives: bool,
+
+    #[arg(
+        long = "no-dir-config",
+        help = "Ignore .fnr.toml files in the searched directories"
+    )]
+    no_dir_config: bool,
+
+    #[arg(long = "no-index", help = "Walk the tree even if `fnr index build` indexed it")]
+    no_index: bool,
+
+    #[arg(
+        long = "text",
+        conflicts_with = "binary",
+        help = "Only files that hold text (sniffs the first 8 KiB, no directories)"
+    )]
+    text: bool,
+
+    #[arg(long = "binary", help = "Only files that hold binary data (sniffs the first 8 KiB, no directories)")]
+    binary: bool,
+
+    #[arg(
+        long = "no-cross-submodules",
+        help = "Skip git submodules and other nested repositories instead of warning about renames inside them"
+    )]
+    no_cross_submodules: bool,
+
+    #[arg(long = "no-hooks", help = "Don't run hooks from the user configuration")]
+    no_hooks: bool,
+
+    #[arg(
+        long = "include-root",
+        help = "Also match the base directory itself (renamed last)"
+    )]
+    include_root: bool,
+
+    #[arg(
+        long = "no-protect",
+        help = "Allow renaming into or out of protected directories (.git, .hg, .svn by default)"
+    )]
+    no_protect: bool,
+
+    #[arg(
+        long = "preview",
+        value_name = "N",
+        conflicts_with = "apply_plan",
+        help = "Show the first N renames as soon as they are found and ask before scanning on"
+    )]
+    preview: Option<usize>,
+
+    #[arg(
+        long = "limit-per-dir",
+        value_name = "N",
+        help = "Keep at most N matches from any one directory"
+    )]
+    limit_per_dir: Option<usize>,
+
+    #[arg(
+        long = "number-duplicates",
+        value_name = "SCOPE",
+        value_enum,
+        conflicts_with_all = ["replacement", "apply_plan"],
+        help = "Append (1), (2), ... to files sharing a name within a directory (dir, case-folded) or the whole tree (tree)"
+    )]
+    number_duplicates: Option<DuplicateScope>,
+
+    #[arg(
+        long = "on-conflict",
+        value_name = "STRATEGY",
+        value_enum,
+        default_value = "error",
+        help = "What to do when a new name is taken: error, skip, overwrite, number (name (1).txt) or prompt"
+    )]
+    on_conflict: OnConflict,
+
+    #[arg(
+        long = "max-name-len",
+        value_name = "N",
+        help = "Shorten new names longer than N bytes, see --shorten (e.g. 143 for encrypted home directories)"
+    )]
+    max_name_len: Option<usize>,
+
+    #[arg(
+        long = "shorten",
+        value_name = "STRATEGY",
+        value_enum,
+        default_value = "truncate",
+        requires = "max_name_len",
+        help = "How --max-name-len shortens names: truncate, hash-suffix or middle-ellipsis (taken names get a hash suffix anyway)"
+    )]
+    shorten: Shorten,
+
+    #[arg(
+        long = "fix-extensions",
+        conflicts_with_all = ["replacement", "apply_plan", "number_duplicates"],
+        help = "Detect file types from their content and fix extensions that don't match"
+    )]
+    fix_extensions: bool,
+
+    #[arg(
+        long = "fix-unicode",
+        conflicts_with_all = ["apply_plan", "anonymize"],
+        help = "Strip invisible characters, plain spaces for exotic ones and Latin letters for lookalikes in names"
+    )]
+    fix_unicode: bool,
+
+    #[arg(
+        long = "fix-encoding",
+        value_name = "CODEPAGE",
+        value_enum,
+        conflicts_with_all = ["apply_plan", "anonymize"],
+        help = "Repair names that are UTF-8 mis-decoded through CODEPAGE (latin1, cp1252, cp437), like cafÃ© for café"
+    )]
+    fix_encoding: Option<Codepage>,
+
+    #[arg(
+        long = "map",
+        value_name = "FILE",
+        help = "Lookup table of key<TAB>value lines, used as {1|map} in replacements"
+    )]
+    map: Option<PathBuf>,
+
+    #[arg(
+        long = "with-sidecars",
+        value_name = "[EXT:]EXTS",
+        value_parser = parse_sidecars,
+        help = "Rename files with these extensions and the same stem along (e.g. jpg:xmp,raw); repeatable"
+    )]
+    with_sidecars: Vec<SidecarSpec>,
+
+    #[arg(
+        long = "group-by-stem",
+        help = "Rename all files sharing a stem in a directory together, or none of them"
+    )]
+    group_by_stem: bool,
+
+    #[arg(
+        long = "rewrite-ignores",
+        conflicts_with_all = ["copy", "stream", "emit_script"],
+        help = "Rewrite .gitignore and .ignore entries naming a renamed path to its new name"
+    )]
+    rewrite_ignores: bool,
+
+    #[arg(
+        long = "fix-manifests",
+        conflicts_with_all = ["copy", "stream", "emit_script"],
+        help = "Update paths to renamed files in Cargo.toml, package.json, CMakeLists.txt and Makefiles"
+    )]
+    fix_manifests: bool,
+
+    #[arg(
+        long = "fix-links",
+        value_enum,
+        value_name = "FORMATS",
+        value_delimiter = ',',
+        conflicts_with_all = ["copy", "stream", "emit_script"],
+        help = "Rewrite relative links to renamed paths in md and/or html documents across the tree (e.g. md,html)"
+    )]
+    fix_links: Vec<LinkFormat>,
+
+    #[arg(
+        long = "content",
+        conflicts_with_all = ["copy", "stream", "emit_script", "apply_plan"],
+        help = "Also replace the pattern inside the contents of the files passing the filters, like sd or fastmod"
+    )]
+    content: bool,
+
+    #[arg(
+        long = "fix-references",
+        conflicts_with_all = ["copy", "stream", "emit_script", "fix_manifests", "fix_links", "content"],
+        help = "Rewrite mentions of renamed files by name in text files across the tree (#include \"foo.h\", import foo)"
+    )]
+    fix_references: bool,
+
+    #[arg(
+        long = "anonymize",
+        value_name = "MAPPING",
+        conflicts_with_all = ["replacement", "apply_plan", "number_duplicates", "fix_extensions", "copy", "emit_inverse"],
+        help = "Rename matches to opaque identifiers and write a plan restoring the original names to MAPPING"
+    )]
+    anonymize: Option<PathBuf>,
+
+    #[arg(
+        long = "untouched-for",
+        value_name = "AGE",
+        value_parser = parse_age,
+        help = "Only entries git hasn't seen change for AGE, like 90d, 6m or 1y (uncommitted changes count)"
+    )]
+    untouched_for: Option<Duration>,
+
+    #[arg(
+        long = "created-after",
+        value_name = "DATE",
+        value_parser = parse_date,
+        help = "Only entries created at or after DATE (YYYY-MM-DD[ HH:MM[:SS]])"
+    )]
+    created_after: Option<NaiveDateTime>,
+
+    #[arg(
+        long = "created-before",
+        value_name = "DATE",
+        value_parser = parse_date,
+        help = "Only entries created before DATE (YYYY-MM-DD[ HH:MM[:SS]])"
+    )]
+    created_before: Option<NaiveDateTime>,
+
+    #[arg(
+        long = "timezone",
+        value_name = "TZ",
+        value_parser = parse_timezone,
+        help = "Timezone for date placeholders and filters, e.g. UTC or Europe/Berlin (default: local)"
+    )]
+    timezone: Option<Tz>,
+
+    #[arg(
+        long = "locale",
+        value_name = "LOCALE",
+        value_parser = parse_locale,
+        help = "Locale for month and day names in date placeholders, e.g. de_DE"
+    )]
+    locale: Option<Locale>,
+
+    #[arg(
+        long = "finder",
+        conflicts_with_all = ["apply_plan", "playground", "replacement"],
+        help = "Refine the pattern interactively and print the selected match"
+    )]
+    finder: bool,
+}
+
+/// How results are written.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    /// Colorful and human friendly.
+    Text,
+    /// A single JSON array of records.
+    Json,
+    /// One JSON record per line.
+    Jsonl,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// List the renames recorded in the undo journal
+    History {
+        #[arg(
+            long = "prune",
+            help = "Delete batches beyond the configured keep_batches / keep_days"
+        )]
+        prune: bool,
+
+        #[arg(
+            short = 'd',
+            long = "base-dir",
+            default_value = ".",
+            help = "Directory whose repository journal to use with journal.location = \"repo\""
+        )]
+        base_dir: PathBuf,
+    },
+    /// Recover journal batches cut short by a crash (don't run while fnr is renaming)
+    Doctor {
+        #[arg(
+            short = 'd',
+            long = "base-dir",
+            default_value = ".",
+            help = "Directory whose repository journal to use with journal.location = \"repo\""
+        )]
+        base_dir: PathBuf,
+    },
+    /// Rename the newest batch in the journal back, and drop it from the journal
+    Undo {
+        #[arg(long = "dry-run", help = "Show what would be renamed back without doing it")]
+        dry_run: bool,
+
+        #[arg(long = "no-color", help = "Disable colored output")]
+        no_color: bool,
+
+        #[arg(
+            short = 'd',
+            long = "base-dir",
+            default_value = ".",
+            help = "Directory whose repository journal to use with journal.location = \"repo\""
+        )]
+        base_dir: PathBuf,
+    },
+    /// Keep an index of the names below a directory for faster repeated searches
+    Index {
+        #[command(subcommand)]
+        action: IndexAction,
+    },
+    /// Show the renames added, removed or changed between two plan files
+    PlanDiff {
+        #[arg(value_name = "OLD")]
+        old: PathBuf,
+
+        #[arg(value_name = "NEW")]
+        new: PathBuf,
+
+        #[arg(long = "no-color", help = "Disable colored output")]
+        no_color: bool,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum IndexAction {
+    /// Index (or re-index) everything below a directory
+    Build {
+        #[arg(value_name = "DIR", default_value = ".")]
+        root: PathBuf,
+
+        #[arg(long = "hidden", help = "Include hidden files and directories")]
+        hidden: bool,
+
+        #[arg(long = "no-skip-gitignore", help = "Disable .gitignore skip")]
+        no_skip_gitignore: bool,
+
+        #[arg(
+            long = "follow",
+            value_enum,
+            default_value = "always",
+            help = "Which symbolic links to follow: never, dirs or always"
+        )]
+        follow: Follow,
+    },
+    /// Delete the index of a directory
+    Drop {
+        #[arg(value_name = "DIR", default_value = ".")]
+        root: PathBuf,
+    },
+}
+
+#[derive(Debug)]
+enum ConfirmResult {
+    Yes,
+    No,
+    All,
+    /// No to this match and everything else in its directory.
+    SkipDir,
+    Quit,
+    Edit,
+}
+
+impl Cli {
+    fn options(&self) -> Options {
+        let date_style = DateStyle {
+            timezone: self.timezone,
+            locale: self.locale,
+        };
+        Options {
+            pattern: self.pattern.clone().unwrap_or_default(),
+            replacement: self.replacement.clone(),
+            glob_patterns: self.glob_patterns.clone(),
+            base_dir: self.base_dir.clone(),
+            regex: self.regex,
+            file_type: self.file_type.clone(),
+            recursive: !self.no_recursive,
+            case_sensitive: self.case_sensitive,
+            hidden: self.hidden,
+            follow: if self.no_symlink { Follow::Never } else { self.follow },
+            git_ignore: !self.no_skip_gitignore,
+            max_depth: self.max_depth,
+            min_depth: self.min_depth,
+            min_components: self.components.or(self.min_components),
+            min_dir_entries: self.dir_min_entries,
+            max_dir_entries: self.dir_max_entries,
+            max_components: self.components.or(self.max_components),
+            include_root: self.include_root,
+            limit_per_dir: self.limit_per_dir,
+            created_after: self.created_after.map(|date| date_style.resolve(date)),
+            created_before: self.created_before.map(|date| date_style.resolve(date)),
+            untouched_for: self.untouched_for,
+            date_style,
+            rules: self.rules.clone(),
+            dir_configs: !self.no_dir_config,
+            index: !self.no_index,
+            cross_submodules: !self.no_cross_submodules,
+            map: self.map.clone(),
+            preserve_case: self.preserve_case,
+            fuzzy: self.fuzzy,
+            phonetic: self.phonetic,
+            // A parallel walk reports once the whole tree is read, streaming doesn't.
+            // Each walk thread reads one directory at a time
+            walk_threads: (self.remote && !self.stream).then_some(REMOTE_THREADS.min(self.max_open.max(1))),
+            content: match (self.text, self.binary) {
+                (true, _) => Some(Content::Text),
+                (_, true) => Some(Content::Binary),
+                _ => None,
+            },
+        }
+    }
+
+    fn jobs(&self) -> usize {
+        self.jobs.unwrap_or(if self.remote { REMOTE_JOBS } else { 1 })
+    }
+
+    fn retry(&self) -> Retry {
+        Retry {
+            attempts: self.retry,
+            delay: Duration::from_millis(self.retry_delay),
+        }
+    }
+}
+
+static THEME: OnceLock<Theme> = OnceLock::new();
+static KEYS: OnceLock<Keys> = OnceLock::new();
+
+/// The colors from the user configuration.
+fn theme() -> &'static Theme {
+    THEME.get_or_init(Theme::default)
+}
+
+/// The prompt keys from the user configuration.
+fn keys() -> &'static Keys {
+    KEYS.get_or_init(Keys::default)
+}
+
+fn main() -> Result<()> {
+    let mut cli = Cli::parse();
+    let config = UserConfig::load()?;
+    if !cli.no_color {
+        let theme = config.theme.resolve().context("Invalid [theme] in the user configuration")?;
+        let _ = THEME.set(theme);
+    }
+    let _ = KEYS.set(config.keys.normalized().context("Invalid [keys] in the user configuration")?);
+
+    match cli.command {
+        Some(Command::History { prune, base_dir }) => return history_mode(prune, &base_dir),
+        Some(Command::Doctor { base_dir }) => return doctor_mode(&base_dir),
+        Some(Command::Undo { dry_run, no_color, base_dir }) => return undo_mode(dry_run, no_color, &base_dir),
+        Some(Command::Index { action }) => return index_mode(action),
+        Some(Command::PlanDiff { old, new, no_color }) => return plan_diff_mode(&old, &new, no_color),
+        None => {}
+    }
+
+    if let Some(name) = cli.pattern.as_deref().and_then(|p| p.strip_prefix('@')) {
+        // `@@name` searches for a literal `@name`
+        match name.strip_prefix('@') {
+            Some(literal) => cli.pattern = Some(format!("@{}", literal)),
+            None => {
+                let name = name.to_string();
+                use_saved_search(&mut cli, &config, &name)?;
+            }
+        }
+    }
+
+    if let Some(path) = &cli.patterns_from {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read patterns from {}", path.display()))?;
+        cli.rules = parse_rules(&text);
+        if cli.rules.is_empty() {
+            bail!("No patterns found in {}", path.display());
+        }
+        let renames = cli.rules.iter().filter(|r| r.replacement.is_some()).count();
+        if renames != 0 && renames != cli.rules.len() {
+            bail!("{} mixes search-only lines with pattern<TAB>replacement lines", path.display());
+        }
+        let positional = cli.pattern.take().into_iter().chain(cli.replacement.take());
+        cli.glob_patterns = positional.chain(std::mem::take(&mut cli.glob_patterns)).collect();
+    }
+
+    if cli.finder {
+        if let Some(path) = playground::run(&cli.options(), Mode::Finder, cli.no_color, None)?.and_then(|o| o.selected) {
+            println!("{}", path.display());
+        }
+        return Ok(());
+    }
+
+    if cli.playground {
+        let session = cli.session.as_deref().map(Session::open).transpose()?;
+        let Some(outcome) = playground::run(&cli.options(), Mode::Playground, cli.no_color, session.as_ref())? else {
+            return Ok(());
+        };
+        cli.pattern = Some(outcome.pattern);
+        cli.replacement = Some(outcome.replacement).filter(|r| !r.is_empty());
+        cli.regex = outcome.regex;
+        cli.case_sensitive = outcome.case_sensitive;
+        cli.deselected = outcome.deselected;
+        cli.edited = outcome.edited;
+    }
+
+    if !cli.regex && !cli.no_hints {
+        let mut patterns = cli.pattern.iter().chain(cli.rules.iter().map(|r| &r.pattern));
+        if let Some(pattern) = patterns.find(|p| looks_like_regex(p)) {
+            let label = if cli.no_color { "Hint:".normal() } else { theme().warning.paint("Hint:") };
+            eprintln!(
+                "{} '{}' looks like a regex, but is matched literally (with * wildcards); use --regex, or --no-hints to silence this",
+                label, pattern
+            );
+        }
+    }
+    if !cli.remote && !cli.no_hints {
+        if let Some(kind) = network_filesystem(&cli.base_dir) {
+            let label = if cli.no_color { "Warning:".normal() } else { theme().warning.paint("Warning:") };
+            eprintln!(
+                "{} {} is on a network filesystem ({}), every stat is a round trip; --remote cuts down on them, --no-hints silences this",
+                label,
+                cli.base_dir.display(),
+                kind
+            );
+        }
+    }
+
+    let renaming = cli.replacement.is_some()
+        || cli.apply_plan.is_some()
+        || cli.number_duplicates.is_some()
+        || cli.fix_extensions
+        || cli.fix_unicode
+        || cli.fix_encoding.is_some()
+        || cli.anonymize.is_some()
+        || cli.rules.iter().any(|r| r.replacement.is_some());
+    if renaming && !cli.dry_run && cli.emit_script.is_none() && cli.output.is_some() {
+        bail!("--output only applies to search mode, --dry-run and --emit-script");
+    }
+
+    if cli.stream && !renaming {
+        bail!("--stream only applies to renames");
+    }
+    if cli.chown_to_invoker && !renaming {
+        bail!("--chown-to-invoker only applies to renames");
+    }
+    if cli.chown_to_invoker {
+        // Before any questions are asked
+        owner::chown_target()?;
+    }
+    if renaming && !cli.dry_run && cli.emit_script.is_none() && owner::is_root() {
+        let label = if cli.no_color { "Warning:".normal() } else { theme().error.paint("Warning:") };
+        let sudo = if owner::invoker().is_some() { " through sudo" } else { "" };
+        eprintln!(
+            "{} running as root{}: nothing on this system is out of reach, and a typo renames /etc as readily as ~/Downloads; try --dry-run first",
+            label, sudo
+        );
+    }
+    if cli.emit_script.is_some() && !renaming {
+        bail!("--emit-script only applies to renames");
+    }
+    if cli.max_name_len.is_some_and(|max_len| max_len < MIN_NAME_LEN) {
+        bail!("--max-name-len needs at least {} bytes, to leave room for a hash suffix", MIN_NAME_LEN);
+    }
+    if cli.content && !renaming {
+        bail!("--content needs a replacement");
+    }
+
+    if renaming && cli.stream {
+        stream_mode(&cli)
+    } else if renaming {
+        // Rename mode
+        rename_mode(&cli)
+    } else {
+        // Search mode
+        search_mode(&cli)
+    }
+}
+
+/// Fill in the pattern, globs and flags of the saved search `name`. Further
+/// positional arguments are globs replacing the saved ones, so `fnr @todo
+/// '*.md'` narrows a search instead of turning it into a rename.
+fn use_saved_search(cli: &mut Cli, config: &UserConfig, name: &str) -> Result<()> {
+    let Some(saved) = config.search.get(name) else {
+        let known: Vec<String> = config.search.keys().map(|name| format!("@{}", name)).collect();
+        if known.is_empty() {
+            bail!("No saved search '{}', add a [search.{}] table to the user configuration", name, name);
+        }
+        bail!("No saved search '{}', expected one of {}", name, known.join(", "));
+    };
+    let positional: Vec<String> = cli.replacement.take().into_iter().chain(cli.glob_patterns.drain(..)).collect();
+    cli.glob_patterns = if positional.is_empty() { saved.glob.clone() } else { positional };
+    cli.pattern = Some(saved.pattern.clone());
+    cli.replacement = saved.replacement.clone();
+    cli.regex |= saved.regex;
+    cli.case_sensitive |= saved.case_sensitive;
+    cli.hidden |= saved.hidden;
+    if let (Some(base_dir), true) = (&saved.base_dir, cli.base_dir == Path::new(".")) {
+        cli.base_dir = base_dir.clone();
+    }
+    Ok(())
+}
+
+/// Where search results and dry-run listings go. Files never get colors.
+/// `--conflicts-report`: everything wrong with the plan as JSON.
+fn write_conflicts(path: &Path, conflicts: &ConflictReport) -> Result<()> {
+    let file = File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    let mut out = BufWriter::new(file);
+    serde_json::to_writer_pretty(&mut out, conflicts)?;
+    writeln!(out)?;
+    out.flush()?;
+    Ok(())
+}
+
+fn open_output(cli: &Cli) -> Result<(Box<dyn Write>, bool)> {
+    match &cli.output {
+        Some(path) => {
+            let file = File::create(path)
+                .with_context(|| format!("Failed to create {}", path.display()))?;
+            Ok((Box::new(BufWriter::new(file)), true))
+        }
+        None => Ok((Box::new(io::stdout().lock()), cli.no_color)),
+    }
+}
+
+/// Matches inside archives, empty unless `--archives` is given.
+#[cfg(feature = "archives")]
+fn archive_matches(cli: &Cli) -> Result<Vec<Match>> {
+    if !cli.archives {
+        return Ok(Vec::new());
+    }
+    fnr_tool::archive::find_in_archives(&cli.options())
+}
+
+#[cfg(not(feature = "archives"))]
+fn archive_matches(cli: &Cli) -> Result<Vec<Match>> {
+    if cli.archives {
+        bail!("fnr was built without archive support");
+    }
+    Ok(Vec::new())
+}
+
+#[cfg(feature = "archives")]
+fn rename_archive_members(members: &[Match]) -> Result<()> {
+    fnr_tool::archive::rename_members(members)
+}
+
+#[cfg(not(feature = "archives"))]
+fn rename_archive_members(_members: &[Match]) -> Result<()> {
+    Ok(())
+}
+
+/// Matches, plus the entries passed over with `--explain`.
+fn find_explained(cli: &Cli) -> Result<(Vec<Match>, Vec<Record>)> {
+    let finder = Finder::new(cli.options());
+    let found = if cli.explain {
+        finder.find_explained()?
+    } else {
+        (finder.find()?, Vec::new())
+    };
+    note_special(cli, &finder);
+    Ok(found)
+}
+
+/// Point out the special files the walk of `finder` passed over, which
+/// would otherwise vanish without a trace.
+fn note_special(cli: &Cli, finder: &Finder) {
+    let count = finder.skipped_special();
+    if count == 0 {
+        return;
+    }
+    let label = if cli.no_color { "Note:".normal() } else { theme().warning.paint("Note:") };
+    let files = if count == 1 { "special file" } else { "special files" };
+    eprintln!("{} skipped {} {} (FIFOs, sockets, devices); --type special renames them", label, count, files);
+}
+
+/// Write records as a JSON array or as JSON lines.
+fn write_records(out: &mut dyn Write, records: &[Record], format: OutputFormat) -> Result<()> {
+    if format == OutputFormat::Json {
+        serde_json::to_writer_pretty(&mut *out, records)?;
+        writeln!(out)?;
+    } else {
+        for record in records {
+            serde_json::to_writer(&mut *out, record)?;
+            writeln!(out)?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn write_skipped(out: &mut dyn Write, record: &Record, no_color: bool) -> io::Result<()> {
+    let reason = match (&record.reason, &record.message) {
+        (Some(reason), Some(message)) => format!("{}: {}", reason, message),
+        (Some(reason), None) => reason.to_string(),
+        (None, _) => String::new(),
+    };
+    if no_color {
+        writeln!(out, "[-] {} ({})", record.path.display(), reason)
+    } else {
+        let skipped = &theme().skipped;
+        writeln!(out, "[{}] {} ({})", skipped.paint("-"), skipped.paint(&record.path.display().to_string()), skipped.paint(&reason))
+    }
+}
+
+fn write_derivation(out: &mut dyn Write, derivation: &Derivation, no_color: bool) -> io::Result<()> {
+    if no_color {
+        writeln!(out, "    ({})", derivation)
+    } else {
+        writeln!(out, "    {}", theme().skipped.paint(&format!("({})", derivation)))
+    }
+}
+
+/// Lines of other files the renames will change, under `title`.
+fn write_edits(out: &mut dyn Write, title: &str, edits: &[Edit], no_color: bool) -> io::Result<()> {
+    if edits.is_empty() {
+        return Ok(());
+    }
+    let paint = |style: &Style, text: &str| if no_color { text.to_string() } else { style.paint(text).to_string() };
+    writeln!(out, "{}", paint(&theme().header, title))?;
+    for edit in edits {
+        writeln!(out, "    {}:{}", paint(&theme().path, &edit.file.display().to_string()), edit.line)?;
+        writeln!(out, "    - {}", edit.old)?;
+        writeln!(out, "    + {}", paint(&theme().replacement, &edit.new))?;
+    }
+    Ok(())
+}
+
+/// `--show-order`: the renames in the order they happen, each with what it
+/// waits for. Directories go after the renames inside them; entries whose
+/// new name is still held, that go round in a circle or only change case
+/// park in staging (unless `staged` is off, for copies) and move on once
+/// everything else is done.
+fn write_order(out: &mut dyn Write, matches: &[Match], staged: bool, no_color: bool) -> io::Result<()> {
+    let paint = |style: &Style, text: &str| if no_color { text.to_string() } else { style.paint(text).to_string() };
+    let detours = if staged { staging::staged_sources(matches) } else { HashSet::new() };
+    let steps: HashMap<&Path, usize> = matches.iter().enumerate().map(|(i, m)| (m.path.as_path(), i + 1)).collect();
+    let circles = cycles(matches);
+    let list = |steps: &[usize]| {
+        let shown: Vec<String> = steps.iter().take(5).map(usize::to_string).collect();
+        match steps.len() > shown.len() {
+            true => format!("{}, ...", shown.join(", ")),
+            false => shown.join(", "),
+        }
+    };
+
+    writeln!(out, "{}", paint(&theme().header, "Rename order:"))?;
+    for (i, m) in matches.iter().enumerate() {
+        writeln!(out, "  {:>3}. {} -> {}", i + 1, paint(&theme().path, &m.path.display().to_string()), m.new_name)?;
+        let mut reasons = Vec::new();
+        if m.is_dir {
+            let inside: Vec<usize> = matches[..i]
+                .iter()
+                .enumerate()
+                .filter(|(_, other)| other.path.starts_with(&m.path))
+                .map(|(j, _)| j + 1)
+                .collect();
+            reasons.push(match inside.len() {
+                0 => "directory, after all files and deeper directories".to_string(),
+                1 => format!("directory, after the rename inside it (step {})", inside[0]),
+                n => format!("directory, after the {} renames inside it (steps {})", n, list(&inside)),
+            });
+        } else {
+            reasons.push("file, files go before directories".to_string());
+        }
+        if detours.contains(&m.path) {
+            let old = m.path.file_name().unwrap_or_default().to_string_lossy();
+            let new_path = m.new_path();
+            let why = if let Some(circle) = circles.iter().find(|circle| circle.contains(&m.path)) {
+                let names: Vec<String> = circle.iter().chain(circle.first()).map(|path| path.display().to_string()).collect();
+                format!("part of the circle {}", names.join(" -> "))
+            } else if old.to_lowercase() == m.new_name.to_lowercase() {
+                "only the case changes".to_string()
+            } else {
+                let holder = steps.get(new_path.as_path()).map_or(String::new(), |step| format!(" (step {})", step));
+                format!("{} is still held{}", new_path.display(), holder)
+            };
+            reasons.push(format!("parks in staging, {}", why));
+        }
+        writeln!(out, "       {}", reasons.join("; "))?;
+    }
+    if detours.is_empty() {
+        return Ok(());
+    }
+    writeln!(out, "{}", paint(&theme().header, "Then out of staging, in the same order:"))?;
+    let parked = matches.iter().filter(|m| detours.contains(&m.path));
+    for (i, m) in parked.enumerate() {
+        let step = matches.len() + i + 1;
+        writeln!(out, "  {:>3}. {} -> {}", step, paint(&theme().path, &m.path.display().to_string()), m.new_name)?;
+    }
+    Ok(())
+}
+
+/// How many renames fall under each top-level directory below `base_dir`
+/// (`.` for entries of `base_dir` itself), and how many of them collide.
+fn write_impact(out: &mut dyn Write, matches: &[Match], base_dir: &Path, probe: &Probe, no_color: bool) -> io::Result<()> {
+    let colliding = colliding(matches, probe);
+    let mut per_dir: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+    for m in matches {
+        let relative = m.path.strip_prefix(base_dir).unwrap_or(&m.path);
+        let mut components = relative.components();
+        let top = match (components.next(), components.next()) {
+            (Some(top), Some(_)) => top.as_os_str().to_string_lossy().into_owned(),
+            _ => ".".to_string(),
+        };
+        let counts = per_dir.entry(top).or_default();
+        counts.0 += 1;
+        counts.1 += usize::from(colliding.contains(&m.path));
+    }
+    let mut per_dir: Vec<(String, (usize, usize))> = per_dir.into_iter().collect();
+    per_dir.sort_by_key(|(_, (count, _))| Reverse(*count));
+
+    let paint = |style: &Style, text: &str| if no_color { text.to_string() } else { style.paint(text).to_string() };
+    let summary = format!(
+        "Impact: {} renames in {} top-level directories, {} collide",
+        matches.len(),
+        per_dir.len(),
+        colliding.len()
+    );
+    writeln!(out, "{}", paint(&theme().header, &summary))?;
+    for (dir, (count, collide)) in per_dir {
+        let note = if collide > 0 {
+            paint(&theme().error, &format!(" ({} collide)", collide))
+        } else {
+            String::new()
+        };
+        writeln!(out, "{:>8}  {}{}", count, dir, note)?;
+    }
+    Ok(())
+}
+
+/// The first match the walk turns up.
+fn find_first(cli: &Cli) -> Result<Option<Match>> {
+    let mut first = None;
+    Finder::new(cli.options()).find_inspect(|found| {
+        first = found.first().cloned();
+        false
+    })?;
+    Ok(first)
+}
+
+fn search_mode(cli: &Cli) -> Result<()> {
+    let (mut matches, skipped) = if cli.first {
+        (find_first(cli)?.into_iter().collect(), Vec::new())
+    } else {
+        find_explained(cli)?
+    };
+    matches.extend(archive_matches(cli)?);
+    let (mut out, no_color) = open_output(cli)?;
+    if cli.first && matches.is_empty() {
+        // Nothing to print, the exit status is the answer
+        out.flush()?;
+        std::process::exit(1);
+    }
+
+    if cli.format != OutputFormat::Text {
+        let records: Vec<Record> = matches
+            .iter()
+            .map(|m| Record::new(Status::Match, &m.path, m.is_dir))
+            .chain(skipped)
+            .collect();
+        return write_records(&mut out, &records, cli.format);
+    }
+    if cli.print0 {
+        // Paths as they are, spaces, newlines and invalid UTF-8 included
+        for m in &matches {
+            out.write_all(m.path.as_os_str().as_encoded_bytes())?;
+            out.write_all(b"\0")?;
+        }
+        out.flush()?;
+        return Ok(());
+    }
+    
+    for m in matches {
+        let type_indicator = if m.is_dir { "d" } else { "f" };
+        let path_str = m.path.display().to_string();
+        
+        if no_color {
+            writeln!(out, "[{}] {}", type_indicator, path_str)?;
+        } else {
+            let colored_type = if m.is_dir {
+                theme().dir.paint(type_indicator)
+            } else {
+                theme().file.paint(type_indicator)
+            };
+            writeln!(out, "[{}] {}", colored_type, theme().path.paint(&path_str))?;
+        }
+    }
+    for record in &skipped {
+        write_skipped(&mut out, record, no_color)?;
+    }
+    
+    out.flush()?;
+    Ok(())
+}
+
+fn rename_mode(cli: &Cli) -> Result<()> {
+    let mut skipped = Vec::new();
+    let (mut matches, cli) = match (&cli.apply_plan, cli.preview) {
+        (Some(path), _) => (
+            Plan::load(path)?.select(&cli.only, cli.skip, cli.take)?.into_matches()?,
+            cli,
+        ),
+        (None, Some(count)) => match find_with_preview(cli, count)? {
+            Previewed::Found(matches) => (matches, cli),
+            Previewed::Stream => {
+                let unattended = Cli {
+                    no_interactive: true,
+                    stream: true,
+                    preview: None,
+                    ..cli.clone()
+                };
+                return stream_mode(&unattended);
+            }
+            Previewed::Aborted => return Ok(()),
+        },
+        (None, None) => {
+            let (matches, passed) = find_explained(cli)?;
+            skipped = passed;
+            (matches, cli)
+        }
+    };
+    matches.retain(|m| !cli.deselected.contains(&m.path));
+    for m in &mut matches {
+        if let Some(name) = cli.edited.get(&m.path) {
+            m.new_name = name.clone();
+        }
+    }
+    if let Some(scope) = cli.number_duplicates {
+        matches = number_duplicates(matches, scope, cli.case_sensitive);
+    }
+    if cli.fix_extensions {
+        matches = fix_extensions(matches);
+    }
+    if let Some(codepage) = cli.fix_encoding {
+        matches = fix_encoding(matches, codepage);
+    }
+    if cli.fix_unicode {
+        matches = fix_unicode(matches);
+    }
+    if cli.anonymize.is_some() {
+        matches = anonymize(matches);
+    }
+    if let Some(max_len) = cli.max_name_len {
+        matches = shorten_names(matches, max_len, cli.shorten, cli.case_sensitive);
+    }
+    if !cli.with_sidecars.is_empty() {
+        matches = add_sidecars(matches, &cli.with_sidecars);
+    }
+    if cli.copy {
+        matches.retain(|m| !m.is_dir);
+    }
+    let probe = Probe::new(cli.remote);
+    let dir_configs = (!cli.no_dir_config).then(DirConfigs::new);
+    strip_trailing(&mut matches, cli.trailing, dir_configs.as_ref())?;
+    let overwrite = settle_conflicts(cli, &probe, &mut matches, &mut skipped)?;
+    let members = archive_matches(cli)?;
+    let mut replaced = match cli.content {
+        true => content_edits(cli)?,
+        false => Vec::new(),
+    };
+    
+    if matches.is_empty() && members.is_empty() && replaced.is_empty() {
+        if let Some(path) = &cli.conflicts_report {
+            write_conflicts(path, &ConflictReport::default())?;
+        }
+        if cli.format != OutputFormat::Text {
+            return write_records(&mut open_output(cli)?.0, &skipped, cli.format);
+        }
+        println!("No matches found.");
+        for record in &skipped {
+            write_skipped(&mut io::stdout(), record, cli.no_color)?;
+        }
+        return Ok(());
+    }
+
+    let config = UserConfig::load()?;
+    let mut conflicts = ConflictReport {
+        collisions: collisions(&matches, &probe),
+        cycles: cycles(&matches),
+        issues: Vec::new(),
+    };
+    let errors = screen(cli, &config.protected(), dir_configs.as_ref(), &probe, &mut matches, &mut skipped, &mut conflicts.issues)?;
+    if let Some(path) = &cli.conflicts_report {
+        write_conflicts(path, &conflicts)?;
+    }
+
+    if let Some(kind) = cli.emit_script {
+        if errors > 0 {
+            bail!("{} planned names are invalid, no script was written", errors);
+        }
+        let (mut out, _) = open_output(cli)?;
+        write_script(&mut out, &matches, kind)?;
+        out.flush()?;
+        return Ok(());
+    }
+
+    // Lines of other files following the renames, by what they are
+    let relocation = Relocation::new(&matches);
+    let mut edits: Vec<(&str, Vec<Edit>)> = Vec::new();
+    if cli.content {
+        // Where the files go is up to the renames, see Edit::replan below
+        if !cli.dry_run && !cli.no_interactive {
+            replaced = confirm_edits(replaced, cli.no_color)?;
+        }
+        edits.push(("Lines to replace:", replaced));
+    }
+    if cli.rewrite_ignores {
+        edits.push(("Ignore entries to rewrite:", references::ignore_edits(&relocation, &cli.base_dir)?));
+    }
+    if cli.fix_manifests {
+        edits.push(("Manifest paths to update:", references::manifest_edits(&relocation, &cli.base_dir)?));
+    }
+    if !cli.fix_links.is_empty() {
+        edits.push(("Links to update:", references::link_edits(&relocation, &cli.base_dir, &cli.fix_links)?));
+    }
+    if cli.fix_references {
+        edits.push(("References to update:", references::reference_edits(&relocation, &cli.base_dir)?));
+    }
+
+    if cli.dry_run {
+        let (mut out, no_color) = open_output(cli)?;
+        // Plans were computed elsewhere, their rules are long gone
+        let rules = match cli.apply_plan {
+            Some(_) => Vec::new(),
+            None => compile_rules(&cli.options())?,
+        };
+        if cli.format != OutputFormat::Text {
+            let records: Vec<Record> = matches
+                .iter()
+                .chain(&members)
+                .map(|m| {
+                    Record::new(Status::Planned, &m.path, m.is_dir)
+                        .with_new_path(&m.new_path())
+                        .with_derivation(derivation(m, &rules))
+                })
+                .chain(skipped)
+                .collect();
+            return write_records(&mut out, &records, cli.format);
+        }
+        let header = if no_color {
+            "Dry run - showing what would be renamed:"
+        } else {
+            &theme().header.paint("Dry run - showing what would be renamed:").to_string()
+        };
+        writeln!(out, "{}", header)?;
+        
+        for m in matches.iter().chain(&members) {
+            write_match(&mut out, m, no_color)?;
+            if let Some(derivation) = derivation(m, &rules).filter(|_| cli.explain) {
+                write_derivation(&mut out, &derivation, no_color)?;
+            }
+        }
+        for record in &skipped {
+            write_skipped(&mut out, record, no_color)?;
+        }
+        if cli.show_order {
+            write_order(&mut out, &matches, !cli.copy, no_color)?;
+        }
+        for (title, edits) in &edits {
+            write_edits(&mut out, title, edits, no_color)?;
+        }
+        let planned: Vec<Match> = matches.iter().chain(&members).cloned().collect();
+        write_impact(&mut out, &planned, &cli.base_dir, &probe, no_color)?;
+        out.flush()?;
+        return Ok(());
+    }
+
+    if errors > 0 {
+        bail!("{} planned names are invalid, nothing was renamed", errors);
+    }
+    if let Some(path) = cli.anonymize.as_ref().filter(|p| p.exists()) {
+        bail!("Mapping file {} already exists, refusing to overwrite it", path.display());
+    }
+
+    let hooks = if cli.no_hooks {
+        Hooks::default()
+    } else {
+        config.hooks
+    };
+    // Copies leave the originals in place, there is nothing to undo
+    let journal = if cli.copy {
+        None
+    } else {
+        Some(Journal::open(&config.journal, &cli.base_dir)?)
+    };
+    if cli.format == OutputFormat::Text {
+        for record in &skipped {
+            write_skipped(&mut io::stdout(), record, cli.no_color)?;
+        }
+        if cli.show_order {
+            write_order(&mut io::stdout(), &matches, !cli.copy, cli.no_color)?;
+        }
+    }
+    let session = match &cli.session {
+        Some(path) if !cli.no_interactive => {
+            let session = Session::open(path)?;
+            if !session.is_empty() && cli.format == OutputFormat::Text {
+                println!("Picking up {} earlier answers from {}", session.len(), path.display());
+            }
+            Some(session)
+        }
+        _ => None,
+    };
+    let runner = Runner {
+        cli,
+        hooks,
+        recorder: Recorder::default(),
+        batch: journal.as_ref().map(Journal::begin).transpose()?,
+        failed: Mutex::new(Vec::new()),
+        throttle: cli.throttle.map(Throttle::new),
+        open_files: HandleLimit::new(cli.max_open),
+        staging: Staging::default(),
+        done: Mutex::new(HashMap::new()),
+        rolled_back: Mutex::new(HashSet::new()),
+        session,
+        overwrite: Mutex::new(overwrite),
+        chown_to: cli.chown_to_invoker.then(owner::chown_target).transpose()?,
+    };
+    // Archives are rewritten first, while their paths are still valid
+    let result = match apply_members(cli, &members) {
+        Ok(true) => apply_matches(&runner, &matches),
+        Ok(false) => Ok(()),
+        Err(e) => Err(e),
+    };
+
+    let applied = runner.recorder.plan();
+    if let Some(path) = cli.emit_inverse.as_ref().or(cli.anonymize.as_ref()) {
+        applied.inverse().save(path)?;
+    }
+    if let (Some(journal), Some(batch)) = (&journal, runner.batch) {
+        batch
+            .finish()
+            .context("Renames were applied, but recording them in the journal failed")?;
+        journal.prune(config.journal.keep_batches, config.journal.keep_days)?;
+    }
+    if !applied.renames.is_empty() {
+        runner.hooks.post_batch(&applied.renames)?;
+    }
+
+    let failed = runner.failed.into_inner().unwrap();
+    if cli.format != OutputFormat::Text {
+        let status = if cli.copy { Status::Copied } else { Status::Renamed };
+        let records: Vec<Record> = members
+            .iter()
+            .map(|m| Record::new(Status::Renamed, &m.path, m.is_dir).with_new_path(&m.new_path()))
+            .chain(
+                applied
+                    .renames
+                    .iter()
+                    .map(|e| Record::new(status, &e.from, e.is_dir).with_new_path(&e.to)),
+            )
+            .chain(failed.iter().cloned())
+            .chain(skipped)
+            .collect();
+        write_records(&mut io::stdout().lock(), &records, cli.format)?;
+    }
+    result?;
+    // Planned for every rename, but only the ones that were done count
+    let relocation = Relocation::applied(&applied.renames);
+    let edits: Vec<Edit> = edits.iter().flat_map(|(_, edits)| edits).filter_map(|edit| edit.replan(&relocation)).collect();
+    if !failed.is_empty() {
+        if !edits.is_empty() {
+            eprintln!("Some renames failed, so paths in other files were left as they are");
+        }
+        bail!("{} renames failed", failed.len());
+    }
+    references::apply(&edits).context("Renames were applied, but updating paths in other files failed")?;
+    if cli.format == OutputFormat::Text && !edits.is_empty() {
+        println!("Updated {} lines in other files", edits.len());
+    }
+    Ok(())
+}
+
+/// Check planned names against the policies and protected names and print
+/// the issues. With `--keep-going` offending matches are moved to `skipped`,
+/// otherwise the number of errors is returned.
+fn screen(
+    cli: &Cli,
+    protected: &[String],
+    dir_configs: Option<&DirConfigs>,
+    probe: &Probe,
+    matches: &mut Vec<Match>,
+    skipped: &mut Vec<Record>,
+    reported: &mut Vec<ReportedIssue>,
+) -> Result<usize> {
+    let mut errors = 0;
+    let mut report = |issue: &Issue, reason: Reason| {
+        reported.push(ReportedIssue {
+            path: issue.path.clone(),
+            reason,
+            severity: issue.severity,
+            message: issue.message.clone(),
+        })
+    };
+    if cli.group_by_stem {
+        let (kept, issues, dropped) = group_by_stem(std::mem::take(matches));
+        *matches = kept;
+        for issue in &issues {
+            print_issue(issue, cli.no_color);
+            report(issue, Reason::StemGroup);
+        }
+        errors += issues.len();
+        if cli.keep_going {
+            skipped.extend(dropped.into_iter().map(|(m, message)| Record {
+                message: Some(message),
+                ..Record::skipped(&m.path, m.is_dir, Reason::StemGroup)
+            }));
+        }
+    }
+    let invalid = check_policies(matches, cli.target_fs, cli.trailing, dir_configs)?;
+    let protected = if cli.no_protect {
+        Vec::new()
+    } else {
+        check_protected(matches, protected)
+    };
+    let submodules = check_submodules(matches, &Submodules::new(&cli.base_dir));
+    // Other strategies settled them already, what is left may overwrite
+    let collision_issues = match cli.on_conflict {
+        OnConflict::Error => check_collisions(matches, probe),
+        _ => Vec::new(),
+    };
+    // Renames go round in circles through staging, copies and scripts can't
+    let cycles = if cli.copy || cli.emit_script.is_some() {
+        check_cycles(matches)
+    } else {
+        Vec::new()
+    };
+    let mut issues: Vec<(Issue, Reason)> = invalid
+        .into_iter()
+        .map(|issue| (issue, Reason::InvalidName))
+        .chain(protected.into_iter().map(|issue| (issue, Reason::Protected)))
+        .chain(collision_issues.into_iter().map(|issue| (issue, Reason::Collision)))
+        .chain(cycles.into_iter().map(|issue| (issue, Reason::Cycle)))
+        .chain(check_lengths(matches).into_iter().map(|issue| (issue, Reason::Length)))
+        .chain(submodules.into_iter().map(|issue| (issue, Reason::Submodule)))
+        .collect();
+    for (issue, reason) in &mut issues {
+        if cli.strict {
+            issue.severity = Severity::Error;
+        }
+        print_issue(issue, cli.no_color);
+        report(issue, *reason);
+    }
+    let mut rejected = HashSet::new();
+    for (issue, reason) in issues.into_iter().filter(|(issue, _)| issue.severity == Severity::Error) {
+        errors += 1;
+        if cli.keep_going && rejected.insert(issue.path.clone()) {
+            let is_dir = matches.iter().any(|m| m.path == issue.path && m.is_dir);
+            skipped.push(Record {
+                message: Some(issue.message.clone()),
+                ..Record::skipped(&issue.path, is_dir, reason)
+            });
+        }
+    }
+    if cli.keep_going {
+        matches.retain(|m| !rejected.contains(&m.path));
+        errors = 0;
+    }
+    Ok(errors)
+}
+
+/// `--stream`: plan, check and apply one directory at a time instead of
+/// collecting the whole tree first.
+fn stream_mode(cli: &Cli) -> Result<()> {
+    if !cli.no_interactive && !cli.dry_run {
+        bail!("--stream never asks, add --no-interactive (or --dry-run)");
+    }
+    if cli.format == OutputFormat::Json {
+        bail!("--stream writes records as it goes, use --format jsonl");
+    }
+    if cli.number_duplicates == Some(DuplicateScope::Tree) {
+        bail!("--stream only sees one directory at a time, use --number-duplicates dir");
+    }
+
+    let config = UserConfig::load()?;
+    let protected = config.protected();
+    let dir_configs = (!cli.no_dir_config).then(DirConfigs::new);
+    let journal = if cli.copy || cli.dry_run {
+        None
+    } else {
+        Some(Journal::open(&config.journal, &cli.base_dir)?)
+    };
+    let runner = Runner {
+        cli,
+        hooks: if cli.no_hooks { Hooks::default() } else { config.hooks },
+        recorder: Recorder::default(),
+        batch: journal.as_ref().map(Journal::begin).transpose()?,
+        failed: Mutex::new(Vec::new()),
+        throttle: cli.throttle.map(Throttle::new),
+        open_files: HandleLimit::new(cli.max_open),
+        staging: Staging::default(),
+        done: Mutex::new(HashMap::new()),
+        rolled_back: Mutex::new(HashSet::new()),
+        session: None,
+        overwrite: Mutex::new(HashSet::new()),
+        chown_to: cli.chown_to_invoker.then(owner::chown_target).transpose()?,
+    };
+    let (mut out, no_color) = if cli.dry_run {
+        open_output(cli)?
+    } else {
+        (Box::new(io::stdout().lock()) as Box<dyn Write>, cli.no_color)
+    };
+    if cli.dry_run && cli.format == OutputFormat::Text {
+        let header = "Dry run - showing what would be renamed:";
+        writeln!(out, "{}", if no_color { header.normal() } else { theme().header.paint(header) })?;
+    }
+
+    let mut found = 0;
+    let mut failed = 0;
+    let finder = Finder::new(cli.options());
+    let result = finder.stream(|mut batch| {
+        if let Some(scope) = cli.number_duplicates {
+            batch = number_duplicates(batch, scope, cli.case_sensitive);
+        }
+        if cli.fix_extensions {
+            batch = fix_extensions(batch);
+        }
+        if let Some(codepage) = cli.fix_encoding {
+            batch = fix_encoding(batch, codepage);
+        }
+        if cli.fix_unicode {
+            batch = fix_unicode(batch);
+        }
+        if let Some(max_len) = cli.max_name_len {
+            batch = shorten_names(batch, max_len, cli.shorten, cli.case_sensitive);
+        }
+        if !cli.with_sidecars.is_empty() {
+            batch = add_sidecars(batch, &cli.with_sidecars);
+        }
+        if cli.copy {
+            batch.retain(|m| !m.is_dir);
+        }
+        found += batch.len();
+        let mut skipped = Vec::new();
+        // Per directory, so listings don't pile up
+        let probe = Probe::new(cli.remote);
+        strip_trailing(&mut batch, cli.trailing, dir_configs.as_ref())?;
+        let overwrite = settle_conflicts(cli, &probe, &mut batch, &mut skipped)?;
+        runner.overwrite.lock().unwrap().extend(overwrite);
+        let errors = screen(cli, &protected, dir_configs.as_ref(), &probe, &mut batch, &mut skipped, &mut Vec::new())?;
+        if errors > 0 {
+            bail!("{} planned names are invalid, stopping here", errors);
+        }
+
+        let mut records = Vec::new();
+        if cli.dry_run {
+            for m in &batch {
+                match cli.format {
+                    OutputFormat::Text => write_match(&mut out, m, no_color)?,
+                    _ => records.push(Record::new(Status::Planned, &m.path, m.is_dir).with_new_path(&m.new_path())),
+                }
+            }
+        } else {
+            apply_matches(&runner, &batch)?;
+            let applied = runner.recorder.take();
+            if !applied.renames.is_empty() {
+                runner.hooks.post_batch(&applied.renames)?;
+            }
+            let status = if cli.copy { Status::Copied } else { Status::Renamed };
+            records.extend(
+                applied
+                    .renames
+                    .iter()
+                    .map(|e| Record::new(status, &e.from, e.is_dir).with_new_path(&e.to)),
+            );
+            let batch_failed = std::mem::take(&mut *runner.failed.lock().unwrap());
+            failed += batch_failed.len();
+            records.extend(batch_failed);
+        }
+        records.extend(skipped);
+        match cli.format {
+            OutputFormat::Text => {
+                for record in records.iter().filter(|r| r.status == Status::Skipped) {
+                    write_skipped(&mut out, record, no_color)?;
+                }
+                Ok(())
+            }
+            format => write_records(&mut out, &records, format),
+        }
+    });
+
+    if let (Some(journal), Some(batch)) = (&journal, runner.batch) {
+        batch
+            .finish()
+            .context("Renames were applied, but recording them in the journal failed")?;
+        journal.prune(config.journal.keep_batches, config.journal.keep_days)?;
+    }
+    result?;
+    out.flush()?;
+    note_special(cli, &finder);
+    if found == 0 && cli.format == OutputFormat::Text {
+        println!("No matches found.");
+    }
+    if failed > 0 {
+        bail!("{} renames failed", failed);
+    }
+    Ok(())
+}
+
+fn doctor_mode(base_dir: &Path) -> Result<()> {
+    let config = UserConfig::load()?;
+    let journal = Journal::open(&config.journal, base_dir)?;
+    let partial = journal.partial_batches()?;
+    for path in &partial {
+        let recovery = journal.recover(path)?;
+        println!(
+            "Recovered interrupted batch {}: {} renames were applied, {} never happened",
+            path.display(),
+            recovery.applied.len(),
+            recovery.pending.len()
+        );
+        for entry in &recovery.pending {
+            println!("      not renamed: {} -> {}", entry.from.display(), entry.to.display());
+        }
+        for entry in &recovery.unstaged {
+            println!("      moved back from staging: {}", entry.from.display());
+        }
+    }
+    // Reading every batch checks the completed ones too
+    let batches = journal.batches()?;
+    if partial.is_empty() {
+        println!("Journal {} is healthy ({} batches).", journal.dir().display(), batches.len());
+    }
+    Ok(())
+}
+
+/// Rename the newest batch back, newest rename first. Entries whose old
+/// path is held by an earlier rename of the batch (swaps, chains) wait in
+/// staging until that one is undone too. What can't be undone stays in the
+/// journal, the rest leaves it.
+fn undo_mode(dry_run: bool, no_color: bool, base_dir: &Path) -> Result<()> {
+    let config = UserConfig::load()?;
+    let journal = Journal::open(&config.journal, base_dir)?;
+    if !journal.partial_batches()?.is_empty() {
+        bail!("The journal has unfinished batches, run `fnr doctor` first");
+    }
+    let Some((path, batch)) = journal.batches()?.pop() else {
+        println!("Journal {} is empty, nothing to undo.", journal.dir().display());
+        return Ok(());
+    };
+
+    let staging = Staging::default();
+    let retry = Retry::default();
+    let mut kept = Vec::new();
+    let mut parked: Vec<(PathBuf, &PlanEntry)> = Vec::new();
+    let mut undone = 0;
+    // Paths a dry run would have freed by now
+    let mut freed: HashSet<&Path> = HashSet::new();
+    let mut fail = |entry: &PlanEntry, e: anyhow::Error| {
+        print_issue(&Issue::error(&entry.to, format!("{:#}", e)), no_color);
+        kept.push(entry.clone());
+    };
+    for (i, entry) in batch.renames.iter().enumerate().rev() {
+        let held = batch.renames[..i].iter().any(|earlier| earlier.to == entry.from);
+        if fs::symlink_metadata(&entry.to).is_err() {
+            fail(entry, anyhow!("not there anymore"));
+        } else if !held && !freed.contains(entry.from.as_path()) && fs::symlink_metadata(&entry.from).is_ok() {
+            fail(entry, anyhow!("can't go back, {} is taken", entry.from.display()));
+        } else if dry_run {
+            print_move(&entry.to, &entry.from, "Would undo:", no_color);
+            freed.insert(&entry.to);
+            undone += 1;
+        } else if held {
+            let tmp = staging.tmp_path(&entry.to, entry.to.file_name().unwrap_or_default());
+            match staging.park(&entry.to, &tmp, &retry) {
+                Ok(()) => parked.push((tmp, entry)),
+                Err(e) => fail(entry, e),
+            }
+        } else {
+            match retry.run(|| fs::rename(&entry.to, &entry.from)) {
+                Ok(()) => {
+                    print_move(&entry.to, &entry.from, "Undone:", no_color);
+                    undone += 1;
+                    if entry.is_dir {
+                        for (tmp, _) in &mut parked {
+                            staging::relocate(tmp, &entry.to, &entry.from);
+                        }
+                    }
+                }
+                Err(e) => fail(entry, anyhow::Error::new(e).context("Failed to rename back")),
+            }
+        }
+    }
+    // Parked entries whose old path was never freed go back to where they
+    // were before parking
+    for (tmp, entry) in parked {
+        let result = if fs::symlink_metadata(&entry.from).is_ok() {
+            Err(anyhow!("can't go back, {} is taken", entry.from.display()))
+        } else {
+            retry.run(|| fs::rename(&tmp, &entry.from)).context("Failed to move out of staging")
+        };
+        match result {
+            Ok(()) => {
+                print_move(&entry.to, &entry.from, "Undone:", no_color);
+                undone += 1;
+            }
+            Err(e) => {
+                let e = match fs::rename(&tmp, &entry.to) {
+                    Ok(()) => e,
+                    Err(stuck) => e.context(format!("{} waits in {}: {}", entry.to.display(), tmp.display(), stuck)),
+                };
+                fail(entry, e);
+            }
+        }
+    }
+    staging.clean_up();
+    if dry_run {
+        return Ok(());
+    }
+
+    kept.reverse();
+    journal.rewrite(&path, &kept)?;
+    let time = DateStyle::default().format(batch.time(), "%Y-%m-%d %H:%M:%S");
+    println!("Undid {} renames of the batch from {}", undone, time);
+    if !kept.is_empty() {
+        bail!("{} renames could not be undone, they stay in the journal", kept.len());
+    }
+    Ok(())
+}
+
+fn index_mode(action: IndexAction) -> Result<()> {
+    match action {
+        IndexAction::Build {
+            root,
+            hidden,
+            no_skip_gitignore,
+            follow,
+        } => {
+            let settings = IndexSettings {
+                hidden,
+                git_ignore: !no_skip_gitignore,
+                follow_links: follow != Follow::Never,
+            };
+            let index = Index::build(&root, settings)?;
+            let file = index.save()?;
+            let (dirs, entries) = index.len();
+            println!(
+                "Indexed {} entries in {} directories below {} ({})",
+                entries,
+                dirs,
+                index.root().display(),
+                file.display()
+            );
+        }
+        IndexAction::Drop { root } => {
+            if Index::remove(&root)? {
+                println!("Removed the index of {}", root.display());
+            } else {
+                println!("{} has no index", root.display());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn plan_diff_mode(old: &Path, new: &Path, no_color: bool) -> Result<()> {
+    let (changes, unchanged) = diff(&Plan::load(old)?, &Plan::load(new)?);
+    let paint = |style: &Style, text: String| if no_color { text } else { style.paint(&text).to_string() };
+    let (mut added, mut removed, mut changed) = (0, 0, 0);
+    for change in &changes {
+        let line = match change {
+            PlanChange::Added(entry) => {
+                added += 1;
+                paint(&theme().replacement, format!("+ {} -> {}", entry.from.display(), entry.to.display()))
+            }
+            PlanChange::Removed(entry) => {
+                removed += 1;
+                paint(&theme().error, format!("- {} -> {}", entry.from.display(), entry.to.display()))
+            }
+            PlanChange::Changed { from, old_to, new_to } => {
+                changed += 1;
+                format!(
+                    "{} {} -> {} (was {})",
+                    paint(&theme().warning, "~".to_string()),
+                    from.display(),
+                    paint(&theme().replacement, new_to.display().to_string()),
+                    paint(&theme().skipped, old_to.display().to_string())
+                )
+            }
+        };
+        println!("{}", line);
+    }
+    println!("{} added, {} removed, {} changed, {} unchanged", added, removed, changed, unchanged);
+    Ok(())
+}
+
+fn history_mode(prune: bool, base_dir: &Path) -> Result<()> {
+    let config = UserConfig::load()?;
+    let journal = Journal::open(&config.journal, base_dir)?;
+    if prune {
+        if config.journal.keep_batches.is_none() && config.journal.keep_days.is_none() {
+            bail!("No retention configured, set journal.keep_batches or journal.keep_days");
+        }
+        let removed = journal.prune(config.journal.keep_batches, config.journal.keep_days)?;
+        println!("Removed {} batches from {}", removed, journal.dir().display());
+        return Ok(());
+    }
+
+    let batches = journal.batches()?;
+    if batches.is_empty() {
+        println!("Journal {} is empty.", journal.dir().display());
+    }
+    let partial = journal.partial_batches()?.len();
+    if partial > 0 {
+        eprintln!("{} unfinished batches in the journal, run `fnr doctor` to recover them", partial);
+    }
+    let date_style = DateStyle::default();
+    for (i, (_, batch)) in batches.iter().enumerate() {
+        let time = date_style.format(batch.time(), "%Y-%m-%d %H:%M:%S");
+        let note = if batch.interrupted { " (interrupted)" } else { "" };
+        println!("{:>4}  {}  {} renames{}", i + 1, theme().label.paint(&time), batch.renames.len(), theme().error.paint(note));
+        for entry in batch.renames.iter().take(3) {
+            println!("      {} -> {}", entry.from.display(), theme().replacement.paint(&entry.to.display().to_string()));
+        }
+        if batch.renames.len() > 3 {
+            println!("      ... and {} more", batch.renames.len() - 3);
+        }
+    }
+    Ok(())
+}
+
+/// What became of a walk with `--preview`.
+enum Previewed {
+    /// The walk went on to the end, the matches are confirmed as usual.
+    Found(Vec<Match>),
+    /// Apply the matches as the walk finds them, see [`stream_mode`].
+    Stream,
+    Aborted,
+}
+
+/// Whether the run can go through [`stream_mode`], which takes none of the
+/// options `--stream` conflicts with.
+fn streamable(cli: &Cli) -> bool {
+    cli.apply_plan.is_none()
+        && cli.emit_inverse.is_none()
+        && cli.anonymize.is_none()
+        && !cli.archives
+        && !cli.explain
+        && cli.confirm_every.is_none()
+        && !cli.playground
+        && !cli.finder
+        && cli.session.is_none()
+        && cli.emit_script.is_none()
+        && cli.conflicts_report.is_none()
+        && !cli.show_order
+        && !cli.rewrite_ignores
+        && !cli.fix_manifests
+        && cli.fix_links.is_empty()
+        && !cli.content
+        && !cli.fix_references
+        && cli.format != OutputFormat::Json
+        && cli.number_duplicates != Some(DuplicateScope::Tree)
+}
+
+/// Find matches, showing the first `count` of them as soon as the walk
+/// turns them up. Applying them as found stops this walk and leaves the
+/// rest to a streaming one, which renames each directory as soon as it is
+/// done with it; only runs that could use `--stream` get that choice.
+fn find_with_preview(cli: &Cli, count: usize) -> Result<Previewed> {
+    let options = cli.options();
+    let rules = compile_rules(&options)?;
+    let date_style = options.date_style;
+    let stream = streamable(cli);
+    let mut answer = None;
+    let mut error = None;
+
+    let finder = Finder::new(options);
+    let found = finder.find_inspect(|found| {
+        if answer.is_some() || found.len() < count.max(1) {
+            return true;
+        }
+        match preview_sample(found, &rules, &date_style, &cli.base_dir, stream, cli.no_color) {
+            Ok(result) => {
+                let go_on = matches!(result, ConfirmResult::Yes);
+                answer = Some(result);
+                go_on
+            }
+            Err(e) => {
+                error = Some(e);
+                false
+            }
+        }
+    })?;
+    if let Some(e) = error {
+        return Err(e);
+    }
+    note_special(cli, &finder);
+    Ok(match (found, answer) {
+        (_, Some(ConfirmResult::All)) => Previewed::Stream,
+        (Some(matches), _) => Previewed::Found(matches),
+        (None, _) => Previewed::Aborted,
+    })
+}
+
+fn preview_sample(
+    found: &[Match],
+    rules: &[(NameMatcher, Option<String>)],
+    date_style: &DateStyle,
+    base_dir: &Path,
+    stream: bool,
+    no_color: bool,
+) -> Result<ConfirmResult> {
+    let mut sample = found.to_vec();
+    sort_matches(&mut sample);
+    compute_new_names_cached(&mut sample, rules, date_style, base_dir, &MetadataCache::new())?;
+
+    let header = format!("First {} matches (still scanning, counters are provisional):", sample.len());
+    if no_color {
+        println!("{}", header);
+    } else {
+        println!("{}", theme().header.paint(&header));
+    }
+    for m in &sample {
+        print_match(m, no_color);
+    }
+    let keys = keys();
+    let mut choices = vec![(keys.scan, "continue scanning")];
+    if stream {
+        choices.push((keys.all, "apply as found, without asking"));
+    }
+    choices.push((keys.quit, "quit"));
+    let key = ask("Keep going?", &choices, no_color)?;
+    Ok(if key == keys.scan {
+        ConfirmResult::Yes
+    } else if key == keys.all {
+        ConfirmResult::All
+    } else {
+        ConfirmResult::Quit
+    })
+}
+
+/// Applies confirmed renames and keeps track of what was done.
+struct Runner<'a> {
+    cli: &'a Cli,
+    hooks: Hooks,
+    recorder: Recorder,
+    batch: Option<BatchWriter>,
+    /// Renames that failed with `--keep-going`.
+    failed: Mutex<Vec<Record>>,
+    throttle: Option<Throttle>,
+    open_files: HandleLimit,
+    staging: Staging,
+    /// Renames done so far by directory with `--atomic-per-dir`, all under
+    /// the empty path with `--all-or-nothing`.
+    done: Mutex<HashMap<PathBuf, Vec<Done>>>,
+    /// Directories whose renames were rolled back.
+    rolled_back: Mutex<HashSet<PathBuf>>,
+    /// Earlier answers, and where new ones go, with `--session`.
+    session: Option<Session>,
+    /// Renames allowed to replace what has their new path, `--on-conflict`.
+    overwrite: Mutex<HashSet<PathBuf>>,
+    /// User and group renamed entries are given to, `--chown-to-invoker`.
+    chown_to: Option<(u32, u32)>,
+}
+
+/// Follow the rename of a directory from `from` to `to` with `done`.
+fn relocate_done<'d>(done: impl IntoIterator<Item = &'d mut Done>, from: &Path, to: &Path) {
+    for entry in done {
+        staging::relocate(&mut entry.new_path, from, to);
+        staging::relocate(&mut entry.origin, from, to);
+    }
+}
+
+/// A rename that may still be rolled back.
+#[derive(Debug)]
+struct Done {
+    m: Match,
+    /// Where the entry is now.
+    new_path: PathBuf,
+    /// Where it was, which moves along with renamed directories.
+    origin: PathBuf,
+    /// Its journal records, one per move.
+    intents: Vec<Intent>,
+}
+
+impl Runner<'_> {
+    /// Rename `m`. With `--keep-going` a failure is reported and recorded
+    /// instead of stopping the run; with `--atomic-per-dir` the renames done
+    /// in its directory are rolled back, and the run goes on elsewhere.
+    fn apply(&self, m: &Match) -> Result<()> {
+        if let Some(throttle) = &self.throttle {
+            throttle.wait();
+        }
+        let dir = self.unit(m);
+        if self.cli.atomic_per_dir && self.rolled_back.lock().unwrap().contains(dir) {
+            self.fail(m, Reason::RolledBack, format!("{} was rolled back", dir.display()));
+            return Ok(());
+        }
+        match self.perform_rename(m) {
+            Err(e) if self.cli.atomic_per_dir => {
+                print_issue(&Issue::error(&m.path, format!("{:#}", e)), self.cli.no_color);
+                self.fail(m, Reason::RenameFailed, format!("{:#}", e));
+                self.roll_back(dir)
+            }
+            Err(e) if self.cli.keep_going => {
+                print_issue(&Issue::error(&m.path, format!("{:#}", e)), self.cli.no_color);
+                self.fail(m, Reason::RenameFailed, format!("{:#}", e));
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    fn fail(&self, m: &Match, reason: Reason, message: String) {
+        let record = Record::failed(&m.path, m.is_dir, reason, message);
+        self.failed.lock().unwrap().push(record.with_new_path(&m.new_path()));
+    }
+
+    /// Follow the rename of a directory with everything waiting for its
+    /// next move.
+    fn moved(&self, from: &Path, to: &Path) {
+        self.staging.moved(from, to);
+        relocate_done(self.done.lock().unwrap().values_mut().flatten(), from, to);
+    }
+
+    /// What is rolled back together with `m`: its directory with
+    /// `--atomic-per-dir`, everything (the empty path) with `--all-or-nothing`.
+    fn unit<'m>(&self, m: &'m Match) -> &'m Path {
+        if self.cli.all_or_nothing {
+            Path::new("")
+        } else {
+            m.path.parent().unwrap_or(Path::new("."))
+        }
+    }
+
+    fn remember(&self, m: &Match, new_path: &Path, intents: Vec<Intent>) {
+        if self.cli.atomic_per_dir || self.cli.all_or_nothing {
+            let done = Done {
+                m: m.clone(),
+                new_path: new_path.to_path_buf(),
+                origin: m.path.clone(),
+                intents,
+            };
+            self.done.lock().unwrap().entry(self.unit(m).to_path_buf()).or_default().push(done);
+        }
+    }
+
+    /// Undo the renames done in the unit `dir` (see [`Runner::unit`]) and
+    /// move what waits in staging back.
+    /// Everything goes through staging first, so swapped names can't get in
+    /// each other's way. Hooks that already ran are not taken back.
+    fn roll_back(&self, dir: &Path) -> Result<()> {
+        self.rolled_back.lock().unwrap().insert(dir.to_path_buf());
+        let mut done = self.done.lock().unwrap().remove(dir).unwrap_or_default();
+        let retry = self.cli.retry();
+        // Out of the way first, newest first; `new_path` is the staging path
+        let mut parked: Vec<Done> = Vec::new();
+        while let Some(mut entry) = done.pop() {
+            let tmp = self.staging.tmp_path(&entry.new_path, entry.m.path.file_name().unwrap_or_default());
+            let intent = self.batch.as_ref().map(|batch| batch.intend(&entry.new_path, &tmp, entry.m.is_dir)).transpose()?;
+            self.staging.park(&entry.new_path, &tmp, &retry)?;
+            if entry.m.is_dir {
+                self.moved(&entry.new_path, &tmp);
+                relocate_done(done.iter_mut().chain(&mut parked), &entry.new_path, &tmp);
+            }
+            entry.new_path = tmp;
+            entry.intents.extend(intent);
+            parked.push(entry);
+        }
+        parked.extend(self.staging.take(|m| self.unit(m) == dir).into_iter().map(|staged| Done {
+            m: staged.m,
+            new_path: staged.tmp,
+            origin: staged.origin,
+            intents: staged.intent.into_iter().collect(),
+        }));
+
+        // Then back where everything came from
+        let mut parked = parked.into_iter();
+        while let Some(Done {
+            m,
+            new_path: tmp,
+            origin,
+            intents,
+        }) = parked.next()
+        {
+            let intent = self.batch.as_ref().map(|batch| batch.intend(&tmp, &origin, m.is_dir)).transpose()?;
+            retry
+                .run(|| fs::rename(&tmp, &origin))
+                .with_context(|| format!("Rolling back {} failed, it is still in {}", m.path.display(), tmp.display()))?;
+            if m.is_dir {
+                self.moved(&tmp, &origin);
+                relocate_done(parked.as_mut_slice(), &tmp, &origin);
+            }
+            if let Some(batch) = &self.batch {
+                for intent in intents.into_iter().chain(intent) {
+                    batch.cancel(intent)?;
+                }
+            }
+            self.recorder.forget(&m.path);
+            if self.cli.format == OutputFormat::Text {
+                let renamed = Match {
+                    path: m.new_path(),
+                    ..m.clone()
+                };
+                print_renamed(&renamed, &m.path, "Rolled back:", self.cli.no_color);
+            }
+            if self.failed.lock().unwrap().iter().all(|record| record.path != m.path) {
+                let message = if dir.as_os_str().is_empty() {
+                    "the whole run was rolled back".to_string()
+                } else {
+                    format!("{} was rolled back", dir.display())
+                };
+                self.fail(&m, Reason::RolledBack, message);
+            }
+        }
+        Ok(())
+    }
+
+    fn perform_rename(&self, m: &Match) -> Result<()> {
+        self.hooks.pre_rename(m, &m.new_path())?;
+        if !self.cli.copy && self.staging.holds(m) {
+            let tmp = self.staging.tmp_path(&m.path, m.path.file_name().unwrap_or_default());
+            let intent = self.batch.as_ref().map(|batch| batch.intend(&m.path, &tmp, m.is_dir)).transpose()?;
+            let staged = self.staging.stage(m, &tmp, intent, &self.cli.retry());
+            if let (Err(_), Some(batch), Some(intent)) = (&staged, &self.batch, intent) {
+                batch.cancel(intent)?;
+            }
+            return staged;
+        }
+        let intent = match &self.batch {
+            Some(batch) => Some(batch.intend(&m.path, &m.new_path(), m.is_dir)?),
+            None => None,
+        };
+        let handles = self.open_files.acquire(HANDLES);
+        let moved = if self.cli.copy {
+            copy_match(m, self.cli.verify)
+        } else {
+            let overwrite = self.overwrite.lock().unwrap().contains(&m.path);
+            move_match(m, self.cli.verify, &self.cli.retry(), overwrite)
+        };
+        drop(handles);
+        let new_path = match (moved, &self.batch, intent) {
+            (Ok(new_path), _, _) => new_path,
+            (Err(e), Some(batch), Some(intent)) => {
+                batch.cancel(intent)?;
+                return Err(e);
+            }
+            (Err(e), _, _) => return Err(e),
+        };
+        if m.is_dir && !self.cli.copy {
+            self.moved(&m.path, &new_path);
+        }
+        self.remember(m, &new_path, intent.into_iter().collect());
+        if let Some((uid, gid)) = self.chown_to {
+            owner::chown(&new_path, uid, gid)?;
+        }
+        self.recorder.record(m, &new_path);
+        let label = if self.cli.copy { "Copied:" } else { "Renamed:" };
+        if self.cli.format == OutputFormat::Text {
+            print_renamed(m, &new_path, label, self.cli.no_color);
+        }
+        if m.path == self.cli.base_dir && self.cli.format == OutputFormat::Text {
+            println!("Base directory is now {}", new_path.display());
+        }
+        self.hooks.post_rename(m, &new_path)
+    }
+
+    /// Move everything waiting in staging on to its new name (or back where
+    /// it was, if that fails) and remove the staging directories.
+    fn finish_staged(&self) -> Result<()> {
+        let mut result = Ok(());
+        while let Some(staged) = self.staging.next() {
+            match self.unstage(&staged) {
+                Err(e) if self.cli.atomic_per_dir => {
+                    print_issue(&Issue::error(&staged.m.path, format!("{:#}", e)), self.cli.no_color);
+                    self.fail(&staged.m, Reason::RenameFailed, format!("{:#}", e));
+                    if let Err(e) = self.roll_back(self.unit(&staged.m)) {
+                        result = result.and(Err(e));
+                    }
+                }
+                Err(e) if self.cli.keep_going => {
+                    print_issue(&Issue::error(&staged.m.path, format!("{:#}", e)), self.cli.no_color);
+                    self.fail(&staged.m, Reason::RenameFailed, format!("{:#}", e));
+                }
+                // Keep going anyway, nothing should be left in staging
+                Err(e) if result.is_ok() => result = Err(e),
+                _ => {}
+            }
+        }
+        for dir in self.staging.clean_up() {
+            print_issue(&Issue::warning(&dir, "staging directory is not empty".to_string()), self.cli.no_color);
+        }
+        result
+    }
+
+    fn unstage(&self, staged: &Staged) -> Result<()> {
+        let m = &staged.m;
+        let retry = self.cli.retry();
+        let intent = match &self.batch {
+            Some(batch) => Some(batch.intend(&staged.tmp, &staged.target, m.is_dir)?),
+            None => None,
+        };
+        let moved = if fs::symlink_metadata(&staged.target).is_ok() {
+            Err(anyhow!("{} already exists", staged.target.display()))
+        } else {
+            retry.run(|| fs::rename(&staged.tmp, &staged.target)).map_err(anyhow::Error::from)
+        };
+        if let Err(e) = moved {
+            if let (Some(batch), Some(intent)) = (&self.batch, intent) {
+                batch.cancel(intent)?;
+            }
+            let e = e.context(format!("Failed to rename {} to {}", m.path.display(), staged.target.display()));
+            // Back to where it came from, as if it never moved
+            if let Err(back) = retry.run(|| fs::rename(&staged.tmp, &staged.origin)) {
+                return Err(e.context(format!("it is still in {} ({})", staged.tmp.display(), back)));
+            }
+            if let (Some(batch), Some(intent)) = (&self.batch, staged.intent) {
+                batch.cancel(intent)?;
+            }
+            return Err(e);
+        }
+
+        if m.is_dir {
+            self.moved(&staged.tmp, &staged.target);
+        }
+        self.remember(m, &staged.target, staged.intent.into_iter().chain(intent).collect());
+        if let Some((uid, gid)) = self.chown_to {
+            owner::chown(&staged.target, uid, gid)?;
+        }
+        self.recorder.record(m, &staged.target);
+        if self.cli.format == OutputFormat::Text {
+            print_renamed(m, &staged.target, "Renamed:", self.cli.no_color);
+        }
+        self.hooks.post_rename(m, &staged.target)
+    }
+}
+
+fn apply_matches(runner: &Runner, matches: &[Match]) -> Result<()> {
+    if !runner.cli.copy {
+        runner.staging.prepare(matches);
+    }
+    let mut result = apply_planned(runner, matches);
+    if result.is_ok() || !runner.cli.all_or_nothing {
+        result = result.and(runner.finish_staged());
+    }
+    if result.is_err() && runner.cli.all_or_nothing {
+        // Only now, when no worker is renaming anymore. What still waits in
+        // staging goes back too, then the staging directories go.
+        runner.roll_back(Path::new(""))?;
+        runner.finish_staged()?;
+        return result.context("Every rename of this run was rolled back");
+    }
+    result
+}
+
+fn apply_planned(runner: &Runner, matches: &[Match]) -> Result<()> {
+    let cli = runner.cli;
+    let matches = &replay_session(runner, matches)?;
+    if let Some(chunk_size) = cli.confirm_every {
+        for chunk in matches.chunks(chunk_size.max(1)) {
+            for m in chunk {
+                print_annotated(m, cli.no_color);
+            }
+            match confirm_chunk(chunk.len(), cli.no_color)? {
+                ConfirmResult::Yes | ConfirmResult::All => {
+                    for m in chunk {
+                        record_answer(runner, m, Answer::Yes)?;
+                        runner.apply(m)?;
+                    }
+                }
+                ConfirmResult::No | ConfirmResult::SkipDir => {
+                    for m in chunk {
+                        record_answer(runner, m, Answer::No)?;
+                    }
+                }
+                ConfirmResult::Edit => {
+                    if !confirm_each(runner, chunk)? {
+                        return Ok(());
+                    }
+                }
+                ConfirmResult::Quit => return Ok(()),
+            }
+        }
+    } else if !cli.no_interactive {
+        confirm_each(runner, matches)?;
+    } else if cli.jobs() > 1 {
+        rename_parallel(matches, cli.jobs(), |m| runner.apply(m))?;
+    } else {
+        for m in matches {
+            runner.apply(m)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Rename confirmed archive members. Returns `false` if the user quit.
+fn apply_members(cli: &Cli, members: &[Match]) -> Result<bool> {
+    let mut confirmed = Vec::new();
+    let mut apply_all = cli.no_interactive;
+    let mut skipped_dirs = HashSet::new();
+    let mut quit = false;
+    for m in members {
+        if !apply_all {
+            if skipped_dirs.contains(&m.path.parent()) {
+                continue;
+            }
+            match confirm_rename(m, cli.no_color)? {
+                ConfirmResult::Yes => {}
+                ConfirmResult::No => continue,
+                ConfirmResult::SkipDir => {
+                    skipped_dirs.insert(m.path.parent());
+                    continue;
+                }
+                ConfirmResult::All => apply_all = true,
+                ConfirmResult::Quit | ConfirmResult::Edit => {
+                    quit = true;
+                    break;
                 }
+            }
+        }
+        confirmed.push(m.clone());
+    }
+
+    rename_archive_members(&confirmed)?;
+    for m in confirmed.iter().filter(|_| cli.format == OutputFormat::Text) {
+        print_renamed(m, &m.new_path(), "Renamed:", cli.no_color);
+    }
+    Ok(!quit)
+}
+
+fn highlight_replacement(new_name: &str, old_name: &str, pattern: &str, replacement: &str, no_color: bool) -> String {
+    if no_color {
+        new_name.to_string()
+    } else if pattern.is_empty() {
+        // The whole name was replaced
+        theme().replacement.paint(new_name).to_string()
+    } else {
+        // Find where the replacement happened
+        if let Some(pos) = old_name.to_lowercase().find(&pattern.to_lowercase()) {
+            let before = &old_name[..pos];
+            let after = &old_name[pos + pattern.len()..];
+            format!("{}{}{}", 
+                theme().path.paint(before),
+                theme().replacement.paint(replacement),
+                theme().path.paint(after)
+            )
+        } else {
+            theme().path.paint(new_name).to_string()
+        }
+    }
+}
+
+fn highlight_pattern(text: &str, pattern: &str, no_color: bool) -> String {
+    if no_color || pattern.is_empty() {
+        text.to_string()
+    } else {
+        if let Some(pos) = text.to_lowercase().find(&pattern.to_lowercase()) {
+            let before = &text[..pos];
+            let matched = &text[pos..pos + pattern.len()];
+            let after = &text[pos + pattern.len()..];
+            let theme = theme();
+            format!("{}{}{}", theme.path.paint(before), theme.matched.paint(matched), theme.path.paint(after))
+        } else {
+            theme().path.paint(text).to_string()
+        }
+    }
+}
+
+/// Apply the matches answered with yes in an earlier session, and drop
+/// those answered with no. Returns the matches still to ask about.
+fn replay_session(runner: &Runner, matches: &[Match]) -> Result<Vec<Match>> {
+    let Some(session) = &runner.session else {
+        return Ok(matches.to_vec());
+    };
+    let mut rest = Vec::new();
+    for m in matches {
+        match session.answer(m) {
+            Some(Answer::Yes) => runner.apply(m)?,
+            Some(Answer::No) => {}
+            None => rest.push(m.clone()),
+        }
+    }
+    Ok(rest)
+}
+
+fn record_answer(runner: &Runner, m: &Match, answer: Answer) -> Result<()> {
+    match &runner.session {
+        Some(session) => session.record_answer(m, answer),
+        None => Ok(()),
+    }
+}
+
+/// Ask about every match individually. Returns `false` if the user quit.
+fn confirm_each(runner: &Runner, matches: &[Match]) -> Result<bool> {
+    let mut apply_all = false;
+    // Matches of one directory aren't necessarily next to each other
+    let mut skipped_dirs = HashSet::new();
+    for m in matches {
+        if !apply_all {
+            if skipped_dirs.contains(&m.path.parent()) {
+                continue;
+            }
+            match confirm_rename(m, runner.cli.no_color)? {
+                ConfirmResult::Yes => record_answer(runner, m, Answer::Yes)?,
+                ConfirmResult::No => {
+                    record_answer(runner, m, Answer::No)?;
+                    continue;
+                }
+                ConfirmResult::SkipDir => {
+                    if let Some(session) = &runner.session {
+                        session.record_skip_dir(m)?;
+                    }
+                    skipped_dirs.insert(m.path.parent());
+                    continue;
+                }
+                ConfirmResult::All => apply_all = true,
+                ConfirmResult::Quit | ConfirmResult::Edit => return Ok(false),
+            }
+        }
+        runner.apply(m)?;
+    }
+    Ok(true)
+}
+
+fn print_issue(issue: &Issue, no_color: bool) {
+    let label = match issue.severity {
+        Severity::Warning => "Warning:",
+        Severity::Error => "Error:",
+    };
+    if no_color {
+        eprintln!("{} {}", label, issue);
+    } else if issue.severity == Severity::Warning {
+        eprintln!("{} {}", theme().warning.paint(label), issue);
+    } else {
+        eprintln!("{} {}", theme().error.paint(label), issue);
+    }
+}
+
+fn print_match(m: &Match, no_color: bool) {
+    // Failing to write to the terminal is not worth aborting a rename for
+    let _ = write_match(&mut io::stdout(), m, no_color);
+}
+
+/// The match for a prompt, with what is worth knowing about it below.
+fn print_annotated(m: &Match, no_color: bool) {
+    print_match(m, no_color);
+    let notes = annotate(m);
+    if notes.is_empty() {
+        return;
+    }
+    let line = format!("    ! {}", notes.join(", "));
+    if no_color {
+        println!("{}", line);
+    } else {
+        println!("{}", theme().warning.paint(&line));
+    }
+}
+
+fn write_match(out: &mut dyn Write, m: &Match, no_color: bool) -> io::Result<()> {
+    if no_color {
+        writeln!(out, "    {}", m.path.display())?;
+        writeln!(out, " -> {}", m.new_name)?;
+    } else {
+        let old_filename = m.path.file_name().unwrap().to_str().unwrap();
+        let parent_path = if let Some(parent) = m.path.parent() {
+            format!("{}/", parent.display())
+        } else {
+            String::new()
+        };
+        
+        writeln!(out, "    {}{}", 
+            theme().path.paint(&parent_path),
+            highlight_pattern(old_filename, &m.pattern, no_color)
+        )?;
+        writeln!(out, " -> {}{}", 
+            theme().path.paint(&parent_path),
+            highlight_replacement(&m.new_name, old_filename, &m.pattern, &m.replacement, no_color)
+        )?;
+    }
+    Ok(())
+}
+
+/// `--content`: the lines of the files passing the filters that the rules
+/// change. Wildcards cover whole names, which makes no sense for lines.
+fn content_edits(cli: &Cli) -> Result<Vec<Edit>> {
+    let options = cli.options();
+    if let Some(rule) = options.rules().iter().find(|rule| !options.regex && (rule.pattern.is_empty() || rule.pattern.contains('*'))) {
+        bail!("--content needs a literal or --regex pattern, '{}' would replace whole lines", rule.pattern);
+    }
+    let rules = compile_rules(&options)?;
+    let files: Vec<PathBuf> = Finder::new(options).entries()?.into_iter().filter(|m| !m.is_dir).map(|m| m.path).collect();
+    references::content_edits(&Relocation::default(), &files, &rules)
+}
+
+/// Ask about each of `edits`, returns the ones to make.
+fn confirm_edits(edits: Vec<Edit>, no_color: bool) -> Result<Vec<Edit>> {
+    let keys = keys();
+    let choices = [(keys.yes, "yes"), (keys.no, "no"), (keys.all, "all"), (keys.quit, "quit")];
+    let mut confirmed = Vec::new();
+    let mut edits = edits.into_iter();
+    while let Some(edit) = edits.next() {
+        let paint = |style: &Style, text: &str| if no_color { text.to_string() } else { style.paint(text).to_string() };
+        println!("{}:{}", paint(&theme().path, &edit.file.display().to_string()), edit.line);
+        println!("    - {}", edit.old);
+        println!("    + {}", paint(&theme().replacement, &edit.new));
+        let key = ask("Replace this line?", &choices, no_color)?;
+        if key == keys.yes {
+            confirmed.push(edit);
+        } else if key == keys.all {
+            confirmed.push(edit);
+            confirmed.extend(edits);
+            break;
+        } else if key == keys.quit {
+            bail!("Stopped, nothing was renamed or replaced");
+        }
+    }
+    Ok(confirmed)
+}
+
+/// `--on-conflict`: settle the renames onto taken paths before anything
+/// else looks at them. Those left out join `skipped`. Returns the sources
+/// of the renames that may overwrite.
+fn settle_conflicts(cli: &Cli, probe: &Probe, matches: &mut Vec<Match>, skipped: &mut Vec<Record>) -> Result<HashSet<PathBuf>> {
+    let strategy = match cli.on_conflict {
+        OnConflict::Error => return Ok(HashSet::new()),
+        OnConflict::Prompt if cli.no_interactive => bail!("--on-conflict prompt asks, drop --no-interactive"),
+        OnConflict::Overwrite if cli.copy => bail!("--copy never overwrites, use --on-conflict skip or number"),
+        strategy => strategy,
+    };
+    let resolved = resolve(std::mem::take(matches), probe, cli.case_sensitive, |m, existing| {
+        Ok(match strategy {
+            OnConflict::Prompt => confirm_conflict(m, existing && !cli.copy, cli.no_color)?,
+            OnConflict::Overwrite => Resolution::Overwrite,
+            OnConflict::Number => Resolution::Number,
+            _ => Resolution::Skip,
+        })
+    })?;
+    *matches = resolved.matches;
+    skipped.extend(resolved.skipped.iter().map(|m| {
+        let new_path = m.new_path();
+        let message = match probe.exists(&new_path) {
+            true => format!("{} already exists", new_path.display()),
+            false => format!("another rename goes to {}", new_path.display()),
+        };
+        Record {
+            message: Some(message),
+            ..Record::skipped(&m.path, m.is_dir, Reason::Collision)
+        }
+    }));
+    Ok(resolved.overwrite)
+}
+
+/// Ask what to do about `m`, whose new path is taken. Overwriting is only
+/// offered when an entry that stays has it.
+fn confirm_conflict(m: &Match, overwrite: bool, no_color: bool) -> Result<Resolution> {
+    print_match(m, no_color);
+    let keys = keys();
+    let mut choices = vec![(keys.skip, "skip")];
+    if overwrite {
+        choices.push((keys.overwrite, "overwrite"));
+    }
+    choices.extend([(keys.number, "number"), (keys.quit, "quit")]);
+    let question = match overwrite {
+        true => "New name is taken.",
+        false => "Another rename wants that name.",
+    };
+    let key = ask(question, &choices, no_color)?;
+    if key == keys.skip {
+        Ok(Resolution::Skip)
+    } else if key == keys.overwrite && overwrite {
+        Ok(Resolution::Overwrite)
+    } else if key == keys.number {
+        Ok(Resolution::Number)
+    } else {
+        bail!("Stopped at a conflict, nothing was renamed")
+    }
+}
+
+fn confirm_rename(m: &Match, no_color: bool) -> Result<ConfirmResult> {
+    print_annotated(m, no_color);
+    let keys = keys();
+    let choices = [
+        (keys.yes, "yes"),
+        (keys.no, "no"),
+        (keys.skip_dir, "skip rest of directory"),
+        (keys.all, "all"),
+        (keys.quit, "quit"),
+    ];
+    let key = ask("Replace filename/dirname?", &choices, no_color)?;
+    Ok(if key == keys.yes {
+        ConfirmResult::Yes
+    } else if key == keys.no {
+        ConfirmResult::No
+    } else if key == keys.skip_dir {
+        ConfirmResult::SkipDir
+    } else if key == keys.all {
+        ConfirmResult::All
+    } else {
+        ConfirmResult::Quit
+    })
+}
+
+fn confirm_chunk(count: usize, no_color: bool) -> Result<ConfirmResult> {
+    let keys = keys();
+    let choices = [(keys.yes, "yes"), (keys.no, "no"), (keys.edit, "edit"), (keys.quit, "quit")];
+    let key = ask(&format!("Rename these {} entries?", count), &choices, no_color)?;
+    Ok(if key == keys.yes {
+        ConfirmResult::Yes
+    } else if key == keys.no {
+        ConfirmResult::No
+    } else if key == keys.edit {
+        ConfirmResult::Edit
+    } else {
+        ConfirmResult::Quit
+    })
+}
+
+/// Ask `question` with the `choices`, like `[Y]es/[n]o/[q]uit`, and wait
+/// for one of their keys. Enter picks the first choice, Esc and Ctrl-C the
+/// last one.
+fn ask(question: &str, choices: &[(char, &str)], no_color: bool) -> Result<char> {
+    let options: Vec<String> = choices
+        .iter()
+        .enumerate()
+        .map(|(i, &(key, word))| {
+            let shown = if i == 0 { key.to_ascii_uppercase() } else { key };
+            match word.strip_prefix(key) {
+                Some(rest) => format!("[{}]{}", shown, rest),
+                None => format!("[{}] {}", shown, word),
+            }
+        })
+        .collect();
+    prompt(&format!("{} {}:", question, options.join("/")), no_color)?;
+    read_key(&choices.iter().map(|&(key, _)| key).collect::<String>())
+}
+
+fn prompt(question: &str, no_color: bool) -> Result<()> {
+    if no_color {
+        print!("{} ", question);
+    } else {
+        print!("{} ", theme().prompt.paint(question));
+    }
+    io::stdout().flush()?;
+    Ok(())
+}
+
+/// Read a single key press out of `keys` (lowercase). Enter selects the first
+/// key, Esc and Ctrl-C the last.
+fn read_key(keys: &str) -> Result<char> {
+    let cancel = keys.chars().last().unwrap_or('q');
+    enable_raw_mode()?;
+    let result = loop {
+        if let Event::Key(key_event) = event::read()? {
+            let choice = match key_event.code {
                 KeyCode::Char('c') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
                     print!("\r^C");
                     io::stdout().flush()?;
-                    break Ok(ConfirmResult::Quit);
+                    break Ok(cancel);
+                }
+                KeyCode::Enter => keys.chars().next(),
+                KeyCode::Esc => Some(cancel),
+                KeyCode::Char(c) => Some(c.to_ascii_lowercase()),
+                _ => None,
+            };
+            match choice {
+                Some(c) if keys.contains(c) => {
+                    print!("\r{}", c);
+                    io::stdout().flush()?;
+                    break Ok(c);
                 }
                 _ => continue,
             }
@@ -519,22 +2944,19 @@ fn confirm_rename(m: &Match, no_color: bool) -> Result<ConfirmResult> {
     result
 }
 
-fn perform_rename(m: &Match, no_color: bool) -> Result<()> {
-    let parent = m.path.parent().unwrap_or(Path::new("."));
-    let new_path = parent.join(&m.new_name);
-    
-    fs::rename(&m.path, &new_path)
-        .with_context(|| format!("Failed to rename {} to {}", m.path.display(), new_path.display()))?;
-    
+fn print_renamed(m: &Match, new_path: &Path, label: &str, no_color: bool) {
+    print_move(&m.path, new_path, label, no_color);
+}
+
+fn print_move(path: &Path, new_path: &Path, label: &str, no_color: bool) {
     if no_color {
-        println!("Renamed: {} -> {}", m.path.display(), new_path.display());
+        println!("{} {} -> {}", label, path.display(), new_path.display());
     } else {
         println!("{} {} {} {}", 
-            "Renamed:".cyan().bold(),
-            m.path.display().to_string().white(),
-            "->".yellow().bold(),
-            new_path.display().to_string().yellow().bold()
+            theme().label.paint(label),
+            theme().path.paint(&path.display().to_string()),
+            theme().replacement.paint("->").bold(),
+            theme().replacement.paint(&new_path.display().to_string()).bold()
         );
     }
-    Ok(())
 }
@@ -0,0 +1,976 @@
+use anyhow::{bail, Context, Result};
+use regex::{Captures, Regex};
+use crate::sidecar::split_extension;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// A search pattern with an optional replacement.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Rule {
+    pub pattern: String,
+    pub replacement: Option<String>,
+}
+
+/// Parse a patterns file: one pattern per line, optionally followed by a TAB
+/// and its replacement. Empty lines and lines starting with `#` are skipped.
+pub fn parse_rules(text: &str) -> Vec<Rule> {
+    text.lines()
+        .map(|line| line.strip_suffix('\r').unwrap_or(line))
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| match line.split_once('\t') {
+            Some((pattern, replacement)) => Rule {
+                pattern: pattern.to_string(),
+                replacement: Some(replacement.to_string()),
+            },
+            None => Rule {
+                pattern: line.to_string(),
+                replacement: None,
+            },
+        })
+        .collect()
+}
+
+/// A search pattern compiled once and matched against many names.
+#[derive(Debug, Clone)]
+pub struct NameMatcher {
+    pattern: Arc<str>,
+    regex: Option<Regex>,
+    case_sensitive: bool,
+    preserve_case: bool,
+    /// Match names close to the pattern instead of containing it.
+    similarity: Option<Similarity>,
+    transforms: Transforms,
+}
+
+impl NameMatcher {
+    pub fn new(pattern: &str, regex: bool, case_sensitive: bool) -> Result<Self> {
+        let regex = if regex {
+            Some(build_regex(pattern, case_sensitive)?)
+        } else {
+            None
+        };
+        Ok(NameMatcher {
+            pattern: pattern.into(),
+            regex,
+            case_sensitive,
+            preserve_case: false,
+            similarity: None,
+            transforms: Transforms::default(),
+        })
+    }
+
+    /// Use `transforms` for `{N|NAME}` in replacements.
+    pub fn with_transforms(self, transforms: Transforms) -> Self {
+        NameMatcher { transforms, ..self }
+    }
+
+    /// The transforms `{N|NAME}` and `{placeholder|NAME}` can use.
+    pub fn transforms(&self) -> &Transforms {
+        &self.transforms
+    }
+
+    /// Give every replaced occurrence the case of what it replaces, see
+    /// [`match_case`]. Literal patterns then replace all occurrences.
+    pub fn with_preserve_case(self, preserve_case: bool) -> Self {
+        NameMatcher { preserve_case, ..self }
+    }
+
+    /// Match the first part of a name within `max_edits` typos of the
+    /// pattern, see [`fuzzy_find`]. Only for literal patterns.
+    pub fn with_fuzzy(self, max_edits: Option<usize>) -> Self {
+        NameMatcher {
+            similarity: max_edits.map(Similarity::Typos),
+            ..self
+        }
+    }
+
+    /// Match the first words of a name sounding like the words of the
+    /// pattern, see [`phonetic_find`]. Only for literal patterns.
+    pub fn with_phonetic(self, phonetic: bool) -> Self {
+        match phonetic {
+            true => NameMatcher {
+                similarity: Some(Similarity::Sound),
+                ..self
+            },
+            false => self,
+        }
+    }
+
+    /// The part of `name` close to the pattern.
+    fn find_similar(&self, similarity: Similarity, name: &str) -> Option<Range<usize>> {
+        match similarity {
+            Similarity::Typos(max_edits) => fuzzy_find(name, &self.pattern, max_edits, self.case_sensitive),
+            Similarity::Sound => phonetic_find(name, &self.pattern),
+        }
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// The pattern, shared with every match it produces.
+    pub fn shared_pattern(&self) -> Arc<str> {
+        self.pattern.clone()
+    }
+
+    pub fn is_match(&self, name: &str) -> bool {
+        if let Some(similarity) = self.similarity {
+            return self.find_similar(similarity, name).is_some();
+        }
+        self.pattern.is_empty()
+            || check_match(name, &self.pattern, None, &self.regex, self.case_sensitive, false, &self.transforms).is_some()
+    }
+
+    /// What the pattern matched in `name` and what each group or wildcard
+    /// captured, or `None` if it does not match. Regexes only report their
+    /// first match.
+    pub fn explain(&self, name: &str) -> Option<(String, Vec<Capture>)> {
+        if let Some(regex) = &self.regex {
+            let found = regex.captures(name)?;
+            let captures = regex
+                .capture_names()
+                .enumerate()
+                .skip(1)
+                .map(|(i, group)| Capture {
+                    group: format!("${}", group.map_or_else(|| i.to_string(), str::to_string)),
+                    text: found.get(i).map(|text| text.as_str().to_string()),
+                })
+                .collect();
+            return Some((found[0].to_string(), captures));
+        }
+        if let Some(similarity) = self.similarity {
+            let found = self.find_similar(similarity, name)?;
+            return Some((name[found].to_string(), Vec::new()));
+        }
+        if self.pattern.contains('*') {
+            let captures = wildcard_captures(name, &self.pattern, self.case_sensitive)?
+                .into_iter()
+                .enumerate()
+                .map(|(i, text)| Capture {
+                    group: format!("{{{}}}", i + 1),
+                    text: Some(text),
+                })
+                .collect();
+            return Some((name.to_string(), captures));
+        }
+        let found = if self.case_sensitive {
+            name.find(&*self.pattern)
+        } else {
+            name.to_lowercase().find(&self.pattern.to_lowercase())
+        }?;
+        // Lowercasing can shift byte offsets, the pattern is close enough then
+        let matched = name.get(found..found + self.pattern.len()).unwrap_or(&self.pattern);
+        Some((matched.to_string(), Vec::new()))
+    }
+
+    /// The new name for `name`, or `None` if it does not match. An empty
+    /// pattern matches every name and replaces it as a whole.
+    pub fn replace(&self, name: &str, replacement: &str) -> Option<String> {
+        self.replace_with_values(name, replacement, &[])
+    }
+
+    /// [`replace`](Self::replace) with `values` taken as they are where
+    /// `replacement` holds their [`value_marker`]s.
+    pub fn replace_with_values(&self, name: &str, replacement: &str, values: &[String]) -> Option<String> {
+        if self.pattern.is_empty() {
+            return Some(fill_values(replacement, values));
+        }
+        if let Some(similarity) = self.similarity {
+            let found = self.find_similar(similarity, name)?;
+            let replacement = fill_values(replacement, values);
+            let replacement = match self.preserve_case {
+                true => match_case(&name[found.clone()], &replacement),
+                false => replacement,
+            };
+            let mut new_name = name.to_string();
+            new_name.replace_range(found, &replacement);
+            return Some(new_name);
+        }
+        check_match(
+            name,
+            &self.pattern,
+            Some((replacement, values)),
+            &self.regex,
+            self.case_sensitive,
+            self.preserve_case,
+            &self.transforms,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Similarity {
+    /// Up to this many typos.
+    Typos(usize),
+    /// Sounds alike.
+    Sound,
+}
+
+/// What `{N|NAME}` in a replacement can do to a capture, and
+/// `{placeholder|NAME}` to a placeholder: `map`, `roman2arabic`,
+/// `arabic2roman` and `trunc:N`.
+#[derive(Debug, Clone, Default)]
+pub struct Transforms {
+    /// The `map` lookup table, from `--map`.
+    map: Option<Arc<HashMap<String, String>>>,
+}
+
+impl Transforms {
+    pub fn with_map(map: HashMap<String, String>) -> Self {
+        Transforms {
+            map: Some(Arc::new(map)),
+        }
+    }
+
+    /// `text` run through the transform `name`, `None` if there is no such
+    /// transform. Text a transform can't handle, like keys missing from the
+    /// map or `IIII`, stays as it is.
+    pub fn apply(&self, name: &str, text: &str) -> Option<String> {
+        let converted = match name {
+            "map" => self.map.as_ref()?.get(text).cloned(),
+            "roman2arabic" => roman_value(text).map(|n| n.to_string()),
+            "arabic2roman" => text.parse().ok().and_then(roman),
+            _ => match name.strip_prefix("trunc:") {
+                Some(count) => Some(truncate(text, count.parse().ok()?)),
+                None => return None,
+            },
+        };
+        Some(converted.unwrap_or_else(|| text.to_string()))
+    }
+}
+
+/// Whether `name` is a transform, with or without a table for `map`.
+pub fn is_transform(name: &str) -> bool {
+    matches!(name, "map" | "roman2arabic" | "arabic2roman")
+        || name.strip_prefix("trunc:").is_some_and(|count| count.parse::<usize>().is_ok())
+}
+
+/// The first `count` grapheme clusters of `text`, so accents, emoji with
+/// skin tones and flags stay whole.
+fn truncate(text: &str, count: usize) -> String {
+    text.graphemes(true).take(count).collect()
+}
+
+const ROMAN: &[(u32, &str)] = &[
+    (1000, "M"),
+    (900, "CM"),
+    (500, "D"),
+    (400, "CD"),
+    (100, "C"),
+    (90, "XC"),
+    (50, "L"),
+    (40, "XL"),
+    (10, "X"),
+    (9, "IX"),
+    (5, "V"),
+    (4, "IV"),
+    (1, "I"),
+];
+
+/// `n` in roman numerals, for 1 to 3999.
+fn roman(mut n: u32) -> Option<String> {
+    if !(1..4000).contains(&n) {
+        return None;
+    }
+    let mut out = String::new();
+    for &(value, numeral) in ROMAN {
+        while n >= value {
+            out.push_str(numeral);
+            n -= value;
+        }
+    }
+    Some(out)
+}
+
+/// The value of a roman numeral in either case. Only the canonical
+/// spelling counts, so `IIII` and `VX` are not numbers.
+fn roman_value(text: &str) -> Option<u32> {
+    let upper = text.to_ascii_uppercase();
+    let mut rest = upper.as_str();
+    let mut n = 0;
+    for &(value, numeral) in ROMAN {
+        while let Some(after) = rest.strip_prefix(numeral) {
+            n += value;
+            rest = after;
+        }
+    }
+    (rest.is_empty() && roman(n).as_deref() == Some(upper.as_str())).then_some(n)
+}
+
+/// Read a lookup table: one `key<TAB>value` pair per line. Empty lines and
+/// lines starting with `#` are skipped.
+pub fn load_map(path: &Path) -> Result<HashMap<String, String>> {
+    let text = fs::read_to_string(path).with_context(|| format!("Failed to read map {}", path.display()))?;
+    let mut map = HashMap::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('\t') else {
+            bail!("{}:{}: expected key<TAB>value", path.display(), i + 1);
+        };
+        map.insert(key.to_string(), value.to_string());
+    }
+    Ok(map)
+}
+
+/// A group of a regex or a `*` of a wildcard pattern, and what it matched.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Capture {
+    /// How the replacement refers to it: `$1`, `$name` or `{1}`.
+    pub group: String,
+    /// `None` for optional groups that didn't take part.
+    pub text: Option<String>,
+}
+
+pub fn build_regex(pattern: &str, case_sensitive: bool) -> Result<Regex> {
+    let mut builder = regex::RegexBuilder::new(pattern);
+    builder.case_insensitive(!case_sensitive);
+    builder.build().context("Invalid regex pattern")
+}
+
+pub fn check_match(
+    filename: &str,
+    pattern: &str,
+    replacement: Option<(&str, &[String])>,
+    regex: &Option<Regex>,
+    case_sensitive: bool,
+    preserve_case: bool,
+    transforms: &Transforms,
+) -> Option<String> {
+    // The replacement comes with the values its markers stand for
+    let (replacement, values) = match replacement {
+        Some((replacement, values)) => (Some(replacement), values),
+        None => (None, &[][..]),
+    };
+    if let Some(regex) = regex {
+        if let Some(replacement) = replacement {
+            if !regex.is_match(filename) {
+                None
+            } else if replacement.contains('{') || replacement.contains('\\') || preserve_case {
+                let parts = split_case_escapes(replacement);
+                let replaced = regex.replace_all(filename, |found: &Captures| {
+                    let captures: Vec<String> =
+                        found.iter().skip(1).map(|group| group.map_or("", |g| g.as_str()).to_string()).collect();
+                    let mut expanded = String::new();
+                    let mut case = Case::default();
+                    for (escape, part) in &parts {
+                        if let Some(escape) = escape {
+                            case.switch(*escape);
+                        }
+                        // `{N}` and `{N|NAME}` first, then `$N`
+                        let filled = if part.contains('{') {
+                            fill_captures(part, &captures, transforms, true)
+                        } else {
+                            part.to_string()
+                        };
+                        let mut piece = String::new();
+                        found.expand(&filled, &mut piece);
+                        case.push(&mut expanded, &fill_values(&piece, values));
+                    }
+                    if preserve_case {
+                        expanded = match_case(&found[0], &expanded);
+                    }
+                    expanded
+                });
+                Some(replaced.to_string())
+            } else if regex.is_match(filename) {
+                Some(fill_values(&regex.replace_all(filename, replacement), values))
+            } else {
+                None
+            }
+        } else {
+            if regex.is_match(filename) {
+                Some(filename.to_string())
+            } else {
+                None
+            }
+        }
+    } else if pattern.contains('*') {
+        // The wildcards have to cover the whole name, and what they matched
+        // goes into `{1}`, `{2}`, ... of the replacement, which replaces the
+        // whole name
+        let captures = wildcard_captures(filename, pattern, case_sensitive)?;
+        match replacement {
+            Some(replacement) => {
+                let filled = fill_values(&fill_captures(replacement, &captures, transforms, false), values);
+                Some(if preserve_case { match_case(filename, &filled) } else { filled })
+            }
+            None => Some(filename.to_string()),
+        }
+    } else if preserve_case && !case_sensitive {
+        let replacement = replacement.map_or_else(|| pattern.to_string(), |r| fill_values(r, values));
+        replace_preserving_case(filename, pattern, &replacement)
+    } else {
+        let matches = if case_sensitive {
+            simple_match(filename, pattern)
+        } else {
+            simple_match(&filename.to_lowercase(), &pattern.to_lowercase())
+        };
+
+        if matches {
+            if let Some(replacement) = replacement {
+                Some(simple_replace(filename, pattern, &fill_values(replacement, values), case_sensitive))
+            } else {
+                Some(filename.to_string())
+            }
+        } else {
+            None
+        }
+    }
+}
+
+/// The edits `--fuzzy` allows without a number: one per four characters
+/// of the pattern, at least one.
+pub fn default_fuzziness(pattern: &str) -> usize {
+    (pattern.chars().count() / 4).max(1)
+}
+
+/// Byte range of the part of `text` closest to `pattern`, if it is at most
+/// `max_edits` insertions, deletions, substitutions or swaps of neighbours
+/// away. Ties go to the earlier part, then to the one as long as the
+/// pattern, so `recieve` and `receve` both find `receive`.
+pub fn fuzzy_find(text: &str, pattern: &str, max_edits: usize, case_sensitive: bool) -> Option<Range<usize>> {
+    let fold = |s: &str| -> Vec<char> {
+        match case_sensitive {
+            true => s.chars().collect(),
+            false => s.chars().flat_map(char::to_lowercase).collect(),
+        }
+    };
+    let pattern = fold(pattern);
+    if pattern.is_empty() {
+        return None;
+    }
+    // Offsets of the chars, lowercasing may not keep the count
+    let offsets: Vec<usize> = text.char_indices().map(|(i, _)| i).chain([text.len()]).collect();
+    let chars: Vec<Vec<char>> = text.chars().map(|c| fold(c.encode_utf8(&mut [0; 4]))).collect();
+    let shortest = pattern.len().saturating_sub(max_edits).max(1);
+    let longest = pattern.len() + max_edits;
+    let mut best: Option<((usize, usize, usize), Range<usize>)> = None;
+    for start in 0..chars.len() {
+        for end in start + shortest..=(start + longest).min(chars.len()) {
+            let candidate: Vec<char> = chars[start..end].iter().flatten().copied().collect();
+            let distance = edit_distance(&candidate, &pattern);
+            if distance > max_edits {
+                continue;
+            }
+            let key = (distance, start, (end - start).abs_diff(pattern.len()));
+            if best.as_ref().is_none_or(|(best_key, _)| key < *best_key) {
+                best = Some((key, offsets[start]..offsets[end]));
+            }
+        }
+    }
+    best.map(|(_, range)| range)
+}
+
+/// Byte range of the first run of words in `text` sounding like the words
+/// of `pattern`, so `Jon Smyth` finds `john_smith`. Words sound alike when
+/// they have the same [`soundex`] code; numbers and words without latin
+/// letters have to be equal, ignoring case.
+pub fn phonetic_find(text: &str, pattern: &str) -> Option<Range<usize>> {
+    let key = |word: &str| soundex(word).unwrap_or_else(|| word.to_lowercase());
+    let wanted: Vec<String> = words(pattern).into_iter().map(|range| key(&pattern[range])).collect();
+    if wanted.is_empty() {
+        return None;
+    }
+    let found = words(text);
+    let keys: Vec<String> = found.iter().map(|range| key(&text[range.clone()])).collect();
+    let start = keys.windows(wanted.len()).position(|window| window == wanted.as_slice())?;
+    Some(found[start].start..found[start + wanted.len() - 1].end)
+}
+
+/// Byte ranges of the runs of letters and the runs of digits.
+fn words(text: &str) -> Vec<Range<usize>> {
+    let kind = |c: char| match c {
+        c if c.is_alphabetic() => 1,
+        c if c.is_numeric() => 2,
+        _ => 0,
+    };
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    let mut last = 0;
+    for (i, c) in text.char_indices() {
+        let current = kind(c);
+        if current != 0 && current == last {
+            if let Some(range) = ranges.last_mut() {
+                range.end = i + c.len_utf8();
+            }
+        } else if current != 0 {
+            ranges.push(i..i + c.len_utf8());
+        }
+        last = current;
+    }
+    ranges
+}
+
+/// The American Soundex code of `word`, like `S530` for both `Smith` and
+/// `Smyth`. Letters outside a-z are ignored, `None` if there are none.
+pub fn soundex(word: &str) -> Option<String> {
+    let digit = |c: char| match c {
+        'b' | 'f' | 'p' | 'v' => Some('1'),
+        'c' | 'g' | 'j' | 'k' | 'q' | 's' | 'x' | 'z' => Some('2'),
+        'd' | 't' => Some('3'),
+        'l' => Some('4'),
+        'm' | 'n' => Some('5'),
+        'r' => Some('6'),
+        _ => None,
+    };
+    let mut letters = word.chars().filter(char::is_ascii_alphabetic).map(|c| c.to_ascii_lowercase());
+    let first = letters.next()?;
+    let mut code = first.to_ascii_uppercase().to_string();
+    let mut previous = digit(first);
+    for c in letters {
+        let current = digit(c);
+        if current.is_some() && current != previous {
+            code.extend(current);
+            if code.len() == 4 {
+                break;
+            }
+        }
+        // `h` and `w` don't separate equal codes, vowels do
+        if !matches!(c, 'h' | 'w') {
+            previous = current;
+        }
+    }
+    Some(format!("{:0<4}", code))
+}
+
+/// Edits turning `a` into `b`, counting a swap of neighbours as one.
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut rows = vec![(0..=b.len()).collect::<Vec<usize>>()];
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            row[j] = (rows[i - 1][j] + 1).min(row[j - 1] + 1).min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(rows[i - 2][j - 2] + 1);
+            }
+        }
+        rows.push(row);
+    }
+    rows[a.len()][b.len()]
+}
+
+pub fn simple_match(text: &str, pattern: &str) -> bool {
+    if pattern.contains('*') {
+        wildcard_captures(text, pattern, true).is_some()
+    } else {
+        text.contains(pattern)
+    }
+}
+
+/// Whether a literal pattern uses regex syntax like `\d`, groups, classes or
+/// `+`, which suggests `--regex` was forgotten.
+pub fn looks_like_regex(pattern: &str) -> bool {
+    let escape = pattern
+        .as_bytes()
+        .windows(2)
+        .any(|pair| pair[0] == b'\\' && b"dDwWsSb.()[]+*?".contains(&pair[1]));
+    escape || (pattern.contains('(') && pattern.contains(')')) || pattern.contains(['[', '+'])
+}
+
+/// Match all of `text` against `pattern`, where `*` stands for any run of
+/// characters, and return what each `*` matched. Earlier wildcards match
+/// as little as possible.
+pub fn wildcard_captures(text: &str, pattern: &str, case_sensitive: bool) -> Option<Vec<String>> {
+    let text: Vec<char> = text.chars().collect();
+    let pattern: Vec<char> = pattern.chars().collect();
+    let same = |a: char, b: char| {
+        a == b || (!case_sensitive && a.to_lowercase().eq(b.to_lowercase()))
+    };
+
+    // Backtracking over the wildcards, `starts[i]` being where the i-th
+    // wildcard's match begins
+    let mut starts: Vec<usize> = Vec::new();
+    let mut ends: Vec<usize> = Vec::new();
+    let (mut t, mut p) = (0, 0);
+    loop {
+        if p == pattern.len() && t == text.len() {
+            break;
+        }
+        match pattern.get(p) {
+            Some('*') => {
+                starts.push(t);
+                ends.push(t);
+                p += 1;
+                continue;
+            }
+            Some(&c) if t < text.len() && same(text[t], c) => {
+                t += 1;
+                p += 1;
+                continue;
+            }
+            _ => {}
+        }
+        // Mismatch: let the last wildcard that still can swallow one more
+        // character, forgetting the ones after it
+        loop {
+            let last = ends.len().checked_sub(1)?;
+            if ends[last] < text.len() {
+                ends[last] += 1;
+                t = ends[last];
+                p = nth_wildcard(&pattern, last)? + 1;
+                break;
+            }
+            starts.pop();
+            ends.pop();
+        }
+    }
+    Some(
+        starts
+            .iter()
+            .zip(&ends)
+            .map(|(&start, &end)| text[start..end].iter().collect())
+            .collect(),
+    )
+}
+
+fn nth_wildcard(pattern: &[char], n: usize) -> Option<usize> {
+    pattern.iter().enumerate().filter(|(_, &c)| c == '*').nth(n).map(|(i, _)| i)
+}
+
+/// Replace `{1}`, `{2}`, ... with the captures, and `{1|NAME}` with the
+/// capture run through a transform (several chain: `{1|roman2arabic|map}`). Other
+/// braces are left alone. With `escape_dollars` the filled in text is kept
+/// safe from a regex expansion afterwards.
+fn fill_captures(replacement: &str, captures: &[String], transforms: &Transforms, escape_dollars: bool) -> String {
+    let mut out = String::new();
+    let mut rest = replacement;
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        let after = &rest[open + 1..];
+        // `${1}` is the regex's own syntax
+        let regex_group = escape_dollars && out.chars().rev().take_while(|&c| c == '$').count() % 2 == 1;
+        let filled = after.find('}').filter(|_| !regex_group).and_then(|close| {
+            let mut parts = after[..close].split('|');
+            let n: usize = parts.next()?.parse().ok()?;
+            let mut text = captures.get(n.checked_sub(1)?)?.clone();
+            for name in parts {
+                text = transforms.apply(name, &text)?;
+            }
+            Some((close, text))
+        });
+        match filled {
+            Some((close, text)) if escape_dollars => {
+                out.push_str(&text.replace('$', "$$"));
+                rest = &after[close + 1..];
+            }
+            Some((close, text)) => {
+                out.push_str(&text);
+                rest = &after[close + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Placeholder values set aside from a replacement are marked by Unicode
+/// noncharacters, which no sane file name contains and which none of the
+/// replacement syntax reads, so the values are put in only after `$N`,
+/// `{N}` and case escapes are done with.
+const VALUE_MARKERS: std::ops::Range<u32> = 0xFDD0..0xFDF0;
+
+/// The marker standing in for value `index`, `None` past the 32 there are.
+pub fn value_marker(index: usize) -> Option<char> {
+    let marker = VALUE_MARKERS.start.checked_add(index.try_into().ok()?)?;
+    VALUE_MARKERS.contains(&marker).then(|| char::from_u32(marker)).flatten()
+}
+
+/// The value `c` is the marker of, if it is one.
+fn marker_index(c: char) -> Option<usize> {
+    VALUE_MARKERS.contains(&(c as u32)).then(|| (c as u32 - VALUE_MARKERS.start) as usize)
+}
+
+/// Whether `text` contains value markers, which would be taken for values.
+pub fn has_value_markers(text: &str) -> bool {
+    text.chars().any(|c| marker_index(c).is_some())
+}
+
+/// `text` with value markers replaced by their `values`.
+pub fn fill_values(text: &str, values: &[String]) -> String {
+    if values.is_empty() {
+        return text.to_string();
+    }
+    let mut out = String::new();
+    for c in text.chars() {
+        match marker_index(c).and_then(|index| values.get(index)) {
+            Some(value) => out.push_str(value),
+            None => out.push(c),
+        }
+    }
+    out
+}
+
+/// A case change in a regex replacement, as in sed and perl.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CaseEscape {
+    /// `\U`, uppercase up to `\E`.
+    Upper,
+    /// `\L`, lowercase up to `\E`.
+    Lower,
+    /// `\E`.
+    End,
+    /// `\u`, uppercase the next character.
+    UpperNext,
+    /// `\l`, lowercase the next character.
+    LowerNext,
+}
+
+/// `replacement` cut at its case escapes, each part with the escape in
+/// front of it. Other backslashes are left alone.
+fn split_case_escapes(replacement: &str) -> Vec<(Option<CaseEscape>, &str)> {
+    let mut parts = Vec::new();
+    let mut escape = None;
+    let mut start = 0;
+    for (i, _) in replacement.match_indices('\\') {
+        let next = match replacement[i + 1..].chars().next() {
+            Some('U') => CaseEscape::Upper,
+            Some('L') => CaseEscape::Lower,
+            Some('E') => CaseEscape::End,
+            Some('u') => CaseEscape::UpperNext,
+            Some('l') => CaseEscape::LowerNext,
+            _ => continue,
+        };
+        parts.push((escape, &replacement[start..i]));
+        escape = Some(next);
+        start = i + 2;
+    }
+    parts.push((escape, &replacement[start..]));
+    parts
+}
+
+/// The case changes in effect while a replacement is put together.
+#[derive(Debug, Default)]
+struct Case {
+    /// `\U` (`true`) or `\L` (`false`) until `\E`.
+    all: Option<bool>,
+    /// `\u` or `\l` waiting for a character.
+    next: Option<bool>,
+}
+
+impl Case {
+    fn switch(&mut self, escape: CaseEscape) {
+        match escape {
+            CaseEscape::Upper => self.all = Some(true),
+            CaseEscape::Lower => self.all = Some(false),
+            CaseEscape::End => self.all = None,
+            CaseEscape::UpperNext => self.next = Some(true),
+            CaseEscape::LowerNext => self.next = Some(false),
+        }
+    }
+
+    /// Append `text` to `out` in the case asked for.
+    fn push(&mut self, out: &mut String, text: &str) {
+        let convert = |out: &mut String, c: char, upper: Option<bool>| match upper {
+            Some(true) => out.extend(c.to_uppercase()),
+            Some(false) => out.extend(c.to_lowercase()),
+            None => out.push(c),
+        };
+        let mut chars = text.chars();
+        if let Some(upper) = self.next {
+            let Some(first) = chars.next() else {
+                return;
+            };
+            convert(out, first, Some(upper));
+            self.next = None;
+        }
+        for c in chars {
+            convert(out, c, self.all);
+        }
+    }
+}
+
+/// Replace every occurrence of `pattern` in any case, each with
+/// `replacement` in the case of the occurrence. `None` if there is none.
+fn replace_preserving_case(text: &str, pattern: &str, replacement: &str) -> Option<String> {
+    if pattern.is_empty() {
+        return None;
+    }
+    let mut out = String::new();
+    let mut rest = text;
+    let mut found = false;
+    'scan: while !rest.is_empty() {
+        let mut candidate = rest.chars();
+        let mut len = 0;
+        for expected in pattern.chars() {
+            match candidate.next() {
+                Some(c) if c == expected || c.to_lowercase().eq(expected.to_lowercase()) => len += c.len_utf8(),
+                _ => {
+                    let skip = rest.chars().next().map_or(0, char::len_utf8);
+                    out.push_str(&rest[..skip]);
+                    rest = &rest[skip..];
+                    continue 'scan;
+                }
+            }
+        }
+        out.push_str(&match_case(&rest[..len], replacement));
+        rest = &rest[len..];
+        found = true;
+    }
+    found.then_some(out)
+}
+
+/// `replacement` in the case of `original`: all caps if it is all caps,
+/// lowercase if it is lowercase, capitalized if it is capitalized, and as
+/// written otherwise.
+pub fn match_case(original: &str, replacement: &str) -> String {
+    let letters: Vec<char> = original.chars().filter(|c| c.is_uppercase() || c.is_lowercase()).collect();
+    let Some((first, rest)) = letters.split_first() else {
+        return replacement.to_string();
+    };
+    if !rest.is_empty() && letters.iter().all(|c| c.is_uppercase()) {
+        return replacement.to_uppercase();
+    }
+    if letters.iter().all(|c| c.is_lowercase()) {
+        return replacement.to_lowercase();
+    }
+    if first.is_uppercase() && rest.iter().all(|c| c.is_lowercase()) {
+        return capitalize(replacement);
+    }
+    replacement.to_string()
+}
+
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars.as_str().to_lowercase().chars()).collect(),
+        None => String::new(),
+    }
+}
+
+/// A quick change to the stem of a name, from the playground.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StemEdit {
+    Prefix(String),
+    Suffix(String),
+    Upper,
+    Lower,
+    /// Every word capitalized.
+    Title,
+}
+
+impl StemEdit {
+    /// `name` with the edit applied to its stem. Directories are all stem.
+    pub fn apply(&self, name: &str, is_dir: bool) -> String {
+        let (stem, extension) = if is_dir { (name, None) } else { split_extension(name) };
+        let stem = match self {
+            StemEdit::Prefix(text) => format!("{}{}", text, stem),
+            StemEdit::Suffix(text) => format!("{}{}", stem, text),
+            StemEdit::Upper => stem.to_uppercase(),
+            StemEdit::Lower => stem.to_lowercase(),
+            StemEdit::Title => {
+                let mut start = true;
+                stem.chars()
+                    .flat_map(|c| {
+                        let word_start = std::mem::replace(&mut start, !c.is_alphanumeric());
+                        if word_start {
+                            c.to_uppercase().collect::<Vec<_>>()
+                        } else {
+                            c.to_lowercase().collect()
+                        }
+                    })
+                    .collect()
+            }
+        };
+        match extension {
+            Some(extension) => format!("{}.{}", stem, extension),
+            None => stem,
+        }
+    }
+}
+
+pub fn simple_replace(text: &str, pattern: &str, replacement: &str, case_sensitive: bool) -> String {
+    if case_sensitive {
+        text.replace(pattern, replacement)
+    } else {
+        // Case-insensitive replace (simple version)
+        let lower_text = text.to_lowercase();
+        let lower_pattern = pattern.to_lowercase();
+        if let Some(pos) = lower_text.find(&lower_pattern) {
+            let mut result = text.to_string();
+            result.replace_range(pos..pos + pattern.len(), replacement);
+            result
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn earlier_wildcards_match_as_little_as_possible() {
+        assert_eq!(wildcard_captures("a_b_c.txt", "*_*.txt", true).unwrap(), ["a", "b_c"]);
+        assert_eq!(wildcard_captures("IMG_001.JPG", "img_*.jpg", false).unwrap(), ["001"]);
+        assert_eq!(wildcard_captures("IMG_001.JPG", "img_*.jpg", true), None);
+        assert_eq!(wildcard_captures("abc", "**c", true).unwrap(), ["", "ab"]);
+        assert_eq!(wildcard_captures("report.txt", "*.md", true), None);
+        assert_eq!(wildcard_captures("", "*", true).unwrap(), [""]);
+    }
+
+    #[test]
+    fn roman_numerals_round_trip_in_canonical_spelling_only() {
+        assert_eq!(roman(1994).as_deref(), Some("MCMXCIV"));
+        assert_eq!(roman(3999).as_deref(), Some("MMMCMXCIX"));
+        assert_eq!(roman(0), None);
+        assert_eq!(roman(4000), None);
+        assert_eq!(roman_value("mcmxciv"), Some(1994));
+        assert_eq!(roman_value("XIV"), Some(14));
+        assert_eq!(roman_value("IIII"), None);
+        assert_eq!(roman_value("VX"), None);
+        assert_eq!(roman_value(""), None);
+        let transforms = Transforms::default();
+        assert_eq!(transforms.apply("arabic2roman", "12").as_deref(), Some("XII"));
+        assert_eq!(transforms.apply("arabic2roman", "4000").as_deref(), Some("4000"));
+        assert_eq!(transforms.apply("roman2arabic", "IIII").as_deref(), Some("IIII"));
+    }
+
+    #[test]
+    fn fuzzy_matches_find_the_closest_part_of_the_name() {
+        assert_eq!(fuzzy_find("my_receive_notes", "recieve", 1, true), Some(3..10));
+        assert_eq!(fuzzy_find("my_receive_notes", "receve", 1, true), Some(3..10));
+        assert_eq!(fuzzy_find("MY_RECEIVE", "recieve", 1, false), Some(3..10));
+        assert_eq!(fuzzy_find("MY_RECEIVE", "recieve", 1, true), None);
+        assert_eq!(fuzzy_find("my_notes", "receive", 1, true), None);
+        // Byte offsets, not char offsets
+        assert_eq!(fuzzy_find("é_receive", "recieve", 1, true), Some(3..10));
+        assert_eq!(fuzzy_find("receive", "", 1, true), None);
+    }
+
+    #[test]
+    fn soundex_codes_follow_the_american_rules() {
+        assert_eq!(soundex("Smith").as_deref(), Some("S530"));
+        assert_eq!(soundex("Smyth").as_deref(), Some("S530"));
+        assert_eq!(soundex("Robert").as_deref(), Some("R163"));
+        assert_eq!(soundex("Rupert").as_deref(), Some("R163"));
+        // The `h` between `s` and `c` does not keep their equal codes apart
+        assert_eq!(soundex("Ashcraft").as_deref(), Some("A261"));
+        assert_eq!(soundex("Tymczak").as_deref(), Some("T522"));
+        assert_eq!(soundex("Pfister").as_deref(), Some("P236"));
+        assert_eq!(soundex("Lee").as_deref(), Some("L000"));
+        assert_eq!(soundex("2024"), None);
+    }
+
+    #[test]
+    fn phonetic_matches_cover_whole_runs_of_words() {
+        assert_eq!(phonetic_find("john_smith_2024.txt", "Jon Smyth"), Some(0..10));
+        assert_eq!(phonetic_find("notes_2024", "2024"), Some(6..10));
+        assert_eq!(phonetic_find("notes_2025", "2024"), None);
+        assert_eq!(phonetic_find("smith_john", "Jon Smyth"), None);
+        assert_eq!(phonetic_find("john_smith", "_"), None);
+    }
+}
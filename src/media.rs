@@ -0,0 +1,158 @@
+//! Video metadata from MP4/QuickTime containers (`.mp4`, `.m4v`, `.mov`)
+//! for the media placeholders. Only the `moov` box is read, never the
+//! sample data.
+
+use crate::open_regular;
+use anyhow::{bail, Context, Result};
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Upper bound for the `moov` box, which normally is a few hundred KiB.
+const MAX_MOOV_SIZE: u64 = 64 << 20;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MediaInfo {
+    /// Duration in seconds.
+    pub duration: Option<f64>,
+    /// Codec of the first video track, e.g. `h264`.
+    pub codec: Option<String>,
+    /// Average frame rate of the first video track.
+    pub fps: Option<f64>,
+}
+
+impl MediaInfo {
+    pub fn read(path: &Path) -> Result<MediaInfo> {
+        let moov = read_moov(path).with_context(|| format!("Failed to read media info of {}", path.display()))?;
+        Ok(parse_moov(&moov))
+    }
+}
+
+fn read_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn read_u64(data: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(data.get(at..at + 8)?.try_into().ok()?))
+}
+
+/// Find the top-level `moov` box and return its body.
+fn read_moov(path: &Path) -> Result<Vec<u8>> {
+    let mut file = BufReader::new(open_regular(path)?);
+    let len = file.get_ref().metadata()?.len();
+    let mut offset = 0;
+    while offset + 8 <= len {
+        let mut header = [0u8; 16];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut header[..8])?;
+        let kind: [u8; 4] = header[4..8].try_into()?;
+        let (size, header_len) = match read_u32(&header, 0).unwrap_or(0) {
+            0 => (len - offset, 8),
+            1 => {
+                file.read_exact(&mut header[8..16])?;
+                (read_u64(&header, 8).unwrap_or(0), 16)
+            }
+            size => (size as u64, 8),
+        };
+        if size < header_len {
+            bail!("Corrupt box at offset {}", offset);
+        }
+        if &kind == b"moov" {
+            let body_len = size - header_len;
+            if body_len > MAX_MOOV_SIZE {
+                bail!("moov box too large ({} bytes)", body_len);
+            }
+            let mut body = vec![0; body_len as usize];
+            file.read_exact(&mut body)?;
+            return Ok(body);
+        }
+        offset += size;
+    }
+    bail!("Not an MP4/QuickTime file (no moov box)")
+}
+
+/// Child boxes of a box body as (type, body) pairs.
+fn boxes(mut data: &[u8]) -> Vec<(&[u8], &[u8])> {
+    let mut children = Vec::new();
+    while data.len() >= 8 {
+        let (size, header_len) = match read_u32(data, 0).unwrap_or(0) {
+            0 => (data.len(), 8),
+            1 => match read_u64(data, 8) {
+                Some(size) => (size as usize, 16),
+                None => break,
+            },
+            size => (size as usize, 8),
+        };
+        if size < header_len || size > data.len() {
+            break;
+        }
+        children.push((&data[4..8], &data[header_len..size]));
+        data = &data[size..];
+    }
+    children
+}
+
+fn child<'a>(data: &'a [u8], kind: &[u8]) -> Option<&'a [u8]> {
+    boxes(data).into_iter().find(|(k, _)| *k == kind).map(|(_, body)| body)
+}
+
+/// Timescale and duration from an `mvhd` or `mdhd` body.
+fn timing(body: &[u8]) -> Option<(u32, u64)> {
+    match body.first()? {
+        1 => Some((read_u32(body, 20)?, read_u64(body, 24)?)),
+        _ => Some((read_u32(body, 12)?, read_u32(body, 16)? as u64)),
+    }
+}
+
+fn codec_name(fourcc: &[u8]) -> String {
+    match fourcc {
+        b"avc1" | b"avc3" => "h264".to_string(),
+        b"hvc1" | b"hev1" => "hevc".to_string(),
+        b"av01" => "av1".to_string(),
+        b"vp09" => "vp9".to_string(),
+        b"vp08" => "vp8".to_string(),
+        b"mp4v" => "mpeg4".to_string(),
+        b"apcn" | b"apch" | b"apcs" | b"apco" | b"ap4h" | b"ap4x" => "prores".to_string(),
+        other => String::from_utf8_lossy(other).trim().to_string(),
+    }
+}
+
+fn parse_moov(moov: &[u8]) -> MediaInfo {
+    let mut info = MediaInfo {
+        duration: child(moov, b"mvhd")
+            .and_then(timing)
+            .filter(|(scale, _)| *scale > 0)
+            .map(|(scale, duration)| duration as f64 / scale as f64),
+        ..MediaInfo::default()
+    };
+
+    for (kind, trak) in boxes(moov) {
+        if kind != b"trak" {
+            continue;
+        }
+        let Some(mdia) = child(trak, b"mdia") else {
+            continue;
+        };
+        if child(mdia, b"hdlr").and_then(|h| h.get(8..12)) != Some(b"vide".as_slice()) {
+            continue;
+        }
+        let stbl = child(mdia, b"minf").and_then(|minf| child(minf, b"stbl"));
+        info.codec = stbl
+            .and_then(|stbl| child(stbl, b"stsd"))
+            .and_then(|stsd| stsd.get(12..16))
+            .map(codec_name);
+
+        let samples: Option<u64> = stbl.and_then(|stbl| child(stbl, b"stts")).map(|stts| {
+            let count = read_u32(stts, 4).unwrap_or(0) as usize;
+            (0..count).filter_map(|i| read_u32(stts, 8 + i * 8)).map(u64::from).sum()
+        });
+        let seconds = child(mdia, b"mdhd")
+            .and_then(timing)
+            .filter(|(scale, duration)| *scale > 0 && *duration > 0)
+            .map(|(scale, duration)| duration as f64 / scale as f64);
+        if let (Some(samples), Some(seconds)) = (samples, seconds) {
+            info.fps = Some(samples as f64 / seconds);
+        }
+        break;
+    }
+    info
+}
@@ -0,0 +1,47 @@
+//! Per-run cache of file metadata, so filters and placeholders looking at
+//! the same entry stat it only once.
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// Metadata of visited paths, not following a final symlink. Safe to share
+/// between threads. Entries are never invalidated, so a cache must not
+/// outlive the renames it was gathered for.
+#[derive(Debug, Default)]
+pub struct MetadataCache {
+    entries: Mutex<HashMap<PathBuf, Arc<Metadata>>>,
+}
+
+impl MetadataCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, path: &Path) -> io::Result<Arc<Metadata>> {
+        if let Some(metadata) = self.entries.lock().unwrap().get(path) {
+            return Ok(metadata.clone());
+        }
+        let metadata = Arc::new(fs::symlink_metadata(path)?);
+        self.entries.lock().unwrap().insert(path.to_path_buf(), metadata.clone());
+        Ok(metadata)
+    }
+
+    /// Modification time of `path`.
+    pub fn modified(&self, path: &Path) -> Result<SystemTime> {
+        self.get(path)
+            .and_then(|m| m.modified())
+            .with_context(|| format!("Modification time of {} is not available", path.display()))
+    }
+
+    /// Creation time of `path`, if the platform and filesystem record one.
+    pub fn created(&self, path: &Path) -> Result<SystemTime> {
+        self.get(path)
+            .and_then(|m| m.created())
+            .with_context(|| format!("Creation time of {} is not available", path.display()))
+    }
+}
@@ -0,0 +1,73 @@
+//! Who owns what: noticing a run as root, keeping the owner of copies, and
+//! handing renamed entries back to the user behind `sudo` for
+//! `--chown-to-invoker`.
+//!
+//! Renames keep the owner by nature, the entry stays the same. Copies and
+//! repacked archives are new files, owned by whoever runs fnr, so as root
+//! they get the owner of the original. Other users can't give files away
+//! and keep their copies, like `cp` does.
+
+use anyhow::{bail, Result};
+use std::fs::Metadata;
+use std::io;
+use std::path::Path;
+
+/// Whether fnr runs with root privileges.
+#[cfg(unix)]
+pub fn is_root() -> bool {
+    // SAFETY: geteuid has no preconditions and cannot fail
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(not(unix))]
+pub fn is_root() -> bool {
+    false
+}
+
+/// The user and group that ran fnr through `sudo`, from `SUDO_UID` and
+/// `SUDO_GID`.
+pub fn invoker() -> Option<(u32, u32)> {
+    let id = |var| std::env::var(var).ok()?.parse().ok();
+    Some((id("SUDO_UID")?, id("SUDO_GID")?))
+}
+
+/// Make `path` owned by `uid` and `gid`. Links themselves change owner,
+/// not what they point to.
+#[cfg(unix)]
+pub fn chown(path: &Path, uid: u32, gid: u32) -> Result<()> {
+    use anyhow::Context;
+    std::os::unix::fs::lchown(path, Some(uid), Some(gid))
+        .with_context(|| format!("Failed to change the owner of {}", path.display()))
+}
+
+#[cfg(not(unix))]
+pub fn chown(path: &Path, _uid: u32, _gid: u32) -> Result<()> {
+    bail!("Cannot change the owner of {}, owners are a Unix thing", path.display())
+}
+
+/// Give `copy` the owner of the file `original` describes, if fnr is root.
+#[cfg(unix)]
+pub(crate) fn keep_owner(original: &Metadata, copy: &Path) -> io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+    if !is_root() {
+        return Ok(());
+    }
+    std::os::unix::fs::lchown(copy, Some(original.uid()), Some(original.gid()))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn keep_owner(_original: &Metadata, _copy: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// The `uid` and `gid` for `--chown-to-invoker`: only root can give files
+/// away, and only `sudo` says to whom.
+pub fn chown_target() -> Result<(u32, u32)> {
+    if !is_root() {
+        bail!("--chown-to-invoker needs fnr run as root, through sudo");
+    }
+    match invoker() {
+        Some(ids) => Ok(ids),
+        None => bail!("--chown-to-invoker needs SUDO_UID and SUDO_GID, fnr wasn't run through sudo"),
+    }
+}
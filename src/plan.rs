@@ -0,0 +1,199 @@
+//! Saved rename plans.
+//!
+//! A plan file is a JSON document listing renames in the order they have to
+//! be applied:
+//!
+//! ```json
+//! { "version": 1, "renames": [ { "from": "a/old.txt", "to": "a/new.txt", "is_dir": false } ] }
+//! ```
+
+use crate::{build_glob_set, Match};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub const PLAN_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlanEntry {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    #[serde(default)]
+    pub is_dir: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Plan {
+    pub version: u32,
+    pub renames: Vec<PlanEntry>,
+}
+
+impl Plan {
+    pub fn new(renames: Vec<PlanEntry>) -> Self {
+        Plan {
+            version: PLAN_VERSION,
+            renames,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Plan> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read plan {}", path.display()))?;
+        let plan: Plan = serde_json::from_str(&text)
+            .with_context(|| format!("Invalid plan file {}", path.display()))?;
+        if plan.version != PLAN_VERSION {
+            bail!("Unsupported plan version {} in {}", plan.version, path.display());
+        }
+        Ok(plan)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let text = serde_json::to_string_pretty(self)?;
+        fs::write(path, text + "\n")
+            .with_context(|| format!("Failed to write plan {}", path.display()))
+    }
+
+    /// The plan undoing this one: every rename reversed, last one first.
+    pub fn inverse(&self) -> Plan {
+        Plan::new(
+            self.renames
+                .iter()
+                .rev()
+                .map(|e| PlanEntry {
+                    from: e.to.clone(),
+                    to: e.from.clone(),
+                    is_dir: e.is_dir,
+                })
+                .collect(),
+        )
+    }
+
+    /// Keep a slice of the plan: entries whose source matches one of `only`
+    /// (all if empty), then `skip` of those dropped and at most `take` kept.
+    pub fn select(self, only: &[String], skip: usize, take: Option<usize>) -> Result<Plan> {
+        let globs = build_glob_set(only)?;
+        let renames = self
+            .renames
+            .into_iter()
+            .filter(|e| only.is_empty() || globs.is_match(&e.from) || globs.is_match(crate::file_name(&e.from)))
+            .skip(skip)
+            .take(take.unwrap_or(usize::MAX))
+            .collect();
+        Ok(Plan { renames, ..self })
+    }
+
+    /// Convert the plan into matches, keeping the plan order.
+    pub fn into_matches(self) -> Result<Vec<Match>> {
+        self.renames
+            .into_iter()
+            .map(|e| {
+                if e.from.parent() != e.to.parent() {
+                    bail!(
+                        "Plan entry {} -> {} moves across directories, which is not supported",
+                        e.from.display(),
+                        e.to.display()
+                    );
+                }
+                let old_name = file_name(&e.from)?;
+                let new_name = file_name(&e.to)?;
+                Ok(Match {
+                    path: e.from,
+                    replacement: new_name.as_str().into(),
+                    new_name,
+                    is_dir: e.is_dir,
+                    pattern: old_name.into(),
+                })
+            })
+            .collect()
+    }
+}
+
+fn file_name(path: &Path) -> Result<String> {
+    match path.file_name().and_then(|n| n.to_str()) {
+        Some(name) => Ok(name.to_string()),
+        None => bail!("Plan entry {} has no valid file name", path.display()),
+    }
+}
+
+/// How an entry differs between two plans, see [`diff`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlanChange {
+    /// Only renamed by the new plan.
+    Added(PlanEntry),
+    /// Only renamed by the old plan.
+    Removed(PlanEntry),
+    /// Renamed to somewhere else by the new plan.
+    Changed { from: PathBuf, old_to: PathBuf, new_to: PathBuf },
+}
+
+/// The differences between two plans by source path, sorted by it, and the
+/// number of renames they agree on.
+pub fn diff(old: &Plan, new: &Plan) -> (Vec<PlanChange>, usize) {
+    let before: BTreeMap<&Path, &PlanEntry> = old.renames.iter().map(|e| (e.from.as_path(), e)).collect();
+    let after: BTreeMap<&Path, &PlanEntry> = new.renames.iter().map(|e| (e.from.as_path(), e)).collect();
+    let mut changes = Vec::new();
+    let mut unchanged = 0;
+    for (from, entry) in &before {
+        match after.get(from) {
+            None => changes.push(PlanChange::Removed((*entry).clone())),
+            Some(other) if other.to != entry.to => changes.push(PlanChange::Changed {
+                from: from.to_path_buf(),
+                old_to: entry.to.clone(),
+                new_to: other.to.clone(),
+            }),
+            Some(_) => unchanged += 1,
+        }
+    }
+    changes.extend(
+        after
+            .iter()
+            .filter(|(from, _)| !before.contains_key(*from))
+            .map(|(_, entry)| PlanChange::Added((*entry).clone())),
+    );
+    changes.sort_by(|a, b| a.from().cmp(b.from()));
+    (changes, unchanged)
+}
+
+impl PlanChange {
+    pub fn from(&self) -> &Path {
+        match self {
+            PlanChange::Added(entry) | PlanChange::Removed(entry) => &entry.from,
+            PlanChange::Changed { from, .. } => from,
+        }
+    }
+}
+
+/// Keeps track of the renames applied during a run. Safe to share between
+/// rename workers.
+#[derive(Debug, Default)]
+pub struct Recorder {
+    entries: Mutex<Vec<PlanEntry>>,
+}
+
+impl Recorder {
+    pub fn record(&self, m: &Match, new_path: &Path) {
+        self.entries.lock().unwrap().push(PlanEntry {
+            from: m.path.clone(),
+            to: new_path.to_path_buf(),
+            is_dir: m.is_dir,
+        });
+    }
+
+    /// Drop the record of the rename of `from`, which was undone.
+    pub fn forget(&self, from: &Path) {
+        self.entries.lock().unwrap().retain(|entry| entry.from != from);
+    }
+
+    /// Everything recorded so far as a plan, in the order it was applied.
+    pub fn plan(&self) -> Plan {
+        Plan::new(self.entries.lock().unwrap().clone())
+    }
+
+    /// Like [`Recorder::plan`], but starts over with an empty record.
+    pub fn take(&self) -> Plan {
+        Plan::new(std::mem::take(&mut *self.entries.lock().unwrap()))
+    }
+}
@@ -0,0 +1,664 @@
+//! Interactive playground: edit pattern and replacement while a preview of
+//! the resulting renames updates live. The finder variant drops the
+//! replacement and lets the user pick one of the matching paths.
+//!
+//! The tree is walked once up front; every keystroke only re-matches the
+//! collected names in memory. Matches can be turned off one by one, and
+//! those whose new name is taken, on disk or by another match still on,
+//! are marked as conflicts. The list can be sorted and narrowed down to
+//! conflicts or directories, which helps with long ones. Quick edits (a
+//! prefix, a suffix, a case change) go to the stems of the ticked matches
+//! shown, on top of the replacement. With a session, the playground starts
+//! where it was left the last time. The UI is drawn on stderr so the finder can be
+//! used inside command substitutions.
+
+use crate::theme;
+use anyhow::Result;
+use colored::*;
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
+    queue,
+    style::Print,
+    terminal::{self, disable_raw_mode, enable_raw_mode, ClearType},
+};
+use fnr_tool::check::colliding;
+use fnr_tool::dates::DateStyle;
+use fnr_tool::matcher::StemEdit;
+use fnr_tool::metadata::MetadataCache;
+use fnr_tool::remote::Probe;
+use fnr_tool::session::{Selection, Session};
+use fnr_tool::{compute_new_names_cached, Finder, Match, NameMatcher, Options};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::path::{self, Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Edit pattern and replacement, preview the renames.
+    Playground,
+    /// Edit the pattern only and select a single match.
+    Finder,
+}
+
+/// Order of the match list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Sort {
+    Path,
+    /// Largest first.
+    Size,
+    /// Newest first.
+    Modified,
+    /// Conflicts first, then ticked, then unticked matches.
+    Status,
+}
+
+impl Sort {
+    fn next(self, mode: Mode) -> Sort {
+        match self {
+            Sort::Path => Sort::Size,
+            Sort::Size => Sort::Modified,
+            Sort::Modified if mode == Mode::Playground => Sort::Status,
+            Sort::Modified | Sort::Status => Sort::Path,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Sort::Path => "path",
+            Sort::Size => "size",
+            Sort::Modified => "mtime",
+            Sort::Status => "status",
+        }
+    }
+}
+
+/// Which matches the list shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Filter {
+    All,
+    Conflicts,
+    Dirs,
+}
+
+impl Filter {
+    fn next(self, mode: Mode) -> Filter {
+        match self {
+            Filter::All if mode == Mode::Playground => Filter::Conflicts,
+            Filter::All | Filter::Conflicts => Filter::Dirs,
+            Filter::Dirs => Filter::All,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Filter::All => "all",
+            Filter::Conflicts => "conflicts only",
+            Filter::Dirs => "directories only",
+        }
+    }
+}
+
+/// Where keys go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    Pattern,
+    Replacement,
+    /// The match list, which takes plain `j` and `k` like vi does.
+    List,
+}
+
+impl Focus {
+    /// The next one with Tab, the previous one with Shift-Tab (`back`).
+    fn next(self, mode: Mode, back: bool) -> Focus {
+        let order: &[Focus] = match mode {
+            Mode::Playground => &[Focus::Pattern, Focus::Replacement, Focus::List],
+            Mode::Finder => &[Focus::Pattern, Focus::List],
+        };
+        let i = order.iter().position(|&focus| focus == self).unwrap_or(0);
+        let step = if back { order.len() - 1 } else { 1 };
+        order[(i + step) % order.len()]
+    }
+}
+
+/// The quick edit prompt.
+#[derive(Debug)]
+enum Prompt {
+    /// Waiting for the kind of edit.
+    Choose,
+    /// Typing a prefix or suffix.
+    Text { suffix: bool, text: String },
+}
+
+/// Pattern settings the user settled on.
+pub struct Outcome {
+    pub pattern: String,
+    pub replacement: String,
+    pub regex: bool,
+    pub case_sensitive: bool,
+    /// Highlighted match in finder mode.
+    pub selected: Option<PathBuf>,
+    /// Matches turned off in playground mode.
+    pub deselected: HashSet<PathBuf>,
+    /// New names changed by quick edits, by path.
+    pub edited: HashMap<PathBuf, String>,
+}
+
+struct State {
+    mode: Mode,
+    entries: Vec<Match>,
+    pattern: String,
+    replacement: String,
+    regex: bool,
+    case_sensitive: bool,
+    focus: Focus,
+    /// The highlighted row.
+    selected: usize,
+    sort: Sort,
+    filter: Filter,
+    /// Indices into `preview` of the rows shown, sorted and filtered.
+    rows: Vec<usize>,
+    /// Matches turned off, by path. Kept while the pattern changes.
+    deselected: HashSet<PathBuf>,
+    /// Matches still on whose new path is taken.
+    conflicts: HashSet<PathBuf>,
+    /// Quick edits in order, each with the paths it applies to. Applied
+    /// again whenever the preview changes.
+    edits: Vec<(StemEdit, HashSet<PathBuf>)>,
+    prompt: Option<Prompt>,
+    preview: std::result::Result<Vec<Match>, String>,
+    date_style: DateStyle,
+    base_dir: PathBuf,
+    /// Kept across keystrokes, so date placeholders don't stat every entry
+    /// again on every edit.
+    metadata: MetadataCache,
+}
+
+impl State {
+    fn update(&mut self) {
+        self.selected = 0;
+        self.preview = NameMatcher::new(&self.pattern, self.regex, self.case_sensitive)
+            .and_then(|matcher| {
+                let replacement = Some(self.replacement.clone()).filter(|r| !r.is_empty());
+                let mut matches: Vec<Match> = self
+                    .entries
+                    .iter()
+                    .filter(|m| matcher.is_match(&m.new_name))
+                    .map(|m| Match {
+                        pattern: matcher.shared_pattern(),
+                        ..m.clone()
+                    })
+                    .collect();
+                compute_new_names_cached(
+                    &mut matches,
+                    &[(matcher, replacement)],
+                    &self.date_style,
+                    &self.base_dir,
+                    &self.metadata,
+                )?;
+                if self.mode == Mode::Playground && !self.replacement.is_empty() {
+                    for (edit, paths) in &self.edits {
+                        for m in matches.iter_mut().filter(|m| paths.contains(&m.path)) {
+                            m.new_name = edit.apply(&m.new_name, m.is_dir);
+                        }
+                    }
+                }
+                Ok(matches)
+            })
+            .map_err(|e| format!("{:#}", e));
+        self.check_conflicts();
+        self.arrange();
+    }
+
+    fn check_conflicts(&mut self) {
+        self.conflicts = match &self.preview {
+            Ok(matches) if self.mode == Mode::Playground && !self.replacement.is_empty() => {
+                let on: Vec<Match> = matches.iter().filter(|m| !self.deselected.contains(&m.path)).cloned().collect();
+                colliding(&on, &Probe::Stat)
+            }
+            _ => HashSet::new(),
+        };
+    }
+
+    /// Sort and filter the rows, keeping the highlighted row in range.
+    fn arrange(&mut self) {
+        let Ok(matches) = &self.preview else {
+            self.rows.clear();
+            return;
+        };
+        let mut rows: Vec<usize> = (0..matches.len())
+            .filter(|&i| match self.filter {
+                Filter::All => true,
+                Filter::Conflicts => self.conflicts.contains(&matches[i].path),
+                Filter::Dirs => matches[i].is_dir,
+            })
+            .collect();
+        // Entries that can't be read sort last
+        match self.sort {
+            Sort::Path => rows.sort_by(|&a, &b| matches[a].path.cmp(&matches[b].path)),
+            Sort::Size => rows.sort_by_cached_key(|&i| {
+                std::cmp::Reverse(self.metadata.get(&matches[i].path).map(|meta| meta.len()).ok())
+            }),
+            Sort::Modified => {
+                rows.sort_by_cached_key(|&i| std::cmp::Reverse(self.metadata.modified(&matches[i].path).ok()))
+            }
+            Sort::Status => rows.sort_by_key(|&i| {
+                let path = &matches[i].path;
+                (!self.conflicts.contains(path), self.deselected.contains(path))
+            }),
+        }
+        self.rows = rows;
+        self.selected = self.selected.min(self.rows.len().saturating_sub(1));
+    }
+
+    /// Pick up `selection`, whose paths are absolute.
+    fn restore(&mut self, selection: &Selection) {
+        let paths: HashMap<PathBuf, &PathBuf> = self.entries.iter().map(|m| (absolute(&m.path), &m.path)).collect();
+        let relative = |list: &[PathBuf]| -> HashSet<PathBuf> {
+            list.iter().filter_map(|path| paths.get(path)).map(|&path| path.clone()).collect()
+        };
+        self.pattern = selection.pattern.clone();
+        self.replacement = selection.replacement.clone();
+        self.regex = selection.regex;
+        self.case_sensitive = selection.case_sensitive;
+        self.deselected = relative(&selection.deselected);
+        self.edits = selection.edits.iter().map(|(edit, list)| (edit.clone(), relative(list))).collect();
+    }
+
+    /// The selection to pick up next time, with absolute paths.
+    fn selection(&self) -> Selection {
+        let all_absolute = |paths: &HashSet<PathBuf>| paths.iter().map(|path| absolute(path)).collect();
+        Selection {
+            pattern: self.pattern.clone(),
+            replacement: self.replacement.clone(),
+            regex: self.regex,
+            case_sensitive: self.case_sensitive,
+            deselected: all_absolute(&self.deselected),
+            edits: self.edits.iter().map(|(edit, paths)| (edit.clone(), all_absolute(paths))).collect(),
+        }
+    }
+
+    /// The highlighted match.
+    fn current(&self) -> Option<&Match> {
+        let matches = self.preview.as_ref().ok()?;
+        self.rows.get(self.selected).map(|&i| &matches[i])
+    }
+
+    /// Turn the highlighted match off, or on again.
+    fn toggle(&mut self) {
+        let Some(path) = self.current().map(|m| m.path.clone()) else {
+            return;
+        };
+        if !self.deselected.remove(&path) {
+            self.deselected.insert(path);
+        }
+        self.check_conflicts();
+        self.arrange();
+    }
+
+    /// Apply `edit` to the ticked matches shown.
+    fn edit(&mut self, edit: StemEdit) {
+        let Ok(matches) = &self.preview else {
+            return;
+        };
+        let paths: HashSet<PathBuf> = self
+            .rows
+            .iter()
+            .map(|&i| &matches[i].path)
+            .filter(|path| !self.deselected.contains(*path))
+            .cloned()
+            .collect();
+        if !paths.is_empty() {
+            self.edits.push((edit, paths));
+            self.refresh();
+        }
+    }
+
+    /// Compute the preview again, keeping the highlighted row.
+    fn refresh(&mut self) {
+        let selected = self.selected;
+        self.update();
+        self.selected = selected.min(self.rows.len().saturating_sub(1));
+    }
+
+    /// The new names quick edits changed.
+    fn edited(&self) -> HashMap<PathBuf, String> {
+        let Ok(matches) = &self.preview else {
+            return HashMap::new();
+        };
+        matches
+            .iter()
+            .filter(|m| self.edits.iter().any(|(_, paths)| paths.contains(&m.path)))
+            .map(|m| (m.path.clone(), m.new_name.clone()))
+            .collect()
+    }
+
+    /// Highlight the next conflict, starting over at the top.
+    fn next_conflict(&mut self) {
+        let Ok(matches) = &self.preview else {
+            return;
+        };
+        let count = self.rows.len();
+        let next = (1..=count)
+            .map(|step| (self.selected + step) % count)
+            .find(|&row| self.conflicts.contains(&matches[self.rows[row]].path));
+        if let Some(row) = next {
+            self.selected = row;
+        }
+    }
+
+    fn field(&mut self) -> &mut String {
+        if self.focus == Focus::Replacement {
+            &mut self.replacement
+        } else {
+            &mut self.pattern
+        }
+    }
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Restores the terminal even if drawing fails halfway.
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn enter() -> Result<Self> {
+        enable_raw_mode()?;
+        queue!(io::stderr(), terminal::EnterAlternateScreen)?;
+        Ok(TerminalGuard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = queue!(io::stderr(), terminal::LeaveAlternateScreen, cursor::Show);
+        let _ = io::stderr().flush();
+        let _ = disable_raw_mode();
+    }
+}
+
+/// Run the playground. Returns `None` if the user left without accepting.
+/// Either way, the selection goes to `session`.
+pub fn run(options: &Options, mode: Mode, no_color: bool, session: Option<&Session>) -> Result<Option<Outcome>> {
+    let entries = Finder::new(options.clone()).entries()?;
+    let mut state = State {
+        mode,
+        entries,
+        pattern: options.pattern.clone(),
+        replacement: options.replacement.clone().unwrap_or_default(),
+        regex: options.regex,
+        case_sensitive: options.case_sensitive,
+        focus: Focus::Pattern,
+        selected: 0,
+        sort: Sort::Path,
+        filter: Filter::All,
+        rows: Vec::new(),
+        deselected: HashSet::new(),
+        conflicts: HashSet::new(),
+        edits: Vec::new(),
+        prompt: None,
+        preview: Ok(Vec::new()),
+        date_style: options.date_style,
+        base_dir: options.base_dir.clone(),
+        metadata: MetadataCache::new(),
+    };
+    if let Some(selection) = session.and_then(Session::selection) {
+        state.restore(selection);
+    }
+    state.update();
+
+    let _guard = TerminalGuard::enter()?;
+    loop {
+        draw(&state, no_color)?;
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        let action = handle_key(&mut state, key);
+        if let (Some(session), Action::Accept | Action::Cancel) = (session, &action) {
+            session.record_selection(state.selection())?;
+        }
+        match action {
+            Action::Continue => {}
+            Action::Accept => {
+                let selected = match mode {
+                    Mode::Finder => state.current().map(|m| m.path.clone()),
+                    Mode::Playground => None,
+                };
+                let edited = state.edited();
+                return Ok(Some(Outcome {
+                    pattern: state.pattern,
+                    replacement: state.replacement,
+                    regex: state.regex,
+                    case_sensitive: state.case_sensitive,
+                    selected,
+                    deselected: state.deselected,
+                    edited,
+                }))
+            }
+            Action::Cancel => return Ok(None),
+        }
+    }
+}
+
+enum Action {
+    Continue,
+    Accept,
+    Cancel,
+}
+
+fn handle_key(state: &mut State, key: KeyEvent) -> Action {
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+    if let Some(prompt) = state.prompt.take() {
+        handle_prompt(state, prompt, key);
+        return Action::Continue;
+    }
+    match key.code {
+        KeyCode::Esc => return Action::Cancel,
+        KeyCode::Char('c') if ctrl => return Action::Cancel,
+        KeyCode::Enter => return Action::Accept,
+        KeyCode::Tab | KeyCode::BackTab => state.focus = state.focus.next(state.mode, key.code == KeyCode::BackTab),
+        // Ctrl-K and Ctrl-J move like vi's k and j (and fzf's), and once
+        // the list has the focus there's no text to type, so k and j do
+        KeyCode::Char('k') if ctrl || state.focus == Focus::List => return handle_key(state, KeyEvent::from(KeyCode::Up)),
+        KeyCode::Char('j') if ctrl || state.focus == Focus::List => return handle_key(state, KeyEvent::from(KeyCode::Down)),
+        KeyCode::Up | KeyCode::PageUp => {
+            let step = if key.code == KeyCode::Up { 1 } else { 10 };
+            state.selected = state.selected.saturating_sub(step);
+        }
+        KeyCode::Down | KeyCode::PageDown => {
+            let step = if key.code == KeyCode::Down { 1 } else { 10 };
+            state.selected = (state.selected + step).min(state.rows.len().saturating_sub(1));
+        }
+        KeyCode::Char('s') if ctrl => {
+            state.sort = state.sort.next(state.mode);
+            state.arrange();
+        }
+        KeyCode::Char('f') if ctrl => {
+            state.filter = state.filter.next(state.mode);
+            state.selected = 0;
+            state.arrange();
+        }
+        KeyCode::Char('x') if ctrl && state.mode == Mode::Playground => state.toggle(),
+        KeyCode::Char('n') if ctrl && state.mode == Mode::Playground => state.next_conflict(),
+        // Quick edits only change new names, so there have to be some
+        KeyCode::Char('e') if ctrl && state.mode == Mode::Playground && !state.replacement.is_empty() => {
+            state.prompt = Some(Prompt::Choose)
+        }
+        KeyCode::Char('z') if ctrl && state.edits.pop().is_some() => state.refresh(),
+        KeyCode::Char('r') if ctrl => {
+            state.regex = !state.regex;
+            state.update();
+        }
+        KeyCode::Char('t') if ctrl => {
+            state.case_sensitive = !state.case_sensitive;
+            state.update();
+        }
+        KeyCode::Char('u') if ctrl && state.focus != Focus::List => {
+            state.field().clear();
+            state.update();
+        }
+        KeyCode::Backspace if state.focus != Focus::List => {
+            state.field().pop();
+            state.update();
+        }
+        KeyCode::Char(c) if !ctrl && state.focus != Focus::List => {
+            state.field().push(c);
+            state.update();
+        }
+        _ => {}
+    }
+    Action::Continue
+}
+
+/// Keys while the quick edit prompt is open. Esc closes it.
+fn handle_prompt(state: &mut State, prompt: Prompt, key: KeyEvent) {
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+    match (prompt, key.code) {
+        (_, KeyCode::Esc) => {}
+        (Prompt::Choose, KeyCode::Char('p')) => state.prompt = Some(Prompt::Text { suffix: false, text: String::new() }),
+        (Prompt::Choose, KeyCode::Char('s')) => state.prompt = Some(Prompt::Text { suffix: true, text: String::new() }),
+        (Prompt::Choose, KeyCode::Char('u')) => state.edit(StemEdit::Upper),
+        (Prompt::Choose, KeyCode::Char('l')) => state.edit(StemEdit::Lower),
+        (Prompt::Choose, KeyCode::Char('t')) => state.edit(StemEdit::Title),
+        (Prompt::Text { suffix, text }, KeyCode::Enter) if !text.is_empty() => {
+            state.edit(if suffix { StemEdit::Suffix(text) } else { StemEdit::Prefix(text) })
+        }
+        (Prompt::Text { suffix, mut text }, KeyCode::Backspace) => {
+            text.pop();
+            state.prompt = Some(Prompt::Text { suffix, text });
+        }
+        (Prompt::Text { suffix, mut text }, KeyCode::Char(c)) if !ctrl => {
+            text.push(c);
+            state.prompt = Some(Prompt::Text { suffix, text });
+        }
+        (prompt, _) => state.prompt = Some(prompt),
+    }
+}
+
+fn truncate(text: &str, width: usize) -> String {
+    text.chars().take(width).collect()
+}
+
+fn paint(text: &str, no_color: bool, style: fn(&str) -> ColoredString) -> String {
+    if no_color {
+        text.to_string()
+    } else {
+        style(text).to_string()
+    }
+}
+
+fn draw(state: &State, no_color: bool) -> Result<()> {
+    let (width, height) = terminal::size()?;
+    let width = width as usize;
+    let finder = state.mode == Mode::Finder;
+    let mut out = io::stderr();
+    queue!(out, cursor::Hide, cursor::MoveTo(0, 0), terminal::Clear(ClearType::All))?;
+
+    let help = if finder {
+        "Tab: list (j/k)  Up/Down/Ctrl-K/Ctrl-J: select  Ctrl-S: sort  Ctrl-F: filter  Ctrl-R: regex  Ctrl-T: case  Ctrl-U: clear  Enter: print  Esc: quit"
+    } else {
+        "Tab: next field, list (j/k)  Up/Down/Ctrl-K/Ctrl-J: select  Ctrl-X: toggle  Ctrl-N: next conflict  Ctrl-E: edit  Ctrl-Z: undo edit  Ctrl-S: sort  Ctrl-F: filter  Ctrl-R: regex  Ctrl-T: case  Ctrl-U: clear  Enter: run  Esc: quit"
+    };
+    queue!(out, Print(paint(&truncate(help, width), no_color, |t| theme().skipped.paint(t))))?;
+
+    let mut flags = format!(
+        "{}{}",
+        if state.regex { " [regex]" } else { "" },
+        if state.case_sensitive { " [case-sensitive]" } else { "" }
+    );
+    if state.sort != Sort::Path {
+        flags.push_str(&format!(" [by {}]", state.sort.label()));
+    }
+    if state.filter != Filter::All {
+        flags.push_str(&format!(" [{}, {} shown]", state.filter.label(), state.rows.len()));
+    }
+    let pattern_line = format!("Pattern:     {}", state.pattern);
+    queue!(out, cursor::MoveTo(0, 1), Print(truncate(&pattern_line, width)))?;
+    if !finder {
+        let replacement_line = format!("Replacement: {}", state.replacement);
+        queue!(out, cursor::MoveTo(0, 2), Print(truncate(&replacement_line, width)))?;
+    }
+    queue!(out, cursor::MoveTo(0, 3))?;
+
+    let rows = (height as usize).saturating_sub(5);
+    match &state.preview {
+        Err(e) => {
+            let status = format!("Invalid pattern: {}", e.lines().next().unwrap_or(""));
+            queue!(out, Print(paint(&truncate(&status, width), no_color, |t| theme().error.paint(t))))?;
+        }
+        Ok(matches) => {
+            let mut status = format!("{} of {} entries match", matches.len(), state.entries.len());
+            if !finder {
+                let off = matches.iter().filter(|m| state.deselected.contains(&m.path)).count();
+                status.push_str(&format!(", {} on, {} conflicts", matches.len() - off, state.conflicts.len()));
+            }
+            status.push_str(&flags);
+            queue!(out, Print(paint(&truncate(&status, width), no_color, |t| theme().prompt.paint(t))))?;
+
+            let scroll = (state.selected + 1).saturating_sub(rows);
+            for (row, m) in state.rows.iter().map(|&i| &matches[i]).enumerate().skip(scroll).take(rows) {
+                let old = m.path.display().to_string();
+                let marker = if row == state.selected { "> " } else { "  " };
+                let line = if finder {
+                    let line = truncate(&format!("{}{}", marker, old), width);
+                    if row == state.selected {
+                        paint(&line, no_color, |t| t.bold())
+                    } else {
+                        line
+                    }
+                } else if state.deselected.contains(&m.path) {
+                    paint(&truncate(&format!("{}[ ] {}", marker, old), width), no_color, |t| theme().skipped.paint(t))
+                } else if state.replacement.is_empty() {
+                    truncate(&format!("{}[x] {}", marker, old), width)
+                } else {
+                    let old = truncate(&format!("{}[x] {}", marker, old), width);
+                    let badge = if state.conflicts.contains(&m.path) { " [conflict]" } else { "" };
+                    let room = width.saturating_sub(old.chars().count() + 4 + badge.len());
+                    let new = truncate(&m.new_name, room);
+                    format!(
+                        "{} -> {}{}",
+                        old,
+                        paint(&new, no_color, |t| theme().replacement.paint(t)),
+                        paint(badge, no_color, |t| theme().error.paint(t))
+                    )
+                };
+                queue!(out, cursor::MoveTo(0, (row - scroll + 5) as u16), Print(line))?;
+            }
+        }
+    }
+
+    if let Some(prompt) = &state.prompt {
+        let line = match prompt {
+            Prompt::Choose => "Edit ticked stems: [p]refix, [s]uffix, [u]ppercase, [l]owercase, [t]itle case".to_string(),
+            Prompt::Text { suffix: false, text } => format!("Prefix: {}", text),
+            Prompt::Text { suffix: true, text } => format!("Suffix: {}", text),
+        };
+        let line = truncate(&line, width);
+        let column = line.chars().count().min(width.saturating_sub(1));
+        queue!(
+            out,
+            cursor::MoveTo(0, 4),
+            Print(paint(&line, no_color, |t| theme().prompt.paint(t))),
+            cursor::MoveTo(column as u16, 4),
+            cursor::Show
+        )?;
+        out.flush()?;
+        return Ok(());
+    }
+
+    let (label, text) = match state.focus {
+        Focus::Pattern => (1, &state.pattern),
+        Focus::Replacement => (2, &state.replacement),
+        // The highlighted row shows where things are
+        Focus::List => {
+            out.flush()?;
+            return Ok(());
+        }
+    };
+    let column = ("Replacement: ".len() + text.chars().count()).min(width.saturating_sub(1));
+    queue!(out, cursor::MoveTo(column as u16, label), cursor::Show)?;
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,948 @@
+//! Mentions of renamed paths in other files of the tree, like the entries
+//! of a `.gitignore`, the sources listed in a `CMakeLists.txt` or links in
+//! the docs, rewritten along with the renames. With `--content` the pattern
+//! itself is replaced inside the files too, see [`content_edits`].
+//!
+//! Edits are planned against the tree as it is before the renames, planned
+//! again for the renames that were actually done, see [`Edit::replan`], and
+//! applied after them, to each file wherever the renames moved it.
+
+use crate::matcher::NameMatcher;
+use crate::plan::PlanEntry;
+use crate::sidecar::split_extension;
+use crate::{file_name, open_regular, Match};
+use anyhow::{bail, Context, Result};
+use ignore::WalkBuilder;
+use regex::Regex;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::io::Read;
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+
+/// One line of a file changed to follow the renames.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edit {
+    /// The file as it is now.
+    pub file: PathBuf,
+    /// Where the file is after the renames.
+    pub target: PathBuf,
+    /// 1-based line number.
+    pub line: usize,
+    pub old: String,
+    pub new: String,
+    /// The parts of `old` that follow the renames, in order.
+    parts: Vec<(Range<usize>, Follow)>,
+}
+
+impl Edit {
+    /// The edit for the renames of `relocation` instead of those it was
+    /// planned for, if they still change the line. Declined and failed
+    /// renames leave their parts of the line as they are. Replaced content
+    /// doesn't follow any rename, it only goes where its file went.
+    pub fn replan(&self, relocation: &Relocation) -> Option<Edit> {
+        if self.parts.is_empty() {
+            return Some(Edit {
+                target: relocation.relocate(&self.file),
+                ..self.clone()
+            });
+        }
+        let mut new = self.old.clone();
+        for (range, follow) in self.parts.iter().rev() {
+            if let Some(text) = follow.rewrite(relocation, &self.old[range.clone()]) {
+                new.replace_range(range.clone(), &text);
+            }
+        }
+        (new != self.old).then(|| Edit {
+            target: relocation.relocate(&self.file),
+            new,
+            ..self.clone()
+        })
+    }
+}
+
+/// What a part of a line is, to rewrite it for any set of renames.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Follow {
+    /// An ignore entry in a file in the directory.
+    Entry(PathBuf),
+    /// A path written in a file in the directory, see
+    /// [`Relocation::rewrite`].
+    Path(PathBuf),
+    /// A link in a document in the directory, see
+    /// [`Relocation::rewrite_link`].
+    Link(PathBuf),
+    /// The name of the renamed `sources`, or with `stem` the name without
+    /// its extension. Only rewritten while all of them take it to the same
+    /// new name.
+    Name { sources: Vec<PathBuf>, stem: bool },
+}
+
+impl Follow {
+    fn rewrite(&self, relocation: &Relocation, written: &str) -> Option<String> {
+        match self {
+            Follow::Entry(dir) => relocation.relative(dir, &dir.join(written)),
+            Follow::Path(dir) => relocation.rewrite(dir, written),
+            Follow::Link(dir) => relocation.rewrite_link(dir, written),
+            Follow::Name { sources, stem } => {
+                let mut names = sources.iter().map(|source| {
+                    let name = relocation.renamed.get(source)?;
+                    Some(if *stem { split_extension(name).0 } else { name.as_str() })
+                });
+                let first = names.next()??;
+                names.all(|name| name == Some(first)).then(|| first.to_string())
+            }
+        }
+    }
+}
+
+/// Where paths of the tree end up after a list of renames.
+#[derive(Debug, Clone, Default)]
+pub struct Relocation {
+    renamed: HashMap<PathBuf, String>,
+}
+
+impl Relocation {
+    pub fn new(matches: &[Match]) -> Self {
+        let renamed = matches
+            .iter()
+            .filter(|m| m.new_name != file_name(&m.path))
+            .map(|m| (m.path.clone(), m.new_name.clone()))
+            .collect();
+        Relocation { renamed }
+    }
+
+    /// Where the renames of `applied` took things, for the edits that
+    /// follow them once they are done.
+    pub fn applied(applied: &[PlanEntry]) -> Self {
+        let renamed = applied
+            .iter()
+            .filter(|entry| entry.from != entry.to)
+            .map(|entry| (entry.from.clone(), file_name(&entry.to).to_string()))
+            .collect();
+        Relocation { renamed }
+    }
+
+    /// The paths that are renamed.
+    pub fn sources(&self) -> impl Iterator<Item = &Path> {
+        self.renamed.keys().map(PathBuf::as_path)
+    }
+
+    /// `path` after the renames, which may have renamed any of its
+    /// ancestors too.
+    pub fn relocate(&self, path: &Path) -> PathBuf {
+        let mut original = PathBuf::new();
+        let mut relocated = PathBuf::new();
+        for component in path.components() {
+            original.push(component);
+            match self.renamed.get(&original) {
+                Some(name) => relocated.push(name),
+                None => relocated.push(component),
+            }
+        }
+        relocated
+    }
+
+    /// `path` relative to `dir` after the renames, with `/` separators, if
+    /// the renames touch it.
+    fn relative(&self, dir: &Path, path: &Path) -> Option<String> {
+        let relocated = self.relocate(path);
+        if relocated == path {
+            return None;
+        }
+        let relative = relocated.strip_prefix(self.relocate(dir)).ok()?;
+        let parts: Vec<_> = relative
+            .components()
+            .map(|c| match c {
+                Component::Normal(part) => part.to_str(),
+                _ => None,
+            })
+            .collect::<Option<_>>()?;
+        Some(parts.join("/"))
+    }
+
+    /// A path as written in a file in `dir`, after the renames, keeping a
+    /// leading `./` and a trailing `/`.
+    fn rewrite(&self, dir: &Path, written: &str) -> Option<String> {
+        let body = written.strip_prefix("./").unwrap_or(written).trim_end_matches('/');
+        if body.is_empty() || body.starts_with('/') {
+            return None;
+        }
+        let new_body = self.relative(dir, &dir.join(body))?;
+        let start = written.len() - written.trim_start_matches("./").len();
+        let end = written.trim_end_matches('/').len();
+        Some(format!("{}{}{}", &written[..start], new_body, &written[end..]))
+    }
+
+    /// A relative link in a file in `dir`, with the renamed parts replaced
+    /// and everything else, `..` included, as written. Parts are
+    /// percent-decoded for the lookup, and spaces in new names encoded
+    /// again where the link was encoded.
+    fn rewrite_link(&self, dir: &Path, link: &str) -> Option<String> {
+        let mut current = dir.to_path_buf();
+        let mut changed = false;
+        let mut parts = Vec::new();
+        for part in link.split('/') {
+            match part {
+                "" | "." => parts.push(part.to_string()),
+                ".." => {
+                    current.pop();
+                    parts.push(part.to_string());
+                }
+                _ => {
+                    current.push(percent_decode(part));
+                    match self.renamed.get(&current) {
+                        Some(name) if part.contains('%') => parts.push(name.replace('%', "%25").replace(' ', "%20")),
+                        Some(name) => parts.push(name.clone()),
+                        None => {
+                            parts.push(part.to_string());
+                            continue;
+                        }
+                    }
+                    changed = true;
+                }
+            }
+        }
+        changed.then(|| parts.join("/"))
+    }
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = text.get(i + 1..i + 3).filter(|_| bytes[i] == b'%');
+        match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// The files named `wanted` in the directories from `base_dir` down to the
+/// renamed paths.
+fn files_above(relocation: &Relocation, base_dir: &Path, wanted: impl Fn(&str) -> bool) -> BTreeSet<PathBuf> {
+    let dirs: BTreeSet<&Path> = relocation
+        .sources()
+        .flat_map(|path| path.ancestors().skip(1).take_while(|dir| dir.starts_with(base_dir)))
+        .collect();
+    dirs.into_iter()
+        .flat_map(|dir| fs::read_dir(dir).into_iter().flatten().flatten())
+        .filter(|entry| entry.file_name().to_str().is_some_and(&wanted))
+        .map(|entry| entry.path())
+        .filter(|file| file.is_file())
+        .collect()
+}
+
+/// Edits for the lines of `file` where the renames change one of the
+/// `parts` of the line.
+fn line_edits(
+    relocation: &Relocation,
+    file: &Path,
+    parts: impl FnMut(&str) -> Vec<(Range<usize>, Follow)>,
+) -> Result<Vec<Edit>> {
+    let content = fs::read_to_string(file).with_context(|| format!("Failed to read {}", file.display()))?;
+    Ok(edits_in(relocation, file, &content, parts))
+}
+
+/// Edits for the lines of `content`, read from `file`, where the renames
+/// change one of the `parts` of the line.
+fn edits_in(
+    relocation: &Relocation,
+    file: &Path,
+    content: &str,
+    mut parts: impl FnMut(&str) -> Vec<(Range<usize>, Follow)>,
+) -> Vec<Edit> {
+    content
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            let parts = parts(line);
+            if parts.is_empty() {
+                return None;
+            }
+            let edit = Edit {
+                file: file.to_path_buf(),
+                target: PathBuf::new(),
+                line: i + 1,
+                old: line.to_string(),
+                new: String::new(),
+                parts,
+            };
+            edit.replan(relocation)
+        })
+        .collect()
+}
+
+/// Lines of `files` the `rules` change, replaced the way names are: the
+/// first rule matching a line wins and replaces every occurrence in it.
+/// Files that aren't UTF-8 text are passed over, and so are search-only
+/// rules.
+pub fn content_edits(
+    relocation: &Relocation,
+    files: &[PathBuf],
+    rules: &[(NameMatcher, Option<String>)],
+) -> Result<Vec<Edit>> {
+    let mut edits = Vec::new();
+    for file in files {
+        let mut bytes = Vec::new();
+        open_regular(file)
+            .and_then(|mut file| file.read_to_end(&mut bytes))
+            .with_context(|| format!("Failed to read {}", file.display()))?;
+        let Ok(content) = String::from_utf8(bytes) else {
+            continue;
+        };
+        for (i, line) in content.lines().enumerate() {
+            let new = rules.iter().find_map(|(matcher, replacement)| matcher.replace(line, replacement.as_deref()?));
+            if let Some(new) = new.filter(|new| new != line) {
+                edits.push(Edit {
+                    file: file.clone(),
+                    target: relocation.relocate(file),
+                    line: i + 1,
+                    old: line.to_string(),
+                    new,
+                    parts: Vec::new(),
+                });
+            }
+        }
+    }
+    Ok(edits)
+}
+
+/// Entries of `.gitignore` and `.ignore` files that list a renamed path
+/// explicitly, as `name`, `/name`, `dir/name/` or `!name`, rewritten to the
+/// new name. Entries with wildcards are left alone, they may well mean
+/// other files too.
+pub fn ignore_edits(relocation: &Relocation, base_dir: &Path) -> Result<Vec<Edit>> {
+    let mut edits = Vec::new();
+    for file in files_above(relocation, base_dir, |name| matches!(name, ".gitignore" | ".ignore")) {
+        let dir = file.parent().unwrap_or(Path::new("."));
+        edits.extend(line_edits(relocation, &file, |line| match ignore_entry(line) {
+            Some((prefix, body, _)) => vec![(prefix.len()..prefix.len() + body.len(), Follow::Entry(dir.to_path_buf()))],
+            None => Vec::new(),
+        })?);
+    }
+    Ok(edits)
+}
+
+/// An ignore entry naming a path, split into the `!` and `/` in front, the
+/// path, and the `/` and blanks after it.
+fn ignore_entry(line: &str) -> Option<(&str, &str, &str)> {
+    let trimmed = line.trim_end();
+    if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.contains(['*', '?', '[', '\\']) {
+        return None;
+    }
+    let start = trimmed.len() - trimmed.trim_start_matches('!').trim_start_matches('/').len();
+    let end = trimmed.trim_end_matches('/').len();
+    let body = trimmed.get(start..end).filter(|body| !body.is_empty())?;
+    Some((&line[..start], body, &line[end..]))
+}
+
+/// Project files `--fix-manifests` knows how to read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Manifest {
+    Cargo,
+    Npm,
+    CMake,
+    Make,
+}
+
+impl Manifest {
+    fn of(name: &str) -> Option<Manifest> {
+        match name {
+            "Cargo.toml" => Some(Manifest::Cargo),
+            "package.json" => Some(Manifest::Npm),
+            "CMakeLists.txt" => Some(Manifest::CMake),
+            "Makefile" | "makefile" | "GNUmakefile" => Some(Manifest::Make),
+            _ if name.ends_with(".cmake") => Some(Manifest::CMake),
+            _ if name.ends_with(".mk") => Some(Manifest::Make),
+            _ => None,
+        }
+    }
+}
+
+/// Keys of `Cargo.toml` holding a path, also inside inline tables like
+/// `foo = { path = "../foo" }`.
+const CARGO_PATH_KEYS: &[&str] = &["path", "build", "readme", "license-file", "workspace"];
+/// Keys of `Cargo.toml` holding a list of paths.
+const CARGO_LIST_KEYS: &[&str] = &["members", "default-members", "exclude", "include"];
+
+/// Paths in `Cargo.toml`, `package.json`, `CMakeLists.txt` and Makefiles
+/// (and `*.cmake`, `*.mk`) from `base_dir` down to the renamed paths that
+/// point at a renamed path, rewritten to the new path. Only paths relative
+/// to the manifest count, and only where the format puts paths:
+///
+/// - `Cargo.toml`: `path`, `build`, `readme`, `license-file` and
+///   `workspace`, and the `members`, `default-members`, `exclude` and
+///   `include` lists
+/// - `package.json`: string values with a `.` or `/` in them, like `main`
+///   or the `files` list
+/// - CMake and Make: words with a `.` or `/` in them, and the arguments of
+///   `add_subdirectory`. Words with variables are left alone
+pub fn manifest_edits(relocation: &Relocation, base_dir: &Path) -> Result<Vec<Edit>> {
+    let mut edits = Vec::new();
+    for file in files_above(relocation, base_dir, |name| Manifest::of(name).is_some()) {
+        let dir = file.parent().unwrap_or(Path::new("."));
+        let Some(kind) = file.file_name().and_then(|name| name.to_str()).and_then(Manifest::of) else {
+            continue;
+        };
+        let mut in_list = false;
+        edits.extend(line_edits(relocation, &file, |line| {
+            let paths = match kind {
+                Manifest::Cargo => cargo_paths(line, &mut in_list),
+                Manifest::Npm => npm_paths(line),
+                Manifest::CMake => words(line, &['(', ')'], true)
+                    .into_iter()
+                    .filter(|word| is_path_like(&line[word.clone()]) || line.trim_start().starts_with("add_subdirectory"))
+                    .collect(),
+                Manifest::Make => words(line, &[':', '=', ';'], false)
+                    .into_iter()
+                    .filter(|word| is_path_like(&line[word.clone()]) && !line[word.clone()].contains(['$', '%']))
+                    .collect(),
+            };
+            paths.into_iter().map(|path| (path, Follow::Path(dir.to_path_buf()))).collect()
+        })?);
+    }
+    Ok(edits)
+}
+
+/// Worth looking up: has a `.` or `/` and no wildcards or variables.
+fn is_path_like(word: &str) -> bool {
+    word.contains(['.', '/']) && !word.contains(['*', '?', '$'])
+}
+
+/// Ranges of the insides of the `"..."` strings in `line`, up to a `#`
+/// comment outside of them.
+fn strings(line: &str) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut chars = line.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '#' => break,
+            '"' => {
+                let mut escaped = false;
+                for (j, c) in chars.by_ref() {
+                    match c {
+                        '\\' if !escaped => escaped = true,
+                        '"' if !escaped => {
+                            ranges.push(i + 1..j);
+                            break;
+                        }
+                        _ => escaped = false,
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    ranges
+}
+
+/// Whitespace separated words of `line` outside of `"..."` strings, also
+/// split at `separators`, up to a `#` comment. With `quoted` the insides of
+/// the strings count as words too.
+fn words(line: &str, separators: &[char], quoted: bool) -> Vec<Range<usize>> {
+    let strings = strings(line);
+    let end = line.find('#').filter(|i| !strings.iter().any(|s| s.contains(i))).unwrap_or(line.len());
+    let mut ranges = Vec::new();
+    let mut start = None;
+    for (i, c) in line[..end].char_indices().chain([(end, ' ')]) {
+        let in_string = strings.iter().any(|s| s.start - 1 <= i && i <= s.end);
+        if c.is_whitespace() || separators.contains(&c) || in_string {
+            if let Some(start) = start.take() {
+                ranges.push(start..i);
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+    if quoted {
+        ranges.extend(strings);
+        ranges.sort_by_key(|range| range.start);
+    }
+    ranges
+}
+
+/// The strings of a `Cargo.toml` line that hold paths. `in_list` carries
+/// an open path list over to the next line.
+fn cargo_paths(line: &str, in_list: &mut bool) -> Vec<Range<usize>> {
+    let list_start = match line.split_once('=') {
+        Some((key, value)) if !*in_list && CARGO_LIST_KEYS.contains(&key.trim()) && value.trim_start().starts_with('[') => {
+            *in_list = true;
+            key.len()
+        }
+        _ => 0,
+    };
+    let strings = strings(line);
+    let paths = strings
+        .iter()
+        .filter(|string| {
+            let key = key_before(&line[..string.start - 1]);
+            (*in_list && string.start > list_start) || CARGO_PATH_KEYS.contains(&key)
+        })
+        .cloned()
+        .collect();
+    if *in_list {
+        let closed = line.char_indices().any(|(i, c)| c == ']' && !strings.iter().any(|s| s.contains(&i)));
+        *in_list = !closed;
+    }
+    paths
+}
+
+/// The key of `key = ` at the end of `text`, or an empty string.
+fn key_before(text: &str) -> &str {
+    let Some(text) = text.trim_end().strip_suffix('=') else {
+        return "";
+    };
+    let text = text.trim_end();
+    let start = text.trim_end_matches(|c: char| c.is_alphanumeric() || c == '-' || c == '_').len();
+    &text[start..]
+}
+
+/// The string values of a `package.json` line that look like paths, not
+/// the keys.
+fn npm_paths(line: &str) -> Vec<Range<usize>> {
+    strings(line)
+        .into_iter()
+        .filter(|string| !line[string.end + 1..].trim_start().starts_with(':') && is_path_like(&line[string.clone()]))
+        .collect()
+}
+
+/// Documents `--fix-links` looks at.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkFormat {
+    /// Markdown (`.md`, `.markdown`).
+    Md,
+    /// HTML (`.html`, `.htm`).
+    Html,
+}
+
+impl LinkFormat {
+    fn of(path: &Path) -> Option<LinkFormat> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "md" | "markdown" => Some(LinkFormat::Md),
+            "html" | "htm" => Some(LinkFormat::Html),
+            _ => None,
+        }
+    }
+}
+
+/// Relative links to renamed paths in the documents of `formats` anywhere
+/// below `base_dir`, rewritten to the new names: `[text](path)`,
+/// `![alt](path)`, `<path>` and `[id]: path` in Markdown outside of code
+/// blocks, `href` and `src` attributes in HTML. Links with a scheme,
+/// absolute links and pure `#anchors` are left alone; anchors and queries
+/// after a path stay as they are. Hidden and ignored documents are not
+/// searched.
+pub fn link_edits(relocation: &Relocation, base_dir: &Path, formats: &[LinkFormat]) -> Result<Vec<Edit>> {
+    let mut edits = Vec::new();
+    for entry in WalkBuilder::new(base_dir).build().flatten() {
+        let file = entry.path();
+        let Some(format) = LinkFormat::of(file).filter(|format| formats.contains(format)) else {
+            continue;
+        };
+        if !entry.file_type().is_some_and(|t| t.is_file()) {
+            continue;
+        }
+        let dir = file.parent().unwrap_or(Path::new("."));
+        let mut fence: Option<&str> = None;
+        edits.extend(line_edits(relocation, file, |line| {
+            let links = match format {
+                LinkFormat::Md => {
+                    let trimmed = line.trim_start();
+                    match fence {
+                        Some(marker) if trimmed.starts_with(marker) => {
+                            fence = None;
+                            return Vec::new();
+                        }
+                        Some(_) => return Vec::new(),
+                        None if trimmed.starts_with("```") || trimmed.starts_with("~~~") => {
+                            fence = Some(if trimmed.starts_with("```") { "```" } else { "~~~" });
+                            return Vec::new();
+                        }
+                        None => markdown_links(line),
+                    }
+                }
+                LinkFormat::Html => html_links(line),
+            };
+            links
+                .into_iter()
+                .filter_map(|link| {
+                    // The path only, anchors and queries stay
+                    let end = link.start + line[link.clone()].find(['#', '?']).unwrap_or(link.len());
+                    let path = &line[link.start..end];
+                    if path.is_empty() || path.starts_with('/') || path.contains(':') {
+                        return None;
+                    }
+                    Some((link.start..end, Follow::Link(dir.to_path_buf())))
+                })
+                .collect()
+        })?);
+    }
+    Ok(edits)
+}
+
+/// Targets of inline links, autolinks and link definitions in a Markdown
+/// line, without the `<>` some of them are wrapped in.
+fn markdown_links(line: &str) -> Vec<Range<usize>> {
+    let mut links = Vec::new();
+    // [id]: path
+    let trimmed = line.trim_start();
+    if let (true, Some(close)) = (trimmed.starts_with('['), trimmed.find("]:")) {
+        let start = line.len() - trimmed.len() + close + 2;
+        let target = line[start..].trim_start();
+        let start = line.len() - target.len();
+        links.push(unwrap_link(line, start));
+        return links;
+    }
+    let mut from = 0;
+    while let Some(found) = line[from..].find("](") {
+        let start = from + found + 2;
+        links.push(unwrap_link(line, start));
+        from = start;
+    }
+    // <path>, only for things that look like paths
+    let mut from = 0;
+    while let Some(found) = line[from..].find('<') {
+        let start = from + found + 1;
+        let Some(len) = line[start..].find('>') else {
+            break;
+        };
+        let inner = &line[start..start + len];
+        if inner.contains(['.', '/']) && !inner.contains([' ', '=', '"']) && !line[..start - 1].ends_with("](") {
+            links.push(start..start + len);
+        }
+        from = start + len;
+    }
+    links.sort_by_key(|link| link.start);
+    links.dedup();
+    links
+}
+
+/// The link starting at `start`: up to `>` when wrapped in `<>`, otherwise
+/// up to the first blank or unmatched `)`.
+fn unwrap_link(line: &str, start: usize) -> Range<usize> {
+    if line[start..].starts_with('<') {
+        let end = line[start + 1..].find('>').map_or(line.len(), |len| start + 1 + len);
+        return start + 1..end;
+    }
+    let mut depth = 0;
+    let end = line[start..]
+        .char_indices()
+        .find(|&(_, c)| match c {
+            '(' => {
+                depth += 1;
+                false
+            }
+            ')' if depth == 0 => true,
+            ')' => {
+                depth -= 1;
+                false
+            }
+            c => c.is_whitespace(),
+        })
+        .map_or(line.len(), |(i, _)| start + i);
+    start..end
+}
+
+/// Values of the `href` and `src` attributes in an HTML line.
+fn html_links(line: &str) -> Vec<Range<usize>> {
+    let lower = line.to_ascii_lowercase();
+    let mut links = Vec::new();
+    for attribute in ["href", "src"] {
+        let mut from = 0;
+        while let Some(found) = lower[from..].find(attribute) {
+            let name_start = from + found;
+            from = name_start + attribute.len();
+            let before = lower[..name_start].chars().next_back();
+            if before.is_some_and(|c| !c.is_whitespace()) {
+                continue;
+            }
+            let rest = lower[from..].trim_start();
+            let Some(rest) = rest.strip_prefix('=') else {
+                continue;
+            };
+            let rest = rest.trim_start();
+            let start = lower.len() - rest.len();
+            let link = match rest.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let len = rest[1..].find(quote).unwrap_or(rest.len() - 1);
+                    start + 1..start + 1 + len
+                }
+                _ => start..start + rest.find(|c: char| c.is_whitespace() || c == '>').unwrap_or(rest.len()),
+            };
+            links.push(link);
+        }
+    }
+    links.sort_by_key(|link| link.start);
+    links
+}
+
+/// How a renamed entry is mentioned by name, see [`reference_edits`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mention {
+    /// A file name, anywhere if it has an extension, otherwise only in an
+    /// import or next to a path separator.
+    File,
+    /// A file's stem, as a module in an import.
+    Module,
+    /// A directory name, as part of a path.
+    Dir,
+}
+
+/// Words that start an import line.
+const IMPORTS: &[&str] = &["import ", "from ", "use ", "mod ", "#include", "#import", "@import", "require", "include"];
+
+/// The old names of renamed entries to look for, see [`reference_edits`].
+struct Names {
+    pattern: Regex,
+    /// The renamed paths by old name, the new names are up to the renames.
+    lookup: HashMap<String, (Vec<PathBuf>, Mention)>,
+}
+
+impl Names {
+    /// The names of the renames of `relocation`, if there are any to look
+    /// for.
+    fn new(relocation: &Relocation) -> Result<Option<Names>> {
+        let mut names: HashMap<String, Option<(Vec<PathBuf>, Mention)>> = HashMap::new();
+        let mut mention = |old: &str, path: &Path, kind: Mention| {
+            let known = names.entry(old.to_string()).or_insert_with(|| Some((Vec::new(), kind)));
+            match known {
+                Some((sources, known_kind)) if *known_kind == kind => sources.push(path.to_path_buf()),
+                _ => *known = None,
+            }
+        };
+        for (path, new_name) in &relocation.renamed {
+            let old_name = file_name(path);
+            if path.is_dir() {
+                mention(old_name, path, Mention::Dir);
+                continue;
+            }
+            mention(old_name, path, Mention::File);
+            let (old_stem, new_stem) = (split_extension(old_name).0, split_extension(new_name).0);
+            if old_stem != old_name && old_stem != new_stem {
+                mention(old_stem, path, Mention::Module);
+            }
+        }
+        let lookup: HashMap<String, (Vec<PathBuf>, Mention)> =
+            names.into_iter().filter_map(|(old, known)| Some((old, known?))).collect();
+        if lookup.is_empty() {
+            return Ok(None);
+        }
+        // Longer names first, so `foo.tar.gz` wins over `foo.tar`
+        let mut alternatives: Vec<&String> = lookup.keys().collect();
+        alternatives.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        let alternatives: Vec<String> = alternatives.into_iter().map(|old| regex::escape(old)).collect();
+        let pattern = Regex::new(&alternatives.join("|"))?;
+        Ok(Some(Names { pattern, lookup }))
+    }
+
+    /// The mentions of the names in `line`.
+    fn parts(&self, line: &str) -> Vec<(Range<usize>, Follow)> {
+        // Word characters around a name make it part of another word
+        let word = |c: Option<char>, dots: bool| c.is_some_and(|c| c.is_alphanumeric() || c == '_' || c == '-' || (dots && c == '.'));
+        let separator = |c: Option<char>| matches!(c, Some('/' | '\\'));
+        let import = IMPORTS.iter().any(|word| line.trim_start().starts_with(word));
+        let mut parts = Vec::new();
+        for found in self.pattern.find_iter(line) {
+            let (sources, kind) = &self.lookup[found.as_str()];
+            let before = line[..found.start()].chars().next_back();
+            let after = line[found.end()..].chars().next();
+            // Without an extension a file name is just a word, like `test`
+            let mentioned = !word(before, true)
+                && !word(after, false)
+                && match kind {
+                    Mention::File if split_extension(found.as_str()).1.is_some() => true,
+                    Mention::File => import || separator(before) || separator(after),
+                    Mention::Module => import,
+                    Mention::Dir => separator(before) || separator(after),
+                };
+            if mentioned {
+                let follow = Follow::Name {
+                    sources: sources.clone(),
+                    stem: *kind == Mention::Module,
+                };
+                parts.push((found.range(), follow));
+            }
+        }
+        parts
+    }
+}
+
+/// Mentions of renamed entries by name in the text files below `base_dir`,
+/// rewritten to the new names: file names with an extension anywhere, as
+/// in `#include "foo.h"` or `see foo.h`, stems on import lines, as in
+/// `import foo` or `use foo::Bar`, and directory names where they are part
+/// of a path, next to a `/` or `\`. File names without an extension are
+/// plain words in prose, they only count on import lines and in paths.
+/// Names only count as whole words, so renaming `foo.h` leaves `myfoo.h`
+/// alone. Names that renames in several places take to different new names
+/// are left alone too, there is no telling which one a file means. Hidden,
+/// ignored and non-UTF-8 files are not searched.
+pub fn reference_edits(relocation: &Relocation, base_dir: &Path) -> Result<Vec<Edit>> {
+    let Some(names) = Names::new(relocation)? else {
+        return Ok(Vec::new());
+    };
+    let mut edits = Vec::new();
+    for entry in WalkBuilder::new(base_dir).build().flatten() {
+        if !entry.file_type().is_some_and(|t| t.is_file()) {
+            continue;
+        }
+        let file = entry.path();
+        let bytes = fs::read(file).with_context(|| format!("Failed to read {}", file.display()))?;
+        let Ok(content) = String::from_utf8(bytes) else {
+            continue;
+        };
+        edits.extend(edits_in(relocation, file, &content, |line| names.parts(line)));
+    }
+    Ok(edits)
+}
+
+/// Apply `edits` to their files, where the renames left them. A line that
+/// changed since the edit was planned stops everything before that file
+/// is written.
+pub fn apply(edits: &[Edit]) -> Result<()> {
+    let mut files: BTreeMap<&Path, Vec<&Edit>> = BTreeMap::new();
+    for edit in edits {
+        files.entry(&edit.target).or_default().push(edit);
+    }
+    for (file, edits) in files {
+        let content = fs::read_to_string(file).with_context(|| format!("Failed to read {}", file.display()))?;
+        let mut lines: Vec<String> = content.split_inclusive('\n').map(str::to_string).collect();
+        for edit in edits {
+            let Some(line) = lines.get_mut(edit.line - 1) else {
+                bail!("{} has no line {} anymore", file.display(), edit.line);
+            };
+            let ending = &line[line.trim_end_matches(['\n', '\r']).len()..];
+            if line[..line.len() - ending.len()] != edit.old {
+                bail!("Line {} of {} changed since the renames were planned", edit.line, file.display());
+            }
+            *line = format!("{}{}", edit.new, ending);
+        }
+        fs::write(file, lines.concat()).with_context(|| format!("Failed to write {}", file.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn relocation(renames: &[(&str, &str)]) -> Relocation {
+        let entries: Vec<PlanEntry> = renames
+            .iter()
+            .map(|(from, to)| PlanEntry {
+                from: from.into(),
+                to: to.into(),
+                is_dir: false,
+            })
+            .collect();
+        Relocation::applied(&entries)
+    }
+
+    #[test]
+    fn replan_follows_only_the_renames_that_were_done() {
+        let planned = relocation(&[("docs/foo.md", "docs/bar.md"), ("docs/foo2.md", "docs/bar2.md")]);
+        let dir = Path::new("docs");
+        let edits = edits_in(&planned, &dir.join("index.md"), "[a](foo.md) and [b](foo2.md)\n", |line| {
+            markdown_links(line).into_iter().map(|link| (link, Follow::Link(dir.to_path_buf()))).collect()
+        });
+        assert_eq!(edits[0].new, "[a](bar.md) and [b](bar2.md)");
+
+        let done = relocation(&[("docs/foo2.md", "docs/bar2.md")]);
+        assert_eq!(edits[0].replan(&done).unwrap().new, "[a](foo.md) and [b](bar2.md)");
+        assert_eq!(edits[0].replan(&Relocation::default()), None);
+    }
+
+    #[test]
+    fn replan_moves_replaced_content_only_where_its_file_went() {
+        let replaced = Edit {
+            file: "foo.txt".into(),
+            target: "bar.txt".into(),
+            line: 1,
+            old: "foo here".to_string(),
+            new: "bar here".to_string(),
+            parts: Vec::new(),
+        };
+        let kept = replaced.replan(&Relocation::default()).unwrap();
+        assert_eq!((kept.target, kept.new), ("foo.txt".into(), "bar here".to_string()));
+        assert_eq!(replaced.replan(&relocation(&[("foo.txt", "bar.txt")])).unwrap().target, Path::new("bar.txt"));
+    }
+
+    #[test]
+    fn names_without_an_extension_are_only_mentioned_in_imports_and_paths() {
+        let renames = relocation(&[("test", "check"), ("src/util.h", "src/helpers.h")]);
+        let names = Names::new(&renames).unwrap().unwrap();
+        let rewrite = |line: &str| {
+            let edits = edits_in(&renames, Path::new("notes.txt"), line, |line| names.parts(line));
+            edits.first().map(|edit| edit.new.clone())
+        };
+        assert_eq!(rewrite("run the test before you push"), None);
+        assert_eq!(rewrite("include test"), Some("include check".to_string()));
+        assert_eq!(rewrite("see scripts/test for more"), Some("see scripts/check for more".to_string()));
+        assert_eq!(rewrite("see util.h, not myutil.h"), Some("see helpers.h, not myutil.h".to_string()));
+        assert_eq!(rewrite("#include \"util\""), Some("#include \"helpers\"".to_string()));
+        assert_eq!(rewrite("a util for that"), None);
+    }
+
+    #[test]
+    fn ignore_entries_name_paths_but_not_patterns() {
+        assert_eq!(ignore_entry("/build/\n"), Some(("/", "build", "/\n")));
+        assert_eq!(ignore_entry("!/docs/keep.md  "), Some(("!/", "docs/keep.md", "  ")));
+        assert_eq!(ignore_entry("target"), Some(("", "target", "")));
+        assert_eq!(ignore_entry("*.log"), None);
+        assert_eq!(ignore_entry("# build"), None);
+        assert_eq!(ignore_entry("/"), None);
+        assert_eq!(ignore_entry("   "), None);
+    }
+
+    #[test]
+    fn cargo_paths_are_the_strings_of_path_keys_and_lists() {
+        let mut in_list = false;
+        let mut paths = |line: &str| -> Vec<String> {
+            cargo_paths(line, &mut in_list).into_iter().map(|range| line[range].to_string()).collect()
+        };
+        assert_eq!(paths(r#"foo = { path = "crates/foo", version = "1.0" }"#), ["crates/foo"]);
+        assert_eq!(paths(r#"readme = "README.md" # path = "nope""#), ["README.md"]);
+        assert_eq!(paths(r#"name = "crates/foo""#), Vec::<String>::new());
+        assert_eq!(paths(r#"members = ["a", "b","#), ["a", "b"]);
+        assert_eq!(paths(r#"    "c", # "d""#), ["c"]);
+        assert_eq!(paths("]"), Vec::<String>::new());
+        assert_eq!(paths(r#"description = "not a path""#), Vec::<String>::new());
+        assert_eq!(paths(r#"exclude = ["odd]name"]"#), ["odd]name"]);
+        assert_eq!(paths(r#"version = "1.0""#), Vec::<String>::new());
+    }
+
+    #[test]
+    fn markdown_links_are_found_without_their_brackets() {
+        let links = |line: &str| -> Vec<String> {
+            markdown_links(line).into_iter().map(|range| line[range].to_string()).collect()
+        };
+        assert_eq!(links("See [a](docs/a.md#top) and ![b](<my img.png>)."), ["docs/a.md#top", "my img.png"]);
+        assert_eq!(links("[x](notes_(1).md) (aside)"), ["notes_(1).md"]);
+        assert_eq!(links(r#"  [guide]: ../guide.md "Title""#), ["../guide.md"]);
+        assert_eq!(links("Read <docs/a.md>, not <b> or <a href=\"x\">"), ["docs/a.md"]);
+        assert_eq!(links("no links [here] (at all)"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn links_keep_their_spelling_around_the_renamed_parts() {
+        let renames = relocation(&[("docs", "manual"), ("docs/my notes.md", "docs/our notes.md")]);
+        let docs = Path::new("docs");
+        assert_eq!(renames.rewrite_link(docs, "my%20notes.md").as_deref(), Some("our%20notes.md"));
+        assert_eq!(renames.rewrite_link(docs, "./my notes.md").as_deref(), Some("./our notes.md"));
+        assert_eq!(
+            renames.rewrite_link(Path::new("src"), "../docs/my%20notes.md").as_deref(),
+            Some("../manual/our%20notes.md")
+        );
+        assert_eq!(renames.rewrite_link(docs, "other.md"), None);
+    }
+}
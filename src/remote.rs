@@ -0,0 +1,111 @@
+//! Trees on network filesystems (NFS, SMB, sshfs), where every stat is a
+//! round trip to the server: spotting them, and finding out whether paths
+//! exist with one listing per directory instead of one stat per path.
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Walker threads for network filesystems, where they mostly wait.
+pub const REMOTE_THREADS: usize = 16;
+
+/// Rename workers for network filesystems, unless `--jobs` says otherwise.
+pub const REMOTE_JOBS: usize = 8;
+
+/// Types of network filesystems, as `/proc/mounts` spells them.
+const NETWORK_FILESYSTEMS: &[&str] = &[
+    "nfs", "nfs4", "cifs", "smb3", "smbfs", "fuse.sshfs", "sshfs", "9p", "afs", "ceph", "glusterfs",
+    "fuse.glusterfs", "fuse.rclone", "davfs", "fuse.davfs2", "lustre", "gpfs",
+];
+
+/// The type of the network filesystem `path` is on. `None` for local
+/// filesystems, and where the mounts can't be read (everywhere but Linux).
+pub fn network_filesystem(path: &Path) -> Option<String> {
+    let path = fs::canonicalize(path).ok()?;
+    let mounts = fs::read_to_string("/proc/self/mounts").ok()?;
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(' ').skip(1);
+            Some((unescape(fields.next()?), fields.next()?))
+        })
+        .filter(|(point, _)| path.starts_with(point))
+        .max_by_key(|(point, _)| point.as_os_str().len())
+        .map(|(_, kind)| kind.to_string())
+        .filter(|kind| NETWORK_FILESYSTEMS.contains(&kind.as_str()))
+}
+
+/// A mount point from `/proc/mounts`, which spells spaces and the like as
+/// octal escapes.
+fn unescape(field: &str) -> PathBuf {
+    let mut text = String::new();
+    let mut rest = field;
+    while let Some(i) = rest.find('\\') {
+        text.push_str(&rest[..i]);
+        let code = rest.get(i + 1..i + 4).and_then(|octal| u8::from_str_radix(octal, 8).ok());
+        match code {
+            Some(code) => {
+                text.push(code as char);
+                rest = &rest[i + 4..];
+            }
+            None => {
+                text.push('\\');
+                rest = &rest[i + 1..];
+            }
+        }
+    }
+    text.push_str(rest);
+    PathBuf::from(text)
+}
+
+/// How to find out whether paths exist.
+#[derive(Debug, Default)]
+pub enum Probe {
+    /// One stat per path.
+    #[default]
+    Stat,
+    /// One listing per directory, kept for later questions. Names that
+    /// only differ in case from a listed one are stat'ed after all, for
+    /// case-insensitive shares.
+    List(Mutex<HashMap<PathBuf, Option<Listing>>>),
+}
+
+/// The names in a directory.
+#[derive(Debug)]
+pub struct Listing {
+    names: HashSet<OsString>,
+    /// The names in lowercase.
+    folded: HashSet<String>,
+}
+
+impl Listing {
+    fn read(dir: &Path) -> Option<Listing> {
+        let names: HashSet<OsString> = fs::read_dir(dir).ok()?.flatten().map(|entry| entry.file_name()).collect();
+        let folded = names.iter().map(|name| name.to_string_lossy().to_lowercase()).collect();
+        Some(Listing { names, folded })
+    }
+}
+
+impl Probe {
+    pub fn new(remote: bool) -> Self {
+        match remote {
+            true => Probe::List(Mutex::new(HashMap::new())),
+            false => Probe::Stat,
+        }
+    }
+
+    pub fn exists(&self, path: &Path) -> bool {
+        let (Probe::List(listings), Some(name)) = (self, path.file_name()) else {
+            return fs::symlink_metadata(path).is_ok();
+        };
+        let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        let mut listings = listings.lock().unwrap();
+        let Some(listing) = listings.entry(dir.to_path_buf()).or_insert_with(|| Listing::read(dir)) else {
+            return false;
+        };
+        listing.names.contains(name)
+            || (listing.folded.contains(&name.to_string_lossy().to_lowercase()) && fs::symlink_metadata(path).is_ok())
+    }
+}
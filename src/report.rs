@@ -0,0 +1,210 @@
+//! Machine-readable records of what happened to each entry, for
+//! `--format json` and `--format jsonl`, and of what is wrong with a plan,
+//! for `--conflicts-report`.
+
+use crate::check::{Collision, Severity};
+use crate::matcher::Capture;
+use serde::Serialize;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    /// Found by a search.
+    Match,
+    /// Would be renamed (dry run).
+    Planned,
+    Renamed,
+    Copied,
+    Skipped,
+    Failed,
+}
+
+/// Why an entry was skipped or failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reason {
+    /// Shallower than `--min-depth`.
+    Depth,
+    /// Outside the `--components` range.
+    Components,
+    /// Not matched by the glob patterns.
+    Glob,
+    /// A symlink to a file while only directory links are followed.
+    Symlink,
+    /// Not of the requested `--type`.
+    FileType,
+    /// A FIFO, socket or device node without `--type special`.
+    Special,
+    /// Created outside the `--created-after`/`--created-before` range.
+    Created,
+    /// Changed in git within `--untouched-for`, or never committed.
+    Touched,
+    /// Rejected by a custom filter.
+    Filter,
+    /// Excluded by a `.fnr.toml`.
+    Excluded,
+    /// Inside a git submodule with `--no-cross-submodules`.
+    Submodule,
+    /// A file, or a directory with an entry count outside
+    /// `--dir-min-entries`/`--dir-max-entries`.
+    Entries,
+    /// Not text with `--text`, or not binary with `--binary`.
+    Content,
+    /// Part of a `--group-by-stem` group that can't be renamed together.
+    StemGroup,
+    /// The name doesn't match the pattern.
+    NoMatch,
+    /// Beyond `--limit-per-dir`.
+    LimitPerDir,
+    /// The new name breaks a target filesystem or trailing policy.
+    InvalidName,
+    /// Inside or named like a protected directory.
+    Protected,
+    /// The new path is taken by another rename or an entry that stays.
+    Collision,
+    /// Part of renames going round in a circle.
+    Cycle,
+    /// The new name or path is long enough to trip up other tools
+    /// (with `--strict`).
+    Length,
+    /// The rename (or a hook) failed.
+    RenameFailed,
+    /// Undone, or never tried, because another rename in the same directory
+    /// failed with `--atomic-per-dir`, or any other with `--all-or-nothing`.
+    RolledBack,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            Reason::Depth => "above --min-depth",
+            Reason::Components => "outside the --components range",
+            Reason::Glob => "not matched by the glob patterns",
+            Reason::Symlink => "symlink to a file",
+            Reason::FileType => "wrong type",
+            Reason::Special => "special file (FIFO, socket or device), see --type special",
+            Reason::Created => "created outside the date range",
+            Reason::Touched => "changed in git too recently, or never committed",
+            Reason::Filter => "rejected by a filter",
+            Reason::Excluded => "excluded by .fnr.toml",
+            Reason::Submodule => "inside a git submodule",
+            Reason::Entries => "not a directory with the wanted number of entries",
+            Reason::Content => "text/binary content doesn't fit",
+            Reason::StemGroup => "its stem group can't be renamed together",
+            Reason::NoMatch => "name doesn't match",
+            Reason::LimitPerDir => "over --limit-per-dir",
+            Reason::InvalidName => "invalid new name",
+            Reason::Protected => "protected",
+            Reason::Collision => "new path is taken",
+            Reason::Cycle => "part of a rename cycle",
+            Reason::Length => "new name or path is too long",
+            Reason::RenameFailed => "rename failed",
+            Reason::RolledBack => "rolled back",
+        };
+        f.write_str(text)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Record {
+    pub status: Status,
+    pub path: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_path: Option<PathBuf>,
+    pub is_dir: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<Reason>,
+    /// Details for humans, like the error message of a failed rename.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// How the new name of a planned rename came about.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub derivation: Option<Derivation>,
+}
+
+impl Record {
+    pub fn new(status: Status, path: &Path, is_dir: bool) -> Self {
+        Record {
+            status,
+            path: path.to_path_buf(),
+            new_path: None,
+            is_dir,
+            reason: None,
+            message: None,
+            derivation: None,
+        }
+    }
+
+    pub fn skipped(path: &Path, is_dir: bool, reason: Reason) -> Self {
+        Record {
+            reason: Some(reason),
+            ..Record::new(Status::Skipped, path, is_dir)
+        }
+    }
+
+    pub fn failed(path: &Path, is_dir: bool, reason: Reason, message: String) -> Self {
+        Record {
+            reason: Some(reason),
+            message: Some(message),
+            ..Record::new(Status::Failed, path, is_dir)
+        }
+    }
+
+    pub fn with_new_path(self, new_path: &Path) -> Self {
+        Record {
+            new_path: Some(new_path.to_path_buf()),
+            ..self
+        }
+    }
+
+    pub fn with_derivation(self, derivation: Option<Derivation>) -> Self {
+        Record { derivation, ..self }
+    }
+}
+
+/// Which rule produced a new name, and what its pattern matched.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Derivation {
+    /// Position in the rule list, starting at 1.
+    pub rule: usize,
+    pub pattern: String,
+    /// The replacement with placeholders filled in.
+    pub replacement: String,
+    /// The part of the old name the pattern matched.
+    pub matched: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub captures: Vec<Capture>,
+}
+
+impl fmt::Display for Derivation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rule {}: '{}' matched '{}'", self.rule, self.pattern, self.matched)?;
+        for capture in &self.captures {
+            match &capture.text {
+                Some(text) => write!(f, ", {}='{}'", capture.group, text)?,
+                None => write!(f, ", {} unset", capture.group)?,
+            }
+        }
+        write!(f, " -> '{}'", self.replacement)
+    }
+}
+
+/// Every problem found in a plan, for `--conflicts-report`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ConflictReport {
+    pub collisions: Vec<Collision>,
+    /// Renames going round in a circle, each starting at its smallest path.
+    pub cycles: Vec<Vec<PathBuf>>,
+    /// Invalid names, protected paths, submodules and stem groups.
+    pub issues: Vec<ReportedIssue>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReportedIssue {
+    pub path: PathBuf,
+    pub reason: Reason,
+    pub severity: Severity,
+    pub message: String,
+}
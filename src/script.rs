@@ -0,0 +1,130 @@
+//! Shell scripts performing a list of renames, for `--emit-script`.
+//!
+//! Every script stops at the first failure and refuses to overwrite
+//! existing entries, like fnr itself. Scripts can't stage, so renames going
+//! round in a circle are refused before, and chains (`a -> b`, `b -> c`)
+//! are written in the order that frees each name before it is taken.
+
+use crate::Match;
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScriptKind {
+    /// POSIX shell (`mv`).
+    Sh,
+    /// Windows batch file (`ren`).
+    Bat,
+    /// PowerShell (`Rename-Item`).
+    Powershell,
+}
+
+/// Write a script renaming `matches` in order, except that a rename onto
+/// the path of another waits for that one.
+pub fn write_script(out: &mut dyn Write, matches: &[Match], kind: ScriptKind) -> Result<()> {
+    let matches = chain_order(matches);
+    match kind {
+        ScriptKind::Sh => {
+            writeln!(out, "#!/bin/sh")?;
+            writeln!(out, "# {} renames planned by fnr", matches.len())?;
+            writeln!(out, "set -e")?;
+            writeln!(
+                out,
+                "rn() {{ if [ -e \"$2\" ] || [ -L \"$2\" ]; then echo \"$2 already exists\" >&2; exit 1; fi; mv -- \"$1\" \"$2\"; }}"
+            )?;
+            for &m in &matches {
+                writeln!(out, "rn {} {}", sh_quote(text(&m.path)?), sh_quote(text(&m.new_path())?))?;
+            }
+        }
+        ScriptKind::Bat => {
+            writeln!(out, "@echo off")?;
+            writeln!(out, "rem {} renames planned by fnr", matches.len())?;
+            for &m in &matches {
+                // `ren` takes the new name only, and fails if it exists
+                writeln!(
+                    out,
+                    "ren {} {} || exit /b 1",
+                    bat_quote(&text(&m.path)?.replace('/', "\\"))?,
+                    bat_quote(&m.new_name)?
+                )?;
+            }
+        }
+        ScriptKind::Powershell => {
+            writeln!(out, "# {} renames planned by fnr", matches.len())?;
+            writeln!(out, "$ErrorActionPreference = 'Stop'")?;
+            for &m in &matches {
+                writeln!(
+                    out,
+                    "Rename-Item -LiteralPath {} -NewName {}",
+                    ps_quote(text(&m.path)?),
+                    ps_quote(&m.new_name)
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `matches` in order, with each rename onto the current path of a later
+/// one moved right behind it. That rename is in the same directory, so
+/// this never moves anything past the rename of its directory.
+fn chain_order(matches: &[Match]) -> Vec<&Match> {
+    let position: HashMap<&Path, usize> = matches.iter().enumerate().map(|(i, m)| (m.path.as_path(), i)).collect();
+    let mut done = vec![false; matches.len()];
+    // Renames waiting for the one at the index to free their new name
+    let mut waiting: HashMap<usize, Vec<usize>> = HashMap::new();
+    let mut order = Vec::with_capacity(matches.len());
+    for (i, m) in matches.iter().enumerate() {
+        match position.get(m.new_path().as_path()) {
+            Some(&holder) if holder != i && !done[holder] => waiting.entry(holder).or_default().push(i),
+            _ => {
+                let mut ready = vec![i];
+                while let Some(next) = ready.pop() {
+                    done[next] = true;
+                    order.push(&matches[next]);
+                    ready.extend(waiting.remove(&next).unwrap_or_default());
+                }
+            }
+        }
+    }
+    // Circles never get their turn, they are refused before
+    order.extend(matches.iter().zip(&done).filter(|(_, &done)| !done).map(|(m, _)| m));
+    order
+}
+
+fn text(path: &Path) -> Result<&str> {
+    match path.to_str() {
+        Some(text) => Ok(text),
+        None => bail!("{} is not valid UTF-8 and can't be written to a script", path.display()),
+    }
+}
+
+/// Single quotes keep everything literal, except single quotes.
+fn sh_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+/// Double quotes take care of spaces and `&|<>^`; `%` has to be doubled
+/// in batch files. Names can't contain double quotes on Windows anyway.
+fn bat_quote(text: &str) -> Result<String> {
+    if text.contains('"') || text.contains(['\n', '\r']) {
+        bail!("{} can't be quoted for cmd.exe", text);
+    }
+    Ok(format!("\"{}\"", text.replace('%', "%%")))
+}
+
+/// PowerShell also takes typographic single quotes as quotes, all of them
+/// are escaped by doubling.
+fn ps_quote(text: &str) -> String {
+    let mut quoted = String::from("'");
+    for c in text.chars() {
+        if matches!(c, '\'' | '\u{2018}' | '\u{2019}' | '\u{201a}' | '\u{201b}') {
+            quoted.push(c);
+        }
+        quoted.push(c);
+    }
+    quoted.push('\'');
+    quoted
+}
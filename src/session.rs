@@ -0,0 +1,147 @@
+//! Review sessions for `--session FILE`: the answers given to rename
+//! prompts and the playground's selection, so a long review can stop and
+//! pick up where it left off.
+//!
+//! The file is JSON lines, appended (and flushed) as answers are given, so
+//! a session survives Ctrl-C. Reading it back, later lines win. Paths are
+//! absolute, so a session can be resumed from another directory.
+
+use crate::matcher::StemEdit;
+use crate::Match;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{self, Path, PathBuf};
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Answer {
+    Yes,
+    No,
+}
+
+/// Where the playground was left.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Selection {
+    pub pattern: String,
+    pub replacement: String,
+    pub regex: bool,
+    pub case_sensitive: bool,
+    /// Matches turned off.
+    pub deselected: Vec<PathBuf>,
+    /// Quick edits in order, each with the paths it applies to.
+    pub edits: Vec<(StemEdit, Vec<PathBuf>)>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Line {
+    /// The rename of `path` to `to` was answered.
+    Answer { path: PathBuf, to: PathBuf, answer: Answer },
+    /// The rest of `dir` was skipped.
+    SkipDir { dir: PathBuf },
+    Selection(Selection),
+}
+
+#[derive(Debug)]
+pub struct Session {
+    path: PathBuf,
+    file: Mutex<File>,
+    /// Earlier answers by path, with the new path they were about.
+    answers: HashMap<PathBuf, (PathBuf, Answer)>,
+    skipped_dirs: HashSet<PathBuf>,
+    selection: Option<Selection>,
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+impl Session {
+    /// Read the session at `path`, or start a new one there.
+    pub fn open(path: &Path) -> Result<Session> {
+        let mut session = Session {
+            path: path.to_path_buf(),
+            file: Mutex::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("Failed to open session {}", path.display()))?,
+            ),
+            answers: HashMap::new(),
+            skipped_dirs: HashSet::new(),
+            selection: None,
+        };
+        let text = fs::read_to_string(path).with_context(|| format!("Failed to read session {}", path.display()))?;
+        for (number, line) in text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+            let line: Line = serde_json::from_str(line)
+                .with_context(|| format!("{}:{}: not a session line", path.display(), number + 1))?;
+            match line {
+                Line::Answer { path, to, answer } => {
+                    session.answers.insert(path, (to, answer));
+                }
+                Line::SkipDir { dir } => {
+                    session.skipped_dirs.insert(dir);
+                }
+                Line::Selection(selection) => session.selection = Some(selection),
+            }
+        }
+        Ok(session)
+    }
+
+    /// Number of earlier answers, skipped directories included.
+    pub fn len(&self) -> usize {
+        self.answers.len() + self.skipped_dirs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The earlier answer about `m`. Answers about another new name don't
+    /// count, and neither do answers about entries of skipped directories.
+    pub fn answer(&self, m: &Match) -> Option<Answer> {
+        let dir = m.path.parent().map(absolute);
+        if dir.is_some_and(|dir| self.skipped_dirs.contains(&dir)) {
+            return Some(Answer::No);
+        }
+        match self.answers.get(&absolute(&m.path)) {
+            Some((to, answer)) if *to == absolute(&m.new_path()) => Some(*answer),
+            _ => None,
+        }
+    }
+
+    /// Where the playground was left, if it was.
+    pub fn selection(&self) -> Option<&Selection> {
+        self.selection.as_ref()
+    }
+
+    pub fn record_answer(&self, m: &Match, answer: Answer) -> Result<()> {
+        self.append(&Line::Answer {
+            path: absolute(&m.path),
+            to: absolute(&m.new_path()),
+            answer,
+        })
+    }
+
+    /// Record that the rest of the directory of `m` was skipped.
+    pub fn record_skip_dir(&self, m: &Match) -> Result<()> {
+        let dir = m.path.parent().unwrap_or(Path::new("."));
+        self.append(&Line::SkipDir { dir: absolute(dir) })
+    }
+
+    pub fn record_selection(&self, selection: Selection) -> Result<()> {
+        self.append(&Line::Selection(selection))
+    }
+
+    fn append(&self, line: &Line) -> Result<()> {
+        let mut file = self.file.lock().unwrap();
+        writeln!(file, "{}", serde_json::to_string(line)?)
+            .and_then(|_| file.flush())
+            .with_context(|| format!("Failed to write session {}", self.path.display()))
+    }
+}
@@ -0,0 +1,118 @@
+//! Bringing new names within a length budget, `--max-name-len`.
+//!
+//! Budgets are in bytes, which is what filesystems count, and names are cut
+//! on grapheme cluster boundaries only. Extensions are kept.
+
+use crate::conflict::NameRegistry;
+use crate::sidecar::split_extension;
+use crate::{file_name, Match};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Hex digits of the hash suffix.
+const HASH_LEN: usize = 8;
+
+/// Marks the cut of a middle ellipsis.
+const ELLIPSIS: &str = "…";
+
+/// The smallest budget every strategy fits in: a `~` and the hash, plus a
+/// character of the stem.
+pub const MIN_NAME_LEN: usize = HASH_LEN + 2;
+
+/// How `--shorten` cuts a name down to the budget.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Shorten {
+    /// Cut the end of the stem.
+    #[default]
+    Truncate,
+    /// Cut the end of the stem and append `~` and a hash of the whole name.
+    HashSuffix,
+    /// Keep the start and the end of the stem, with `…` in between.
+    MiddleEllipsis,
+}
+
+/// Shorten the new names longer than `max_len` bytes with `strategy`. A
+/// shortened name that is taken in its directory, on disk or by another
+/// new name, gets a hash suffix instead, so names that only differ after
+/// the cut stay apart. Names that don't change are left alone, however
+/// long they are.
+pub fn shorten_names(mut matches: Vec<Match>, max_len: usize, strategy: Shorten, case_sensitive: bool) -> Vec<Match> {
+    let too_long = |m: &Match| m.new_name.len() > max_len && m.new_name != file_name(&m.path);
+    let mut registry = NameRegistry::new(case_sensitive);
+    for m in matches.iter().filter(|m| !too_long(m)) {
+        registry.claim(parent(m), &m.new_name);
+    }
+    for m in matches.iter_mut().filter(|m| too_long(m)) {
+        let dir = parent(m);
+        let mut name = shorten(&m.new_name, m.is_dir, max_len, strategy);
+        if strategy != Shorten::HashSuffix && name != file_name(&m.path) && registry.is_taken(dir, &name) {
+            name = shorten(&m.new_name, m.is_dir, max_len, Shorten::HashSuffix);
+        }
+        registry.claim(dir, &name);
+        m.pattern = file_name(&m.path).into();
+        m.new_name = name;
+        m.replacement = m.new_name.as_str().into();
+    }
+    matches
+}
+
+fn parent(m: &Match) -> &Path {
+    m.path.parent().unwrap_or(Path::new("."))
+}
+
+/// `name` cut down to `max_len` bytes. The extension stays unless it leaves
+/// no room for the stem, then the name is cut as a whole.
+fn shorten(name: &str, is_dir: bool, max_len: usize, strategy: Shorten) -> String {
+    let needed = match strategy {
+        Shorten::Truncate => 1,
+        Shorten::HashSuffix => MIN_NAME_LEN,
+        Shorten::MiddleEllipsis => ELLIPSIS.len() + 2,
+    };
+    let (stem, extension) = match split_extension(name) {
+        (stem, Some(extension)) if !is_dir && extension.len() + 1 + needed <= max_len => (stem, Some(extension)),
+        _ => (name, None),
+    };
+    let budget = max_len - extension.map_or(0, |extension| extension.len() + 1);
+    let stem = match strategy {
+        Shorten::Truncate => head(stem, budget).to_string(),
+        Shorten::HashSuffix => {
+            let hash: String = Sha256::digest(name.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect();
+            format!("{}~{}", head(stem, budget.saturating_sub(HASH_LEN + 1)), &hash[..HASH_LEN])
+        }
+        Shorten::MiddleEllipsis => {
+            let room = budget.saturating_sub(ELLIPSIS.len());
+            let start = head(stem, room.div_ceil(2));
+            format!("{}{}{}", start, ELLIPSIS, tail(stem, room - start.len()))
+        }
+    };
+    match extension {
+        Some(extension) => format!("{}.{}", stem, extension),
+        None => stem,
+    }
+}
+
+/// The longest start of `text` with whole grapheme clusters in `budget`
+/// bytes.
+fn head(text: &str, budget: usize) -> &str {
+    let end = text
+        .grapheme_indices(true)
+        .map(|(i, grapheme)| i + grapheme.len())
+        .take_while(|&end| end <= budget)
+        .last()
+        .unwrap_or(0);
+    &text[..end]
+}
+
+/// The longest end of `text` with whole grapheme clusters in `budget`
+/// bytes.
+fn tail(text: &str, budget: usize) -> &str {
+    let start = text
+        .grapheme_indices(true)
+        .rev()
+        .map(|(i, _)| i)
+        .take_while(|&i| text.len() - i <= budget)
+        .last()
+        .unwrap_or(text.len());
+    &text[start..]
+}
@@ -0,0 +1,251 @@
+//! Files travelling with a renamed file, like the `.xmp` next to a photo,
+//! and files sharing a stem renamed as a unit.
+
+use crate::check::Issue;
+use crate::{file_name, Match};
+use anyhow::{bail, Result};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Sidecar extensions, for files with the `primary` extension or for all.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SidecarSpec {
+    pub primary: Option<String>,
+    pub extensions: Vec<String>,
+}
+
+/// Parse `[PRIMARY:]EXT,EXT...`, like `jpg:xmp,raw` or `xmp`.
+pub fn parse_sidecars(text: &str) -> Result<SidecarSpec> {
+    let (primary, list) = match text.split_once(':') {
+        Some((primary, list)) => (Some(primary), list),
+        None => (None, text),
+    };
+    let clean = |ext: &str| ext.trim().trim_start_matches('.').to_lowercase();
+    let extensions: Vec<String> = list.split(',').map(clean).filter(|ext| !ext.is_empty()).collect();
+    if extensions.is_empty() {
+        bail!("No sidecar extensions in '{}'", text);
+    }
+    let primary = primary.map(clean);
+    if primary.as_deref() == Some("") {
+        bail!("Empty primary extension in '{}'", text);
+    }
+    Ok(SidecarSpec { primary, extensions })
+}
+
+/// `name` split into stem and extension (without the dot). Dotfiles are
+/// all stem.
+pub(crate) fn split_extension(name: &str) -> (&str, Option<&str>) {
+    match name.rfind('.') {
+        Some(0) | None => (name, None),
+        Some(i) => (&name[..i], Some(&name[i + 1..])),
+    }
+}
+
+/// Add a rename for every sidecar of a renamed file, right after it. Both
+/// `IMG_1.xmp` and `IMG_1.jpg.xmp` count as sidecars of `IMG_1.jpg`, and
+/// follow it to `photo.xmp` and `photo.jpg.xmp`. The sidecar keeps its
+/// extension as spelled. Sidecars that are matches themselves are left
+/// to their own rename.
+pub fn add_sidecars(matches: Vec<Match>, specs: &[SidecarSpec]) -> Vec<Match> {
+    let renamed: HashSet<PathBuf> = matches.iter().map(|m| m.path.clone()).collect();
+    let mut listings: HashMap<PathBuf, Vec<String>> = HashMap::new();
+    let mut claimed = HashSet::new();
+    let mut result = Vec::with_capacity(matches.len());
+    for m in matches {
+        let sidecars = if m.is_dir || m.new_name == file_name(&m.path) {
+            Vec::new()
+        } else {
+            find_sidecars(&m, specs, &mut listings)
+        };
+        result.push(m);
+        for sidecar in sidecars {
+            if !renamed.contains(&sidecar.path) && claimed.insert(sidecar.path.clone()) {
+                result.push(sidecar);
+            }
+        }
+    }
+    result
+}
+
+fn find_sidecars(m: &Match, specs: &[SidecarSpec], listings: &mut HashMap<PathBuf, Vec<String>>) -> Vec<Match> {
+    let name = file_name(&m.path);
+    let (stem, extension) = split_extension(name);
+    let extension = extension.map(str::to_lowercase);
+    let wanted: Vec<&str> = specs
+        .iter()
+        .filter(|spec| spec.primary.is_none() || spec.primary == extension)
+        .flat_map(|spec| spec.extensions.iter().map(String::as_str))
+        .filter(|ext| Some(*ext) != extension.as_deref())
+        .collect();
+    if wanted.is_empty() {
+        return Vec::new();
+    }
+
+    let dir = m.path.parent().unwrap_or(Path::new("."));
+    let listing = listings.entry(dir.to_path_buf()).or_insert_with(|| {
+        fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .collect()
+    });
+    let new_stem = split_extension(&m.new_name).0;
+    let mut found = Vec::new();
+    for candidate in listing.iter() {
+        let (base, Some(ext)) = split_extension(candidate) else {
+            continue;
+        };
+        if !wanted.iter().any(|w| w.eq_ignore_ascii_case(ext)) {
+            continue;
+        }
+        let new_name = if base == stem {
+            format!("{}.{}", new_stem, ext)
+        } else if base == name {
+            format!("{}.{}", m.new_name, ext)
+        } else {
+            continue;
+        };
+        found.push(Match {
+            path: dir.join(candidate),
+            replacement: new_name.as_str().into(),
+            new_name,
+            is_dir: false,
+            pattern: candidate.as_str().into(),
+        });
+    }
+    found.sort_by(|a, b| a.path.cmp(&b.path));
+    found
+}
+
+/// Treat the files sharing a stem in a directory as one unit: when one of
+/// them is renamed, all of them get the new stem. A group whose renamed
+/// members disagree on the new stem, or whose new names are taken, is
+/// left out as a whole and reported. Directories are passed through.
+///
+/// Returns the matches to apply, the issues, and the matches left out with
+/// the issue of their group.
+pub fn group_by_stem(matches: Vec<Match>) -> (Vec<Match>, Vec<Issue>, Vec<(Match, String)>) {
+    // Groups keep the place of their first match
+    let mut slots: Vec<Result<Match, (PathBuf, String)>> = Vec::new();
+    let mut groups: HashMap<(PathBuf, String), Vec<Match>> = HashMap::new();
+    for m in matches {
+        if m.is_dir {
+            slots.push(Ok(m));
+            continue;
+        }
+        let dir = m.path.parent().unwrap_or(Path::new(".")).to_path_buf();
+        let key = (dir, split_extension(file_name(&m.path)).0.to_string());
+        let members = groups.entry(key.clone()).or_default();
+        if members.is_empty() {
+            slots.push(Err(key));
+        }
+        members.push(m);
+    }
+
+    let mut listings: HashMap<PathBuf, Vec<String>> = HashMap::new();
+    let mut issues = Vec::new();
+    // Groups with the stem they share, `None` for lone entries
+    let mut planned: Vec<(Option<String>, Vec<Match>)> = Vec::new();
+    for slot in slots {
+        let (dir, stem) = match slot {
+            Ok(dir) => {
+                planned.push((None, vec![dir]));
+                continue;
+            }
+            Err(key) => key,
+        };
+        let members = groups.remove(&(dir.clone(), stem.clone())).unwrap_or_default();
+        let mut new_stems: Vec<&str> = members
+            .iter()
+            .filter(|m| m.new_name != file_name(&m.path))
+            .map(|m| split_extension(&m.new_name).0)
+            .collect();
+        new_stems.sort_unstable();
+        new_stems.dedup();
+        let new_stem = match new_stems.as_slice() {
+            [] => {
+                planned.push((None, members));
+                continue;
+            }
+            [new_stem] => new_stem.to_string(),
+            _ => {
+                issues.push(Issue::error(
+                    &members[0].path,
+                    format!("the files named '{}.*' want different stems: {}", stem, new_stems.join(", ")),
+                ));
+                planned.push((Some(stem), members));
+                continue;
+            }
+        };
+        let with_stem = |name: &str| match split_extension(name).1 {
+            Some(ext) => format!("{}.{}", new_stem, ext),
+            None => new_stem.clone(),
+        };
+
+        let listing = listings.entry(dir.clone()).or_insert_with(|| {
+            fs::read_dir(&dir)
+                .into_iter()
+                .flatten()
+                .flatten()
+                .filter(|entry| entry.file_type().is_ok_and(|t| !t.is_dir()))
+                .filter_map(|entry| entry.file_name().into_string().ok())
+                .collect()
+        });
+        let known: HashSet<PathBuf> = members.iter().map(|m| m.path.clone()).collect();
+        let mut group = members;
+        for m in group.iter_mut().filter(|m| m.new_name == file_name(&m.path)) {
+            m.new_name = with_stem(file_name(&m.path));
+            m.replacement = m.new_name.as_str().into();
+        }
+        for name in listing.iter().filter(|name| split_extension(name).0 == stem) {
+            let path = dir.join(name);
+            if known.contains(&path) {
+                continue;
+            }
+            let new_name = with_stem(name);
+            group.push(Match {
+                path,
+                replacement: new_name.as_str().into(),
+                new_name,
+                is_dir: false,
+                pattern: name.as_str().into(),
+            });
+        }
+        planned.push((Some(stem), group));
+    }
+
+    // A taken name sinks the whole group
+    let sources: HashSet<PathBuf> = planned.iter().flat_map(|(_, group)| group.iter().map(|m| m.path.clone())).collect();
+    let mut targets: HashMap<PathBuf, usize> = HashMap::new();
+    for m in planned.iter().flat_map(|(_, group)| group) {
+        *targets.entry(m.new_path()).or_default() += 1;
+    }
+    let mut kept = Vec::new();
+    let mut dropped = Vec::new();
+    for (stem, group) in planned {
+        let Some(stem) = stem else {
+            kept.extend(group);
+            continue;
+        };
+        let disagreeing = issues.iter().find(|issue| issue.path == group[0].path).map(|issue| issue.message.clone());
+        let taken = group.iter().map(Match::new_path).find(|target| {
+            targets[target] > 1 || (!sources.contains(target) && fs::symlink_metadata(target).is_ok())
+        });
+        let message = match (disagreeing, taken) {
+            (Some(message), _) => message,
+            (None, Some(target)) => {
+                let message = format!("the files named '{}.*' stay together, but {} is taken", stem, target.display());
+                issues.push(Issue::error(&group[0].path, message.clone()));
+                message
+            }
+            (None, None) => {
+                kept.extend(group);
+                continue;
+            }
+        };
+        dropped.extend(group.into_iter().map(|m| (m, message.clone())));
+    }
+    (kept, issues, dropped)
+}
@@ -0,0 +1,88 @@
+//! Detecting file types from content, to fix misleading extensions and to
+//! tell text files from binaries.
+
+use crate::{file_name, open_regular, Match};
+use std::io::Read;
+use std::path::Path;
+
+/// What a file holds, for `--text` and `--binary`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Content {
+    Text,
+    Binary,
+}
+
+/// Extensions that are equally right for the same content.
+const ALIASES: &[&[&str]] = &[
+    &["jpg", "jpeg", "jpe", "jfif"],
+    &["tif", "tiff"],
+    &["mpg", "mpeg"],
+    &["mp4", "m4v", "m4a", "m4b", "mov"],
+    &["gz", "tgz"],
+    &["heic", "heif"],
+];
+
+/// Formats that are zip files underneath; plain zip detection says nothing
+/// about them.
+const ZIP_BASED: &[&str] = &[
+    "docx", "xlsx", "pptx", "odt", "ods", "odp", "epub", "jar", "apk", "xpi", "whl", "nupkg", "cbz",
+    "kmz", "3mf", "vsix", "ipa",
+];
+
+/// Bytes read to detect the type.
+const SNIFF_LEN: usize = 8192;
+
+/// The extension the content of `path` calls for, if it can be told.
+pub fn sniff_extension(path: &Path) -> Option<&'static str> {
+    let mut buf = Vec::with_capacity(SNIFF_LEN);
+    open_regular(path).ok()?.take(SNIFF_LEN as u64).read_to_end(&mut buf).ok()?;
+    infer::get(&buf).map(|kind| kind.extension())
+}
+
+/// Whether `path` holds text or binary data, going by the first bytes like
+/// git does: a NUL byte or a known binary format means binary. UTF-16 with
+/// a byte order mark is text, and so are empty files.
+pub fn sniff_content(path: &Path) -> std::io::Result<Content> {
+    let mut buf = Vec::with_capacity(SNIFF_LEN);
+    open_regular(path)?.take(SNIFF_LEN as u64).read_to_end(&mut buf)?;
+    if buf.starts_with(&[0xff, 0xfe]) || buf.starts_with(&[0xfe, 0xff]) {
+        return Ok(Content::Text);
+    }
+    let known_binary = infer::get(&buf).is_some_and(|kind| kind.matcher_type() != infer::MatcherType::Text);
+    if known_binary || buf.contains(&0) {
+        Ok(Content::Binary)
+    } else {
+        Ok(Content::Text)
+    }
+}
+
+fn acceptable(current: &str, detected: &str) -> bool {
+    current == detected
+        || ALIASES.iter().any(|set| set.contains(&current) && set.contains(&detected))
+        || (detected == "zip" && ZIP_BASED.contains(&current))
+}
+
+/// Keep the files whose extension does not match their content and set their
+/// new name to the stem with the detected extension. Files without an
+/// extension get one added.
+pub fn fix_extensions(matches: Vec<Match>) -> Vec<Match> {
+    matches
+        .into_iter()
+        .filter(|m| !m.is_dir)
+        .filter_map(|mut m| {
+            let detected = sniff_extension(&m.path)?;
+            let name = file_name(&m.path).to_string();
+            let (stem, current) = match name.rfind('.') {
+                Some(i) if i > 0 => (&name[..i], name[i + 1..].to_lowercase()),
+                _ => (name.as_str(), String::new()),
+            };
+            if acceptable(&current, detected) {
+                return None;
+            }
+            m.new_name = format!("{}.{}", stem, detected);
+            m.replacement = m.new_name.as_str().into();
+            m.pattern = name.into();
+            Some(m)
+        })
+        .collect()
+}
@@ -0,0 +1,202 @@
+//! Staging for renames that can't happen in place: renames going round in
+//! a circle (`a -> b`, `b -> a`), chains whose new name is still held by
+//! another rename, and case-only renames, which case-insensitive
+//! filesystems tend to trip over.
+//!
+//! Such an entry first moves into `.fnr-tmp-<id>/` next to it, and on to
+//! its new name once everything else is done. Both moves go through the
+//! journal, so [`crate::journal::Journal::recover`] can move entries
+//! stranded by a crash back to where they were.
+
+use crate::journal::Intent;
+use crate::transfer::Retry;
+use crate::Match;
+use anyhow::{Context, Result};
+use std::collections::{BTreeSet, HashSet};
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Prefix of staging directory names.
+pub const STAGING_PREFIX: &str = ".fnr-tmp-";
+
+/// Whether `path` is inside a staging directory.
+pub fn in_staging(path: &Path) -> bool {
+    path.components()
+        .any(|c| c.as_os_str().to_string_lossy().starts_with(STAGING_PREFIX))
+}
+
+/// Where the entry waiting at `tmp` in a staging directory was before, see
+/// [`Staging::tmp_path`].
+pub fn unstaged(tmp: &Path) -> PathBuf {
+    let parent = tmp.parent().and_then(Path::parent).unwrap_or(Path::new("."));
+    parent.join(tmp.file_name().unwrap_or_default())
+}
+
+/// Follow the rename of a directory from `from` to `to` with `path`, if it
+/// is below. A path equal to `from` is free now, not moved.
+pub fn relocate(path: &mut PathBuf, from: &Path, to: &Path) {
+    if let Some(rest) = path.strip_prefix(from).ok().filter(|rest| !rest.as_os_str().is_empty()) {
+        *path = to.join(rest);
+    }
+}
+
+/// The matches that have to go through staging: those renamed to the
+/// current path of another match, and those only changing case.
+pub fn staged_sources(matches: &[Match]) -> HashSet<PathBuf> {
+    let sources: HashSet<&Path> = matches.iter().map(|m| m.path.as_path()).collect();
+    matches
+        .iter()
+        .filter(|m| {
+            let old = m.path.file_name().unwrap_or_default().to_string_lossy();
+            let case_only = old != m.new_name && old.to_lowercase() == m.new_name.to_lowercase();
+            case_only || (old != m.new_name && sources.contains(m.new_path().as_path()))
+        })
+        .map(|m| m.path.clone())
+        .collect()
+}
+
+/// An entry waiting in a staging directory.
+#[derive(Debug, Clone)]
+pub struct Staged {
+    pub m: Match,
+    /// Where the entry was before staging.
+    pub origin: PathBuf,
+    /// Where it is now.
+    pub tmp: PathBuf,
+    /// Where it goes.
+    pub target: PathBuf,
+    /// The journal record of the move into staging.
+    pub intent: Option<Intent>,
+}
+
+/// The staging directories of one run. Directories renamed while entries
+/// wait take their paths with them, see [`Staging::moved`].
+#[derive(Debug)]
+pub struct Staging {
+    name: String,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    sources: HashSet<PathBuf>,
+    pending: Vec<Staged>,
+    dirs: BTreeSet<PathBuf>,
+}
+
+impl Default for Staging {
+    fn default() -> Self {
+        let millis = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        Staging {
+            name: format!("{}{}-{}", STAGING_PREFIX, millis, process::id()),
+            state: Mutex::new(State::default()),
+        }
+    }
+}
+
+impl State {
+    fn relocate(&mut self, from: &Path, to: &Path) {
+        for staged in &mut self.pending {
+            relocate(&mut staged.origin, from, to);
+            relocate(&mut staged.tmp, from, to);
+            relocate(&mut staged.target, from, to);
+        }
+        self.dirs = std::mem::take(&mut self.dirs)
+            .into_iter()
+            .map(|mut dir| {
+                relocate(&mut dir, from, to);
+                dir
+            })
+            .collect();
+    }
+
+    /// Move `from` to `tmp`, creating its staging directory.
+    fn park(&mut self, from: &Path, tmp: &Path, retry: &Retry) -> Result<()> {
+        let dir = tmp.parent().unwrap_or(Path::new("."));
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create staging directory {}", dir.display()))?;
+        self.dirs.insert(dir.to_path_buf());
+        retry
+            .run(|| fs::rename(from, tmp))
+            .with_context(|| format!("Failed to move {} to {}", from.display(), tmp.display()))
+    }
+}
+
+impl Staging {
+    /// Decide which of `matches` go through staging.
+    pub fn prepare(&self, matches: &[Match]) {
+        self.state.lock().unwrap().sources = staged_sources(matches);
+    }
+
+    pub fn holds(&self, m: &Match) -> bool {
+        self.state.lock().unwrap().sources.contains(&m.path)
+    }
+
+    /// Where the entry at `path` waits in staging, next to it and under the
+    /// name it had before.
+    pub fn tmp_path(&self, path: &Path, name: &OsStr) -> PathBuf {
+        path.parent().unwrap_or(Path::new(".")).join(&self.name).join(name)
+    }
+
+    /// Move `m` into staging at `tmp`, to move on to its new name later.
+    pub fn stage(&self, m: &Match, tmp: &Path, intent: Option<Intent>, retry: &Retry) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.park(&m.path, tmp, retry)?;
+        if m.is_dir {
+            state.relocate(&m.path, tmp);
+        }
+        state.pending.push(Staged {
+            m: m.clone(),
+            origin: m.path.clone(),
+            tmp: tmp.to_path_buf(),
+            target: m.new_path(),
+            intent,
+        });
+        Ok(())
+    }
+
+    /// Follow the rename of a directory from `from` to `to` with the paths
+    /// of everything waiting below it.
+    pub fn moved(&self, from: &Path, to: &Path) {
+        self.state.lock().unwrap().relocate(from, to);
+    }
+
+    /// Move `from` to `tmp`, just out of the way.
+    pub fn park(&self, from: &Path, tmp: &Path, retry: &Retry) -> Result<()> {
+        self.state.lock().unwrap().park(from, tmp, retry)
+    }
+
+    /// Take the entries waiting in staging whose matches are `wanted`, see
+    /// [`Staging::next`].
+    pub fn take(&self, wanted: impl Fn(&Match) -> bool) -> Vec<Staged> {
+        let mut state = self.state.lock().unwrap();
+        let (taken, kept) = std::mem::take(&mut state.pending)
+            .into_iter()
+            .partition(|staged| wanted(&staged.m));
+        state.pending = kept;
+        taken
+    }
+
+    /// The next entry waiting in staging, in the order they were staged.
+    pub fn next(&self) -> Option<Staged> {
+        let mut state = self.state.lock().unwrap();
+        (!state.pending.is_empty()).then(|| state.pending.remove(0))
+    }
+
+    /// Remove the staging directories. Returns those that aren't empty,
+    /// because something couldn't move on.
+    pub fn clean_up(&self) -> Vec<PathBuf> {
+        let dirs = std::mem::take(&mut self.state.lock().unwrap().dirs);
+        // Nested staging directories go first
+        dirs.into_iter()
+            .rev()
+            .filter(|dir| fs::remove_dir(dir).is_err() && dir.exists())
+            .collect()
+    }
+}
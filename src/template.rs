@@ -0,0 +1,735 @@
+//! Placeholders in replacement strings.
+//!
+//! Replacements are rendered once per match before the actual substitution,
+//! so placeholders can depend on the whole match list (e.g. the counter width).
+//!
+//! Supported placeholders:
+//! - `{counter}`: 1-based position in the match list, zero padded to the
+//!   number of digits of the match count
+//! - `{counter:N}`: same, padded to `N` digits
+//! - `{n}`: the same counter without padding, `{n:03}` padded to 3 digits;
+//!   `{n+10}` (or `{counter+10}`) adds 10, so counting starts at 11
+//! - `{duration}`, `{codec}`, `{fps}`: video metadata of MP4/QuickTime
+//!   files, e.g. `1h02m03s`, `h264`, `29.97` (requires the `media` feature)
+//! - `{btime}`: creation time, `{btime:FORMAT}` with a strftime format
+//!   (default `%Y-%m-%d`), where the filesystem records it. Rendered in the
+//!   configured [`DateStyle`], so `%B`/`%A` follow the locale
+//! - `{exif:TAG}`: EXIF data of JPEG and raw photos, e.g.
+//!   `{exif:Model}`; dates like `{exif:DateTimeOriginal}` take a strftime
+//!   format, `{exif:DateTimeOriginal:%Y%m%d_%H%M%S}` (requires the `exif`
+//!   feature)
+//! - `{mtime}`: modification time, `{mtime:FORMAT}` like `{btime}`
+//! - `{size}`: size in bytes, `{size:human}` in powers of 1024 like
+//!   `ls -h` does, e.g. `532`, `1.5K` or `12M`
+//! - `{doc.title}`: title from PDF, Office or OpenDocument metadata;
+//!   `{pdf.title}` accepts PDFs only (requires the `documents` feature)
+//! - `{age_bucket}`: how recently the entry was modified: `today` (less
+//!   than a day ago), `this-week` (a week), `this-month` (30 days) or
+//!   `older`. `{age_bucket:12h,5d,2w}` moves the thresholds, see
+//!   [`dates::parse_age`]
+//! - `{relpath}`: path relative to the base directory with the separators
+//!   replaced by `__`, `{relpath:JOINER}` with another joiner, e.g.
+//!   `docs/intro.md` becomes `docs__intro.md`
+//! - `{name}`, `{stem}`, `{ext}`: the original name, without and only its
+//!   extension (empty for directories and names without one), e.g. `notes`
+//!   and `md` for `notes.md`
+//! - `{parent}`: name of the directory the entry is in
+//!
+//! Placeholders take the transforms of captures, chained behind `|` like
+//! `{stem|trunc:20}` or `{exif:Model|map}`, see [`Transforms`].
+//!
+//! Conditionals pick between two parts of the template, inline as
+//! `{COND?THEN:ELSE}` or as a block `{if:COND}THEN{else}ELSE{end}`, where
+//! the else part is optional and both parts may hold placeholders. The
+//! conditions are `dir`, `file` and `ext` (a file with an extension), and
+//! `!` negates them.
+//!
+//! `{{` and `}}` produce literal braces. Unknown placeholders are kept as-is.
+//! Placeholder values go into the new name as they are: a `$1`, `{1}` or
+//! `\U` in a file name is not taken for a capture or a case escape.
+
+use crate::dates::{self, DateStyle};
+use crate::matcher::{self, Transforms};
+use crate::metadata::MetadataCache;
+use crate::sidecar::split_extension;
+use anyhow::{bail, Context as _, Result};
+use std::iter::Peekable;
+use std::path::{Component, Path};
+use std::str::Chars;
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Literal(String),
+    /// `width` `None` pads to the digits of the last value.
+    Counter { width: Option<usize>, offset: usize },
+    Media(MediaField),
+    /// `format` is used for date tags only.
+    Exif { tag: String, format: String },
+    Title { pdf_only: bool },
+    BirthTime { format: String },
+    ModifiedTime { format: String },
+    Size { human: bool },
+    RelPath { joiner: String },
+    Part(PathPart),
+    /// A placeholder run through transforms.
+    Transformed { token: Box<Token>, transforms: Vec<String> },
+    /// Upper age limits of `today`, `this-week` and `this-month`.
+    AgeBucket { limits: [Duration; 3] },
+    If {
+        condition: Condition,
+        then: Vec<Token>,
+        otherwise: Vec<Token>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Condition {
+    test: Test,
+    negated: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Test {
+    Dir,
+    File,
+    /// A file with an extension.
+    Ext,
+}
+
+/// Parts of the original path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PathPart {
+    Name,
+    Stem,
+    Ext,
+    Parent,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MediaField {
+    Duration,
+    Codec,
+    Fps,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    tokens: Vec<Token>,
+}
+
+/// Per-match values placeholders are rendered from.
+#[derive(Debug, Clone, Copy)]
+pub struct Context<'a> {
+    /// Zero-based position of the match in the sorted match list.
+    pub index: usize,
+    /// Number of matches.
+    pub total: usize,
+    /// Current path of the matched entry.
+    pub path: &'a Path,
+    pub is_dir: bool,
+    /// Directory `{relpath}` is relative to.
+    pub base_dir: &'a Path,
+    pub date_style: &'a DateStyle,
+    /// Where file metadata is looked up, shared with the walk.
+    pub metadata: &'a MetadataCache,
+    /// What `{placeholder|NAME}` runs values through.
+    pub transforms: &'a Transforms,
+}
+
+fn digits(mut n: usize) -> usize {
+    let mut count = 1;
+    while n >= 10 {
+        n /= 10;
+        count += 1;
+    }
+    count
+}
+
+impl Template {
+    pub fn parse(text: &str) -> Result<Template> {
+        let mut chars = text.chars().peekable();
+        match parse_tokens(&mut chars)? {
+            (tokens, None) => Ok(Template { tokens }),
+            (_, Some(Block::Else)) => bail!("{{else}} without {{if:...}} in '{}'", text),
+            (_, Some(Block::End)) => bail!("{{end}} without {{if:...}} in '{}'", text),
+        }
+    }
+
+    /// Whether the template contains any placeholder.
+    pub fn has_placeholders(&self) -> bool {
+        self.tokens.iter().any(|t| !matches!(t, Token::Literal(_)))
+    }
+
+    pub fn render(&self, ctx: &Context) -> Result<String> {
+        let mut out = Output::default();
+        render_tokens(&self.tokens, ctx, &mut out)?;
+        Ok(out.text)
+    }
+
+    /// The template rendered with the placeholder values set aside: the
+    /// text holds a [`matcher::value_marker`] for each, for
+    /// [`NameMatcher::replace_with_values`](matcher::NameMatcher::replace_with_values).
+    pub fn render_values(&self, ctx: &Context) -> Result<(String, Vec<String>)> {
+        let mut out = Output {
+            text: String::new(),
+            values: Some(Vec::new()),
+        };
+        render_tokens(&self.tokens, ctx, &mut out)?;
+        Ok((out.text, out.values.unwrap_or_default()))
+    }
+}
+
+/// Rendered text, with the placeholder values in place or set aside.
+#[derive(Debug, Default)]
+struct Output {
+    text: String,
+    /// The values set aside, `None` to put them in place.
+    values: Option<Vec<String>>,
+}
+
+impl Output {
+    fn value(&mut self, value: &str) -> Result<()> {
+        let Some(values) = &mut self.values else {
+            self.text.push_str(value);
+            return Ok(());
+        };
+        match matcher::value_marker(values.len()) {
+            Some(marker) => self.text.push(marker),
+            None => bail!("Too many placeholders in one replacement, 32 is the limit"),
+        }
+        values.push(value.to_string());
+        Ok(())
+    }
+}
+
+/// The `{else}` or `{end}` a run of tokens stopped at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Block {
+    Else,
+    End,
+}
+
+/// Tokens up to the end of the text or the next `{else}`/`{end}`.
+fn parse_tokens(chars: &mut Peekable<Chars>) -> Result<(Vec<Token>, Option<Block>)> {
+    let mut tokens = Vec::new();
+    let mut literal = String::new();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '{' => {
+                // Braces nest, for placeholders inside conditionals
+                let mut inner = String::new();
+                let mut depth = 0;
+                let mut closed = false;
+                for c in chars.by_ref() {
+                    match c {
+                        '}' if depth == 0 => {
+                            closed = true;
+                            break;
+                        }
+                        '{' => depth += 1,
+                        '}' => depth -= 1,
+                        _ => {}
+                    }
+                    inner.push(c);
+                }
+                if !closed {
+                    literal.push('{');
+                    literal.push_str(&inner);
+                    continue;
+                }
+                let token = match inner.as_str() {
+                    "else" | "end" => {
+                        if !literal.is_empty() {
+                            tokens.push(Token::Literal(literal));
+                        }
+                        let block = if inner == "else" { Block::Else } else { Block::End };
+                        return Ok((tokens, Some(block)));
+                    }
+                    _ => match inner.strip_prefix("if:") {
+                        Some(condition) => Some(parse_block(condition, chars)?),
+                        None => parse_transformed(&inner)?,
+                    },
+                };
+                match token {
+                    Some(token) => {
+                        if !literal.is_empty() {
+                            tokens.push(Token::Literal(std::mem::take(&mut literal)));
+                        }
+                        tokens.push(token);
+                    }
+                    None => {
+                        literal.push('{');
+                        literal.push_str(&inner);
+                        literal.push('}');
+                    }
+                }
+            }
+            c => literal.push(c),
+        }
+    }
+    if !literal.is_empty() {
+        tokens.push(Token::Literal(literal));
+    }
+    Ok((tokens, None))
+}
+
+/// The rest of an `{if:COND}...{else}...{end}` block.
+fn parse_block(condition: &str, chars: &mut Peekable<Chars>) -> Result<Token> {
+    let condition = parse_condition(condition)?;
+    let (then, block) = parse_tokens(chars)?;
+    let otherwise = match block {
+        Some(Block::End) => Vec::new(),
+        Some(Block::Else) => match parse_tokens(chars)? {
+            (otherwise, Some(Block::End)) => otherwise,
+            (_, Some(Block::Else)) => bail!("Second {{else}} in {{if:...}}"),
+            (_, None) => bail!("{{if:...}} without {{end}}"),
+        },
+        None => bail!("{{if:...}} without {{end}}"),
+    };
+    Ok(Token::If {
+        condition,
+        then,
+        otherwise,
+    })
+}
+
+/// `COND?THEN:ELSE`, where THEN and ELSE are templates themselves.
+fn parse_inline_if(inner: &str) -> Result<Option<Token>> {
+    let Some((condition, branches)) = inner.split_once('?') else {
+        return Ok(None);
+    };
+    if !CONDITIONS.contains(&condition.trim_start_matches('!')) {
+        return Ok(None);
+    }
+    // The `:` outside of nested placeholders separates the branches
+    let mut depth = 0;
+    let split = branches.char_indices().find(|&(_, c)| {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ => {}
+        }
+        c == ':' && depth == 0
+    });
+    let (then, otherwise) = match split {
+        Some((i, _)) => (&branches[..i], &branches[i + 1..]),
+        None => (branches, ""),
+    };
+    Ok(Some(Token::If {
+        condition: parse_condition(condition)?,
+        then: Template::parse(then)?.tokens,
+        otherwise: Template::parse(otherwise)?.tokens,
+    }))
+}
+
+const CONDITIONS: &[&str] = &["dir", "file", "ext"];
+
+fn parse_condition(text: &str) -> Result<Condition> {
+    let (negated, name) = match text.strip_prefix('!') {
+        Some(name) => (true, name),
+        None => (false, text),
+    };
+    let test = match name {
+        "dir" => Test::Dir,
+        "file" => Test::File,
+        "ext" => Test::Ext,
+        _ => bail!("Unknown condition '{}', expected one of {}", name, CONDITIONS.join(", ")),
+    };
+    Ok(Condition { test, negated })
+}
+
+impl Condition {
+    fn holds(&self, ctx: &Context) -> bool {
+        let holds = match self.test {
+            Test::Dir => ctx.is_dir,
+            Test::File => !ctx.is_dir,
+            Test::Ext => {
+                let name = ctx.path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+                !ctx.is_dir && name.rfind('.').is_some_and(|i| i > 0)
+            }
+        };
+        holds != self.negated
+    }
+}
+
+fn render_tokens(tokens: &[Token], ctx: &Context, out: &mut Output) -> Result<()> {
+    for token in tokens {
+        match token {
+            Token::Literal(text) => out.text.push_str(text),
+            Token::Counter { width, offset } => {
+                let width = width.unwrap_or_else(|| digits(ctx.total + offset));
+                out.value(&format!("{:0width$}", ctx.index + 1 + offset, width = width))?;
+            }
+            Token::Media(field) => out.value(&media_value(*field, ctx.path)?)?,
+            Token::Exif { tag, format } => out.value(&exif_value(tag, format, ctx)?)?,
+            Token::Title { pdf_only } => out.value(&title_value(*pdf_only, ctx.path)?)?,
+            Token::BirthTime { format } => {
+                out.value(&ctx.date_style.format(ctx.metadata.created(ctx.path)?, format))?;
+            }
+            Token::ModifiedTime { format } => {
+                out.value(&ctx.date_style.format(ctx.metadata.modified(ctx.path)?, format))?;
+            }
+            Token::Size { human } => {
+                let size = ctx
+                    .metadata
+                    .get(ctx.path)
+                    .with_context(|| format!("Size of {} is not available", ctx.path.display()))?
+                    .len();
+                out.value(&if *human { human_size(size) } else { size.to_string() })?;
+            }
+            Token::RelPath { joiner } => out.value(&relative_path(ctx.path, ctx.base_dir, joiner))?,
+            Token::Part(part) => out.value(&path_part(*part, ctx))?,
+            Token::Transformed { token, transforms } => {
+                let mut value = Output::default();
+                render_tokens(std::slice::from_ref(token), ctx, &mut value)?;
+                let mut value = value.text;
+                for name in transforms {
+                    value = match ctx.transforms.apply(name, &value) {
+                        Some(value) => value,
+                        None => bail!("{{...|{}}} in a replacement needs a lookup table from --map FILE", name),
+                    };
+                }
+                out.value(&value)?;
+            }
+            Token::AgeBucket { limits } => {
+                let modified = ctx.metadata.modified(ctx.path)?;
+                // Modified in the future counts as today
+                let age = SystemTime::now().duration_since(modified).unwrap_or_default();
+                let bucket = AGE_BUCKETS.iter().zip(limits).find(|(_, limit)| age < **limit);
+                out.value(bucket.map_or("older", |(name, _)| name))?;
+            }
+            Token::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                let branch = if condition.holds(ctx) { then } else { otherwise };
+                render_tokens(branch, ctx, out)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A placeholder, with the transforms chained behind it if any.
+fn parse_transformed(inner: &str) -> Result<Option<Token>> {
+    // Conditionals keep their `|`, their branches have placeholders of their own
+    let (placeholder, transforms) = match inner.split_once('|') {
+        Some((placeholder, transforms)) if !placeholder.contains('?') => (placeholder, transforms),
+        _ => return parse_placeholder(inner),
+    };
+    // `{1|map}` is the capture's, left for the matcher
+    let Some(token) = parse_placeholder(placeholder)? else {
+        return Ok(None);
+    };
+    let transforms: Vec<String> = transforms.split('|').map(str::to_string).collect();
+    if let Some(unknown) = transforms.iter().find(|name| !matcher::is_transform(name)) {
+        bail!(
+            "Unknown transform '{}' in {{{}}}, expected map, roman2arabic, arabic2roman or trunc:N",
+            unknown,
+            inner
+        );
+    }
+    Ok(Some(Token::Transformed {
+        token: Box::new(token),
+        transforms,
+    }))
+}
+
+fn parse_placeholder(inner: &str) -> Result<Option<Token>> {
+    let (name, spec) = match inner.split_once(':') {
+        Some((name, spec)) => (name, Some(spec)),
+        None => (inner, None),
+    };
+    // `{n+10}` and `{counter+10}` count from 11
+    let (name, offset) = match name.split_once('+') {
+        Some((counter @ ("n" | "counter"), offset)) => match offset.parse() {
+            Ok(offset) => (counter, offset),
+            Err(_) => bail!("Invalid counter offset '{}' in {{{}}}", offset, inner),
+        },
+        _ => (name, 0),
+    };
+    match name {
+        "counter" | "n" => {
+            let width = match spec {
+                Some(spec) => match spec.parse() {
+                    Ok(width) => Some(width),
+                    Err(_) => bail!("Invalid counter width '{}' in {{{}}}", spec, inner),
+                },
+                // `{n}` is the plain number
+                None if name == "n" => Some(1),
+                None => None,
+            };
+            Ok(Some(Token::Counter { width, offset }))
+        }
+        "duration" => media_token(MediaField::Duration),
+        "codec" => media_token(MediaField::Codec),
+        "fps" => media_token(MediaField::Fps),
+        "exif" => exif_token(spec.unwrap_or_default()),
+        "btime" | "mtime" => {
+            let format = spec.unwrap_or(dates::DEFAULT_FORMAT).to_string();
+            dates::validate_format(&format)?;
+            Ok(Some(if name == "btime" {
+                Token::BirthTime { format }
+            } else {
+                Token::ModifiedTime { format }
+            }))
+        }
+        "size" => match spec {
+            None => Ok(Some(Token::Size { human: false })),
+            Some("human") => Ok(Some(Token::Size { human: true })),
+            Some(spec) => bail!("Unknown size format '{}' in {{{}}}, expected {{size}} or {{size:human}}", spec, inner),
+        },
+        "relpath" => {
+            let joiner = spec.unwrap_or("__");
+            // The joiner ends up in a name, it can't make a path again
+            if joiner.chars().any(|c| std::path::is_separator(c) || c == '\0') {
+                bail!("The joiner in {{{}}} can't contain a path separator or NUL", inner.escape_debug());
+            }
+            Ok(Some(Token::RelPath {
+                joiner: joiner.to_string(),
+            }))
+        }
+        "name" => Ok(Some(Token::Part(PathPart::Name))),
+        "stem" => Ok(Some(Token::Part(PathPart::Stem))),
+        "ext" => Ok(Some(Token::Part(PathPart::Ext))),
+        "parent" => Ok(Some(Token::Part(PathPart::Parent))),
+        "age_bucket" => {
+            let limits = match spec {
+                Some(spec) => {
+                    let limits: Vec<Duration> = spec.split(',').map(dates::parse_age).collect::<Result<_>>()?;
+                    match <[Duration; 3]>::try_from(limits) {
+                        Ok(limits) if limits.is_sorted() => limits,
+                        _ => bail!("{{age_bucket:...}} takes three growing ages like 1d,7d,30d, not '{}'", spec),
+                    }
+                }
+                None => [dates::DAY, 7 * dates::DAY, 30 * dates::DAY],
+            };
+            Ok(Some(Token::AgeBucket { limits }))
+        }
+        "doc.title" | "pdf.title" => {
+            if !cfg!(feature = "documents") {
+                bail!("{{{}}} needs fnr built with the 'documents' feature", name);
+            }
+            Ok(Some(Token::Title {
+                pdf_only: name == "pdf.title",
+            }))
+        }
+        _ => parse_inline_if(inner),
+    }
+}
+
+const AGE_BUCKETS: [&str; 3] = ["today", "this-week", "this-month"];
+
+/// `size` in the largest power of 1024 it reaches, with one decimal below
+/// 10 and none above.
+fn human_size(size: u64) -> String {
+    const UNITS: [&str; 6] = ["K", "M", "G", "T", "P", "E"];
+    let mut value = size as f64;
+    let mut unit = "";
+    for next in UNITS {
+        if value < 1024.0 {
+            break;
+        }
+        value /= 1024.0;
+        unit = next;
+    }
+    if unit.is_empty() {
+        size.to_string()
+    } else if value < 10.0 {
+        format!("{:.1}{}", value, unit)
+    } else {
+        format!("{:.0}{}", value, unit)
+    }
+}
+
+/// `path` below `base_dir`, without `.` and root components, joined with
+/// `joiner`.
+fn relative_path(path: &Path, base_dir: &Path, joiner: &str) -> String {
+    let relative = path.strip_prefix(base_dir).unwrap_or(path);
+    let parts: Vec<_> = relative
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect();
+    parts.join(joiner)
+}
+
+fn path_part(part: PathPart, ctx: &Context) -> String {
+    let name = ctx.path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    let (stem, ext) = match split_extension(&name) {
+        (stem, Some(ext)) if !ctx.is_dir => (stem, ext),
+        _ => (&*name, ""),
+    };
+    match part {
+        PathPart::Name => name.to_string(),
+        PathPart::Stem => stem.to_string(),
+        PathPart::Ext => ext.to_string(),
+        PathPart::Parent => {
+            let parent = ctx.path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+            // Entries in `.` are in whatever directory that is
+            match parent.file_name() {
+                Some(parent) => parent.to_string_lossy().into_owned(),
+                None => parent
+                    .canonicalize()
+                    .ok()
+                    .and_then(|parent| parent.file_name().map(|n| n.to_string_lossy().into_owned()))
+                    .unwrap_or_default(),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "documents")]
+fn title_value(pdf_only: bool, path: &Path) -> Result<String> {
+    let title = if pdf_only {
+        crate::document::pdf_title(path)?
+    } else {
+        crate::document::title(path)?
+    };
+    match title {
+        Some(title) => Ok(title),
+        None => bail!("{} has no title", path.display()),
+    }
+}
+
+#[cfg(not(feature = "documents"))]
+fn title_value(_pdf_only: bool, _path: &Path) -> Result<String> {
+    unreachable!("title placeholders are rejected when parsing")
+}
+
+fn media_token(field: MediaField) -> Result<Option<Token>> {
+    if !cfg!(feature = "media") {
+        bail!("Media placeholders need fnr built with the 'media' feature");
+    }
+    Ok(Some(Token::Media(field)))
+}
+
+#[cfg(feature = "media")]
+fn media_value(field: MediaField, path: &Path) -> Result<String> {
+    let info = crate::media::MediaInfo::read(path)?;
+    let (name, value) = match field {
+        MediaField::Duration => ("duration", info.duration.map(format_duration)),
+        MediaField::Codec => ("codec", info.codec),
+        MediaField::Fps => ("fps", info.fps.map(format_fps)),
+    };
+    match value {
+        Some(value) => Ok(value),
+        None => bail!("{} has no {} information", path.display(), name),
+    }
+}
+
+#[cfg(not(feature = "media"))]
+fn media_value(_field: MediaField, _path: &Path) -> Result<String> {
+    unreachable!("media placeholders are rejected when parsing")
+}
+
+fn exif_token(spec: &str) -> Result<Option<Token>> {
+    if !cfg!(feature = "exif") {
+        bail!("{{exif:...}} needs fnr built with the 'exif' feature");
+    }
+    let (name, format) = match spec.split_once(':') {
+        Some((name, format)) => (name, Some(format)),
+        None => (spec, None),
+    };
+    let tag = exif_tag(name, format.is_some())?;
+    let format = format.unwrap_or(dates::DEFAULT_FORMAT);
+    dates::validate_format(format)?;
+    Ok(Some(Token::Exif {
+        tag: tag.to_string(),
+        format: format.to_string(),
+    }))
+}
+
+/// The spelling of the EXIF tag `name` as the docs have it.
+#[cfg(feature = "exif")]
+fn exif_tag(name: &str, formatted: bool) -> Result<&'static str> {
+    use crate::exif::{Tag, TAGS};
+    let Some(tag) = Tag::parse(name) else {
+        let known: Vec<&str> = TAGS.iter().map(|(name, _)| *name).collect();
+        bail!("Unknown EXIF tag '{}', expected one of {}", name, known.join(", "));
+    };
+    if formatted && !tag.is_date() {
+        bail!("{{exif:{}}} is not a date, only dates take a format", tag.name());
+    }
+    Ok(tag.name())
+}
+
+#[cfg(not(feature = "exif"))]
+fn exif_tag(_name: &str, _formatted: bool) -> Result<&'static str> {
+    unreachable!("EXIF placeholders are rejected without the feature")
+}
+
+#[cfg(feature = "exif")]
+fn exif_value(name: &str, format: &str, ctx: &Context) -> Result<String> {
+    use crate::exif::{self, Tag, Value};
+    let tag = Tag::parse(name).expect("tags are checked when parsing");
+    match exif::read(ctx.path, tag)? {
+        Some(Value::Date(date)) => Ok(ctx.date_style.format_naive(date, format)),
+        Some(value) => Ok(value.to_name(tag)),
+        None => bail!("{} has no EXIF {}", ctx.path.display(), name),
+    }
+}
+
+#[cfg(not(feature = "exif"))]
+fn exif_value(_name: &str, _format: &str, _ctx: &Context) -> Result<String> {
+    unreachable!("EXIF placeholders are rejected when parsing")
+}
+
+#[cfg(feature = "media")]
+fn format_duration(seconds: f64) -> String {
+    let total = seconds.round() as u64;
+    let (hours, minutes, seconds) = (total / 3600, total / 60 % 60, total % 60);
+    if hours > 0 {
+        format!("{}h{:02}m{:02}s", hours, minutes, seconds)
+    } else {
+        format!("{}m{:02}s", minutes, seconds)
+    }
+}
+
+#[cfg(feature = "media")]
+fn format_fps(fps: f64) -> String {
+    let text = format!("{:.2}", fps);
+    text.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counters_pad_to_the_width_asked_for() {
+        let counter = |inner| parse_placeholder(inner).unwrap();
+        assert_eq!(counter("counter"), Some(Token::Counter { width: None, offset: 0 }));
+        assert_eq!(counter("counter:3"), Some(Token::Counter { width: Some(3), offset: 0 }));
+        assert_eq!(counter("n"), Some(Token::Counter { width: Some(1), offset: 0 }));
+        assert_eq!(counter("n+10"), Some(Token::Counter { width: Some(1), offset: 10 }));
+        assert_eq!(counter("counter+5:2"), Some(Token::Counter { width: Some(2), offset: 5 }));
+        assert!(parse_placeholder("counter:wide").is_err());
+        assert!(parse_placeholder("n+many").is_err());
+        // Not a placeholder, the braces stay as they are
+        assert_eq!(counter("1"), None);
+    }
+
+    #[test]
+    fn relpath_joiners_stay_inside_a_name() {
+        assert!(Template::parse("{relpath}").is_ok());
+        assert!(Template::parse("{relpath:-}").is_ok());
+        assert!(Template::parse("{relpath:/}").is_err());
+        assert!(Template::parse("{relpath:a\0b}").is_err());
+        #[cfg(windows)]
+        assert!(Template::parse("{relpath:\\}").is_err());
+    }
+}
@@ -0,0 +1,216 @@
+//! Colors of the command line output.
+//!
+//! A theme is picked by name in the `[theme]` table of the user
+//! configuration, and single elements can be overridden with a style: color
+//! names like `blue` or `bright red`, `#rrggbb`, and the modifiers `bold`,
+//! `dimmed`, `italic` and `underline`.
+//!
+//! ```toml
+//! [theme]
+//! name = "colorblind"
+//! replacement = "#cc79a7 bold"
+//! ```
+
+use anyhow::{bail, Result};
+use colored::{Color, ColoredString, Colorize};
+use serde::Deserialize;
+
+pub const THEMES: &[&str] = &["default", "colorblind"];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Style {
+    pub color: Option<Color>,
+    pub bold: bool,
+    pub dimmed: bool,
+    pub italic: bool,
+    pub underline: bool,
+}
+
+impl Style {
+    pub fn parse(spec: &str) -> Result<Style> {
+        let mut style = Style::default();
+        let mut words = spec.split_whitespace();
+        while let Some(word) = words.next() {
+            match word.to_lowercase().as_str() {
+                "bold" => style.bold = true,
+                "dimmed" => style.dimmed = true,
+                "italic" => style.italic = true,
+                "underline" => style.underline = true,
+                "bright" => {
+                    let Some(color) = words.next() else {
+                        bail!("Invalid style '{}': bright what?", spec);
+                    };
+                    style.color = Some(parse_color(&format!("bright {}", color), spec)?);
+                }
+                name => style.color = Some(parse_color(name, spec)?),
+            }
+        }
+        Ok(style)
+    }
+
+    pub fn paint(&self, text: &str) -> ColoredString {
+        let mut painted = text.normal();
+        if let Some(color) = self.color {
+            painted = painted.color(color);
+        }
+        if self.bold {
+            painted = painted.bold();
+        }
+        if self.dimmed {
+            painted = painted.dimmed();
+        }
+        if self.italic {
+            painted = painted.italic();
+        }
+        if self.underline {
+            painted = painted.underline();
+        }
+        painted
+    }
+}
+
+fn parse_color(name: &str, spec: &str) -> Result<Color> {
+    if let Some(hex) = name.strip_prefix('#') {
+        let channel = |i: usize| hex.get(i..i + 2).and_then(|c| u8::from_str_radix(c, 16).ok());
+        if let (6, Some(r), Some(g), Some(b)) = (hex.len(), channel(0), channel(2), channel(4)) {
+            return Ok(Color::TrueColor { r, g, b });
+        }
+    }
+    match name.replace(['-', '_'], " ").parse() {
+        Ok(color) => Ok(color),
+        Err(()) => bail!("Invalid style '{}': unknown color '{}'", spec, name),
+    }
+}
+
+/// Styles of all output elements.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Theme {
+    /// The `d` marker of directories.
+    pub dir: Style,
+    /// The `f` marker of files.
+    pub file: Style,
+    /// Paths and the unchanged parts of names.
+    pub path: Style,
+    /// The matched part of a name.
+    pub matched: Style,
+    /// The replacement in a new name, and new paths.
+    pub replacement: Style,
+    /// Labels like `Renamed:` and times.
+    pub label: Style,
+    /// Headers like the dry-run notice.
+    pub header: Style,
+    pub prompt: Style,
+    pub warning: Style,
+    pub error: Style,
+    /// Entries left out and help texts.
+    pub skipped: Style,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            dir: bold(Color::Blue),
+            file: bold(Color::Green),
+            path: plain(Color::White),
+            matched: plain(Color::Yellow),
+            replacement: plain(Color::Yellow),
+            label: bold(Color::Cyan),
+            header: plain(Color::Yellow),
+            prompt: plain(Color::Cyan),
+            warning: bold(Color::Yellow),
+            error: bold(Color::Red),
+            skipped: Style {
+                dimmed: true,
+                ..Style::default()
+            },
+        }
+    }
+}
+
+fn plain(color: Color) -> Style {
+    Style {
+        color: Some(color),
+        ..Style::default()
+    }
+}
+
+fn bold(color: Color) -> Style {
+    Style {
+        bold: true,
+        ..plain(color)
+    }
+}
+
+impl Theme {
+    pub fn named(name: &str) -> Result<Theme> {
+        match name {
+            "default" => Ok(Theme::default()),
+            "colorblind" => Ok(Theme::colorblind()),
+            _ => bail!("Unknown theme '{}', expected one of {}", name, THEMES.join(", ")),
+        }
+    }
+
+    /// Blue and orange from the Okabe-Ito palette instead of red and green,
+    /// which look the same to many people.
+    pub fn colorblind() -> Theme {
+        let sky_blue = Color::TrueColor { r: 86, g: 180, b: 233 };
+        let orange = Color::TrueColor { r: 230, g: 159, b: 0 };
+        let vermillion = Color::TrueColor { r: 213, g: 94, b: 0 };
+        Theme {
+            dir: bold(sky_blue),
+            file: bold(orange),
+            matched: plain(sky_blue),
+            replacement: plain(orange),
+            label: bold(sky_blue),
+            header: plain(orange),
+            prompt: plain(sky_blue),
+            warning: bold(orange),
+            error: bold(vermillion),
+            ..Theme::default()
+        }
+    }
+}
+
+/// The `[theme]` table of the user configuration.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeConfig {
+    pub name: Option<String>,
+    pub dir: Option<String>,
+    pub file: Option<String>,
+    pub path: Option<String>,
+    pub matched: Option<String>,
+    pub replacement: Option<String>,
+    pub label: Option<String>,
+    pub header: Option<String>,
+    pub prompt: Option<String>,
+    pub warning: Option<String>,
+    pub error: Option<String>,
+    pub skipped: Option<String>,
+}
+
+impl ThemeConfig {
+    /// The named theme with the overrides applied.
+    pub fn resolve(&self) -> Result<Theme> {
+        let mut theme = Theme::named(self.name.as_deref().unwrap_or("default"))?;
+        let overrides = [
+            (&self.dir, &mut theme.dir),
+            (&self.file, &mut theme.file),
+            (&self.path, &mut theme.path),
+            (&self.matched, &mut theme.matched),
+            (&self.replacement, &mut theme.replacement),
+            (&self.label, &mut theme.label),
+            (&self.header, &mut theme.header),
+            (&self.prompt, &mut theme.prompt),
+            (&self.warning, &mut theme.warning),
+            (&self.error, &mut theme.error),
+            (&self.skipped, &mut theme.skipped),
+        ];
+        for (spec, style) in overrides {
+            if let Some(spec) = spec {
+                *style = Style::parse(spec)?;
+            }
+        }
+        Ok(theme)
+    }
+}
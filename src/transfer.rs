@@ -0,0 +1,320 @@
+//! Moving and copying matches on disk.
+//!
+//! A rename that the filesystem refuses because source and target are on
+//! different devices (which happens with some network and overlay mounts
+//! even inside one directory) falls back to copy and delete for files.
+//! With `verify`, copies are checked by hashing both sides before the
+//! source is deleted or the copy is reported as done. With the `xattrs`
+//! feature, the SELinux context and ACL come along, see [`crate::xattrs`].
+//!
+//! Planning refuses renames onto taken paths, but a path can be taken by
+//! the time the rename happens: something else created it meanwhile, or
+//! the rename that would have freed it was declined at the prompt. A plain
+//! rename would replace it without a word, so [`move_match`] renames in a
+//! way that refuses taken paths in the same step, see [`rename_new`].
+//!
+//! Renames refused because something else holds the entry (busy, access
+//! denied on Windows, sharing violations) can be retried with a [`Retry`]
+//! policy, waiting twice as long after every attempt.
+
+use crate::{file_name, open_regular, owner, Match};
+use anyhow::{bail, Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+/// How often to retry a rename failing for a possibly transient reason.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retry {
+    /// Retries after the first attempt, 0 to give up right away.
+    pub attempts: u32,
+    /// Wait before the first retry, doubled for every further one.
+    pub delay: Duration,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Retry {
+            attempts: 0,
+            delay: Duration::from_millis(100),
+        }
+    }
+}
+
+impl Retry {
+    /// Run `op` until it succeeds, fails for good or the attempts are used up.
+    pub fn run<T>(&self, mut op: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+        let mut delay = self.delay;
+        for _ in 0..self.attempts {
+            match op() {
+                Err(e) if is_transient(&e) => {
+                    thread::sleep(delay);
+                    delay = delay.saturating_mul(2);
+                }
+                result => return result,
+            }
+        }
+        op()
+    }
+}
+
+/// Whether an error may go away by itself, like a file that's open in
+/// another program or a share that's momentarily locked. Access denied
+/// only counts on Windows, which says so for files open elsewhere; on
+/// other systems it's the permissions, and those don't change by waiting.
+pub fn is_transient(e: &io::Error) -> bool {
+    // ERROR_SHARING_VIOLATION and ERROR_LOCK_VIOLATION
+    let sharing = cfg!(windows) && matches!(e.raw_os_error(), Some(32 | 33));
+    let denied = cfg!(windows) && e.kind() == io::ErrorKind::PermissionDenied;
+    sharing || denied || e.kind() == io::ErrorKind::ResourceBusy
+}
+
+/// SHA-256 of a file's content as lowercase hex.
+pub fn checksum(path: &Path) -> Result<String> {
+    let mut file = open_regular(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Files a copy or move holds open at once.
+pub const HANDLES: usize = 2;
+
+/// Copy a file to `to`, which must not exist yet unless `overwrite` is
+/// given. What it replaces then goes in one rename, by a copy made next to
+/// it first, so `to` is never half written. With `verify`, both sides are
+/// hashed afterwards and a mismatching copy is removed again.
+pub fn copy_file(from: &Path, to: &Path, verify: bool, overwrite: bool) -> Result<()> {
+    if !overwrite {
+        return copy_new(from, to, verify);
+    }
+    let tmp = to.with_file_name(format!(".{}.fnr-tmp", file_name(to)));
+    copy_new(from, &tmp, verify)?;
+    fs::rename(&tmp, to).or_else(|e| {
+        let _ = fs::remove_file(&tmp);
+        Err(e).with_context(|| format!("Failed to replace {} with a copy of {}", to.display(), from.display()))
+    })
+}
+
+/// [`copy_file`] to a path that must not exist yet.
+fn copy_new(from: &Path, to: &Path, verify: bool) -> Result<()> {
+    let mut source = open_regular(from).with_context(|| format!("Failed to open {}", from.display()))?;
+    let mut target = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(to)
+        .with_context(|| format!("Failed to create {}", to.display()))?;
+    let copied = io::copy(&mut source, &mut target)
+        .and_then(|_| target.sync_all())
+        .and_then(|_| {
+            let metadata = source.metadata()?;
+            fs::set_permissions(to, metadata.permissions())?;
+            owner::keep_owner(&metadata, to)
+        });
+    // Two open files at a time is all a copy takes, see `HANDLES`
+    drop((source, target));
+    if let Err(e) = copied {
+        let _ = fs::remove_file(to);
+        return Err(e).with_context(|| format!("Failed to copy {} to {}", from.display(), to.display()));
+    }
+
+    if verify {
+        let (expected, actual) = (checksum(from)?, checksum(to)?);
+        if expected != actual {
+            let _ = fs::remove_file(to);
+            bail!(
+                "Copy of {} to {} is corrupt (sha256 {} != {}), copy removed",
+                from.display(),
+                to.display(),
+                actual,
+                expected
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Copy a matched file to its new name, keeping the original.
+pub fn copy_match(m: &Match, verify: bool) -> Result<PathBuf> {
+    if m.is_dir {
+        bail!("Cannot copy directory {}", m.path.display());
+    }
+    let new_path = m.new_path();
+    copy_file(&m.path, &new_path, verify, false)?;
+    Ok(new_path)
+}
+
+/// Whether `to` is taken by an entry other than `from` itself, which a
+/// case-insensitive filesystem finds under its new name in case-only
+/// renames.
+pub(crate) fn taken(from: &Path, to: &Path) -> bool {
+    match fs::symlink_metadata(to) {
+        Ok(target) => !same_entry(from, to, &target),
+        Err(_) => false,
+    }
+}
+
+/// Whether the move from `from` to `to` leaves the filesystem `from` is
+/// on, which turns it into a copy. Unknown where devices can't be told.
+#[cfg(unix)]
+pub(crate) fn crosses_filesystem(from: &Path, to: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    let dir = to.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    match (fs::symlink_metadata(from), fs::metadata(dir)) {
+        (Ok(source), Ok(dir)) => source.dev() != dir.dev(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn crosses_filesystem(_from: &Path, _to: &Path) -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn same_entry(from: &Path, _to: &Path, target: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    fs::symlink_metadata(from).is_ok_and(|source| (source.dev(), source.ino()) == (target.dev(), target.ino()))
+}
+
+#[cfg(not(unix))]
+fn same_entry(from: &Path, to: &Path, _target: &fs::Metadata) -> bool {
+    let name = |path: &Path| path.file_name().map(|name| name.to_string_lossy().to_lowercase());
+    from.parent() == to.parent() && name(from) == name(to)
+}
+
+/// Rename `from` to `to`, failing with [`io::ErrorKind::AlreadyExists`] if
+/// `to` is taken. Checked in the same step where the system can: with
+/// `renameat2` and `RENAME_NOREPLACE` on Linux, with a hard link and
+/// unlink for files elsewhere. Directories elsewhere and filesystems
+/// that can do neither get a look right before a plain rename.
+fn rename_new(from: &Path, to: &Path, is_dir: bool) -> io::Result<()> {
+    match rename_exclusive(from, to, is_dir) {
+        // A case-insensitive filesystem finds the entry itself there
+        Some(Err(e)) if e.kind() == io::ErrorKind::AlreadyExists && !taken(from, to) => fs::rename(from, to),
+        Some(result) => result,
+        None if taken(from, to) => Err(io::ErrorKind::AlreadyExists.into()),
+        None => fs::rename(from, to),
+    }
+}
+
+/// `rename_new` in one step, `None` where the filesystem can't do it.
+#[cfg(target_os = "linux")]
+fn rename_exclusive(from: &Path, to: &Path, _is_dir: bool) -> Option<io::Result<()>> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    let from = CString::new(from.as_os_str().as_bytes()).ok()?;
+    let to = CString::new(to.as_os_str().as_bytes()).ok()?;
+    // SAFETY: both strings are NUL terminated, AT_FDCWD resolves them like rename(2)
+    let renamed = unsafe {
+        libc::syscall(libc::SYS_renameat2, libc::AT_FDCWD, from.as_ptr(), libc::AT_FDCWD, to.as_ptr(), libc::RENAME_NOREPLACE)
+    };
+    if renamed == 0 {
+        return Some(Ok(()));
+    }
+    let e = io::Error::last_os_error();
+    match e.raw_os_error() {
+        // Old kernels and filesystems without the flag
+        Some(libc::ENOSYS | libc::EINVAL) => None,
+        _ => Some(Err(e)),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn rename_exclusive(from: &Path, to: &Path, is_dir: bool) -> Option<io::Result<()>> {
+    // Linking a symlink follows it on some systems
+    if is_dir || !fs::symlink_metadata(from).is_ok_and(|metadata| metadata.is_file()) {
+        return None;
+    }
+    match fs::hard_link(from, to) {
+        Ok(()) => Some(fs::remove_file(from).inspect_err(|_| {
+            let _ = fs::remove_file(to);
+        })),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Some(Err(e)),
+        // No hard links on this filesystem, or across devices
+        Err(_) => None,
+    }
+}
+
+/// Rename a match, falling back to copy and delete for files the
+/// filesystem cannot rename in place. Only with `overwrite` does it replace
+/// what has the new path.
+pub fn move_match(m: &Match, verify: bool, retry: &Retry, overwrite: bool) -> Result<PathBuf> {
+    let new_path = m.new_path();
+    let renamed = retry.run(|| match overwrite {
+        true => fs::rename(&m.path, &new_path),
+        false => rename_new(&m.path, &new_path, m.is_dir),
+    });
+    match renamed {
+        Ok(()) => Ok(new_path),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => bail!("{} already exists, not overwriting it", new_path.display()),
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices && !m.is_dir => {
+            copy_file(&m.path, &new_path, verify, overwrite)?;
+            #[cfg(feature = "xattrs")]
+            for lost in crate::xattrs::carry(&m.path, &new_path) {
+                eprintln!("Warning: {} was copied to {} without its {}", m.path.display(), new_path.display(), lost);
+            }
+            fs::remove_file(&m.path)
+                .with_context(|| format!("Copied to {}, but failed to remove {}", new_path.display(), m.path.display()))?;
+            Ok(new_path)
+        }
+        Err(e) => Err(e)
+            .with_context(|| format!("Failed to rename {} to {}", m.path.display(), new_path.display())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn access_denied_is_only_worth_waiting_for_on_windows() {
+        assert!(is_transient(&io::ErrorKind::ResourceBusy.into()));
+        assert_eq!(is_transient(&io::ErrorKind::PermissionDenied.into()), cfg!(windows));
+        assert!(!is_transient(&io::ErrorKind::NotFound.into()));
+    }
+
+    #[test]
+    fn copies_replace_the_target_only_when_told_to() {
+        let dir = std::env::temp_dir().join(format!("fnr-transfer-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (from, to) = (dir.join("from"), dir.join("to"));
+        fs::write(&from, "new").unwrap();
+        fs::write(&to, "old").unwrap();
+
+        assert!(copy_file(&from, &to, true, false).is_err());
+        assert_eq!(fs::read_to_string(&to).unwrap(), "old");
+        copy_file(&from, &to, true, true).unwrap();
+        assert_eq!(fs::read_to_string(&to).unwrap(), "new");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2, "no copy left behind");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn renames_never_replace_what_appeared_meanwhile() {
+        let dir = std::env::temp_dir().join(format!("fnr-rename-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (from, to) = (dir.join("a"), dir.join("b"));
+        fs::write(&from, "a").unwrap();
+        fs::write(&to, "b").unwrap();
+
+        let e = rename_new(&from, &to, false).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(&to).unwrap(), "b");
+        fs::remove_file(&to).unwrap();
+        rename_new(&from, &to, false).unwrap();
+        assert_eq!(fs::read_to_string(&to).unwrap(), "a");
+        assert!(!from.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
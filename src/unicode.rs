@@ -0,0 +1,92 @@
+//! Cleaning up names that look fine and aren't: invisible characters,
+//! exotic spaces and lookalike letters, as they arrive with uploads and
+//! copy and paste, for `--fix-unicode`.
+
+use crate::{file_name, Match};
+
+/// Characters that take no room and mean nothing in a name: zero width
+/// spaces, soft hyphens, byte order marks, direction marks and the bidi
+/// controls that make names read differently than they sort.
+const INVISIBLE: &[char] = &[
+    '\u{00AD}', '\u{180E}', '\u{200B}', '\u{200E}', '\u{200F}', '\u{202A}', '\u{202B}', '\u{202C}', '\u{202D}',
+    '\u{202E}', '\u{2060}', '\u{2061}', '\u{2062}', '\u{2063}', '\u{2064}', '\u{2066}', '\u{2067}', '\u{2068}',
+    '\u{2069}', '\u{FEFF}',
+];
+
+/// Joiners, which hold emoji sequences and some scripts together, but
+/// mean nothing next to ASCII.
+const JOINERS: &[char] = &['\u{200C}', '\u{200D}'];
+
+/// Spaces other than the plain one.
+const SPACES: &[char] = &[
+    '\u{00A0}', '\u{1680}', '\u{2000}', '\u{2001}', '\u{2002}', '\u{2003}', '\u{2004}', '\u{2005}', '\u{2006}',
+    '\u{2007}', '\u{2008}', '\u{2009}', '\u{200A}', '\u{202F}', '\u{205F}', '\u{3000}',
+];
+
+/// Cyrillic and Greek letters that look like Latin ones.
+const HOMOGLYPHS: &[(char, char)] = &[
+    ('а', 'a'), ('е', 'e'), ('о', 'o'), ('р', 'p'), ('с', 'c'), ('у', 'y'), ('х', 'x'), ('і', 'i'), ('ј', 'j'),
+    ('ѕ', 's'), ('ԁ', 'd'), ('ԛ', 'q'), ('ԝ', 'w'), ('һ', 'h'), ('ӏ', 'l'), ('А', 'A'), ('В', 'B'), ('Е', 'E'),
+    ('К', 'K'), ('М', 'M'), ('Н', 'H'), ('О', 'O'), ('Р', 'P'), ('С', 'C'), ('Т', 'T'), ('Х', 'X'), ('І', 'I'),
+    ('Ј', 'J'), ('Ѕ', 'S'), ('Ү', 'Y'), ('ο', 'o'), ('ν', 'v'), ('ϲ', 'c'), ('Α', 'A'), ('Β', 'B'), ('Ε', 'E'),
+    ('Ζ', 'Z'), ('Η', 'H'), ('Ι', 'I'), ('Κ', 'K'), ('Μ', 'M'), ('Ν', 'N'), ('Ο', 'O'), ('Ρ', 'P'), ('Τ', 'T'),
+    ('Υ', 'Y'), ('Χ', 'X'),
+];
+
+/// The Latin letter or digit `c` looks like: Cyrillic and Greek lookalikes
+/// and fullwidth forms.
+fn latin(c: char) -> Option<char> {
+    match c {
+        '\u{FF10}'..='\u{FF19}' | '\u{FF21}'..='\u{FF3A}' | '\u{FF41}'..='\u{FF5A}' => {
+            char::from_u32(c as u32 - 0xFF10 + '0' as u32)
+        }
+        _ => HOMOGLYPHS.iter().find(|&&(glyph, _)| glyph == c).map(|&(_, latin)| latin),
+    }
+}
+
+/// `name` without invisible characters, with plain spaces, and with the
+/// lookalike letters of words that are otherwise Latin spelled in Latin.
+/// Words entirely in another script are left alone: a Russian name is
+/// Russian, not a disguise.
+pub fn clean_name(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut visible: Vec<char> = Vec::with_capacity(chars.len());
+    for (i, &c) in chars.iter().enumerate() {
+        let ascii_around = || {
+            let before = i.checked_sub(1).map(|i| chars[i]);
+            before.is_some_and(|c| c.is_ascii()) || chars.get(i + 1).is_some_and(char::is_ascii)
+        };
+        if INVISIBLE.contains(&c) || (JOINERS.contains(&c) && ascii_around()) {
+            continue;
+        }
+        visible.push(if SPACES.contains(&c) { ' ' } else { c });
+    }
+
+    let mut cleaned = String::with_capacity(name.len());
+    for word in visible.chunk_by(|a, b| a.is_alphanumeric() == b.is_alphanumeric()) {
+        let latin_word = word.iter().any(|c| c.is_ascii_alphabetic());
+        cleaned.extend(word.iter().map(|&c| match latin(c) {
+            Some(latin) if latin_word => latin,
+            _ => c,
+        }));
+    }
+    cleaned
+}
+
+/// Clean up the new names of `matches` with [`clean_name`]. Keeps the
+/// matches whose name changes after all; names that would be left empty
+/// keep their new name as it is.
+pub fn fix_unicode(matches: Vec<Match>) -> Vec<Match> {
+    matches
+        .into_iter()
+        .filter_map(|mut m| {
+            let cleaned = clean_name(&m.new_name);
+            if !cleaned.is_empty() && cleaned != m.new_name {
+                m.new_name = cleaned;
+                m.replacement = m.new_name.as_str().into();
+                m.pattern = file_name(&m.path).into();
+            }
+            (m.new_name != file_name(&m.path)).then_some(m)
+        })
+        .collect()
+}
@@ -0,0 +1,87 @@
+//! Access control that a copy has to be told about: SELinux contexts and
+//! POSIX ACLs, both kept in extended attributes on Linux. A rename keeps
+//! them along with the file; the copy and delete fallback of
+//! [`move_match`](crate::transfer::move_match) carries them across with
+//! [`carry`]. Elsewhere there is nothing to carry.
+
+use std::path::Path;
+
+/// Extended attributes to carry, with what they are called in warnings.
+#[cfg(target_os = "linux")]
+const CARRIED: &[(&str, &str)] = &[
+    ("security.selinux", "SELinux context"),
+    ("system.posix_acl_access", "ACL"),
+];
+
+/// Give `to` the SELinux context and ACLs of `from`. Returns what couldn't
+/// be carried across and why, like `SELinux context (Operation not
+/// supported)`. What `from` doesn't have is nothing to carry.
+#[cfg(target_os = "linux")]
+pub fn carry(from: &Path, to: &Path) -> Vec<String> {
+    let mut lost = Vec::new();
+    for &(name, label) in CARRIED {
+        let result = match get(from, name) {
+            Ok(Some(value)) => set(to, name, &value),
+            Ok(None) => Ok(()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            lost.push(format!("{} ({})", label, e));
+        }
+    }
+    lost
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn carry(_from: &Path, _to: &Path) -> Vec<String> {
+    Vec::new()
+}
+
+#[cfg(target_os = "linux")]
+fn c_string(text: &[u8]) -> std::io::Result<std::ffi::CString> {
+    std::ffi::CString::new(text).map_err(|_| std::io::ErrorKind::InvalidInput.into())
+}
+
+/// The value of the attribute `name` of `path`, `None` if it has none or
+/// its filesystem has no such thing.
+#[cfg(target_os = "linux")]
+fn get(path: &Path, name: &str) -> std::io::Result<Option<Vec<u8>>> {
+    use std::io::Error;
+    use std::os::unix::ffi::OsStrExt;
+    let (path, name) = (c_string(path.as_os_str().as_bytes())?, c_string(name.as_bytes())?);
+    loop {
+        // SAFETY: both strings are NUL terminated, a null buffer asks for the size
+        let size = unsafe { libc::lgetxattr(path.as_ptr(), name.as_ptr(), std::ptr::null_mut(), 0) };
+        if size < 0 {
+            let e = Error::last_os_error();
+            return match e.raw_os_error() {
+                Some(libc::ENODATA | libc::EOPNOTSUPP) => Ok(None),
+                _ => Err(e),
+            };
+        }
+        let mut value = vec![0u8; size as usize];
+        // SAFETY: `value` holds `size` bytes
+        let read = unsafe { libc::lgetxattr(path.as_ptr(), name.as_ptr(), value.as_mut_ptr().cast(), value.len()) };
+        if read >= 0 {
+            value.truncate(read as usize);
+            return Ok(Some(value));
+        }
+        let e = Error::last_os_error();
+        // Grew in between, ask again
+        if e.raw_os_error() != Some(libc::ERANGE) {
+            return Err(e);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set(path: &Path, name: &str, value: &[u8]) -> std::io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    let (path, name) = (c_string(path.as_os_str().as_bytes())?, c_string(name.as_bytes())?);
+    // SAFETY: both strings are NUL terminated, `value` is `value.len()` bytes
+    let result = unsafe { libc::lsetxattr(path.as_ptr(), name.as_ptr(), value.as_ptr().cast(), value.len(), 0) };
+    if result < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}